@@ -0,0 +1,293 @@
+use crate::exchange::{Event, Exchange};
+use crate::http::Request;
+use crate::market::{now_millis, Tick, Trade, Venue};
+use futures::{SinkExt, StreamExt};
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+/* Binance Combined Streams */
+
+// USDⓈ-M, COIN-M and spot are served from the same path. The futures event format is a
+// superset of the spot one, so one rendering fits every handler
+
+const FUNDING_INTERVAL_MS: u64 = 8 * 60 * 60 * 1000;
+// Listed by the COIN-M exchangeInfo whether asked for or not
+const COIN_M_PERPETUALS: [&str; 4] = ["BTCUSD_PERP", "ETHUSD_PERP", "BNBUSD_PERP", "SOLUSD_PERP"];
+
+/// Streams come from the URL, e.g. `/stream?streams=btcusdt@depth/btcusdt@aggTrade`
+pub async fn serve_websocket(
+    mut ws: WebSocketStream<TcpStream>,
+    request: Request,
+    exchange: Exchange,
+) {
+    let streams: Vec<String> = request
+        .param("streams")
+        .unwrap_or_default()
+        .split('/')
+        .filter(|stream| !stream.is_empty())
+        .map(|stream| stream.to_string())
+        .collect();
+    if !exchange.is_scripted() {
+        for (symbol, _) in streams.iter().filter_map(|stream| stream.split_once('@')) {
+            if !symbol.starts_with('!') {
+                exchange.book(Venue::Binance, symbol);
+            }
+        }
+    }
+    info!("Mock Binance stream: Serving {:?}", streams);
+
+    let mut events = exchange.subscribe();
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let frames = match event {
+                    Ok(Event::Tick(tick)) if tick.venue == Venue::Binance => streams
+                        .iter()
+                        .filter_map(|stream| render(stream, &tick))
+                        .collect(),
+                    Ok(Event::Frame(frame))
+                        if frame.venue == Venue::Binance && streams.contains(&frame.topic) =>
+                    {
+                        vec![frame.payload.clone()]
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Mock Binance stream: Lagging, skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                for frame in frames {
+                    if ws.send(Message::Text(frame.into())).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => (), // Pings are answered by tungstenite
+            }
+        }
+    }
+    info!("Mock Binance stream: Connection closed");
+}
+
+fn render(stream: &str, tick: &Tick) -> Option<String> {
+    let (symbol, kind) = stream.split_once('@')?;
+    let data = match kind {
+        "arr" if symbol == "!forceOrder" => force_order(tick, tick.liquidation.as_ref()?),
+        _ if !symbol.eq_ignore_ascii_case(&tick.symbol) => return None,
+        "aggTrade" => agg_trade(tick, tick.trade.as_ref()?),
+        "bookTicker" => book_ticker(tick),
+        "forceOrder" => force_order(tick, tick.liquidation.as_ref()?),
+        kind if kind.starts_with("depth") => depth_update(tick),
+        kind if kind.starts_with("markPrice") => mark_price(tick),
+        _ => return None,
+    };
+    Some(json!({ "stream": stream, "data": data }).to_string())
+}
+
+fn depth_update(tick: &Tick) -> Value {
+    json!({
+        "e": "depthUpdate",
+        "E": tick.time,
+        "T": tick.time,
+        "s": tick.symbol,
+        "U": tick.first_update_id,
+        "u": tick.last_update_id,
+        "pu": tick.prev_update_id,
+        "b": levels(&tick.bids),
+        "a": levels(&tick.asks),
+    })
+}
+
+fn agg_trade(tick: &Tick, trade: &Trade) -> Value {
+    json!({
+        "e": "aggTrade",
+        "E": tick.time,
+        "s": tick.symbol,
+        "a": trade.id,
+        "p": trade.price.to_string(),
+        "q": trade.quantity.to_string(),
+        "f": trade.id,
+        "l": trade.id,
+        "T": tick.time,
+        "m": trade.buyer_market_maker,
+    })
+}
+
+fn book_ticker(tick: &Tick) -> Value {
+    let (bid_price, bid_quantity) = tick.book.bids.first().copied().unwrap_or_default();
+    let (ask_price, ask_quantity) = tick.book.asks.first().copied().unwrap_or_default();
+    json!({
+        "e": "bookTicker",
+        "u": tick.last_update_id,
+        "E": tick.time,
+        "T": tick.time,
+        "s": tick.symbol,
+        "b": bid_price.to_string(),
+        "B": bid_quantity.to_string(),
+        "a": ask_price.to_string(),
+        "A": ask_quantity.to_string(),
+    })
+}
+
+fn mark_price(tick: &Tick) -> Value {
+    // Delivery contracts, e.g. BTCUSDT_250627, have no funding
+    let delivery = tick
+        .symbol
+        .split_once('_')
+        .is_some_and(|(_, suffix)| suffix.chars().all(|c| c.is_ascii_digit()));
+    let (funding_rate, next_funding_time) = match delivery {
+        true => (String::new(), 0),
+        false => (
+            "0.00010000".to_string(),
+            (tick.time / FUNDING_INTERVAL_MS + 1) * FUNDING_INTERVAL_MS,
+        ),
+    };
+    json!({
+        "e": "markPriceUpdate",
+        "E": tick.time,
+        "s": tick.symbol,
+        "p": tick.mark_price.to_string(),
+        "i": tick.index_price.to_string(),
+        "P": tick.mark_price.to_string(),
+        "r": funding_rate,
+        "T": next_funding_time,
+    })
+}
+
+fn force_order(tick: &Tick, liquidation: &Trade) -> Value {
+    // A liquidated long is sold into the bids
+    let side = match liquidation.buyer_market_maker {
+        true => "SELL",
+        false => "BUY",
+    };
+    json!({
+        "e": "forceOrder",
+        "E": tick.time,
+        "o": {
+            "s": tick.symbol,
+            "S": side,
+            "o": "LIMIT",
+            "f": "IOC",
+            "q": liquidation.quantity.to_string(),
+            "p": liquidation.price.to_string(),
+            "ap": liquidation.price.to_string(),
+            "X": "FILLED",
+            "l": liquidation.quantity.to_string(),
+            "z": liquidation.quantity.to_string(),
+            "T": tick.time,
+        },
+    })
+}
+
+/* Binance REST */
+
+pub fn rest(request: &Request, exchange: &Exchange) -> (u16, String) {
+    let symbol = request.param("symbol").unwrap_or_default().to_uppercase();
+    let body = match request.path.as_str() {
+        "/fapi/v1/depth" | "/dapi/v1/depth" | "/api/v3/depth" if !symbol.is_empty() => {
+            let book = exchange.book(Venue::Binance, &symbol);
+            let now = now_millis();
+            json!({
+                "lastUpdateId": book.snapshot_id(),
+                "E": now,
+                "T": now,
+                "bids": levels(&book.bids),
+                "asks": levels(&book.asks),
+            })
+        }
+        "/fapi/v1/time" | "/dapi/v1/time" | "/api/v3/time" => {
+            json!({ "serverTime": now_millis() })
+        }
+        // Trades from `fromId` on, or the latest ones. Only the recent history is kept
+        "/fapi/v1/aggTrades" | "/dapi/v1/aggTrades" | "/api/v3/aggTrades" if !symbol.is_empty() => {
+            let limit = request
+                .param("limit")
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(500)
+                .min(1000);
+            let trades = exchange.trades(Venue::Binance, &symbol);
+            let page: Vec<&Trade> = match request
+                .param("fromId")
+                .and_then(|id| id.parse::<u64>().ok())
+            {
+                Some(from) => trades
+                    .iter()
+                    .filter(|trade| trade.id >= from)
+                    .take(limit)
+                    .collect(),
+                None => trades
+                    .iter()
+                    .skip(trades.len().saturating_sub(limit))
+                    .collect(),
+            };
+            json!(page.into_iter().map(rest_agg_trade).collect::<Vec<_>>())
+        }
+        // USDⓈ-M quantities are in coins, it lists no contract sizes
+        "/fapi/v1/exchangeInfo" => json!({ "symbols": [] }),
+        "/dapi/v1/exchangeInfo" => {
+            // Other contracts that have been asked for, e.g. BTCUSD_250627
+            let asked: Vec<String> = exchange
+                .symbols(Venue::Binance)
+                .into_iter()
+                .filter(|symbol| {
+                    symbol.contains("USD_") && !COIN_M_PERPETUALS.contains(&symbol.as_str())
+                })
+                .collect();
+            let symbols: Vec<Value> = COIN_M_PERPETUALS
+                .iter()
+                .map(|symbol| symbol.to_string())
+                .chain(asked)
+                .map(|symbol| json!({ "symbol": symbol, "contractSize": contract_size(&symbol) }))
+                .collect();
+            json!({ "symbols": symbols })
+        }
+        "/fapi/v1/openInterest" if !symbol.is_empty() => json!({
+            "symbol": symbol,
+            "openInterest": "1000.000",
+            "time": now_millis(),
+        }),
+        // No figure for the period yet
+        path if path.starts_with("/futures/data/") => json!([]),
+        _ => {
+            return (
+                404,
+                json!({ "code": -1121, "msg": "Invalid symbol." }).to_string(),
+            )
+        }
+    };
+    (200, body.to_string())
+}
+
+fn rest_agg_trade(trade: &Trade) -> Value {
+    json!({
+        "a": trade.id,
+        "p": trade.price.to_string(),
+        "q": trade.quantity.to_string(),
+        "f": trade.id,
+        "l": trade.id,
+        "T": trade.time,
+        "m": trade.buyer_market_maker,
+    })
+}
+
+// USD per COIN-M contract, as on Binance: 100 for BTC, 10 for the rest
+fn contract_size(symbol: &str) -> u64 {
+    match symbol.starts_with("BTCUSD_") {
+        true => 100,
+        false => 10,
+    }
+}
+
+fn levels(levels: &[(Decimal, Decimal)]) -> Vec<[String; 2]> {
+    levels
+        .iter()
+        .map(|(price, quantity)| [price.to_string(), quantity.to_string()])
+        .collect()
+}
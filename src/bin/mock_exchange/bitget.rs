@@ -0,0 +1,246 @@
+use crate::exchange::{Event, Exchange};
+use crate::market::{now_millis, Tick, Venue};
+use cryptoquant::data::depth::checksum::{ChecksumBook, CHECKSUM_DEPTH};
+use futures::{SinkExt, StreamExt};
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+/* Bitget Public Websocket */
+
+// `{"op": "subscribe", "args": [{"instType", "channel", "instId"}]}` is confirmed with an
+// event per arg. `books` starts with a snapshot, then sends updates with a checksum of the
+// top 25 levels. A plain "ping" text is answered with "pong"
+
+#[derive(Debug, Deserialize)]
+struct Operation {
+    op: String,
+    args: Vec<Value>,
+}
+
+// Books as the client sees them, to compute the checksum of every update
+struct BookMirror {
+    book: ChecksumBook,
+    update_id: u64, // Ticks up to here are covered by the snapshot
+}
+
+pub async fn serve_websocket(mut ws: WebSocketStream<TcpStream>, exchange: Exchange) {
+    let mut subscriptions: HashSet<(String, String)> = HashSet::new(); // (channel, instId)
+    let mut books: HashMap<String, BookMirror> = HashMap::new();
+    let mut events = exchange.subscribe();
+
+    loop {
+        let frames = tokio::select! {
+            event = events.recv() => match event {
+                Ok(Event::Tick(tick)) if tick.venue == Venue::Bitget => {
+                    render(&subscriptions, &mut books, &tick)
+                }
+                Ok(Event::Frame(frame)) if frame.venue == Venue::Bitget => {
+                    let subscribed = frame
+                        .topic
+                        .split_once('.')
+                        .is_some_and(|(channel, symbol)| subscriptions.contains(&(channel.to_string(), symbol.to_string())));
+                    match subscribed {
+                        true => vec![frame.payload.clone()],
+                        false => continue,
+                    }
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Mock Bitget stream: Lagging, skipped {} events", skipped);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Text(text))) if text.as_str() == "ping" => vec!["pong".to_string()],
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<Operation>(&text) {
+                        Ok(operation) => apply_operation(&exchange, operation, &mut subscriptions, &mut books),
+                        Err(e) => {
+                            warn!("Mock Bitget stream: Invalid operation {}: {}", text, e);
+                            continue;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue, // Pings are answered by tungstenite
+            },
+        };
+        for frame in frames {
+            if ws.send(Message::Text(frame.into())).await.is_err() {
+                return;
+            }
+        }
+    }
+    info!("Mock Bitget stream: Connection closed");
+}
+
+/// Confirmations, and a snapshot for every `books` subscription
+fn apply_operation(
+    exchange: &Exchange,
+    operation: Operation,
+    subscriptions: &mut HashSet<(String, String)>,
+    books: &mut HashMap<String, BookMirror>,
+) -> Vec<String> {
+    let mut frames = Vec::new();
+    for arg in operation.args {
+        let (Some(channel), Some(symbol)) = (arg["channel"].as_str(), arg["instId"].as_str())
+        else {
+            continue;
+        };
+        let key = (channel.to_string(), symbol.to_uppercase());
+        frames.push(json!({ "event": operation.op, "arg": arg }).to_string());
+
+        match operation.op.as_str() {
+            "subscribe" => {
+                info!("Mock Bitget stream: Serving {} {}", channel, symbol);
+                subscriptions.insert(key);
+                if exchange.is_scripted() {
+                    continue;
+                }
+                let book = exchange.book(Venue::Bitget, symbol);
+                if channel == "books" {
+                    let mut mirror = BookMirror {
+                        book: ChecksumBook::new(),
+                        update_id: book.update_id,
+                    };
+                    frames.push(books_frame(
+                        "snapshot",
+                        symbol,
+                        &mut mirror,
+                        &book.bids,
+                        &book.asks,
+                        now_millis(),
+                    ));
+                    books.insert(symbol.to_uppercase(), mirror);
+                }
+            }
+            "unsubscribe" => {
+                subscriptions.remove(&key);
+                if channel == "books" {
+                    books.remove(&key.1);
+                }
+            }
+            _ => warn!("Mock Bitget stream: Unknown operation {}", operation.op),
+        }
+    }
+    frames
+}
+
+fn render(
+    subscriptions: &HashSet<(String, String)>,
+    books: &mut HashMap<String, BookMirror>,
+    tick: &Tick,
+) -> Vec<String> {
+    let subscribed =
+        |channel: &str| subscriptions.contains(&(channel.to_string(), tick.symbol.clone()));
+
+    let mut frames = Vec::new();
+    if let Some(mirror) = books.get_mut(&tick.symbol) {
+        if tick.last_update_id > mirror.update_id {
+            mirror.update_id = tick.last_update_id;
+            frames.push(books_frame(
+                "update",
+                &tick.symbol,
+                mirror,
+                &tick.bids,
+                &tick.asks,
+                tick.time,
+            ));
+        }
+    }
+    if let (true, Some(trade)) = (subscribed("trade"), &tick.trade) {
+        let side = match trade.buyer_market_maker {
+            true => "sell",
+            false => "buy",
+        };
+        frames.push(message(
+            "trade",
+            &tick.symbol,
+            json!({
+                "ts": tick.time.to_string(),
+                "price": trade.price.to_string(),
+                "size": trade.quantity.to_string(),
+                "side": side,
+                "tradeId": trade.id.to_string(),
+            }),
+        ));
+    }
+    if subscribed("ticker") {
+        let last = tick
+            .trade
+            .as_ref()
+            .map_or(tick.mark_price, |trade| trade.price);
+        frames.push(message(
+            "ticker",
+            &tick.symbol,
+            json!({
+                "instId": tick.symbol,
+                "lastPr": last.to_string(),
+                "markPrice": tick.mark_price.to_string(),
+                "indexPrice": tick.index_price.to_string(),
+                "fundingRate": "0.0001",
+                "nextFundingTime": next_funding_time(tick.time).to_string(),
+                "ts": tick.time.to_string(),
+            }),
+        ));
+    }
+    frames
+}
+
+fn books_frame(
+    action: &str,
+    symbol: &str,
+    mirror: &mut BookMirror,
+    bids: &[(Decimal, Decimal)],
+    asks: &[(Decimal, Decimal)],
+    ts: u64,
+) -> String {
+    let bids = levels(bids);
+    let asks = levels(asks);
+    mirror.book.apply(&bids, &asks);
+    let data = json!({
+        "asks": asks,
+        "bids": bids,
+        "checksum": mirror.book.checksum(CHECKSUM_DEPTH),
+        "ts": ts.to_string(),
+    });
+    json!({
+        "action": action,
+        "arg": arg("books", symbol),
+        "data": [data],
+    })
+    .to_string()
+}
+
+fn message(channel: &str, symbol: &str, data: Value) -> String {
+    json!({
+        "action": "update",
+        "arg": arg(channel, symbol),
+        "data": [data],
+    })
+    .to_string()
+}
+
+fn arg(channel: &str, symbol: &str) -> Value {
+    json!({ "instType": "USDT-FUTURES", "channel": channel, "instId": symbol })
+}
+
+fn levels(levels: &[(Decimal, Decimal)]) -> Vec<(String, String)> {
+    levels
+        .iter()
+        .map(|(price, size)| (price.to_string(), size.to_string()))
+        .collect()
+}
+
+// Bitget settles funding every 8 hours
+fn next_funding_time(now: u64) -> u64 {
+    const INTERVAL: u64 = 8 * 60 * 60 * 1000;
+    (now / INTERVAL + 1) * INTERVAL
+}
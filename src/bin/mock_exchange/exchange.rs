@@ -0,0 +1,122 @@
+use crate::http::Request;
+use crate::market::{Book, SimMarket, Tick, Trade, Venue};
+use crate::script::Frame;
+use rand::rngs::StdRng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::{broadcast, Notify};
+
+const EVENT_CAPACITY: usize = 4096; // Slow connections lag and skip events, like a real feed
+
+/// Published to every connection. Each one renders what its subscriptions ask for
+#[derive(Debug, Clone)]
+pub enum Event {
+    Tick(Arc<Tick>),   // Synthetic mode
+    Frame(Arc<Frame>), // Scripted mode, sent as is
+}
+
+/// State shared by every connection: the synthetic markets or the scripted REST responses
+#[derive(Clone)]
+pub struct Exchange {
+    scripted: bool,
+    start_price: i64, // Cents
+    markets: Arc<Mutex<HashMap<(Venue, String), SimMarket>>>,
+    rest: Arc<RwLock<Vec<(Request, String)>>>, // Scripted responses, latest first
+    events: broadcast::Sender<Event>,
+    client_connected: Arc<Notify>,
+}
+
+impl Exchange {
+    pub fn new(scripted: bool, start_price: i64) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            scripted,
+            start_price,
+            markets: Arc::new(Mutex::new(HashMap::new())),
+            rest: Arc::new(RwLock::new(Vec::new())),
+            events,
+            client_connected: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn is_scripted(&self) -> bool {
+        self.scripted
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.client_connected.notify_one();
+        self.events.subscribe()
+    }
+
+    /// Resolves once a WebSocket client has connected
+    pub async fn client_connected(&self) {
+        self.client_connected.notified().await
+    }
+
+    /// Current book of `symbol`. The market starts ticking from here on
+    pub fn book(&self, venue: Venue, symbol: &str) -> Book {
+        let mut markets = self.markets.lock().unwrap();
+        markets
+            .entry((venue, symbol.to_uppercase()))
+            .or_insert_with(|| SimMarket::new(self.start_price))
+            .book()
+    }
+
+    /// Recent trades of `symbol`, oldest first. Empty until its market has been asked for
+    pub fn trades(&self, venue: Venue, symbol: &str) -> Vec<Trade> {
+        let markets = self.markets.lock().unwrap();
+        markets
+            .get(&(venue, symbol.to_uppercase()))
+            .map(|market| market.trades().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Symbols of every market of `venue` that has been asked for
+    pub fn symbols(&self, venue: Venue) -> Vec<String> {
+        let markets = self.markets.lock().unwrap();
+        markets
+            .keys()
+            .filter(|(market_venue, _)| *market_venue == venue)
+            .map(|(_, symbol)| symbol.clone())
+            .collect()
+    }
+
+    /// Step every market that has been asked for
+    pub fn tick(&self, rng: &mut StdRng) {
+        let ticks: Vec<Tick> = {
+            let mut markets = self.markets.lock().unwrap();
+            markets
+                .iter_mut()
+                .map(|((venue, symbol), market)| market.step(*venue, symbol, rng))
+                .collect()
+        };
+        for tick in ticks {
+            // No receivers is fine, the market keeps moving
+            let _ = self.events.send(Event::Tick(Arc::new(tick)));
+        }
+    }
+
+    pub fn publish(&self, frame: Frame) {
+        let _ = self.events.send(Event::Frame(Arc::new(frame)));
+    }
+
+    pub fn set_rest(&self, request: Request, body: String) {
+        self.rest.write().unwrap().insert(0, (request, body));
+    }
+
+    /// Latest scripted response whose path matches and whose query is a subset of the request's
+    pub fn scripted_rest(&self, request: &Request) -> Option<String> {
+        self.rest
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(scripted, _)| {
+                scripted.path == request.path
+                    && scripted
+                        .query
+                        .iter()
+                        .all(|(key, value)| request.param(key) == Some(value.as_str()))
+            })
+            .map(|(_, body)| body.clone())
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{handshake::derive_accept_key, protocol::Role};
+use tokio_tungstenite::WebSocketStream;
+
+// Just enough HTTP/1.1 for the clients of this repository: GET requests without a body,
+// answered with `Connection: close`, and the WebSocket upgrade handshake
+
+const MAX_HEADER_LINES: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub path: String,
+    pub query: HashMap<String, String>,
+    headers: HashMap<String, String>, // Lowercase names
+}
+
+impl Request {
+    /// Parse a request target, e.g. "/fapi/v1/depth?symbol=BTCUSDT&limit=1000"
+    pub fn from_target(target: &str) -> Self {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (key.to_string(), percent_decode(value))
+            })
+            .collect();
+
+        Self {
+            path: path.trim_end_matches('/').to_string(),
+            query,
+            headers: HashMap::new(),
+        }
+    }
+
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.query.get(key).map(|value| value.as_str())
+    }
+
+    pub fn is_upgrade(&self) -> bool {
+        self.headers
+            .get("upgrade")
+            .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+pub async fn read_request(stream: &mut TcpStream) -> std::io::Result<Request> {
+    // Clients wait for the response before sending anything else, nothing is left buffered
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (Some(_method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+    let mut request = Request::from_target(target);
+
+    for _ in 0..MAX_HEADER_LINES {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            return Ok(request);
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            request
+                .headers
+                .insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    Err(invalid("too many headers"))
+}
+
+pub async fn respond(stream: &mut TcpStream, status: u16, body: &str) -> std::io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        _ => "Error",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nDate: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        chrono::Utc::now().format("%a, %d %b %Y %H:%M:%S GMT"),
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Complete the handshake of an upgrade request read by `read_request`
+pub async fn accept_websocket(
+    mut stream: TcpStream,
+    request: &Request,
+) -> std::io::Result<WebSocketStream<TcpStream>> {
+    let Some(key) = request.headers.get("sec-websocket-key") else {
+        return Err(invalid("missing Sec-WebSocket-Key"));
+    };
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    stream.write_all(response.as_bytes()).await?;
+
+    Ok(WebSocketStream::from_raw_socket(stream, Role::Server, None).await)
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn invalid(reason: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason.to_string())
+}
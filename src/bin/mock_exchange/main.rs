@@ -0,0 +1,163 @@
+use exchange::Exchange;
+use log::{error, info, warn};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use script::Script;
+use std::env;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+mod binance;
+mod bitget;
+mod exchange;
+mod http;
+mod market;
+mod script;
+mod upbit;
+
+/*
+Offline stand-in for Binance, Upbit and Bitget. One port serves every venue:
+     Binance  ws://<addr>/stream?streams=...   http://<addr>/fapi/v1/..., /dapi/v1/..., /api/v3/...
+     Upbit    ws://<addr>/websocket/v1         http://<addr>/v1/trades/ticks
+     Bitget   ws://<addr>/v2/ws/public
+Point the main binary at it through the endpoint overrides, e.g.
+     BINANCE_FUTURE_WS_URL=ws://127.0.0.1:9000 BINANCE_FUTURE_REST_URL=http://127.0.0.1:9000
+     UPBIT_WS_URL=ws://127.0.0.1:9000/websocket/v1 UPBIT_REST_URL=http://127.0.0.1:9000
+
+Without MOCK_SCRIPT every requested symbol gets a synthetic market stepped each MOCK_TICK_MS.
+With it the scripted frames are played instead (see script.rs)
+MOCK_SOCKET_LIFETIME_MS drops every WebSocket after that long, so that clients reconnect
+and backfill the trades they missed
+*/
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let addr = env::var("MOCK_ADDR").unwrap_or_else(|_| "127.0.0.1:9000".to_string());
+    let script = env::var("MOCK_SCRIPT").ok();
+    let tick_ms: u64 = env::var("MOCK_TICK_MS")
+        .unwrap_or_else(|_| "100".to_string())
+        .parse()
+        .unwrap_or(100);
+    let seed: u64 = env::var("MOCK_SEED")
+        .unwrap_or_else(|_| "0".to_string())
+        .parse()
+        .unwrap_or(0);
+    let start_price: i64 = env::var("MOCK_START_PRICE_CENTS")
+        .unwrap_or_else(|_| "10000".to_string())
+        .parse()
+        .unwrap_or(10000);
+    let script_delay_ms: u64 = env::var("MOCK_SCRIPT_DELAY_MS")
+        .unwrap_or_else(|_| "2000".to_string())
+        .parse()
+        .unwrap_or(2000);
+    let socket_lifetime = env::var("MOCK_SOCKET_LIFETIME_MS")
+        .ok()
+        .and_then(|ms| ms.parse().ok())
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis);
+
+    let exchange = Exchange::new(script.is_some(), start_price);
+    match script {
+        Some(path) => {
+            let script = match Script::load(&path) {
+                Ok(script) => script,
+                Err(e) => {
+                    error!("Mock exchange: Failed to load script {}: {}", path, e);
+                    return;
+                }
+            };
+            info!("Mock exchange: Loaded script {}", path);
+            let exchange = exchange.clone();
+            tokio::spawn(script.play(exchange, Duration::from_millis(script_delay_ms)));
+        }
+        None => {
+            let exchange = exchange.clone();
+            tokio::spawn(async move {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut interval = tokio::time::interval(Duration::from_millis(tick_ms.max(1)));
+                loop {
+                    interval.tick().await;
+                    exchange.tick(&mut rng);
+                }
+            });
+        }
+    }
+
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Mock exchange: Failed to bind {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Mock exchange: Listening on {}", addr);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(stream, exchange.clone(), socket_lifetime));
+            }
+            Err(e) => warn!("Mock exchange: Failed to accept connection: {}", e),
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    exchange: Exchange,
+    socket_lifetime: Option<Duration>,
+) {
+    let request = match http::read_request(&mut stream).await {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Mock exchange: Invalid request: {}", e);
+            return;
+        }
+    };
+
+    if request.is_upgrade() {
+        let ws = match http::accept_websocket(stream, &request).await {
+            Ok(ws) => ws,
+            Err(e) => {
+                warn!("Mock exchange: WebSocket handshake failed: {}", e);
+                return;
+            }
+        };
+        let serve = async {
+            match request.path.as_str() {
+                "/stream" => binance::serve_websocket(ws, request.clone(), exchange).await,
+                "/websocket/v1" => upbit::serve_websocket(ws, exchange).await,
+                "/v2/ws/public" => bitget::serve_websocket(ws, exchange).await,
+                path => warn!("Mock exchange: No WebSocket at {}", path),
+            }
+        };
+        match socket_lifetime {
+            Some(lifetime) => {
+                if tokio::time::timeout(lifetime, serve).await.is_err() {
+                    info!("Mock exchange: Dropped the WebSocket at {}", request.path);
+                }
+            }
+            None => serve.await,
+        }
+        return;
+    }
+
+    let (status, body) = match exchange.is_scripted() {
+        true => match exchange.scripted_rest(&request) {
+            Some(body) => (200, body),
+            None => (404, r#"{"msg":"Not scripted"}"#.to_string()),
+        },
+        false => match request.path.as_str() {
+            path if path.starts_with("/v1/") => upbit::rest(&request, &exchange),
+            _ => binance::rest(&request, &exchange),
+        },
+    };
+    if let Err(e) = http::respond(&mut stream, status, &body).await {
+        warn!(
+            "Mock exchange: Failed to respond to {}: {}",
+            request.path, e
+        );
+    }
+}
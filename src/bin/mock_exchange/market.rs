@@ -0,0 +1,205 @@
+use rand::rngs::StdRng;
+use rand::Rng;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Synthetic market of one symbol. Prices are kept in cents and quantities in thousandths
+// so that every level has an exact string form (the Bitget checksum is computed over them)
+
+pub const BOOK_DEPTH: usize = 20; // Levels per side
+const CHANGES_PER_TICK: usize = 3; // Resized levels per tick on top of the price move
+const TRADE_PROBABILITY: f64 = 0.6;
+const LIQUIDATION_PROBABILITY: f64 = 0.02;
+const TRADE_HISTORY: usize = 1000; // Recent trades served to backfills
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Venue {
+    Binance, // USDⓈ-M, COIN-M and spot share one book per symbol
+    Upbit,
+    Bitget,
+}
+
+#[derive(Debug, Clone)]
+pub struct Trade {
+    pub id: u64,
+    pub price: Decimal,
+    pub quantity: Decimal,
+    pub buyer_market_maker: bool,
+    pub time: u64,
+}
+
+/// Levels best first
+#[derive(Debug, Clone)]
+pub struct Book {
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+    pub update_id: u64, // Final update id of the last tick
+}
+
+impl Book {
+    /// `lastUpdateId` of a Binance REST snapshot. Ticks span at least two update ids, so
+    /// claiming the first id of the next tick (as if taken mid-batch) lets that tick bridge
+    /// the snapshot under both the spot and the futures rules
+    pub fn snapshot_id(&self) -> u64 {
+        self.update_id + 1
+    }
+}
+
+/// One step of a market. Changed levels carry absolute quantities, zero removes the level
+#[derive(Debug, Clone)]
+pub struct Tick {
+    pub venue: Venue,
+    pub symbol: String,
+    pub prev_update_id: u64,
+    pub first_update_id: u64,
+    pub last_update_id: u64,
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+    pub book: Book, // State after the tick
+    pub trade: Option<Trade>,
+    pub liquidation: Option<Trade>,
+    pub mark_price: Decimal,
+    pub index_price: Decimal,
+    pub time: u64,
+}
+
+pub struct SimMarket {
+    mid: i64,                 // Cents
+    bids: BTreeMap<i64, i64>, // Price in cents -> quantity in thousandths
+    asks: BTreeMap<i64, i64>,
+    update_id: u64,
+    trade_id: u64,
+    trades: VecDeque<Trade>, // Recent trades, oldest first
+}
+
+impl SimMarket {
+    pub fn new(start_price: i64) -> Self {
+        let mid = start_price.max(BOOK_DEPTH as i64 + 1);
+        Self {
+            mid,
+            bids: (1..=BOOK_DEPTH as i64).map(|i| (mid - i, 1000)).collect(),
+            asks: (1..=BOOK_DEPTH as i64).map(|i| (mid + i, 1000)).collect(),
+            update_id: 1,
+            trade_id: 0,
+            trades: VecDeque::new(),
+        }
+    }
+
+    pub fn book(&self) -> Book {
+        Book {
+            bids: self.bids.iter().rev().map(|(p, q)| level(*p, *q)).collect(),
+            asks: self.asks.iter().map(|(p, q)| level(*p, *q)).collect(),
+            update_id: self.update_id,
+        }
+    }
+
+    pub fn trades(&self) -> &VecDeque<Trade> {
+        &self.trades
+    }
+
+    pub fn step(&mut self, venue: Venue, symbol: &str, rng: &mut StdRng) -> Tick {
+        let time = now_millis();
+        self.mid = (self.mid + rng.random_range(-2..=2)).max(BOOK_DEPTH as i64 + 1);
+
+        // Shift the book around the new mid, keeping the quantities of surviving levels
+        let mut bids: BTreeMap<i64, i64> = (1..=BOOK_DEPTH as i64)
+            .map(|i| self.mid - i)
+            .map(|p| (p, self.bids.get(&p).copied().unwrap_or(1000)))
+            .collect();
+        let mut asks: BTreeMap<i64, i64> = (1..=BOOK_DEPTH as i64)
+            .map(|i| self.mid + i)
+            .map(|p| (p, self.asks.get(&p).copied().unwrap_or(1000)))
+            .collect();
+        for _ in 0..CHANGES_PER_TICK {
+            let offset = rng.random_range(1..=BOOK_DEPTH as i64);
+            let quantity = rng.random_range(1..5000);
+            match rng.random_bool(0.5) {
+                true => bids.insert(self.mid - offset, quantity),
+                false => asks.insert(self.mid + offset, quantity),
+            };
+        }
+
+        let bid_changes = diff(&self.bids, &bids);
+        let ask_changes = diff(&self.asks, &asks);
+        self.bids = bids;
+        self.asks = asks;
+
+        let prev_update_id = self.update_id;
+        let changes = (bid_changes.len() + ask_changes.len()) as u64;
+        self.update_id += changes + 1; // At least two ids per tick, see `Book::snapshot_id`
+
+        let trade = rng.random_bool(TRADE_PROBABILITY).then(|| {
+            self.trade_id += 1;
+            self.taker(self.trade_id, time, rng)
+        });
+        if let Some(trade) = &trade {
+            if self.trades.len() == TRADE_HISTORY {
+                self.trades.pop_front();
+            }
+            self.trades.push_back(trade.clone());
+        }
+        // Liquidations are not part of the trade id sequence
+        let liquidation = rng
+            .random_bool(LIQUIDATION_PROBABILITY)
+            .then(|| self.taker(0, time, rng));
+
+        Tick {
+            venue,
+            symbol: symbol.to_string(),
+            prev_update_id,
+            first_update_id: prev_update_id + 1,
+            last_update_id: self.update_id,
+            bids: bid_changes,
+            asks: ask_changes,
+            book: self.book(),
+            trade,
+            liquidation,
+            mark_price: Decimal::new(self.mid, 2),
+            index_price: Decimal::new(self.mid + rng.random_range(-5..=5), 2),
+            time,
+        }
+    }
+
+    // Taker hits the best level of the other side
+    fn taker(&self, id: u64, time: u64, rng: &mut StdRng) -> Trade {
+        let buyer_market_maker = rng.random_bool(0.5);
+        let price = match buyer_market_maker {
+            true => self.bids.keys().next_back().copied(),
+            false => self.asks.keys().next().copied(),
+        };
+        Trade {
+            id,
+            price: Decimal::new(price.unwrap_or(self.mid), 2),
+            quantity: Decimal::new(rng.random_range(1..2000), 3),
+            buyer_market_maker,
+            time,
+        }
+    }
+}
+
+// Levels of `new` that differ from `old`, removed levels with zero quantity
+fn diff(old: &BTreeMap<i64, i64>, new: &BTreeMap<i64, i64>) -> Vec<(Decimal, Decimal)> {
+    let removed = old
+        .keys()
+        .filter(|price| !new.contains_key(price))
+        .map(|price| level(*price, 0));
+    let changed = new
+        .iter()
+        .filter(|(price, quantity)| old.get(price) != Some(quantity))
+        .map(|(price, quantity)| level(*price, *quantity));
+    removed.chain(changed).collect()
+}
+
+fn level(price: i64, quantity: i64) -> (Decimal, Decimal) {
+    (Decimal::new(price, 2), Decimal::new(quantity, 3))
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,107 @@
+use crate::exchange::Exchange;
+use crate::http::Request;
+use crate::market::Venue;
+use log::info;
+use serde::Deserialize;
+use std::time::Duration;
+
+// Scripted market data, one JSON object per line:
+//   {"delay_ms": 100, "venue": "binance", "topic": "btcusdt@depth", "payload": {"stream": ..., "data": ...}}
+//   {"rest": "/fapi/v1/depth?symbol=BTCUSDT", "payload": {"lastUpdateId": ...}}
+//
+// `delay_ms` waits after the previous line. `payload` is sent exactly as written to every
+// connection subscribed to `topic`:
+//   binance: combined stream name, e.g. "btcusdt@aggTrade" or "!forceOrder@arr"
+//   upbit:   "<type>.<code>", e.g. "trade.KRW-BTC". Sent as a binary frame
+//   bitget:  "<channel>.<instId>", e.g. "books.BTCUSDT"
+// `rest` lines answer GET requests with that path and at least that query from then on.
+// The ones before the first frame are served from the start, e.g. depth snapshots
+
+#[derive(Debug, Deserialize)]
+struct ScriptLine {
+    #[serde(default)]
+    delay_ms: u64,
+    venue: Option<Venue>,
+    topic: Option<String>,
+    rest: Option<String>, // Request target
+    payload: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub venue: Venue,
+    pub topic: String,
+    pub payload: String,
+}
+
+enum Step {
+    Frame(Frame),
+    Rest(Request, String),
+}
+
+pub struct Script {
+    steps: Vec<(Duration, Step)>,
+}
+
+impl Script {
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let mut steps = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line = serde_json::from_str::<ScriptLine>(line).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number + 1, e),
+                )
+            })?;
+            let payload = line.payload.to_string();
+            let step = match (line.venue, line.topic, line.rest) {
+                (Some(venue), Some(topic), None) => Step::Frame(Frame {
+                    venue,
+                    topic,
+                    payload,
+                }),
+                (None, None, Some(target)) => Step::Rest(Request::from_target(&target), payload),
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("line {}: needs `venue` and `topic`, or `rest`", number + 1),
+                    ))
+                }
+            };
+            steps.push((Duration::from_millis(line.delay_ms), step));
+        }
+        Ok(Self { steps })
+    }
+
+    /// Serve the leading REST responses, then play the frames once a client has connected
+    /// and `start_delay` has passed so that every stream of the client is subscribed
+    pub async fn play(self, exchange: Exchange, start_delay: Duration) {
+        let mut steps = self.steps;
+        let leading = steps
+            .iter()
+            .take_while(|(_, step)| matches!(step, Step::Rest(..)))
+            .count();
+        for (_, step) in steps.drain(..leading) {
+            if let Step::Rest(request, body) = step {
+                exchange.set_rest(request, body);
+            }
+        }
+
+        exchange.client_connected().await;
+        tokio::time::sleep(start_delay).await;
+        info!("Mock exchange: Playing script");
+
+        for (delay, step) in steps {
+            tokio::time::sleep(delay).await;
+            match step {
+                Step::Frame(frame) => exchange.publish(frame),
+                Step::Rest(request, body) => exchange.set_rest(request, body),
+            }
+        }
+        info!("Mock exchange: Script finished");
+    }
+}
@@ -0,0 +1,202 @@
+use crate::exchange::{Event, Exchange};
+use crate::http::Request;
+use crate::market::{Tick, Trade, Venue};
+use chrono::DateTime;
+use futures::{SinkExt, StreamExt};
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+
+/* Upbit Websocket */
+
+// Clients send `[{"ticket": ...}, {"type": "trade", "codes": [...]}, ...]` and receive
+// binary JSON frames. A new subscription message replaces the previous one
+
+const ORDERBOOK_UNITS: usize = 15;
+const MAX_TICKS: usize = 500; // Per `/v1/trades/ticks` page
+
+pub async fn serve_websocket(mut ws: WebSocketStream<TcpStream>, exchange: Exchange) {
+    let mut subscriptions: HashSet<(String, String)> = HashSet::new(); // (type, code)
+    let mut events = exchange.subscribe();
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let frames = match event {
+                    Ok(Event::Tick(tick)) if tick.venue == Venue::Upbit => render(&subscriptions, &tick),
+                    Ok(Event::Frame(frame)) if frame.venue == Venue::Upbit => {
+                        let subscribed = frame
+                            .topic
+                            .split_once('.')
+                            .is_some_and(|(kind, code)| subscriptions.contains(&(kind.to_string(), code.to_string())));
+                        match subscribed {
+                            true => vec![frame.payload.clone()],
+                            false => continue,
+                        }
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Mock Upbit stream: Lagging, skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                for frame in frames {
+                    if ws.send(Message::Binary(frame.into())).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    subscriptions = parse_subscription(&text);
+                    if !exchange.is_scripted() {
+                        for (_, code) in subscriptions.iter() {
+                            exchange.book(Venue::Upbit, code);
+                        }
+                    }
+                    info!("Mock Upbit stream: Serving {:?}", subscriptions);
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => (), // Pings are answered by tungstenite
+            }
+        }
+    }
+    info!("Mock Upbit stream: Connection closed");
+}
+
+fn parse_subscription(text: &str) -> HashSet<(String, String)> {
+    let Ok(Value::Array(fields)) = serde_json::from_str::<Value>(text) else {
+        warn!("Mock Upbit stream: Invalid subscription {}", text);
+        return HashSet::new();
+    };
+    let mut subscriptions = HashSet::new();
+    for field in fields.iter() {
+        let (Some(kind), Some(codes)) = (field["type"].as_str(), field["codes"].as_array()) else {
+            continue;
+        };
+        for code in codes.iter().filter_map(|code| code.as_str()) {
+            subscriptions.insert((kind.to_string(), code.to_uppercase()));
+        }
+    }
+    subscriptions
+}
+
+fn render(subscriptions: &HashSet<(String, String)>, tick: &Tick) -> Vec<String> {
+    let subscribed = |kind: &str| subscriptions.contains(&(kind.to_string(), tick.symbol.clone()));
+
+    let mut frames = Vec::new();
+    if let (true, Some(trade)) = (subscribed("trade"), &tick.trade) {
+        frames.push(trade_frame(tick, trade).to_string());
+    }
+    if subscribed("orderbook") {
+        frames.push(orderbook_frame(tick).to_string());
+    }
+    frames
+}
+
+fn trade_frame(tick: &Tick, trade: &Trade) -> Value {
+    let time = DateTime::from_timestamp_millis(tick.time as i64).unwrap_or_default();
+    json!({
+        "type": "trade",
+        "code": tick.symbol,
+        "timestamp": tick.time,
+        "trade_date": time.format("%Y-%m-%d").to_string(),
+        "trade_time": time.format("%H:%M:%S").to_string(),
+        "trade_timestamp": tick.time,
+        "trade_price": number(trade.price),
+        "trade_volume": number(trade.quantity),
+        // ASK: The seller took the bid
+        "ask_bid": if trade.buyer_market_maker { "ASK" } else { "BID" },
+        "sequential_id": trade.id,
+        "stream_type": "REALTIME",
+    })
+}
+
+fn orderbook_frame(tick: &Tick) -> Value {
+    let units: Vec<Value> = tick
+        .book
+        .asks
+        .iter()
+        .zip(tick.book.bids.iter())
+        .take(ORDERBOOK_UNITS)
+        .map(|((ask_price, ask_size), (bid_price, bid_size))| {
+            json!({
+                "ask_price": number(*ask_price),
+                "ask_size": number(*ask_size),
+                "bid_price": number(*bid_price),
+                "bid_size": number(*bid_size),
+            })
+        })
+        .collect();
+    let total = |levels: &[(Decimal, Decimal)]| -> Decimal {
+        levels
+            .iter()
+            .take(ORDERBOOK_UNITS)
+            .map(|(_, size)| *size)
+            .sum()
+    };
+
+    json!({
+        "type": "orderbook",
+        "code": tick.symbol,
+        "timestamp": tick.time,
+        "total_ask_size": number(total(&tick.book.asks)),
+        "total_bid_size": number(total(&tick.book.bids)),
+        "orderbook_units": units,
+        "stream_type": "REALTIME",
+    })
+}
+
+/* Upbit REST */
+
+/// `/v1/trades/ticks`: trades older than `cursor` (a sequential id), newest first
+pub fn rest(request: &Request, exchange: &Exchange) -> (u16, String) {
+    let market = request.param("market").unwrap_or_default().to_uppercase();
+    if request.path != "/v1/trades/ticks" || market.is_empty() {
+        return (
+            404,
+            json!({ "error": { "name": 404, "message": "Code not found" } }).to_string(),
+        );
+    }
+    let count = request
+        .param("count")
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(1)
+        .min(MAX_TICKS);
+    let cursor = request
+        .param("cursor")
+        .and_then(|cursor| cursor.parse().ok())
+        .unwrap_or(u64::MAX);
+
+    let ticks: Vec<Value> = exchange
+        .trades(Venue::Upbit, &market)
+        .iter()
+        .rev()
+        .filter(|trade| trade.id < cursor)
+        .take(count)
+        .map(|trade| {
+            let time = DateTime::from_timestamp_millis(trade.time as i64).unwrap_or_default();
+            json!({
+                "market": market,
+                "trade_date_utc": time.format("%Y-%m-%d").to_string(),
+                "trade_time_utc": time.format("%H:%M:%S").to_string(),
+                "timestamp": trade.time,
+                "trade_price": number(trade.price),
+                "trade_volume": number(trade.quantity),
+                "ask_bid": if trade.buyer_market_maker { "ASK" } else { "BID" },
+                "sequential_id": trade.id,
+            })
+        })
+        .collect();
+    (200, json!(ticks).to_string())
+}
+
+// Upbit sends numbers, not strings. Parsed back exactly with `arbitrary_precision`
+fn number(value: Decimal) -> Value {
+    serde_json::from_str(&value.to_string()).unwrap_or(Value::Null)
+}
@@ -1,6 +1,6 @@
 use crate::data::{
-    depth::OrderbookUpdateStream, liquidation::LiquidationData, market::MarketData,
-    markprice::MarkPriceData,
+    bbo::BboData, depth::OrderbookUpdateStream, liquidation::LiquidationData, market::MarketData,
+    markprice::MarkPriceData, stats::FuturesStatsData,
 };
 use tokio::sync::mpsc;
 
@@ -12,6 +12,8 @@ pub struct DataChannelPairs<Channel> {
     ),
     // Data Channel: Aggtrade -> Engine
     pub agg: (mpsc::Sender<MarketData>, mpsc::Receiver<MarketData>),
+    // Data Channel: Best bid/offer -> Engine
+    pub bbo: (mpsc::Sender<BboData>, mpsc::Receiver<BboData>),
     // Data Channel for futures(etc)
     // Data Channel: Mark Price -> Engine,
     // Data Channel: Liquidation -> Engine
@@ -27,6 +29,11 @@ pub struct Future {
         mpsc::Sender<LiquidationData>,
         mpsc::Receiver<LiquidationData>,
     ),
+    // Polled futures statistics -> Engine
+    pub stats: (
+        mpsc::Sender<FuturesStatsData>,
+        mpsc::Receiver<FuturesStatsData>,
+    ),
 }
 
 pub type SpotChannel = DataChannelPairs<Spot>;
@@ -36,10 +43,12 @@ impl SpotChannel {
     pub fn new(max_capacity: usize) -> Self {
         let (tx_ob_raw, rx_ob_raw) = mpsc::channel(max_capacity);
         let (tx_agg, rx_agg) = mpsc::channel(max_capacity);
+        let (tx_bbo, rx_bbo) = mpsc::channel(max_capacity);
 
         Self {
             ob: (tx_ob_raw, rx_ob_raw),
             agg: (tx_agg, rx_agg),
+            bbo: (tx_bbo, rx_bbo),
             additional: Spot,
         }
     }
@@ -49,15 +58,19 @@ impl FutureChannel {
     pub fn new(max_capacity: usize) -> Self {
         let (tx_ob_raw, rx_ob_raw) = mpsc::channel(max_capacity);
         let (tx_agg, rx_agg) = mpsc::channel(max_capacity);
+        let (tx_bbo, rx_bbo) = mpsc::channel(max_capacity);
         let (tx_mark, rx_mark) = mpsc::channel(max_capacity);
         let (tx_liq, rx_liq) = mpsc::channel(max_capacity);
+        let (tx_stats, rx_stats) = mpsc::channel(max_capacity);
 
         Self {
             ob: (tx_ob_raw, rx_ob_raw),
             agg: (tx_agg, rx_agg),
+            bbo: (tx_bbo, rx_bbo),
             additional: Future {
                 mark: (tx_mark, rx_mark),
                 liq: (tx_liq, rx_liq),
+                stats: (tx_stats, rx_stats),
             },
         }
     }
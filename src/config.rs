@@ -1,13 +1,24 @@
+use cryptoquant::data::endpoints::{BinanceEndpoints, Endpoint, Endpoints};
+use cryptoquant::data::fault::FaultConfig;
+use cryptoquant::data::recorder::RecorderConfig;
+use cryptoquant::data::replay::{ReplayConfig, ReplaySpeed};
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[allow(dead_code)] // There can be unused variable
 pub struct PrismEnvConfig {
     // Symbols
-    pub symbol_binance_fut: String,
-    pub symbol_binance_spt: String,
-    pub symbol_upbit_krw: String,
-    pub symbol_upbit_btc: String,
-    pub symbol_upbit_usdt: String,
+    pub symbol_binance_fut: Vec<String>,
+    pub symbol_binance_coin: Vec<String>, // COIN-M, e.g. BTCUSD_PERP, BTCUSD_250627
+    pub symbol_binance_spt: Vec<String>,
+    pub symbol_upbit_krw: Vec<String>,
+    pub symbol_upbit_btc: Vec<String>,
+    pub symbol_upbit_usdt: Vec<String>,
+    pub symbol_bitget_fut: Vec<String>,
+    pub symbol_bybit_fut: Vec<String>,
+    pub symbol_okx_fut: Vec<String>, // Instrument ids, e.g. BTC-USDT-SWAP
+    pub symbol_bithumb_krw: Vec<String>, // e.g. BTC_KRW
 
     // Database Tables
     pub table_fut: String, // For Raw data
@@ -16,19 +27,46 @@ pub struct PrismEnvConfig {
     // Other
     pub data_dump: bool,
     pub channel_capacity: usize,
+    pub binance_streams_per_connection: usize,
+    pub binance_all_market_liquidations: bool, // `!forceOrder@arr` instead of `<symbol>@forceOrder`
+    pub liquidation_window_secs: u64,
+    pub binance_weight_per_minute: u32, // Per REST host, shared by pollers, snapshots and backfills
+    pub sequencer_lateness_ms: u64,     // 0: Cores apply events in arrival order
+    // REST Pollers: Seconds between polls, 0 disables the endpoint
+    pub poll_open_interest_secs: u64,
+    pub poll_account_ratio_secs: u64,
+    pub poll_position_ratio_secs: u64,
+    pub poll_taker_volume_secs: u64,
+    // Latency: Seconds between clock syncs and between latency reports, 0 disables
+    pub clock_sync_secs: u64,
+    pub latency_report_secs: u64,
+    // Stream Reconnect
+    pub reconnect_base_ms: u64,
+    pub reconnect_max_ms: u64,
+    pub reconnect_max_retries: u32, // 0: Retry forever
+    // Exchange Endpoints
+    pub endpoints: Endpoints,
+    // Raw Frame Capture
+    pub recorder: Option<RecorderConfig>,
+    // Capture Replay: Feeds the engine from a capture instead of the exchanges
+    pub replay: Option<ReplayConfig>,
+    // Fault Injection: Testing only
+    pub faults: Option<FaultConfig>,
 }
 
 pub fn read_env_config() -> PrismEnvConfig {
     PrismEnvConfig {
         // Symbols
-        symbol_binance_fut: env::var("SYMBOLS_BINANCE_FUT")
-            .unwrap_or_else(|_| "NO_SYMBOL".to_string()),
-        symbol_binance_spt: env::var("SYMBOLS_BINANCE_SPT")
-            .unwrap_or_else(|_| "NO_SYMBOL".to_string()),
-        symbol_upbit_krw: env::var("SYMBOLS_UPBIT_KRW").unwrap_or_else(|_| "NO_SYMBOL".to_string()),
-        symbol_upbit_btc: env::var("SYMBOLS_UPBIT_BTC").unwrap_or_else(|_| "NO_SYMBOL".to_string()),
-        symbol_upbit_usdt: env::var("SYMBOLS_UPBIT_USDT")
-            .unwrap_or_else(|_| "NO_SYMBOL".to_string()),
+        symbol_binance_fut: read_symbols("SYMBOLS_BINANCE_FUT"),
+        symbol_binance_coin: read_symbols("SYMBOLS_BINANCE_COIN"),
+        symbol_binance_spt: read_symbols("SYMBOLS_BINANCE_SPT"),
+        symbol_upbit_krw: read_symbols("SYMBOLS_UPBIT_KRW"),
+        symbol_upbit_btc: read_symbols("SYMBOLS_UPBIT_BTC"),
+        symbol_upbit_usdt: read_symbols("SYMBOLS_UPBIT_USDT"),
+        symbol_bitget_fut: read_symbols("SYMBOLS_BITGET_FUT"),
+        symbol_bybit_fut: read_symbols("SYMBOLS_BYBIT_FUT"),
+        symbol_okx_fut: read_symbols("SYMBOLS_OKX_FUT"),
+        symbol_bithumb_krw: read_symbols("SYMBOLS_BITHUMB_KRW"),
 
         // Database Tables
         table_fut: env::var("TABLE_FUT").unwrap_or_else(|_| "unspecified".to_string()),
@@ -41,5 +79,185 @@ pub fn read_env_config() -> PrismEnvConfig {
             .unwrap_or_else(|_| "999".to_string())
             .parse()
             .unwrap_or(999),
+        binance_streams_per_connection: env::var("BINANCE_STREAMS_PER_CONNECTION")
+            .unwrap_or_else(|_| "200".to_string())
+            .parse()
+            .unwrap_or(200),
+
+        binance_all_market_liquidations: env::var("BINANCE_ALL_MARKET_LIQUIDATIONS")
+            .unwrap_or_else(|_| "false".to_string())
+            == "true",
+        liquidation_window_secs: env::var("LIQUIDATION_WINDOW_SECS")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .unwrap_or(300),
+        binance_weight_per_minute: env::var("BINANCE_WEIGHT_PER_MINUTE")
+            .unwrap_or_else(|_| "1200".to_string())
+            .parse()
+            .unwrap_or(1200),
+        sequencer_lateness_ms: env::var("SEQUENCER_LATENESS_MS")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0),
+
+        // REST Pollers
+        poll_open_interest_secs: env::var("POLL_OPEN_INTEREST_SECS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .unwrap_or(10),
+        poll_account_ratio_secs: env::var("POLL_ACCOUNT_RATIO_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60),
+        poll_position_ratio_secs: env::var("POLL_POSITION_RATIO_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60),
+        poll_taker_volume_secs: env::var("POLL_TAKER_VOLUME_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60),
+
+        // Latency
+        clock_sync_secs: env::var("CLOCK_SYNC_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60),
+        latency_report_secs: env::var("LATENCY_REPORT_SECS")
+            .unwrap_or_else(|_| "60".to_string())
+            .parse()
+            .unwrap_or(60),
+
+        // Stream Reconnect
+        reconnect_base_ms: env::var("RECONNECT_BASE_MS")
+            .unwrap_or_else(|_| "1000".to_string())
+            .parse()
+            .unwrap_or(1000),
+        reconnect_max_ms: env::var("RECONNECT_MAX_MS")
+            .unwrap_or_else(|_| "60000".to_string())
+            .parse()
+            .unwrap_or(60000),
+        reconnect_max_retries: env::var("RECONNECT_MAX_RETRIES")
+            .unwrap_or_else(|_| "20".to_string())
+            .parse()
+            .unwrap_or(20),
+
+        // Exchange Endpoints
+        endpoints: read_endpoints(),
+
+        // Raw Frame Capture
+        recorder: read_recorder(),
+
+        // Capture Replay
+        replay: read_replay(),
+
+        // Fault Injection
+        faults: read_faults(),
+    }
+}
+
+// Comma separated list, e.g. "BTCUSDT,ETHUSDT". "NO_SYMBOL" or unset disables the streams
+fn read_symbols(key: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or_else(|_| "NO_SYMBOL".to_string())
+        .split(',')
+        .map(|symbol| symbol.trim().to_uppercase())
+        .filter(|symbol| !symbol.is_empty() && symbol != "NO_SYMBOL")
+        .collect()
+}
+
+// `BINANCE_NETWORK=testnet` switches every Binance market to its testnet.
+// `<VENUE>_WS_URL` and `<VENUE>_REST_URL` override a single venue, e.g. `ws://localhost:9000`
+fn read_endpoints() -> Endpoints {
+    let mut endpoints = Endpoints::mainnet();
+    if env::var("BINANCE_NETWORK").unwrap_or_else(|_| "mainnet".to_string()) == "testnet" {
+        endpoints.binance = BinanceEndpoints::testnet();
+    }
+
+    for (venue, endpoint) in [
+        ("BINANCE_FUTURE", &mut endpoints.binance.future),
+        ("BINANCE_COIN", &mut endpoints.binance.coin),
+        ("BINANCE_SPOT", &mut endpoints.binance.spot),
+        ("UPBIT", &mut endpoints.upbit),
+        ("BITHUMB", &mut endpoints.bithumb),
+        ("BITGET", &mut endpoints.bitget),
+        ("BYBIT", &mut endpoints.bybit),
+        ("OKX", &mut endpoints.okx),
+    ] {
+        let ws = env::var(format!("{}_WS_URL", venue)).unwrap_or_else(|_| endpoint.ws.clone());
+        let rest =
+            env::var(format!("{}_REST_URL", venue)).unwrap_or_else(|_| endpoint.rest.clone());
+        *endpoint = Endpoint::new(&ws, &rest);
     }
+    endpoints
+}
+
+// `RECORD_DIR` enables the capture of every frame received by the streams whose name starts
+// with one of `RECORD_STREAMS` (all if unset), e.g. "binance/future/orderbook,upbit"
+fn read_recorder() -> Option<RecorderConfig> {
+    let dir = env::var("RECORD_DIR").ok().filter(|dir| !dir.is_empty())?;
+    Some(RecorderConfig {
+        dir: PathBuf::from(dir),
+        streams: read_prefixes("RECORD_STREAMS"),
+    })
+}
+
+// `REPLAY_DIR` replays the capture recorded there. `REPLAY_SPEED` is "max" (default)
+// to go as fast as the engine keeps up, or a factor of the recorded pace, e.g. "1" or "10"
+fn read_replay() -> Option<ReplayConfig> {
+    let dir = env::var("REPLAY_DIR").ok().filter(|dir| !dir.is_empty())?;
+    let speed = env::var("REPLAY_SPEED").unwrap_or_else(|_| "max".to_string());
+    Some(ReplayConfig {
+        dir: PathBuf::from(dir),
+        speed: match speed.parse::<f64>() {
+            Ok(factor) if factor > 0.0 => ReplaySpeed::Factor(factor),
+            _ => ReplaySpeed::Max,
+        },
+    })
+}
+
+// `FAULT_INJECTION=true` injects faults into the sockets and REST snapshots of the streams
+// whose name starts with one of `FAULT_STREAMS` (all if unset), e.g. "binance/future/orderbook".
+// `FAULT_<KIND>` is the probability per frame (per request for snapshot timeouts)
+fn read_faults() -> Option<FaultConfig> {
+    if env::var("FAULT_INJECTION").unwrap_or_else(|_| "false".to_string()) != "true" {
+        return None;
+    }
+    let probability = |key: &str| -> f64 {
+        env::var(key)
+            .unwrap_or_else(|_| "0".to_string())
+            .parse::<f64>()
+            .unwrap_or(0.0)
+            .clamp(0.0, 1.0)
+    };
+
+    Some(FaultConfig {
+        seed: env::var("FAULT_SEED")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0),
+        streams: read_prefixes("FAULT_STREAMS"),
+        drop: probability("FAULT_DROP"),
+        duplicate: probability("FAULT_DUPLICATE"),
+        reorder: probability("FAULT_REORDER"),
+        stall: probability("FAULT_STALL"),
+        close: probability("FAULT_CLOSE"),
+        snapshot_timeout: probability("FAULT_SNAPSHOT_TIMEOUT"),
+        stall_duration: Duration::from_millis(
+            env::var("FAULT_STALL_MS")
+                .unwrap_or_else(|_| "5000".to_string())
+                .parse()
+                .unwrap_or(5000),
+        ),
+    })
+}
+
+// Comma separated stream name prefixes. Unset selects every stream
+fn read_prefixes(key: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(|prefix| prefix.trim().to_string())
+        .filter(|prefix| !prefix.is_empty())
+        .collect()
 }
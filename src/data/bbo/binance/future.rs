@@ -0,0 +1,164 @@
+use crate::data::bbo::BboData;
+use crate::data::binance::{
+    combined_streams, fetch_contract_sizes, BinanceFutureMarket, ContractSizes,
+};
+use crate::data::endpoints::Endpoint;
+use crate::data::health::StreamMonitor;
+use crate::data::recorder::FrameRecorder;
+use crate::data::router::SymbolRouter;
+use crate::data::stream::StreamHandler;
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/* Binance BookTicker Stream */
+
+#[derive(Debug, Deserialize)]
+pub struct BinanceWebsocketFutureBookTicker {
+    pub data: FutureBookTickerEvent,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct FutureBookTickerEvent {
+    pub u: u64,     // Order book update ID
+    pub E: u64,     // Event time
+    pub T: u64,     // Transaction time
+    pub s: String,  // Symbol
+    pub b: Decimal, // Best bid price
+    pub B: Decimal, // Best bid qty
+    pub a: Decimal, // Best ask price
+    pub A: Decimal, // Best ask qty
+}
+
+pub struct BinanceFutureBookTickerStreamHandler {
+    market: BinanceFutureMarket,
+    pub streams: String,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<BboData>,
+    contract_sizes: ContractSizes,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for BinanceFutureBookTickerStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let market = self.market;
+        let symbols = self.symbols.clone();
+        let streams = self.streams.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let contract_sizes =
+                fetch_contract_sizes(market, &endpoint, &recorder, &monitor).await?;
+
+            let ws_url = format!(
+                "{}?streams={}",
+                market.ws_url(&endpoint),
+                combined_streams(&symbols, &streams)
+            );
+            let (read, write) = recorder.connect(&ws_url).await?;
+
+            let handler = BinanceFutureBookTickerStreamHandler {
+                market,
+                symbols,
+                streams,
+                tx,
+                contract_sizes,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_bookticker(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+impl BinanceFutureBookTickerStreamHandler {
+    pub fn new(
+        market: BinanceFutureMarket,
+        symbols: Vec<String>,
+        tx: SymbolRouter<BboData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            market,
+            symbols,
+            streams: "bookTicker".to_string(),
+            tx,
+            contract_sizes: ContractSizes::default(),
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_bookticker<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        while let Some(msg) = read.next().await {
+            match msg {
+                Ok(Message::Text(text)) => {
+                    match serde_json::from_str::<BinanceWebsocketFutureBookTicker>(&text) {
+                        Ok(bookticker) => {
+                            let update = self.generate_bbo_update(&bookticker);
+                            if self.tx.send(update).await.is_err() {
+                                error!("Binance bookticker stream: Failed to send update");
+                            }
+                        }
+                        Err(e) => {
+                            error!("Binance bookticker stream: Failed to parse message: {}", e);
+                            self.monitor.incr("parse_failures");
+                        }
+                    }
+                }
+                Ok(Message::Ping(payload)) => {
+                    if let Err(e) = write.send(Message::Pong(payload)).await {
+                        error!("Binance bookticker stream: Failed to send Pong: {}", e);
+                    }
+                }
+                Ok(Message::Pong(_)) => info!("Binance bookticker stream: Pong received"),
+                Ok(Message::Close(_)) => {
+                    info!("Binance bookticker stream: Connection closed");
+                    break;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn generate_bbo_update(&self, update: &BinanceWebsocketFutureBookTicker) -> BboData {
+        let event = &update.data;
+        BboData {
+            symbol: event.s.clone(),
+            bid_price: event.b,
+            bid_quantity: self.contract_sizes.to_coins(&event.s, event.b, event.B),
+            ask_price: event.a,
+            ask_quantity: self.contract_sizes.to_coins(&event.s, event.a, event.A),
+            update_id: event.u,
+            trade_time: event.T,
+            event_time: event.E,
+            received: self.monitor.stamp(self.recorder.now_millis(), event.E),
+        }
+    }
+}
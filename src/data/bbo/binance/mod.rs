@@ -0,0 +1,2 @@
+pub mod future;
+pub mod spot;
@@ -0,0 +1,19 @@
+pub mod binance;
+
+use crate::data::latency::ReceiveStamp;
+use rust_decimal::Decimal;
+
+// Top of book pushed on every change. Lower latency than the best levels of the diff book
+
+#[derive(Debug)]
+pub struct BboData {
+    pub symbol: String,
+    pub bid_price: Decimal,
+    pub bid_quantity: Decimal,
+    pub ask_price: Decimal,
+    pub ask_quantity: Decimal,
+    pub update_id: u64, // Orderbook update id, shared with the diff book
+    pub trade_time: u64,
+    pub event_time: u64,
+    pub received: ReceiveStamp,
+}
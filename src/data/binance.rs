@@ -1,130 +1,562 @@
 use crate::data::{
+    bbo::binance::{
+        future::BinanceFutureBookTickerStreamHandler, spot::BinanceSpotBookTickerStreamHandler,
+    },
     depth::{
         binance::future::BinanceFutureOrderbookStreamHandler,
         binance::spot::BinanceSpotOrderbookStreamHandler,
     },
+    endpoints::{BinanceEndpoints, Endpoint},
     exchanges::{FutureDataChannels, SpotDataChannels},
-    liquidation::binance::future::BinanceFutureLiquidationStreamHandler,
+    health::StreamMonitor,
+    limiter::WeightLimiter,
+    liquidation::{
+        binance::future::BinanceFutureLiquidationStreamHandler, rolling::MarketLiquidations,
+    },
     market::{
         binance::future::BinanceFutureAggTradeStreamHandler,
         binance::spot::BinanceSpotAggTradeStreamHandler,
     },
     markprice::binance::future::BinanceFutureMarkPriceStreamHandler,
-    stream::StreamHandler,
+    recorder::FrameRecorder,
+    stats::binance::future::{BinanceFutureStatsPoller, BinanceStatsEndpoint},
+    stream::io_error,
+    supervisor::Supervisor,
 };
-use log::{error, warn};
+use chrono::NaiveDate;
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::task::JoinSet;
+use tokio_tungstenite::tungstenite;
+
+/// Binance futures venue. Both share the stream and REST formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinanceFutureMarket {
+    UsdM,  // USDⓈ-M, e.g. BTCUSDT, BTCUSDT_250627. Quantities in coins
+    CoinM, // COIN-M, e.g. BTCUSD_PERP, BTCUSD_250627. Quantities in contracts
+}
+
+impl BinanceFutureMarket {
+    /// Used in stream names, e.g. "binance/coin/aggtrade/0"
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinanceFutureMarket::UsdM => "future",
+            BinanceFutureMarket::CoinM => "coin",
+        }
+    }
+
+    /// Combined stream URL, e.g. "wss://fstream.binance.com/stream"
+    pub fn ws_url(&self, endpoint: &Endpoint) -> String {
+        format!("{}/stream", endpoint.ws)
+    }
+
+    /// REST API root, e.g. "https://fapi.binance.com/fapi/v1"
+    pub fn rest_url(&self, endpoint: &Endpoint) -> String {
+        match self {
+            BinanceFutureMarket::UsdM => format!("{}/fapi/v1", endpoint.rest),
+            BinanceFutureMarket::CoinM => format!("{}/dapi/v1", endpoint.rest),
+        }
+    }
+}
 
 pub struct BinanceThreads {
     future: FutureDataChannels,
+    coin: FutureDataChannels,
     spot: SpotDataChannels,
+    supervisor: Supervisor,
+    endpoints: BinanceEndpoints,
+    streams_per_connection: usize,
+    market_liquidations: Option<MarketLiquidations>, // All-market liquidation mode
+    stats_pollers: Vec<(BinanceStatsEndpoint, Duration)>,
+    weight_per_minute: Option<u32>, // Per REST host. None: Requests never wait
 }
 
 impl BinanceThreads {
-    pub fn new(future: FutureDataChannels, spot: SpotDataChannels) -> Self {
-        Self { future, spot }
+    pub fn new(
+        future: FutureDataChannels,
+        coin: FutureDataChannels,
+        spot: SpotDataChannels,
+        supervisor: Supervisor,
+        endpoints: BinanceEndpoints,
+        streams_per_connection: usize,
+    ) -> Self {
+        Self {
+            future,
+            coin,
+            spot,
+            supervisor,
+            endpoints,
+            // Futures allow 200 streams per connection, spot 1024
+            streams_per_connection: streams_per_connection.clamp(1, 200),
+            market_liquidations: None,
+            stats_pollers: Vec::new(),
+            weight_per_minute: None,
+        }
+    }
+
+    /// Poll each endpoint for every USDⓈ-M perpetual at its own interval
+    pub fn with_stats_pollers(mut self, pollers: Vec<(BinanceStatsEndpoint, Duration)>) -> Self {
+        self.stats_pollers = pollers;
+        self
+    }
+
+    /// Budget the request weight of every REST host. Pollers, depth snapshots and trade
+    /// backfills of all shards draw from the budget of their host
+    pub fn with_weight_limit(mut self, weight_per_minute: u32) -> Self {
+        self.weight_per_minute = Some(weight_per_minute);
+        self
+    }
+
+    /// Replace the per symbol liquidation streams with one `!forceOrder@arr` stream per market
+    pub fn with_market_liquidations(mut self, market_liquidations: MarketLiquidations) -> Self {
+        self.market_liquidations = Some(market_liquidations);
+        self
     }
 
     pub fn spawn_streams(
         self,
         tasks: &mut JoinSet<()>,
-        future_symbol: String,
-        spot_symbol: String,
+        future_symbols: Vec<String>,
+        coin_symbols: Vec<String>,
+        spot_symbols: Vec<String>,
     ) {
-        if future_symbol == "NO_SYMBOL" && spot_symbol == "NO_SYMBOL" {
+        if future_symbols.is_empty() && coin_symbols.is_empty() && spot_symbols.is_empty() {
             warn!("No symbols specified, skipping Binance streams");
             return;
         }
+        let weights = BinanceWeights::new(self.weight_per_minute);
 
+        // Every connection subscribes to one stream type for a shard of symbols
         // Future Streams
-        tasks.spawn(spawn_future_aggtrade_task(
-            BinanceFutureAggTradeStreamHandler::new(future_symbol.clone(), self.future.agg_out),
-        ));
-        tasks.spawn(spawn_future_orderbook_task(
-            BinanceFutureOrderbookStreamHandler::new(future_symbol.clone(), self.future.ob_out),
-        ));
-        tasks.spawn(spawn_future_liquidation_task(
-            BinanceFutureLiquidationStreamHandler::new(future_symbol.clone(), self.future.liq_out),
-        ));
-        tasks.spawn(spawn_future_markprice_task(
-            BinanceFutureMarkPriceStreamHandler::new(future_symbol.clone(), self.future.mark_out),
-        ));
+        for (market, endpoint, channels, symbols) in [
+            (
+                BinanceFutureMarket::UsdM,
+                &self.endpoints.future,
+                &self.future,
+                &future_symbols,
+            ),
+            (
+                BinanceFutureMarket::CoinM,
+                &self.endpoints.coin,
+                &self.coin,
+                &coin_symbols,
+            ),
+        ] {
+            for (shard, symbols) in symbols.chunks(self.streams_per_connection).enumerate() {
+                info!(
+                    "Starting Binance {} Streams for {:?}",
+                    market.name(),
+                    symbols
+                );
+                self.supervisor.spawn(
+                    tasks,
+                    &format!("binance/{}/aggtrade/{}", market.name(), shard),
+                    |monitor| {
+                        let recorder = self.supervisor.recorder(&monitor);
+                        let faults = self.supervisor.faults(&monitor);
+                        BinanceFutureAggTradeStreamHandler::new(
+                            market,
+                            symbols.to_vec(),
+                            channels.agg_out.clone(),
+                            endpoint.clone(),
+                            monitor,
+                        )
+                        .with_recorder(recorder)
+                        .with_faults(faults)
+                        .with_limiter(weights.future(market).clone())
+                    },
+                );
+                self.supervisor.spawn(
+                    tasks,
+                    &format!("binance/{}/bookticker/{}", market.name(), shard),
+                    |monitor| {
+                        let recorder = self.supervisor.recorder(&monitor);
+                        BinanceFutureBookTickerStreamHandler::new(
+                            market,
+                            symbols.to_vec(),
+                            channels.bbo_out.clone(),
+                            endpoint.clone(),
+                            monitor,
+                        )
+                        .with_recorder(recorder)
+                    },
+                );
+                self.supervisor.spawn(
+                    tasks,
+                    &format!("binance/{}/orderbook/{}", market.name(), shard),
+                    |monitor| {
+                        let recorder = self.supervisor.recorder(&monitor);
+                        let faults = self.supervisor.faults(&monitor);
+                        BinanceFutureOrderbookStreamHandler::new(
+                            market,
+                            symbols.to_vec(),
+                            channels.ob_out.clone(),
+                            endpoint.clone(),
+                            monitor,
+                        )
+                        .with_recorder(recorder)
+                        .with_faults(faults)
+                        .with_limiter(weights.future(market).clone())
+                    },
+                );
+                if self.market_liquidations.is_none() {
+                    self.supervisor.spawn(
+                        tasks,
+                        &format!("binance/{}/liquidation/{}", market.name(), shard),
+                        |monitor| {
+                            let recorder = self.supervisor.recorder(&monitor);
+                            BinanceFutureLiquidationStreamHandler::new(
+                                market,
+                                symbols.to_vec(),
+                                channels.liq_out.clone(),
+                                endpoint.clone(),
+                                monitor,
+                            )
+                            .with_recorder(recorder)
+                        },
+                    );
+                }
+                self.supervisor.spawn(
+                    tasks,
+                    &format!("binance/{}/markprice/{}", market.name(), shard),
+                    |monitor| {
+                        let recorder = self.supervisor.recorder(&monitor);
+                        BinanceFutureMarkPriceStreamHandler::new(
+                            market,
+                            symbols.to_vec(),
+                            channels.mark_out.clone(),
+                            endpoint.clone(),
+                            monitor,
+                        )
+                        .with_recorder(recorder)
+                    },
+                );
+            }
+
+            // One connection carries the liquidations of the whole market
+            match &self.market_liquidations {
+                Some(market_liquidations) if !symbols.is_empty() => {
+                    info!(
+                        "Starting Binance {} all-market liquidation stream",
+                        market.name()
+                    );
+                    self.supervisor.spawn(
+                        tasks,
+                        &format!("binance/{}/liquidation", market.name()),
+                        |monitor| {
+                            let recorder = self.supervisor.recorder(&monitor);
+                            BinanceFutureLiquidationStreamHandler::new(
+                                market,
+                                symbols.to_vec(),
+                                channels.liq_out.clone(),
+                                endpoint.clone(),
+                                monitor,
+                            )
+                            .with_recorder(recorder)
+                            .all_market(market_liquidations.clone())
+                        },
+                    );
+                }
+                _ => (),
+            }
+        }
+
+        // Futures Statistics Pollers
+        // Delivery contracts have no long/short statistics
+        let perpetuals: Vec<String> = future_symbols
+            .iter()
+            .filter(|symbol| delivery_time(symbol).is_none())
+            .cloned()
+            .collect();
+        if !perpetuals.is_empty() {
+            for (endpoint, interval) in self.stats_pollers.iter() {
+                let limiter = match endpoint.is_futures_data() {
+                    true => weights.futures_data.clone(),
+                    false => weights.fapi.clone(),
+                };
+                let demand = limiter.demand(perpetuals.len(), endpoint.weight(), *interval);
+                if demand > limiter.limit() as u64 {
+                    warn!(
+                        "Binance {} poller needs {} weight per window over a budget of {}. Polls will be spaced out",
+                        endpoint.name(),
+                        demand,
+                        limiter.limit()
+                    );
+                }
+
+                info!(
+                    "Starting Binance {} poller every {:?}",
+                    endpoint.name(),
+                    interval
+                );
+                self.supervisor.spawn(
+                    tasks,
+                    &format!("binance/future/stats/{}", endpoint.name()),
+                    |monitor| {
+                        BinanceFutureStatsPoller::new(
+                            *endpoint,
+                            *interval,
+                            perpetuals.clone(),
+                            self.future.stats_out.clone(),
+                            limiter,
+                            &self.endpoints.future,
+                            monitor,
+                        )
+                    },
+                );
+            }
+        }
 
         // Spot Streams
-        tasks.spawn(spawn_spot_aggtrade_task(
-            BinanceSpotAggTradeStreamHandler::new(spot_symbol.clone(), self.spot.agg_out),
-        ));
-        tasks.spawn(spawn_spot_orderbook_task(
-            BinanceSpotOrderbookStreamHandler::new(spot_symbol.clone(), self.spot.ob_out),
-        ));
+        for (shard, symbols) in spot_symbols.chunks(self.streams_per_connection).enumerate() {
+            info!("Starting Binance Spot Streams for {:?}", symbols);
+            self.supervisor.spawn(
+                tasks,
+                &format!("binance/spot/aggtrade/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    let faults = self.supervisor.faults(&monitor);
+                    BinanceSpotAggTradeStreamHandler::new(
+                        symbols.to_vec(),
+                        self.spot.agg_out.clone(),
+                        self.endpoints.spot.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                    .with_faults(faults)
+                    .with_limiter(weights.spot.clone())
+                },
+            );
+            self.supervisor.spawn(
+                tasks,
+                &format!("binance/spot/bookticker/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    BinanceSpotBookTickerStreamHandler::new(
+                        symbols.to_vec(),
+                        self.spot.bbo_out.clone(),
+                        self.endpoints.spot.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                },
+            );
+            self.supervisor.spawn(
+                tasks,
+                &format!("binance/spot/orderbook/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    let faults = self.supervisor.faults(&monitor);
+                    BinanceSpotOrderbookStreamHandler::new(
+                        symbols.to_vec(),
+                        self.spot.ob_out.clone(),
+                        self.endpoints.spot.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                    .with_faults(faults)
+                    .with_limiter(weights.spot.clone())
+                },
+            );
+        }
     }
 }
 
-/* Future Streams */
+/// Weight budgets of the Binance REST hosts. Clones draw from the same budgets
+#[derive(Debug, Clone)]
+struct BinanceWeights {
+    fapi: WeightLimiter,         // USDⓈ-M, 2400 per minute
+    dapi: WeightLimiter,         // COIN-M, 2400 per minute
+    spot: WeightLimiter,         // Spot, 6000 per minute
+    futures_data: WeightLimiter, // `futures/data` statistics, 1000 requests per 5 minutes
+}
 
-async fn spawn_future_aggtrade_task(handler: BinanceFutureAggTradeStreamHandler) {
-    loop {
-        warn!("Attempting to connect to Binance Aggtrades");
-        if let Err(e) = handler.connect().await {
-            error!("Binance Aggtrades connection error: {}", e);
+impl BinanceWeights {
+    fn new(weight_per_minute: Option<u32>) -> Self {
+        let Some(weight_per_minute) = weight_per_minute else {
+            return Self {
+                fapi: WeightLimiter::unlimited(),
+                dapi: WeightLimiter::unlimited(),
+                spot: WeightLimiter::unlimited(),
+                futures_data: WeightLimiter::unlimited(),
+            };
+        };
+        let per_minute = || WeightLimiter::new(weight_per_minute, Duration::from_secs(60));
+        Self {
+            fapi: per_minute(),
+            dapi: per_minute(),
+            spot: per_minute(),
+            futures_data: WeightLimiter::new(1000, Duration::from_secs(300)),
         }
-        warn!("Binance Aggtrades: Retrying in 5 seconds");
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
     }
-}
 
-async fn spawn_future_orderbook_task(handler: BinanceFutureOrderbookStreamHandler) {
-    loop {
-        warn!("Attempting to connect to Binance");
-        if let Err(e) = handler.connect().await {
-            error!("Binance connection error: {}", e);
+    fn future(&self, market: BinanceFutureMarket) -> &WeightLimiter {
+        match market {
+            BinanceFutureMarket::UsdM => &self.fapi,
+            BinanceFutureMarket::CoinM => &self.dapi,
         }
-        warn!("Binance: Retrying in 5 seconds");
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
     }
 }
 
-async fn spawn_future_liquidation_task(handler: BinanceFutureLiquidationStreamHandler) {
-    loop {
-        warn!("Attempting to connect to Binance Liquidation");
-        if let Err(e) = handler.connect().await {
-            error!("Binance Liquidation connection error: {}", e);
+/// Path of a combined stream subscription, e.g. `btcusdt@depth/ethusdt@depth`
+/// https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#general-wss-information
+pub fn combined_streams(symbols: &[String], stream: &str) -> String {
+    symbols
+        .iter()
+        .map(|symbol| format!("{}@{}", symbol.to_lowercase(), stream))
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+/// Delivery time in unix millis of a dated contract, e.g. BTCUSDT_250627 or BTCUSD_250627.
+/// `None` for perpetuals. Contracts settle at 08:00 UTC of the date in the symbol
+pub fn delivery_time(symbol: &str) -> Option<u64> {
+    let (_, suffix) = symbol.split_once('_')?;
+    let date = NaiveDate::parse_from_str(suffix, "%y%m%d").ok()?;
+    let settlement = date.and_hms_opt(8, 0, 0)?.and_utc();
+    u64::try_from(settlement.timestamp_millis()).ok()
+}
+
+/* COIN-M Contract Sizes */
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct ExchangeInfo {
+    symbols: Vec<ExchangeInfoSymbol>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct ExchangeInfoSymbol {
+    symbol: String,
+    contractSize: Option<Decimal>, // USD per contract. COIN-M only
+}
+
+/// USD value of one contract of every COIN-M symbol.
+/// Empty for USDⓈ-M, whose quantities are in coins already
+#[derive(Debug, Clone, Default)]
+pub struct ContractSizes {
+    sizes: HashMap<String, Decimal>,
+    monitor: Option<StreamMonitor>, // COIN-M only. Counts the symbols exchangeInfo left out
+    unlisted: Arc<Mutex<HashSet<String>>>, // Warned about once
+}
+
+impl ContractSizes {
+    /// Coins worth `quantity` contracts at `price`. A COIN-M symbol missing from exchangeInfo
+    /// stays in contracts
+    pub fn to_coins(&self, symbol: &str, price: Decimal, quantity: Decimal) -> Decimal {
+        match self.sizes.get(symbol) {
+            Some(size) if !price.is_zero() => quantity * size / price,
+            Some(_) => quantity,
+            None => {
+                self.unlisted(symbol);
+                quantity
+            }
         }
-        warn!("Binance Liquidation: Retrying in 5 seconds");
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
     }
-}
 
-async fn spawn_future_markprice_task(handler: BinanceFutureMarkPriceStreamHandler) {
-    loop {
-        warn!("Attempting to connect to Binance Mark Price");
-        if let Err(e) = handler.connect().await {
-            error!("Binance Mark Price connection error: {}", e);
+    fn unlisted(&self, symbol: &str) {
+        let Some(monitor) = &self.monitor else {
+            return;
+        };
+        monitor.incr("unknown_contract_sizes");
+        if self.unlisted.lock().unwrap().insert(symbol.to_string()) {
+            warn!(
+                "{}: No contract size for {}, its quantities stay in contracts",
+                monitor.name(),
+                symbol
+            );
         }
-        warn!("Binance Mark Price: Retrying in 5 seconds");
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    }
+
+    pub fn levels_to_coins(
+        &self,
+        symbol: &str,
+        levels: &[(Decimal, Decimal)],
+    ) -> Vec<(Decimal, Decimal)> {
+        levels
+            .iter()
+            .map(|(price, quantity)| (*price, self.to_coins(symbol, *price, *quantity)))
+            .collect()
     }
 }
 
-/* Spot Streams */
-async fn spawn_spot_aggtrade_task(handler: BinanceSpotAggTradeStreamHandler) {
-    loop {
-        warn!("Attempting to connect to Binance Aggtrades");
-        if let Err(e) = handler.connect().await {
-            error!("Binance Aggtrades connection error: {}", e);
-        }
-        warn!("Binance Aggtrades: Retrying in 5 seconds");
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+/// Contract sizes of `market`. Conversions the sizes cannot make are counted on `monitor`
+pub async fn fetch_contract_sizes(
+    market: BinanceFutureMarket,
+    endpoint: &Endpoint,
+    recorder: &FrameRecorder,
+    monitor: &StreamMonitor,
+) -> Result<ContractSizes, tungstenite::Error> {
+    if market == BinanceFutureMarket::UsdM {
+        return Ok(ContractSizes::default());
     }
+
+    // Weight 1
+    let url = format!("{}/exchangeInfo", market.rest_url(endpoint));
+    let request = reqwest::Client::new()
+        .get(&url)
+        .timeout(std::time::Duration::from_secs(10));
+    let body = recorder.fetch(request).await?;
+    let info = serde_json::from_slice::<ExchangeInfo>(&body).map_err(io_error)?;
+
+    let sizes = info
+        .symbols
+        .into_iter()
+        .filter_map(|symbol| Some((symbol.symbol, symbol.contractSize?)))
+        .collect();
+    Ok(ContractSizes {
+        sizes,
+        monitor: Some(monitor.clone()),
+        unlisted: Arc::default(),
+    })
 }
 
-async fn spawn_spot_orderbook_task(handler: BinanceSpotOrderbookStreamHandler) {
-    loop {
-        warn!("Attempting to connect to Binance Spot");
-        if let Err(e) = handler.connect().await {
-            error!("Binance Spot connection error: {}", e);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::health::StreamHealth;
+
+    #[test]
+    fn dated_contracts_deliver_at_eight_utc() {
+        // 2025-06-27T08:00:00Z
+        assert_eq!(delivery_time("BTCUSDT_250627"), Some(1_751_011_200_000));
+        assert_eq!(delivery_time("BTCUSD_250627"), Some(1_751_011_200_000));
+
+        assert_eq!(delivery_time("BTCUSDT"), None);
+        assert_eq!(delivery_time("BTCUSD_PERP"), None);
+        assert_eq!(delivery_time("BTCUSDT_251341"), None);
+    }
+
+    #[test]
+    fn contracts_turn_into_coins_at_the_price() {
+        let health = StreamHealth::new();
+        let sizes = ContractSizes {
+            sizes: HashMap::from([("BTCUSD_PERP".to_string(), Decimal::from(100))]),
+            monitor: Some(health.monitor("binance/coin/aggtrade/0")),
+            unlisted: Arc::default(),
+        };
+        // 10 contracts of 100 USD at 50000 USD
+        let coins = sizes.to_coins("BTCUSD_PERP", Decimal::from(50_000), Decimal::from(10));
+        assert_eq!(coins, Decimal::new(2, 2));
+
+        // Not listed: left in contracts and counted
+        for _ in 0..2 {
+            let quantity = sizes.to_coins("ETHUSD_PERP", Decimal::from(2_000), Decimal::from(10));
+            assert_eq!(quantity, Decimal::from(10));
         }
-        warn!("Binance Spot: Retrying in 5 seconds");
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+        let (_, status) = health.snapshot().remove(0);
+        assert_eq!(status.counters.get("unknown_contract_sizes"), Some(&2));
+
+        // USDⓈ-M quantities are coins already
+        let usd_m = ContractSizes::default();
+        assert_eq!(
+            usd_m.to_coins("BTCUSDT", Decimal::from(50_000), Decimal::ONE),
+            Decimal::ONE
+        );
     }
 }
@@ -0,0 +1,84 @@
+use crate::data::{
+    depth::bitget::future::BitgetFutureOrderbookStreamHandler, endpoints::Endpoint,
+    exchanges::FutureDataChannels, market::bitget::future::BitgetFutureTradeStreamHandler,
+    markprice::bitget::future::BitgetFutureTickerStreamHandler, supervisor::Supervisor,
+};
+use log::{info, warn};
+use tokio::task::JoinSet;
+
+// Bitget recommends fewer than 50 channels per connection
+// https://www.bitget.com/api-doc/common/websocket-intro
+const CHANNELS_PER_CONNECTION: usize = 50;
+
+pub struct BitgetThreads {
+    future: FutureDataChannels,
+    supervisor: Supervisor,
+    endpoint: Endpoint,
+}
+
+impl BitgetThreads {
+    pub fn new(future: FutureDataChannels, supervisor: Supervisor, endpoint: Endpoint) -> Self {
+        Self {
+            future,
+            supervisor,
+            endpoint,
+        }
+    }
+
+    pub fn spawn_streams(self, tasks: &mut JoinSet<()>, future_symbols: Vec<String>) {
+        if future_symbols.is_empty() {
+            warn!("No symbols specified, skipping Bitget streams");
+            return;
+        }
+
+        // Future Streams
+        // Bitget has no public liquidation channel. `liq_out` stays idle
+        for (shard, symbols) in future_symbols.chunks(CHANNELS_PER_CONNECTION).enumerate() {
+            info!("Starting Bitget Streams for {:?}", symbols);
+            self.supervisor.spawn(
+                tasks,
+                &format!("bitget/future/trade/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    BitgetFutureTradeStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.agg_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                },
+            );
+            self.supervisor.spawn(
+                tasks,
+                &format!("bitget/future/orderbook/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    let faults = self.supervisor.faults(&monitor);
+                    BitgetFutureOrderbookStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.ob_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                    .with_faults(faults)
+                },
+            );
+            self.supervisor.spawn(
+                tasks,
+                &format!("bitget/future/ticker/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    BitgetFutureTickerStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.mark_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                },
+            );
+        }
+    }
+}
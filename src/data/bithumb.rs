@@ -0,0 +1,79 @@
+use crate::data::{
+    depth::bithumb::spot::BithumbSpotOrderbookStreamHandler, endpoints::Endpoint,
+    exchanges::SpotDataChannels, market::bithumb::spot::BithumbSpotTradeStreamHandler,
+    supervisor::Supervisor,
+};
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::task::JoinSet;
+
+// Bithumb public streams. Symbols are order currency and payment currency, e.g. "BTC_KRW"
+// https://apidocs.bithumb.com
+
+// Keep the connection busy on quiet markets
+pub const PING_INTERVAL_SECS: u64 = 30;
+
+/// Reply to the connection and to every subscription
+#[derive(Debug, Deserialize)]
+pub struct BithumbStatus {
+    pub status: String, // "0000" on success
+    pub resmsg: String,
+}
+
+pub struct BithumbThreads {
+    spot: SpotDataChannels,
+    supervisor: Supervisor,
+    endpoint: Endpoint,
+}
+
+impl BithumbThreads {
+    pub fn new(spot: SpotDataChannels, supervisor: Supervisor, endpoint: Endpoint) -> Self {
+        Self {
+            spot,
+            supervisor,
+            endpoint,
+        }
+    }
+
+    pub fn spawn_streams(self, tasks: &mut JoinSet<()>, symbols: Vec<String>) {
+        if symbols.is_empty() {
+            warn!("No symbols specified, skipping Bithumb streams");
+            return;
+        }
+
+        // Spot Streams
+        // One connection takes every symbol of a stream type
+        info!("Starting Bithumb krw Streams for {:?}", symbols);
+        self.supervisor
+            .spawn(tasks, "bithumb/krw/aggtrade", |monitor| {
+                let recorder = self.supervisor.recorder(&monitor);
+                BithumbSpotTradeStreamHandler::new(
+                    symbols.clone(),
+                    self.spot.agg_out,
+                    self.endpoint.clone(),
+                    monitor,
+                )
+                .with_recorder(recorder)
+            });
+        self.supervisor
+            .spawn(tasks, "bithumb/krw/orderbook", |monitor| {
+                let recorder = self.supervisor.recorder(&monitor);
+                let faults = self.supervisor.faults(&monitor);
+                BithumbSpotOrderbookStreamHandler::new(
+                    symbols.clone(),
+                    self.spot.ob_out,
+                    self.endpoint.clone(),
+                    monitor,
+                )
+                .with_recorder(recorder)
+                .with_faults(faults)
+            });
+    }
+}
+
+/// Subscription message of `stream` for every symbol
+pub fn subscription_message(stream: &str, symbols: &[String]) -> String {
+    let symbols: Vec<String> = symbols.iter().map(|symbol| symbol.to_uppercase()).collect();
+
+    serde_json::json!({ "type": stream, "symbols": symbols }).to_string()
+}
@@ -0,0 +1,137 @@
+use crate::data::{
+    depth::bybit::future::BybitFutureOrderbookStreamHandler, endpoints::Endpoint,
+    exchanges::FutureDataChannels, liquidation::bybit::future::BybitFutureLiquidationStreamHandler,
+    market::bybit::future::BybitFutureTradeStreamHandler,
+    markprice::bybit::future::BybitFutureTickerStreamHandler, supervisor::Supervisor,
+};
+use futures::{Sink, SinkExt};
+use log::{info, warn};
+use serde::Deserialize;
+use tokio::task::JoinSet;
+use tokio_tungstenite::tungstenite::Message;
+
+// Bybit v5 public streams for USDT perpetuals
+// https://bybit-exchange.github.io/docs/v5/ws/connect
+
+// Bybit recommends a ping every 20 seconds
+pub const PING_INTERVAL_SECS: u64 = 20;
+
+const ARGS_PER_REQUEST: usize = 10; // Max args of one subscribe request
+const SYMBOLS_PER_CONNECTION: usize = 50;
+
+/// Reply to `subscribe`, `unsubscribe` and `ping` operations
+#[derive(Debug, Deserialize)]
+pub struct BybitOpResponse {
+    pub success: bool,
+    pub ret_msg: String, // "pong" for pings, error reason for failed subscriptions
+    pub op: String,
+}
+
+pub struct BybitThreads {
+    future: FutureDataChannels,
+    supervisor: Supervisor,
+    endpoint: Endpoint,
+}
+
+impl BybitThreads {
+    pub fn new(future: FutureDataChannels, supervisor: Supervisor, endpoint: Endpoint) -> Self {
+        Self {
+            future,
+            supervisor,
+            endpoint,
+        }
+    }
+
+    pub fn spawn_streams(self, tasks: &mut JoinSet<()>, future_symbols: Vec<String>) {
+        if future_symbols.is_empty() {
+            warn!("No symbols specified, skipping Bybit streams");
+            return;
+        }
+
+        // Future Streams
+        for (shard, symbols) in future_symbols.chunks(SYMBOLS_PER_CONNECTION).enumerate() {
+            info!("Starting Bybit Future Streams for {:?}", symbols);
+            self.supervisor
+                .spawn(tasks, &format!("bybit/future/trade/{}", shard), |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    BybitFutureTradeStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.agg_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                });
+            self.supervisor.spawn(
+                tasks,
+                &format!("bybit/future/orderbook/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    let faults = self.supervisor.faults(&monitor);
+                    BybitFutureOrderbookStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.ob_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                    .with_faults(faults)
+                },
+            );
+            self.supervisor.spawn(
+                tasks,
+                &format!("bybit/future/ticker/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    BybitFutureTickerStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.mark_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                },
+            );
+            self.supervisor.spawn(
+                tasks,
+                &format!("bybit/future/liquidation/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    BybitFutureLiquidationStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.liq_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                },
+            );
+        }
+    }
+}
+
+/// Send `op` ("subscribe" or "unsubscribe") for `topic` of every symbol, e.g. `publicTrade.BTCUSDT`
+pub async fn send_operation<S>(
+    write: &mut S,
+    op: &str,
+    topic: &str,
+    symbols: &[String],
+) -> Result<(), S::Error>
+where
+    S: Sink<Message> + Unpin,
+{
+    let args: Vec<String> = symbols
+        .iter()
+        .map(|symbol| format!("{}.{}", topic, symbol.to_uppercase()))
+        .collect();
+
+    for args in args.chunks(ARGS_PER_REQUEST) {
+        let request = serde_json::json!({ "op": op, "args": args }).to_string();
+        write.send(Message::Text(request.into())).await?;
+    }
+    Ok(())
+}
+
+pub fn ping_message() -> Message {
+    Message::Text(r#"{"op":"ping"}"#.into())
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/* Clock */
+
+// Where "now" comes from for anything compared against local time. Live runs read the
+// wall clock. Replays and tests move a manual clock, so the same input gives the same state
+
+#[derive(Debug, Clone, Default)]
+pub enum Clock {
+    #[default]
+    Wall,
+    Manual(Arc<watch::Sender<u64>>), // Unix millis. Clones share the time
+}
+
+impl Clock {
+    pub fn wall() -> Self {
+        Self::Wall
+    }
+
+    pub fn manual(start_ms: u64) -> Self {
+        Self::Manual(Arc::new(watch::Sender::new(start_ms)))
+    }
+
+    /// Unix millis
+    pub fn now_millis(&self) -> u64 {
+        match self {
+            Clock::Wall => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis() as u64),
+            Clock::Manual(now) => *now.borrow(),
+        }
+    }
+
+    /// Move a manual clock to `now_ms`. The wall clock cannot be moved
+    pub fn set(&self, now_ms: u64) {
+        if let Clock::Manual(now) = self {
+            now.send_replace(now_ms);
+        }
+    }
+
+    /// Wait until the clock reads `deadline_ms`. A manual clock wakes once it is set there
+    pub async fn sleep_until(&self, deadline_ms: u64) {
+        match self {
+            Clock::Wall => {
+                let left = deadline_ms.saturating_sub(self.now_millis());
+                tokio::time::sleep(Duration::from_millis(left)).await;
+            }
+            Clock::Manual(now) => {
+                // Cannot fail, `now` is the sender
+                let _ = now.subscribe().wait_for(|now| *now >= deadline_ms).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn manual_clock_wakes_once_set_past_the_deadline() {
+        let clock = Clock::manual(1_000);
+        let sleeper = tokio::spawn({
+            let clock = clock.clone();
+            async move { clock.sleep_until(1_500).await }
+        });
+
+        clock.set(1_400);
+        tokio::task::yield_now().await;
+        assert!(!sleeper.is_finished());
+
+        clock.set(1_500);
+        sleeper.await.unwrap();
+        assert_eq!(clock.now_millis(), 1_500);
+    }
+}
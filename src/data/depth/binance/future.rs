@@ -1,45 +1,88 @@
-use crate::data::{depth::OrderbookUpdateStream, stream::StreamHandler};
-use futures::{SinkExt, StreamExt};
-use log::{error, info};
+use crate::data::endpoints::Endpoint;
+use crate::data::fault::{FaultInjector, InjectedTimeout, REQUEST_TIMEOUT};
+use crate::data::latency::ReceiveStamp;
+use crate::data::recorder::{FrameRecorder, RestError};
+use crate::data::{
+    binance::{combined_streams, fetch_contract_sizes, BinanceFutureMarket, ContractSizes},
+    depth::{
+        binance::sync::{DepthEvent, DepthSync, DepthSyncError, INITIAL_SNAPSHOT_SPACING},
+        OrderbookUpdateKind, OrderbookUpdateStream,
+    },
+    health::StreamMonitor,
+    limiter::WeightLimiter,
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{stream::FuturesUnordered, SinkExt, StreamExt};
+use log::{error, info, warn};
+use rust_decimal::Decimal;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::future::Future;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{self, Message},
-};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::{self, Message};
 
 /* Binance Orderbook Snapshot */
 
+const SNAPSHOT_WEIGHT: u32 = 20; // At limit=1000
+
 #[allow(dead_code, non_snake_case)]
 #[derive(Debug, Deserialize)]
 pub struct FutureDepthSnapShot {
     pub lastUpdateId: u64,
     pub E: u64, // Message output time
     pub T: u64, // Transaction time
-    pub bids: Vec<(String, String)>,
-    pub asks: Vec<(String, String)>,
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
 }
 
-#[allow(dead_code)]
-pub async fn fetch_depth_snapshot(symbol: &str) -> Result<FutureDepthSnapShot, reqwest::Error> {
+pub async fn fetch_depth_snapshot(
+    market: BinanceFutureMarket,
+    endpoint: &Endpoint,
+    symbol: &str,
+    recorder: &FrameRecorder,
+) -> Result<FutureDepthSnapShot, RestError> {
     // https://developers.binance.com/docs/derivatives/usds-margined-futures/market-data/rest-api/Order-Book
     let url = format!(
-        "https://fapi.binance.com/fapi/v1/depth?symbol={}&limit=1000", // 1000 is the max limit. Weight is 20
-        symbol
+        "{}/depth?symbol={}&limit=1000", // 1000 is the max limit. Weight is 20
+        market.rest_url(endpoint),
+        symbol.to_uppercase()
     );
 
-    let client = reqwest::Client::new();
-    let response = client
+    let request = reqwest::Client::new()
         .get(&url)
-        .timeout(std::time::Duration::from_secs(10))
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<FutureDepthSnapShot>()
-        .await?;
-
-    Ok(response)
+        .timeout(std::time::Duration::from_secs(10));
+    let body = recorder.fetch(request).await?;
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+type SnapshotResult = Result<FutureDepthSnapShot, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Fetch a snapshot after `delay`, tagged with its symbol so that
+/// snapshots of several symbols can be awaited together
+async fn fetch_tagged_snapshot(
+    market: BinanceFutureMarket,
+    endpoint: Endpoint,
+    symbol: String,
+    delay: Duration,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
+    limiter: WeightLimiter,
+) -> (String, SnapshotResult) {
+    // Replays skip the wait. A zero sleep still waits for the next timer tick
+    if !delay.is_zero() && !recorder.is_replaying() {
+        tokio::time::sleep(delay).await;
+    }
+    if faults.snapshot_timeout() {
+        tokio::time::sleep(REQUEST_TIMEOUT).await;
+        return (symbol, Err(Box::new(InjectedTimeout)));
+    }
+    limiter.acquire(SNAPSHOT_WEIGHT).await;
+    let result = fetch_depth_snapshot(market, &endpoint, &symbol, &recorder)
+        .await
+        .map_err(Into::into);
+    (symbol, result)
 }
 
 /* Binance Orderbook Stream */
@@ -54,123 +97,315 @@ pub struct BinanceWebsocketFutureDiffBook {
 #[allow(dead_code, non_snake_case)]
 #[derive(Debug, Deserialize)]
 pub struct FutureDepthEvent {
-    pub e: String,                // Event type
-    pub E: u64,                   // Event time
-    pub T: u64,                   // Transaction time
-    pub s: String,                // Symbol
-    pub U: u64,                   // First update ID in event
-    pub u: u64,                   // Final update ID in event
-    pub pu: u64,                  // Final update ID from previous event
-    pub b: Vec<(String, String)>, // Bids to update
-    pub a: Vec<(String, String)>, // Asks to update
+    pub e: String,                  // Event type
+    pub E: u64,                     // Event time
+    pub T: u64,                     // Transaction time
+    pub s: String,                  // Symbol
+    pub U: u64,                     // First update ID in event
+    pub u: u64,                     // Final update ID in event
+    pub pu: u64,                    // Final update ID from previous event
+    pub b: Vec<(Decimal, Decimal)>, // Bids to update
+    pub a: Vec<(Decimal, Decimal)>, // Asks to update
+    #[serde(skip)]
+    pub local_time: u64, // Receive time. Kept while the event waits in the sync buffer
+}
+
+impl DepthEvent for FutureDepthEvent {
+    fn final_update_id(&self) -> u64 {
+        self.u
+    }
+
+    fn is_before(&self, snapshot_id: u64) -> bool {
+        self.u < snapshot_id
+    }
+
+    fn bridges(&self, snapshot_id: u64) -> bool {
+        self.U <= snapshot_id && self.u >= snapshot_id
+    }
+
+    fn follows(&self, last: u64) -> bool {
+        self.pu == last
+    }
 }
 
 pub struct BinanceFutureOrderbookStreamHandler {
+    market: BinanceFutureMarket,
     streams: String,
-    pub symbol: String,
-    pub tx: mpsc::Sender<OrderbookUpdateStream>,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<OrderbookUpdateStream>,
+    contract_sizes: ContractSizes,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
+    limiter: WeightLimiter,
 }
 
 impl StreamHandler for BinanceFutureOrderbookStreamHandler {
     fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
-        let symbol = self.symbol.clone();
+        let market = self.market;
+        let symbols = self.symbols.clone();
         let streams = self.streams.clone();
         let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let faults = self.faults.clone();
+        let recorder = self.recorder.clone();
+        let limiter = self.limiter.clone();
 
         Box::new(Box::pin(async move {
+            let contract_sizes =
+                fetch_contract_sizes(market, &endpoint, &recorder, &monitor).await?;
+
             let ws_url = format!(
-                "wss://fstream.binance.com/stream?streams={}@{}",
-                symbol, streams
+                "{}?streams={}",
+                market.ws_url(&endpoint),
+                combined_streams(&symbols, &streams)
             );
-            let (ws_stream, _) = connect_async(&ws_url).await?;
-            let (write, read) = ws_stream.split();
-
-            let snapshot = fetch_depth_snapshot(&symbol).await.unwrap();
+            let (read, write) = recorder.connect(&ws_url).await?;
+            let (read, write) = faults.wrap(read, write);
 
             // Create a new handler instance for the async block
             let handler = BinanceFutureOrderbookStreamHandler {
-                symbol,
+                market,
+                symbols,
                 streams,
                 tx,
+                contract_sizes,
+                endpoint,
+                monitor,
+                faults,
+                recorder,
+                limiter,
             };
-            handler.handle_orderbook(read, write, snapshot).await;
-
-            Ok(())
+            handler.monitor.live();
+            handler.handle_orderbook(read, write).await
         }))
     }
 }
 
 impl BinanceFutureOrderbookStreamHandler {
-    pub fn new(symbol: String, tx: mpsc::Sender<OrderbookUpdateStream>) -> Self {
+    pub fn new(
+        market: BinanceFutureMarket,
+        symbols: Vec<String>,
+        tx: SymbolRouter<OrderbookUpdateStream>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
         Self {
-            symbol,
+            market,
+            symbols,
             streams: "depth".to_string(),
             tx,
+            contract_sizes: ContractSizes::default(),
+            endpoint,
+            monitor,
+            faults: FaultInjector::disabled(),
+            recorder: FrameRecorder::disabled(),
+            limiter: WeightLimiter::unlimited(),
         }
     }
 
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Inject faults into the socket and the snapshots
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Draw the snapshots from the weight budget of the REST host
+    pub fn with_limiter(mut self, limiter: WeightLimiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
     pub async fn handle_orderbook<R, S>(
         &self,
         mut read: R,
         mut write: S,
-        snapshot: FutureDepthSnapShot,
-    ) where
+    ) -> Result<(), tungstenite::Error>
+    where
         R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
         S: SinkExt<Message> + Unpin,
         S::Error: std::fmt::Display,
     {
-        let mut last_update_id = snapshot.lastUpdateId;
-
-        while let Some(msg) = read.next().await {
-            match msg {
-                Ok(Message::Text(text)) => {
-                    match serde_json::from_str::<BinanceWebsocketFutureDiffBook>(&text) {
-                        Ok(diff) => {
-                            // Snapshot handling
-                            if diff.data.u <= last_update_id || diff.data.U <= last_update_id {
-                                error!(
-                                    "Binance orderbook stream: Event out of order - reinitializing"
-                                );
-                                continue;
+        // One sync per symbol. Events are buffered there while its snapshot is in flight
+        let mut books: HashMap<String, DepthSync<FutureDepthEvent>> = self
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.to_uppercase(), DepthSync::new()))
+            .collect();
+        // Applied snapshots no event has bridged yet. The book is reset once one does
+        let mut unbridged: HashMap<String, OrderbookUpdateStream> = HashMap::new();
+        let mut snapshots = FuturesUnordered::new();
+        for (index, symbol) in self.symbols.iter().enumerate() {
+            let symbol = symbol.to_uppercase();
+            let Some(sync) = books.get_mut(&symbol) else {
+                continue;
+            };
+            let delay = sync.next_snapshot_delay() + INITIAL_SNAPSHOT_SPACING * index as u32;
+            snapshots.push(self.fetch_snapshot(symbol, delay));
+        }
+
+        loop {
+            tokio::select! {
+                Some((symbol, result)) = snapshots.next() => {
+                    let Some(sync) = books.get_mut(&symbol) else {
+                        continue;
+                    };
+                    let snapshot = match result {
+                        Ok(snapshot) => snapshot,
+                        Err(e) => {
+                            error!("Binance orderbook stream: Failed to fetch {} snapshot: {}", symbol, e);
+                            self.monitor.incr("snapshot_failures");
+                            // Rate limited or banned: the supervisor waits it out before reconnecting
+                            if let Some(ban) = e.downcast_ref::<RestError>().and_then(RestError::ban) {
+                                return Err(ban);
                             }
+                            snapshots.push(self.fetch_snapshot(symbol, sync.next_snapshot_delay()));
+                            continue;
+                        }
+                    };
 
-                            // Send processed binance update to tx
-                            let update = self.generate_orderbook_update(&diff);
-                            if self.tx.send(update).await.is_err() {
-                                error!("Binance orderbook stream: Failed to send update")
-                            };
+                    let snapshot_id = snapshot.lastUpdateId;
+                    let reset = self.generate_snapshot_update(&symbol, snapshot);
 
-                            last_update_id = diff.data.u;
+                    // Only a snapshot the events bridge resets the book
+                    match sync.apply_snapshot(snapshot_id) {
+                        Ok(events) if events.is_empty() => {
+                            unbridged.insert(symbol, reset);
+                        }
+                        Ok(events) => {
+                            self.send_snapshot(reset).await;
+                            for event in events {
+                                self.send_update(&event).await;
+                            }
                         }
                         Err(e) => {
-                            error!("Binance orderbook stream: Failed to parse message: {}", e)
+                            warn!("Binance orderbook stream: {} snapshot out of sync ({}) - resyncing", symbol, e);
+                            self.monitor.incr("resyncs");
+                            snapshots.push(self.fetch_snapshot(symbol, sync.next_snapshot_delay()));
                         }
                     }
                 }
-                Ok(Message::Ping(payload)) => {
-                    if let Err(e) = write.send(Message::Pong(payload)).await {
-                        error!("Binance orderbook stream: Failed to send Pong: {}", e);
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<BinanceWebsocketFutureDiffBook>(&text) {
+                                Ok(mut diff) => {
+                                    diff.data.local_time = self.recorder.now_millis();
+                                    let symbol = diff.data.s.clone();
+                                    let Some(sync) = books.get_mut(&symbol) else {
+                                        continue;
+                                    };
+                                    match sync.push(diff.data) {
+                                        Ok(Some(event)) => {
+                                            if let Some(reset) = unbridged.remove(&symbol) {
+                                                self.send_snapshot(reset).await;
+                                            }
+                                            self.send_update(&event).await;
+                                        }
+                                        Ok(None) => (),
+                                        Err(DepthSyncError::BufferOverflow) => {
+                                            // Snapshot is still in flight
+                                            warn!("Binance orderbook stream: {} event buffer overflow", symbol);
+                                        }
+                                        Err(e) => {
+                                            warn!("Binance orderbook stream: {} {} - resyncing", symbol, e);
+                                            self.monitor.incr("resyncs");
+                                            unbridged.remove(&symbol);
+                                            snapshots.push(self.fetch_snapshot(symbol, sync.next_snapshot_delay()));
+                                        }
+                                    }
+                                }
+                                Err(e) => {
+                                    // A dropped diff breaks the update id chain and triggers a resync
+                                    error!("Binance orderbook stream: Failed to parse message: {}", e);
+                                    self.monitor.incr("parse_failures");
+                                }
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Binance orderbook stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Binance orderbook stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Binance orderbook stream: Connection closed");
+                            return Ok(());
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => return Err(e),
                     }
                 }
-                Ok(Message::Pong(_)) => info!("Binance orderbook stream: Pong received"),
-                Ok(Message::Close(_)) => {
-                    info!("Binance orderbook stream: Connection closed");
-                    break;
-                }
-                _ => (),
             }
         }
     }
 
-    fn generate_orderbook_update(
+    fn fetch_snapshot(
         &self,
-        update: &BinanceWebsocketFutureDiffBook,
+        symbol: String,
+        delay: Duration,
+    ) -> impl Future<Output = (String, SnapshotResult)> {
+        fetch_tagged_snapshot(
+            self.market,
+            self.endpoint.clone(),
+            symbol,
+            delay,
+            self.faults.clone(),
+            self.recorder.clone(),
+            self.limiter.clone(),
+        )
+    }
+
+    async fn send_snapshot(&self, reset: OrderbookUpdateStream) {
+        if self.tx.send(reset).await.is_err() {
+            error!("Binance orderbook stream: Failed to send snapshot");
+        }
+    }
+
+    async fn send_update(&self, event: &FutureDepthEvent) {
+        let update = self.generate_orderbook_update(event);
+        if self.tx.send(update).await.is_err() {
+            error!("Binance orderbook stream: Failed to send update")
+        };
+    }
+
+    fn generate_snapshot_update(
+        &self,
+        symbol: &str,
+        snapshot: FutureDepthSnapShot,
     ) -> OrderbookUpdateStream {
         OrderbookUpdateStream {
-            bids: update.data.b.clone(),
-            asks: update.data.a.clone(),
-            trade_time: update.data.T,
-            event_time: update.data.E,
+            symbol: symbol.to_string(),
+            bids: self.contract_sizes.levels_to_coins(symbol, &snapshot.bids),
+            asks: self.contract_sizes.levels_to_coins(symbol, &snapshot.asks),
+            kind: OrderbookUpdateKind::Snapshot,
+            trade_time: snapshot.T,
+            event_time: snapshot.E,
+            update_id: Some(snapshot.lastUpdateId),
+            received: ReceiveStamp::local(self.recorder.now_millis()),
+            last_update_exchange: "Binance".to_string(),
+        }
+    }
+
+    fn generate_orderbook_update(&self, update: &FutureDepthEvent) -> OrderbookUpdateStream {
+        OrderbookUpdateStream {
+            symbol: update.s.clone(),
+            bids: self.contract_sizes.levels_to_coins(&update.s, &update.b),
+            asks: self.contract_sizes.levels_to_coins(&update.s, &update.a),
+            kind: OrderbookUpdateKind::Delta,
+            trade_time: update.T,
+            event_time: update.E,
+            update_id: Some(update.u),
+            received: self.monitor.stamp(update.local_time, update.E),
             last_update_exchange: "Binance".to_string(),
         }
     }
@@ -1,2 +1,3 @@
 pub mod future;
 pub mod spot;
+pub mod sync;
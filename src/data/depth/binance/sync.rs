@@ -0,0 +1,301 @@
+use std::fmt;
+use std::time::Duration;
+
+// Binance local order book synchronization
+// https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/How-to-manage-a-local-order-book-correctly
+// https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#how-to-manage-a-local-order-book-correctly
+//
+// 1. Open the stream and buffer events
+// 2. Fetch the REST snapshot
+// 3. Drop buffered events older than the snapshot
+// 4. The first applied event must bridge the snapshot's `lastUpdateId`
+// 5. Every following event must continue the previous one. Otherwise start over from (1)
+
+const MAX_BUFFERED_EVENTS: usize = 10_000;
+
+/// First wait of the snapshot backoff. It doubles with every snapshot in a row that fails
+pub const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_SNAPSHOT_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Space between the first snapshots of the symbols of a connection, so they do not all go out at once
+pub const INITIAL_SNAPSHOT_SPACING: Duration = Duration::from_millis(250);
+
+/// Update-id rules of a depth event. Spot and futures chain their events differently.
+pub trait DepthEvent {
+    fn final_update_id(&self) -> u64;
+    /// Event is fully covered by the snapshot and must be dropped
+    fn is_before(&self, snapshot_id: u64) -> bool;
+    /// Event is the first one to apply on top of the snapshot
+    fn bridges(&self, snapshot_id: u64) -> bool;
+    /// Event directly continues the event with final update id `last`
+    fn follows(&self, last: u64) -> bool;
+}
+
+#[derive(Debug)]
+pub enum DepthSyncError {
+    Gap { last: u64, next: u64 }, // Continuity broken between two events
+    BufferOverflow,               // Snapshot took too long to arrive
+}
+
+impl fmt::Display for DepthSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DepthSyncError::Gap { last, next } => {
+                write!(f, "update id gap (last {}, next {})", last, next)
+            }
+            DepthSyncError::BufferOverflow => write!(f, "event buffer overflow"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SyncState {
+    AwaitingSnapshot,
+    AwaitingBridge(u64), // Snapshot `lastUpdateId`
+    Synced(u64),         // Final update id of the last applied event
+}
+
+pub struct DepthSync<E> {
+    state: SyncState,
+    buffer: Vec<E>,
+    requests: u32, // Snapshots requested since the last one that applied
+}
+
+impl<E: DepthEvent> Default for DepthSync<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E: DepthEvent> DepthSync<E> {
+    pub fn new() -> Self {
+        Self {
+            state: SyncState::AwaitingSnapshot,
+            buffer: Vec::new(),
+            requests: 0,
+        }
+    }
+
+    /// Wait before requesting the next snapshot. The first request after an applied snapshot
+    /// goes out at once, the following ones back off from `SNAPSHOT_RETRY_DELAY`
+    pub fn next_snapshot_delay(&mut self) -> Duration {
+        let delay = match self.requests {
+            0 => Duration::ZERO,
+            n => SNAPSHOT_RETRY_DELAY
+                .saturating_mul(1 << (n - 1).min(16))
+                .min(MAX_SNAPSHOT_RETRY_DELAY),
+        };
+        self.requests = self.requests.saturating_add(1);
+        delay
+    }
+
+    /// Feed a live event. Returns the event if it can be applied to the book.
+    /// On error the sync is reset and the offending event is kept in the buffer,
+    /// the caller only has to fetch a new snapshot.
+    pub fn push(&mut self, event: E) -> Result<Option<E>, DepthSyncError> {
+        match self.state {
+            SyncState::AwaitingSnapshot => {
+                if self.buffer.len() >= MAX_BUFFERED_EVENTS {
+                    self.buffer.clear();
+                    return Err(DepthSyncError::BufferOverflow);
+                }
+                self.buffer.push(event);
+                Ok(None)
+            }
+            SyncState::AwaitingBridge(snapshot_id) => {
+                if event.is_before(snapshot_id) {
+                    return Ok(None);
+                }
+                if !event.bridges(snapshot_id) {
+                    return Err(self.restart(snapshot_id, event));
+                }
+                self.state = SyncState::Synced(event.final_update_id());
+                Ok(Some(event))
+            }
+            SyncState::Synced(last) => {
+                if event.follows(last) {
+                    self.state = SyncState::Synced(event.final_update_id());
+                    return Ok(Some(event));
+                }
+                if event.final_update_id() <= last {
+                    // Replayed event that is already in the book
+                    return Ok(None);
+                }
+                Err(self.restart(last, event))
+            }
+        }
+    }
+
+    /// Apply the REST snapshot. Returns the buffered events to apply on top of it, in order.
+    pub fn apply_snapshot(&mut self, snapshot_id: u64) -> Result<Vec<E>, DepthSyncError> {
+        self.state = SyncState::AwaitingBridge(snapshot_id);
+
+        let mut ready = Vec::new();
+        let mut buffered = std::mem::take(&mut self.buffer).into_iter();
+        while let Some(event) = buffered.next() {
+            match self.push(event) {
+                Ok(Some(event)) => ready.push(event),
+                Ok(None) => (),
+                Err(e) => {
+                    // Keep the rest. They are newer than the event that broke the chain
+                    self.buffer.extend(buffered);
+                    return Err(e);
+                }
+            }
+        }
+
+        self.requests = 0;
+        Ok(ready)
+    }
+
+    fn restart(&mut self, last: u64, event: E) -> DepthSyncError {
+        let next = event.final_update_id();
+        self.state = SyncState::AwaitingSnapshot;
+        self.buffer.clear();
+        self.buffer.push(event);
+        DepthSyncError::Gap { last, next }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Futures rules: `pu` chains events, the bridge covers `lastUpdateId`
+    #[derive(Debug, Clone, PartialEq)]
+    struct Event {
+        first: u64,
+        last: u64,
+        previous: u64,
+    }
+
+    fn event(first: u64, last: u64, previous: u64) -> Event {
+        Event {
+            first,
+            last,
+            previous,
+        }
+    }
+
+    impl DepthEvent for Event {
+        fn final_update_id(&self) -> u64 {
+            self.last
+        }
+
+        fn is_before(&self, snapshot_id: u64) -> bool {
+            self.last < snapshot_id
+        }
+
+        fn bridges(&self, snapshot_id: u64) -> bool {
+            self.first <= snapshot_id && self.last >= snapshot_id
+        }
+
+        fn follows(&self, last: u64) -> bool {
+            self.previous == last
+        }
+    }
+
+    #[test]
+    fn buffered_events_bridge_the_snapshot() {
+        let mut sync = DepthSync::new();
+        for e in [event(90, 95, 89), event(96, 105, 95), event(106, 110, 105)] {
+            assert!(matches!(sync.push(e), Ok(None)));
+        }
+
+        // The first event ends before the snapshot, the second covers it
+        let ready = sync.apply_snapshot(100).unwrap();
+        assert_eq!(ready, vec![event(96, 105, 95), event(106, 110, 105)]);
+
+        let next = event(111, 120, 110);
+        assert_eq!(sync.push(next.clone()).unwrap(), Some(next));
+    }
+
+    #[test]
+    fn bridge_arrives_after_the_snapshot() {
+        let mut sync = DepthSync::new();
+        assert!(sync.apply_snapshot(100).unwrap().is_empty());
+
+        assert_eq!(sync.push(event(90, 99, 89)).unwrap(), None);
+        let bridge = event(98, 102, 97);
+        assert_eq!(sync.push(bridge.clone()).unwrap(), Some(bridge));
+    }
+
+    #[test]
+    fn snapshot_newer_than_the_buffer_fails_to_bridge() {
+        let mut sync = DepthSync::new();
+        sync.push(event(101, 105, 100)).unwrap();
+        sync.push(event(106, 110, 105)).unwrap();
+
+        // Nothing covers update 120, the event after the snapshot is missing
+        assert!(matches!(
+            sync.apply_snapshot(120),
+            Ok(events) if events.is_empty()
+        ));
+        assert!(matches!(
+            sync.push(event(125, 130, 124)),
+            Err(DepthSyncError::Gap {
+                last: 120,
+                next: 130
+            })
+        ));
+    }
+
+    #[test]
+    fn gap_restarts_and_keeps_the_offending_event() {
+        let mut sync = DepthSync::new();
+        sync.push(event(95, 105, 94)).unwrap();
+        sync.apply_snapshot(100).unwrap();
+
+        // 106..110 was lost
+        assert!(matches!(
+            sync.push(event(111, 115, 110)),
+            Err(DepthSyncError::Gap {
+                last: 105,
+                next: 115
+            })
+        ));
+        // Events buffer until the next snapshot
+        assert_eq!(sync.push(event(116, 120, 115)).unwrap(), None);
+
+        let ready = sync.apply_snapshot(112).unwrap();
+        assert_eq!(ready, vec![event(111, 115, 110), event(116, 120, 115)]);
+    }
+
+    #[test]
+    fn stale_events_are_dropped_once_synced() {
+        let mut sync = DepthSync::new();
+        sync.push(event(95, 105, 94)).unwrap();
+        sync.apply_snapshot(100).unwrap();
+
+        assert_eq!(sync.push(event(95, 105, 94)).unwrap(), None);
+        let next = event(106, 110, 105);
+        assert_eq!(sync.push(next.clone()).unwrap(), Some(next));
+    }
+
+    #[test]
+    fn buffer_overflows_while_the_snapshot_is_in_flight() {
+        let mut sync = DepthSync::new();
+        for id in 0..MAX_BUFFERED_EVENTS as u64 {
+            sync.push(event(id, id, id.saturating_sub(1))).unwrap();
+        }
+        assert!(matches!(
+            sync.push(event(10_000, 10_000, 9_999)),
+            Err(DepthSyncError::BufferOverflow)
+        ));
+    }
+
+    #[test]
+    fn snapshot_requests_back_off_until_one_applies() {
+        let mut sync: DepthSync<Event> = DepthSync::new();
+        assert_eq!(sync.next_snapshot_delay(), Duration::ZERO);
+        assert_eq!(sync.next_snapshot_delay(), SNAPSHOT_RETRY_DELAY);
+        assert_eq!(sync.next_snapshot_delay(), SNAPSHOT_RETRY_DELAY * 2);
+        for _ in 0..40 {
+            sync.next_snapshot_delay();
+        }
+        assert_eq!(sync.next_snapshot_delay(), MAX_SNAPSHOT_RETRY_DELAY);
+
+        sync.apply_snapshot(100).unwrap();
+        assert_eq!(sync.next_snapshot_delay(), Duration::ZERO);
+    }
+}
@@ -1,13 +1,22 @@
-use crate::data::{depth::OrderbookUpdateStream, stream::StreamHandler};
+use crate::data::endpoints::Endpoint;
+use crate::data::fault::FaultInjector;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    depth::{
+        checksum::{ChecksumBook, CHECKSUM_DEPTH},
+        parse_levels, OrderbookUpdateKind, OrderbookUpdateStream,
+    },
+    health::StreamMonitor,
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
 use futures::{SinkExt, StreamExt};
 use log::{error, info};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::future::Future;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{self, Message},
-};
+use tokio_tungstenite::tungstenite::{self, Message};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(non_snake_case)]
@@ -42,112 +51,269 @@ pub struct DepthData {
     pub ts: String,                  // Timestamp as a string
 }
 
+/// Subscribe or unsubscribe message for `channel` of every symbol
+pub fn bitget_operation(op: &str, channel: &str, symbols: &[String]) -> String {
+    let args: Vec<BitgetStreamArg> = symbols
+        .iter()
+        .map(|symbol| BitgetStreamArg {
+            instType: "USDT-FUTURES".to_string(),
+            channel: channel.to_string(),
+            instId: symbol.to_uppercase(),
+        })
+        .collect();
+
+    serde_json::json!({ "op": op, "args": args }).to_string()
+}
+
+// Checksum state of one symbol's book
+struct BookState {
+    book: ChecksumBook, // Local copy of the book, only used to verify Bitget's checksum
+    last_update_id: u64,
+    awaiting_snapshot: bool,
+}
+
 pub struct BitgetFutureOrderbookStreamHandler {
-    pub symbol: String,
-    pub tx: mpsc::Sender<OrderbookUpdateStream>,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<OrderbookUpdateStream>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
 }
 
 impl StreamHandler for BitgetFutureOrderbookStreamHandler {
     fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
-        let symbol = self.symbol.clone();
+        let symbols = self.symbols.clone();
         let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let faults = self.faults.clone();
+        let recorder = self.recorder.clone();
 
         Box::new(Box::pin(async move {
-            let ws_url = "wss://ws.bitget.com/v2/ws/public";
-            let (ws_stream, _) = connect_async(ws_url).await?;
-            let (mut write, read) = ws_stream.split();
-
-            let subscription = serde_json::json!({
-                "op": "subscribe",
-                "args": [BitgetStreamArg{
-                    instType: "USDT-FUTURES".to_string(),
-                    channel: "books".to_string(),
-                    instId: symbol.clone()
-                }]
-            })
-            .to_string();
+            let (read, write) = recorder.connect(&endpoint.ws).await?;
+            let (read, mut write) = faults.wrap(read, write);
 
+            let subscription = bitget_operation("subscribe", "books", &symbols);
             write.send(Message::Text(subscription.into())).await?;
 
             // Create a new handler instance for the async block
-            let handler = BitgetFutureOrderbookStreamHandler { symbol, tx };
-            handler.handle_orderbook(read, write).await;
-
-            Ok(())
+            let handler = BitgetFutureOrderbookStreamHandler {
+                symbols,
+                tx,
+                endpoint,
+                monitor,
+                faults,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_orderbook(read, write).await
         }))
     }
 }
 
-#[allow(dead_code)]
 impl BitgetFutureOrderbookStreamHandler {
-    pub fn new(symbol: String, tx: mpsc::Sender<OrderbookUpdateStream>) -> Self {
-        Self { symbol, tx }
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<OrderbookUpdateStream>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            endpoint,
+            monitor,
+            faults: FaultInjector::disabled(),
+            recorder: FrameRecorder::disabled(),
+        }
     }
 
-    pub async fn handle_orderbook<R, S>(&self, mut read: R, mut write: S)
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Inject faults into the socket
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub async fn handle_orderbook<R, S>(
+        &self,
+        mut read: R,
+        mut write: S,
+    ) -> Result<(), tungstenite::Error>
     where
         R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
         S: SinkExt<Message> + Unpin,
         S::Error: std::fmt::Display,
     {
-        let mut last_update_id = 0;
-        while let Some(msg) = read.next().await {
-            match msg {
-                Ok(Message::Text(text)) => {
-                    match serde_json::from_str::<BitgetDepthMessage>(&text) {
-                        Ok(msg) => match msg.action.as_str() {
-                            "snapshot" => {
-                                last_update_id = msg.data[0].ts.clone().parse::<u64>().unwrap();
-                                let update = self.generate_orderbook_update(&msg);
-                                if self.tx.send(update).await.is_err() {
-                                    error!("Failed to send update");
+        let mut books: HashMap<String, BookState> = self
+            .symbols
+            .iter()
+            .map(|symbol| {
+                let state = BookState {
+                    book: ChecksumBook::new(),
+                    last_update_id: 0,
+                    awaiting_snapshot: true,
+                };
+                (symbol.to_uppercase(), state)
+            })
+            .collect();
+
+        // Bitget drops connections without a "ping" for 2 minutes
+        // https://www.bitget.com/api-doc/common/websocket-intro
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(30));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(Message::Text("ping".into())).await {
+                        error!("Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    let Some(msg) = msg else {
+                        break;
+                    };
+                    match msg {
+                        Ok(Message::Text(text)) if text.as_str() == "pong" => (),
+                        Ok(Message::Text(text)) => {
+                            let msg = match serde_json::from_str::<BitgetDepthMessage>(&text) {
+                                Ok(msg) => msg,
+                                Err(_) => {
+                                    match serde_json::from_str::<BitgetStreamConfirm>(&text) {
+                                        Ok(confirm) => info!("Bitget orderbook stream: {}", confirm.event),
+                                        Err(e) => error!("Failed to parse message: {}", e),
+                                    }
+                                    continue;
                                 }
-                            }
-                            "update" => {
-                                if msg.data[0].ts.clone().parse::<u64>().unwrap() <= last_update_id
-                                {
-                                    error!("Event out of order - reinitializing");
+                            };
+                            let symbol = &msg.arg.instId;
+                            let Some(state) = books.get_mut(symbol) else {
+                                continue;
+                            };
+                            let Some(depth) = msg.data.first() else {
+                                continue;
+                            };
+                            let Ok(ts) = depth.ts.parse::<u64>() else {
+                                error!("Bitget orderbook stream: Invalid timestamp {}", depth.ts);
+                                self.monitor.incr("parse_failures");
+                                continue;
+                            };
+
+                            match msg.action.as_str() {
+                                "snapshot" => {
+                                    state.book.clear();
+                                    state.awaiting_snapshot = false;
+                                }
+                                "update" if state.awaiting_snapshot => continue,
+                                "update" if ts < state.last_update_id => {
+                                    error!("Bitget orderbook stream: {} event out of order - resubscribing", symbol);
+                                    state.awaiting_snapshot = true;
+                                }
+                                "update" => (),
+                                _ => {
+                                    error!("Unknown action {}", msg.action);
                                     continue;
                                 }
-                                let update = self.generate_orderbook_update(&msg);
-                                if self.tx.send(update).await.is_err() {
-                                    error!("Failed to send update");
+                            }
+
+                            // Parse the levels before they reach the book, so that an applied message always goes out
+                            let levels = parse_levels(&depth.bids).zip(parse_levels(&depth.asks));
+                            if !state.awaiting_snapshot
+                                && (levels.is_none() || !state.book.apply(&depth.bids, &depth.asks))
+                            {
+                                error!("Bitget orderbook stream: {} invalid price level - resubscribing", symbol);
+                                self.monitor.incr("parse_failures");
+                                state.awaiting_snapshot = true;
+                            }
+
+                            if !state.awaiting_snapshot
+                                && state.book.checksum(CHECKSUM_DEPTH) != depth.checksum as i32
+                            {
+                                error!("Bitget orderbook stream: {} checksum mismatch - resubscribing", symbol);
+                                self.monitor.incr("checksum_failures");
+                                state.awaiting_snapshot = true;
+                            }
+
+                            if state.awaiting_snapshot {
+                                // Unsubscribe and subscribe again to receive a fresh snapshot
+                                state.book.clear();
+                                if let Err(e) = self.resubscribe(&mut write, symbol).await {
+                                    error!("Bitget orderbook stream: Failed to resubscribe: {}", e);
+                                    break;
                                 }
-                                last_update_id = msg.data[0].ts.clone().parse::<u64>().unwrap();
+                                continue;
                             }
-                            _ => error!("Unknown action {}", msg.action.clone()),
-                        },
-                        Err(_) => match serde_json::from_str::<BitgetStreamConfirm>(&text) {
-                            Ok(_) => {
-                                info!("Successfully subscribed");
+
+                            let Some((bids, asks)) = levels else {
+                                continue; // Resubscribed above
+                            };
+                            let update = self.generate_orderbook_update(&msg, bids, asks, ts);
+                            if self.tx.send(update).await.is_err() {
+                                error!("Failed to send update");
                             }
-                            Err(e) => {
-                                error!("Failed to parse message: {}", e);
+                            state.last_update_id = ts;
+                        }
+                        Ok(Message::Ping(payload)) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Failed to send Pong: {}", e);
                             }
-                        },
-                    }
-                }
-                Ok(Message::Ping(payload)) => {
-                    if let Err(e) = write.send(Message::Pong(payload)).await {
-                        error!("Failed to send Pong: {}", e);
+                        }
+                        Ok(Message::Pong(_)) => info!("Pong received"),
+                        Ok(Message::Close(_)) => {
+                            info!("Connection closed");
+                            break;
+                        }
+                        Ok(_) => (),
+                        Err(e) => {
+                            error!("WebSocket error: {}", e);
+                            return Err(e);
+                        }
                     }
                 }
-                Ok(Message::Pong(_)) => info!("Pong received"),
-                Ok(Message::Close(_)) => {
-                    info!("Connection closed");
-                    break;
-                }
-                _ => (),
             }
         }
+        Ok(())
+    }
+
+    async fn resubscribe<S>(&self, write: &mut S, symbol: &str) -> Result<(), S::Error>
+    where
+        S: SinkExt<Message> + Unpin,
+    {
+        let symbols = [symbol.to_string()];
+        let unsubscribe = bitget_operation("unsubscribe", "books", &symbols);
+        write.send(Message::Text(unsubscribe.into())).await?;
+
+        let subscribe = bitget_operation("subscribe", "books", &symbols);
+        write.send(Message::Text(subscribe.into())).await
     }
 
-    fn generate_orderbook_update(&self, update: &BitgetDepthMessage) -> OrderbookUpdateStream {
+    fn generate_orderbook_update(
+        &self,
+        update: &BitgetDepthMessage,
+        bids: Vec<(Decimal, Decimal)>,
+        asks: Vec<(Decimal, Decimal)>,
+        ts: u64,
+    ) -> OrderbookUpdateStream {
         OrderbookUpdateStream {
-            bids: update.data[0].bids.clone(),
-            asks: update.data[0].asks.clone(),
-            trade_time: update.data[0].ts.parse::<u64>().unwrap(),
-            event_time: update.data[0].ts.parse::<u64>().unwrap(),
+            symbol: update.arg.instId.clone(),
+            bids,
+            asks,
+            kind: match update.action.as_str() {
+                "snapshot" => OrderbookUpdateKind::Snapshot,
+                _ => OrderbookUpdateKind::Delta,
+            },
+            trade_time: ts,
+            event_time: ts,
+            update_id: None,
+            received: self.monitor.stamp(self.recorder.now_millis(), ts),
             last_update_exchange: "bitget".to_string(),
         }
     }
@@ -0,0 +1,462 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::fault::{FaultInjector, InjectedTimeout, REQUEST_TIMEOUT};
+use crate::data::latency::ReceiveStamp;
+use crate::data::recorder::{FrameRecorder, RestError};
+use crate::data::{
+    bithumb::{subscription_message, BithumbStatus, PING_INTERVAL_SECS},
+    depth::{OrderbookUpdateKind, OrderbookUpdateStream},
+    health::StreamMonitor,
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{stream::FuturesUnordered, SinkExt, StreamExt};
+use log::{error, info, warn};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::{self, Bytes, Message};
+
+// Bithumb orderbook rules
+// 1. `orderbookdepth` only sends changed levels with their new total quantity. Zero removes the level
+// 2. There is no snapshot on the socket and no sequence number. The REST book seeds it
+// 3. Changes older than the REST book are dropped
+// 4. The REST book stops at 30 levels while changes come for every depth. Below the top 30 the
+//    book only holds the levels that changed since it was seeded, it is partial there
+
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/* Bithumb Orderbook Snapshot */
+
+#[derive(Debug, Deserialize)]
+pub struct BithumbOrderbookResponse {
+    pub status: String, // "0000" on success
+    pub data: Option<BithumbOrderbookSnapshot>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BithumbOrderbookSnapshot {
+    pub timestamp: String, // Millis
+    pub bids: Vec<SnapshotLevel>,
+    pub asks: Vec<SnapshotLevel>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SnapshotLevel {
+    pub price: Decimal,
+    pub quantity: Decimal,
+}
+
+pub async fn fetch_orderbook_snapshot(
+    endpoint: &Endpoint,
+    symbol: &str,
+    recorder: &FrameRecorder,
+) -> Result<BithumbOrderbookResponse, RestError> {
+    let url = format!(
+        "{}/public/orderbook/{}?count=30", // 30 levels is the maximum
+        endpoint.rest,
+        symbol.to_uppercase()
+    );
+
+    let request = reqwest::Client::new()
+        .get(&url)
+        .timeout(std::time::Duration::from_secs(10));
+    let body = recorder.fetch(request).await?;
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Fetch a snapshot after `delay`, tagged with its symbol so that
+/// snapshots of several symbols can be awaited together
+async fn fetch_tagged_snapshot(
+    endpoint: Endpoint,
+    symbol: String,
+    delay: Duration,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
+) -> (
+    String,
+    Result<BithumbOrderbookResponse, Box<dyn std::error::Error + Send + Sync>>,
+) {
+    // A zero sleep still waits for the next timer tick
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    if faults.snapshot_timeout() {
+        tokio::time::sleep(REQUEST_TIMEOUT).await;
+        return (symbol, Err(Box::new(InjectedTimeout)));
+    }
+    let result = fetch_orderbook_snapshot(&endpoint, &symbol, &recorder)
+        .await
+        .map_err(Into::into);
+    (symbol, result)
+}
+
+/* Bithumb Orderbook Stream */
+
+#[derive(Debug, Deserialize)]
+pub struct BithumbWebsocketOrderbookDepth {
+    pub content: OrderbookDepthContent,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrderbookDepthContent {
+    pub list: Vec<DepthLevel>,
+    pub datetime: String, // Micros
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct DepthLevel {
+    pub symbol: String,
+    pub orderType: String, // "bid" or "ask"
+    pub price: Decimal,
+    pub quantity: Decimal, // Total quantity at the price
+}
+
+// Book of one symbol on this connection
+enum BookState {
+    Seeding(Vec<OrderbookUpdateStream>), // Changes received while the snapshot is in flight
+    Live { snapshot_time: u64 },
+}
+
+pub struct BithumbSpotOrderbookStreamHandler {
+    streams: String,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<OrderbookUpdateStream>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for BithumbSpotOrderbookStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let faults = self.faults.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let (read, write) = recorder.connect(&endpoint.ws).await?;
+            let (read, write) = faults.wrap(read, write);
+
+            let handler = BithumbSpotOrderbookStreamHandler {
+                symbols,
+                streams: "orderbookdepth".to_string(),
+                tx,
+                endpoint,
+                monitor,
+                faults,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_orderbook(read, write).await
+        }))
+    }
+}
+
+impl BithumbSpotOrderbookStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<OrderbookUpdateStream>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            streams: "orderbookdepth".to_string(),
+            tx,
+            endpoint,
+            monitor,
+            faults: FaultInjector::disabled(),
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Inject faults into the socket and the snapshots
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub async fn handle_orderbook<R, S>(
+        &self,
+        mut read: R,
+        mut write: S,
+    ) -> Result<(), tungstenite::Error>
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        // Subscribe first so that no change is lost while the snapshots are fetched
+        let subscribe_message = subscription_message(&self.streams, &self.symbols);
+        if let Err(e) = write.send(Message::Text(subscribe_message.into())).await {
+            error!(
+                "Bithumb orderbook stream: Failed to send subscription message: {}",
+                e
+            );
+            return Ok(());
+        }
+
+        let mut books: HashMap<String, BookState> = self
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.to_uppercase(), BookState::Seeding(Vec::new())))
+            .collect();
+        let mut snapshots = FuturesUnordered::new();
+        for symbol in books.keys() {
+            snapshots.push(fetch_tagged_snapshot(
+                self.endpoint.clone(),
+                symbol.clone(),
+                Duration::ZERO,
+                self.faults.clone(),
+                self.recorder.clone(),
+            ));
+        }
+
+        let mut interval = tokio::time::interval(Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(e) = write.send(Message::Ping(Bytes::from_static(&[]))).await {
+                        error!("Bithumb orderbook stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                Some((symbol, result)) = snapshots.next() => {
+                    let Some(state) = books.get_mut(&symbol) else {
+                        continue;
+                    };
+                    let snapshot = match result.map(|response| (response.status, response.data)) {
+                        Ok((_, Some(snapshot))) => snapshot,
+                        Ok((status, None)) => {
+                            error!("Bithumb orderbook stream: {} snapshot rejected ({})", symbol, status);
+                            self.monitor.incr("snapshot_failures");
+                            snapshots.push(fetch_tagged_snapshot(self.endpoint.clone(), symbol, SNAPSHOT_RETRY_DELAY, self.faults.clone(), self.recorder.clone()));
+                            continue;
+                        }
+                        Err(e) => {
+                            error!("Bithumb orderbook stream: Failed to fetch {} snapshot: {}", symbol, e);
+                            self.monitor.incr("snapshot_failures");
+                            // Rate limited or banned: the supervisor waits it out before reconnecting
+                            if let Some(ban) = e.downcast_ref::<RestError>().and_then(RestError::ban) {
+                                return Err(ban);
+                            }
+                            snapshots.push(fetch_tagged_snapshot(self.endpoint.clone(), symbol, SNAPSHOT_RETRY_DELAY, self.faults.clone(), self.recorder.clone()));
+                            continue;
+                        }
+                    };
+                    let Ok(snapshot_time) = snapshot.timestamp.parse::<u64>() else {
+                        error!("Bithumb orderbook stream: {} snapshot without timestamp", symbol);
+                        self.monitor.incr("parse_failures");
+                        snapshots.push(fetch_tagged_snapshot(self.endpoint.clone(), symbol, SNAPSHOT_RETRY_DELAY, self.faults.clone(), self.recorder.clone()));
+                        continue;
+                    };
+
+                    let reset = self.generate_snapshot_update(&symbol, &snapshot, snapshot_time);
+                    if self.tx.send(reset).await.is_err() {
+                        error!("Bithumb orderbook stream: Failed to send snapshot");
+                    }
+
+                    let pending = match std::mem::replace(state, BookState::Live { snapshot_time }) {
+                        BookState::Seeding(pending) => pending,
+                        BookState::Live { .. } => Vec::new(),
+                    };
+                    for update in pending.into_iter().filter(|update| update.event_time >= snapshot_time) {
+                        if self.tx.send(update).await.is_err() {
+                            error!("Bithumb orderbook stream: Failed to send update");
+                        }
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<BithumbWebsocketOrderbookDepth>(&text) {
+                                Ok(depth) => {
+                                    let Some(updates) = self.generate_orderbook_updates(&depth) else {
+                                        // Without a time the book would drop the changes
+                                        error!("Bithumb orderbook stream: Invalid datetime {}", depth.content.datetime);
+                                        self.monitor.incr("parse_failures");
+                                        continue;
+                                    };
+                                    for update in updates {
+                                        let Some(state) = books.get_mut(&update.symbol) else {
+                                            continue;
+                                        };
+                                        match state {
+                                            BookState::Seeding(pending) => pending.push(update),
+                                            BookState::Live { snapshot_time } if update.event_time < *snapshot_time => (),
+                                            BookState::Live { .. } => {
+                                                if self.tx.send(update).await.is_err() {
+                                                    error!("Bithumb orderbook stream: Failed to send update");
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                                Err(e) => match serde_json::from_str::<BithumbStatus>(&text) {
+                                    Ok(status) if status.status == "0000" => {
+                                        info!("Bithumb orderbook stream: {}", status.resmsg)
+                                    }
+                                    Ok(status) => warn!(
+                                        "Bithumb orderbook stream: {} ({})",
+                                        status.resmsg, status.status
+                                    ),
+                                    Err(_) => {
+                                        // A lost change stays in the book until the level changes again
+                                        error!("Bithumb orderbook stream: Failed to parse message: {}", e);
+                                        self.monitor.incr("parse_failures");
+                                    }
+                                },
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Bithumb orderbook stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Bithumb orderbook stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Bithumb orderbook stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("Bithumb orderbook stream: WebSocket error: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn generate_snapshot_update(
+        &self,
+        symbol: &str,
+        snapshot: &BithumbOrderbookSnapshot,
+        snapshot_time: u64,
+    ) -> OrderbookUpdateStream {
+        let levels = |levels: &[SnapshotLevel]| -> Vec<(Decimal, Decimal)> {
+            levels
+                .iter()
+                .map(|level| (level.price, level.quantity))
+                .collect()
+        };
+
+        OrderbookUpdateStream {
+            symbol: symbol.to_string(),
+            bids: levels(&snapshot.bids),
+            asks: levels(&snapshot.asks),
+            kind: OrderbookUpdateKind::Snapshot,
+            trade_time: snapshot_time,
+            event_time: snapshot_time,
+            update_id: None,
+            received: ReceiveStamp::local(self.recorder.now_millis()),
+            last_update_exchange: "Bithumb".to_string(),
+        }
+    }
+
+    /// One delta per symbol. A message may carry levels of several symbols.
+    /// None when the message has no valid time
+    fn generate_orderbook_updates(
+        &self,
+        update: &BithumbWebsocketOrderbookDepth,
+    ) -> Option<Vec<OrderbookUpdateStream>> {
+        // Micros to millis
+        let time = update.content.datetime.parse::<u64>().ok()? / 1000;
+        if time == 0 {
+            return None;
+        }
+
+        let mut updates: Vec<OrderbookUpdateStream> = Vec::new();
+        for level in update.content.list.iter() {
+            let index = match updates.iter().position(|u| u.symbol == level.symbol) {
+                Some(index) => index,
+                None => {
+                    updates.push(OrderbookUpdateStream {
+                        symbol: level.symbol.clone(),
+                        bids: Vec::new(),
+                        asks: Vec::new(),
+                        kind: OrderbookUpdateKind::Delta,
+                        trade_time: time,
+                        event_time: time,
+                        update_id: None,
+                        received: self.monitor.stamp(self.recorder.now_millis(), time),
+                        last_update_exchange: "Bithumb".to_string(),
+                    });
+                    updates.len() - 1
+                }
+            };
+
+            match level.orderType.as_str() {
+                "bid" => updates[index].bids.push((level.price, level.quantity)),
+                "ask" => updates[index].asks.push((level.price, level.quantity)),
+                _ => warn!(
+                    "Bithumb orderbook stream: Unknown order type {}",
+                    level.orderType
+                ),
+            }
+        }
+        Some(updates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::health::StreamHealth;
+
+    fn handler() -> BithumbSpotOrderbookStreamHandler {
+        BithumbSpotOrderbookStreamHandler::new(
+            vec!["BTC_KRW".to_string()],
+            SymbolRouter::default(),
+            Endpoint::new("ws://127.0.0.1:0", "http://127.0.0.1:0"),
+            StreamHealth::new().monitor("bithumb/krw/orderbook"),
+        )
+    }
+
+    fn depth(datetime: &str) -> BithumbWebsocketOrderbookDepth {
+        serde_json::from_value(serde_json::json!({
+            "content": {
+                "list": [
+                    { "symbol": "BTC_KRW", "orderType": "bid", "price": "100", "quantity": "0" },
+                    { "symbol": "BTC_KRW", "orderType": "ask", "price": "101", "quantity": "2" },
+                ],
+                "datetime": datetime,
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn stamps_the_message_time_in_millis() {
+        let updates = handler()
+            .generate_orderbook_updates(&depth("1700000000123456"))
+            .unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].event_time, 1_700_000_000_123);
+        assert_eq!(updates[0].bids, vec![(Decimal::from(100), Decimal::ZERO)]);
+    }
+
+    #[test]
+    fn rejects_a_message_without_a_valid_time() {
+        assert!(handler().generate_orderbook_updates(&depth("")).is_none());
+        assert!(handler().generate_orderbook_updates(&depth("0")).is_none());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::fault::FaultInjector;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    bybit::{ping_message, send_operation, BybitOpResponse, PING_INTERVAL_SECS},
+    depth::{OrderbookUpdateKind, OrderbookUpdateStream},
+    health::StreamMonitor,
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+// Bybit orderbook rules
+// https://bybit-exchange.github.io/docs/v5/websocket/public/orderbook
+//
+// 1. The first message after subscribing is a "snapshot". Every "snapshot" resets the book
+// 2. "delta" messages are merged. Zero size removes the level
+// 3. `u` increases by one with every message of the topic. `u` == 1 is a snapshot after a service restart
+// 4. `seq` never goes backwards
+// A broken chain is recovered by subscribing again, which pushes a fresh snapshot
+
+const ORDERBOOK_DEPTH: u32 = 50; // 20ms push frequency for linear contracts
+
+#[derive(Debug, Deserialize)]
+pub struct BybitDepthMessage {
+    #[serde(rename = "type")]
+    pub type_fields: String, // "snapshot" or "delta"
+    pub ts: u64,  // When the message was sent
+    pub cts: u64, // Matching engine time
+    pub data: DepthData,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DepthData {
+    pub s: String,                  // Symbol
+    pub b: Vec<(Decimal, Decimal)>, // Bids (price, size)
+    pub a: Vec<(Decimal, Decimal)>, // Asks (price, size)
+    pub u: u64,                     // Update ID
+    pub seq: u64,                   // Cross sequence
+}
+
+// Last applied message of a symbol. `None` while waiting for a snapshot
+#[derive(Debug, Clone, Copy)]
+struct BookSequence {
+    u: u64,
+    seq: u64,
+}
+
+pub struct BybitFutureOrderbookStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<OrderbookUpdateStream>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for BybitFutureOrderbookStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let faults = self.faults.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let (read, write) = recorder.connect(&endpoint.ws).await?;
+            let (read, mut write) = faults.wrap(read, write);
+
+            send_operation(&mut write, "subscribe", &topic(), &symbols).await?;
+
+            let handler = BybitFutureOrderbookStreamHandler {
+                symbols,
+                tx,
+                endpoint,
+                monitor,
+                faults,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_orderbook(read, write).await
+        }))
+    }
+}
+
+fn topic() -> String {
+    format!("orderbook.{}", ORDERBOOK_DEPTH)
+}
+
+impl BybitFutureOrderbookStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<OrderbookUpdateStream>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            endpoint,
+            monitor,
+            faults: FaultInjector::disabled(),
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Inject faults into the socket
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub async fn handle_orderbook<R, S>(
+        &self,
+        mut read: R,
+        mut write: S,
+    ) -> Result<(), tungstenite::Error>
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        let mut books: HashMap<String, Option<BookSequence>> = self
+            .symbols
+            .iter()
+            .map(|symbol| (symbol.to_uppercase(), None))
+            .collect();
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(ping_message()).await {
+                        error!("Bybit orderbook stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            let msg = match serde_json::from_str::<BybitDepthMessage>(&text) {
+                                Ok(msg) => msg,
+                                Err(e) => {
+                                    match serde_json::from_str::<BybitOpResponse>(&text) {
+                                        Ok(response) if response.success => (),
+                                        Ok(response) => error!("Bybit orderbook stream: {} failed: {}", response.op, response.ret_msg),
+                                        Err(_) => {
+                                            // The chain is checked on the next message
+                                            error!("Bybit orderbook stream: Failed to parse message: {}", e);
+                                            self.monitor.incr("parse_failures");
+                                        }
+                                    }
+                                    continue;
+                                }
+                            };
+                            let symbol = &msg.data.s;
+                            let Some(book) = books.get_mut(symbol) else {
+                                continue;
+                            };
+
+                            let kind = if msg.type_fields == "snapshot" || msg.data.u == 1 {
+                                OrderbookUpdateKind::Snapshot
+                            } else {
+                                match *book {
+                                    None => continue, // Waiting for a snapshot
+                                    Some(last) if msg.data.seq < last.seq => continue, // Stale
+                                    Some(last) if msg.data.u == last.u + 1 => OrderbookUpdateKind::Delta,
+                                    Some(last) => {
+                                        warn!(
+                                            "Bybit orderbook stream: {} update id gap (last {}, next {}) - resubscribing",
+                                            symbol, last.u, msg.data.u
+                                        );
+                                        self.monitor.incr("resyncs");
+                                        *book = None;
+                                        if let Err(e) = self.resubscribe(&mut write, symbol).await {
+                                            error!("Bybit orderbook stream: Failed to resubscribe: {}", e);
+                                            break;
+                                        }
+                                        continue;
+                                    }
+                                }
+                            };
+
+                            *book = Some(BookSequence {
+                                u: msg.data.u,
+                                seq: msg.data.seq,
+                            });
+                            let update = self.generate_orderbook_update(&msg, kind);
+                            if self.tx.send(update).await.is_err() {
+                                error!("Bybit orderbook stream: Failed to send update");
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Bybit orderbook stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Bybit orderbook stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Bybit orderbook stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("Bybit orderbook stream: WebSocket error: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn resubscribe<S>(&self, write: &mut S, symbol: &str) -> Result<(), S::Error>
+    where
+        S: SinkExt<Message> + Unpin,
+    {
+        let symbols = [symbol.to_string()];
+        send_operation(write, "unsubscribe", &topic(), &symbols).await?;
+        send_operation(write, "subscribe", &topic(), &symbols).await
+    }
+
+    fn generate_orderbook_update(
+        &self,
+        update: &BybitDepthMessage,
+        kind: OrderbookUpdateKind,
+    ) -> OrderbookUpdateStream {
+        OrderbookUpdateStream {
+            symbol: update.data.s.clone(),
+            bids: update.data.b.clone(),
+            asks: update.data.a.clone(),
+            kind,
+            trade_time: update.cts,
+            event_time: update.ts,
+            update_id: None,
+            received: self.monitor.stamp(self.recorder.now_millis(), update.ts),
+            last_update_exchange: "Bybit".to_string(),
+        }
+    }
+}
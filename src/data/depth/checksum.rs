@@ -0,0 +1,141 @@
+use rust_decimal::prelude::FromStr;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+// Local book kept inside a handler only to verify exchange checksums.
+// Levels keep the exact strings sent by the exchange, since the checksum
+// is computed over them ("0.50" and "0.5" give different results).
+//
+// Bitget and OKX use the same scheme over the top 25 levels:
+//   bid1_price:bid1_size:ask1_price:ask1_size:bid2_price:...
+// When one side runs out, the remaining levels of the other side are appended.
+// The CRC32 of that string is compared as a signed 32-bit integer.
+
+pub const CHECKSUM_DEPTH: usize = 25;
+
+#[derive(Debug, Default)]
+pub struct ChecksumBook {
+    bids: BTreeMap<Decimal, (String, String)>,
+    asks: BTreeMap<Decimal, (String, String)>,
+}
+
+impl ChecksumBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+
+    /// Merge levels into the book. Returns `false` if a price or size fails to parse.
+    pub fn apply(&mut self, bids: &[(String, String)], asks: &[(String, String)]) -> bool {
+        bids.iter()
+            .all(|(price, size)| Self::apply_level(&mut self.bids, price, size))
+            && asks
+                .iter()
+                .all(|(price, size)| Self::apply_level(&mut self.asks, price, size))
+    }
+
+    fn apply_level(
+        side: &mut BTreeMap<Decimal, (String, String)>,
+        price: &str,
+        size: &str,
+    ) -> bool {
+        let (Ok(key), Ok(volume)) = (Decimal::from_str(price), Decimal::from_str(size)) else {
+            return false;
+        };
+
+        if volume.is_zero() {
+            side.remove(&key);
+        } else {
+            side.insert(key, (price.to_string(), size.to_string()));
+        }
+        true
+    }
+
+    pub fn checksum(&self, depth: usize) -> i32 {
+        let mut bids = self.bids.values().rev().take(depth);
+        let mut asks = self.asks.values().take(depth);
+
+        let mut fields: Vec<&str> = Vec::with_capacity(depth * 4);
+        loop {
+            let (bid, ask) = (bids.next(), asks.next());
+            if bid.is_none() && ask.is_none() {
+                break;
+            }
+            for (price, size) in [bid, ask].into_iter().flatten() {
+                fields.push(price);
+                fields.push(size);
+            }
+        }
+
+        crc32fast::hash(fields.join(":").as_bytes()) as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(levels: &[(&str, &str)]) -> Vec<(String, String)> {
+        levels
+            .iter()
+            .map(|(price, size)| (price.to_string(), size.to_string()))
+            .collect()
+    }
+
+    // Examples of the OKX order book channel documentation
+    // https://www.okx.com/docs-v5/en/#order-book-trading-market-data-ws-order-book-channel
+
+    #[test]
+    fn interleaves_bids_and_asks() {
+        let mut book = ChecksumBook::new();
+        assert!(book.apply(
+            &levels(&[("3366.1", "7"), ("3366", "6")]),
+            &levels(&[("3366.8", "9"), ("3368", "8")]),
+        ));
+        // CRC32 of "3366.1:7:3366.8:9:3366:6:3368:8" as a signed integer
+        assert_eq!(book.checksum(CHECKSUM_DEPTH), -1881014294);
+    }
+
+    #[test]
+    fn appends_the_longer_side() {
+        let mut book = ChecksumBook::new();
+        assert!(book.apply(
+            &levels(&[("3366.1", "7")]),
+            &levels(&[("3366.8", "9"), ("3368", "8"), ("3372", "8")]),
+        ));
+        // CRC32 of "3366.1:7:3366.8:9:3368:8:3372:8"
+        assert_eq!(book.checksum(CHECKSUM_DEPTH), 831078360);
+    }
+
+    #[test]
+    fn updates_replace_and_remove_levels() {
+        let mut book = ChecksumBook::new();
+        book.apply(
+            &levels(&[("3366.1", "7"), ("3366", "6"), ("3365", "1")]),
+            &levels(&[("3366.8", "9"), ("3368", "8")]),
+        );
+        // Size 0 removes a level, the exact size string is kept
+        book.apply(&levels(&[("3365", "0"), ("3366", "6")]), &[]);
+        assert_eq!(book.checksum(CHECKSUM_DEPTH), -1881014294);
+    }
+
+    #[test]
+    fn keeps_the_strings_as_sent() {
+        let mut book = ChecksumBook::new();
+        book.apply(
+            &levels(&[("3366.10", "7")]),
+            &levels(&[("3366.8", "9"), ("3368", "8"), ("3372", "8")]),
+        );
+        assert_ne!(book.checksum(CHECKSUM_DEPTH), 831078360);
+    }
+
+    #[test]
+    fn rejects_malformed_levels() {
+        let mut book = ChecksumBook::new();
+        assert!(!book.apply(&levels(&[("3366.1", "seven")]), &[]));
+    }
+}
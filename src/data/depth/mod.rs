@@ -1,13 +1,53 @@
 pub mod binance;
+pub mod bitget;
+pub mod bithumb;
+pub mod bybit;
+pub mod checksum;
+pub mod okx;
 pub mod upbit;
 
+use crate::data::latency::ReceiveStamp;
+use rust_decimal::prelude::FromStr;
+use rust_decimal::Decimal;
+
+// How the levels of an `OrderbookUpdateStream` relate to the book downstream
+// - Binance: REST snapshot on (re)sync, then `@depth` diffs
+// - Upbit: Every message is a full top-N snapshot (both SNAPSHOT and REALTIME)
+// - Bithumb: REST snapshot on (re)connect, then `orderbookdepth` changes
+// - Bitget: `action` is "snapshot" on subscribe, "update" afterwards
+// - OKX: `action` is "snapshot" on subscribe, "update" afterwards
+// - Bybit: `type` is "snapshot" on subscribe (and after a service restart), "delta" afterwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderbookUpdateKind {
+    Snapshot, // Levels replace the whole book
+    Delta,    // Levels are merged into the book. Zero quantity removes the level
+}
+
+#[derive(Debug)]
 pub struct OrderbookUpdateStream {
-    pub bids: Vec<(String, String)>, // key: price, value: order id
-    pub asks: Vec<(String, String)>, // key: price, value: order id
+    pub symbol: String,
+    pub bids: Vec<(Decimal, Decimal)>, // (price, quantity)
+    pub asks: Vec<(Decimal, Decimal)>, // (price, quantity)
 
+    pub kind: OrderbookUpdateKind,
     pub trade_time: u64,
     pub event_time: u64,
+    pub update_id: Option<u64>, // Final exchange update id. Binance only, shared with bookTicker
     pub last_update_exchange: String,
+    pub received: ReceiveStamp,
+}
+
+/// Parse (price, quantity) levels sent as strings. `None` if any of them is malformed
+pub fn parse_levels(levels: &[(String, String)]) -> Option<Vec<(Decimal, Decimal)>> {
+    levels
+        .iter()
+        .map(|(price, quantity)| {
+            Some((
+                Decimal::from_str(price).ok()?,
+                Decimal::from_str(quantity).ok()?,
+            ))
+        })
+        .collect()
 }
 
 // #[allow(dead_code)]
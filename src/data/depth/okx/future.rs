@@ -0,0 +1,349 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::fault::FaultInjector;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    depth::{
+        checksum::{ChecksumBook, CHECKSUM_DEPTH},
+        parse_levels, OrderbookUpdateKind, OrderbookUpdateStream,
+    },
+    health::StreamMonitor,
+    okx::{fetch_contract_values, send_operation, OkxEvent, OkxStreamArg, PING_INTERVAL_SECS},
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+// OKX orderbook rules
+// https://www.okx.com/docs-v5/en/#order-book-trading-market-data-ws-order-book-channel
+//
+// 1. "snapshot" on subscribe (400 levels), "update" afterwards
+// 2. `prevSeqId` of an update equals the `seqId` of the previous message
+// 3. `checksum` covers the top 25 levels after applying the message (see `ChecksumBook`)
+// A broken chain or checksum is recovered by subscribing again
+
+#[derive(Debug, Deserialize)]
+pub struct OkxDepthMessage {
+    pub arg: OkxStreamArg,
+    pub action: String, // "snapshot" or "update"
+    pub data: Vec<DepthData>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct DepthData {
+    pub asks: Vec<Vec<String>>, // [price, size in contracts, deprecated, order count]
+    pub bids: Vec<Vec<String>>, // [price, size in contracts, deprecated, order count]
+    pub ts: String,
+    pub checksum: i64,
+    pub prevSeqId: i64, // -1 for snapshots
+    pub seqId: i64,
+}
+
+// Checksum state of one symbol's book
+struct BookState {
+    book: ChecksumBook,
+    last_seq_id: Option<i64>, // `None` while waiting for a snapshot
+}
+
+pub struct OkxFutureOrderbookStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<OrderbookUpdateStream>,
+    contract_values: HashMap<String, Decimal>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for OkxFutureOrderbookStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let faults = self.faults.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let contract_values = fetch_contract_values(&endpoint, &symbols, &recorder).await?;
+
+            let (read, write) = recorder.connect(&endpoint.ws).await?;
+            let (read, mut write) = faults.wrap(read, write);
+
+            let args = symbols
+                .iter()
+                .map(|symbol| OkxStreamArg::instrument("books", symbol))
+                .collect();
+            send_operation(&mut write, "subscribe", args).await?;
+
+            let handler = OkxFutureOrderbookStreamHandler {
+                symbols,
+                tx,
+                contract_values,
+                endpoint,
+                monitor,
+                faults,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_orderbook(read, write).await
+        }))
+    }
+}
+
+impl OkxFutureOrderbookStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<OrderbookUpdateStream>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            contract_values: HashMap::new(),
+            endpoint,
+            monitor,
+            faults: FaultInjector::disabled(),
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Inject faults into the socket
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub async fn handle_orderbook<R, S>(
+        &self,
+        mut read: R,
+        mut write: S,
+    ) -> Result<(), tungstenite::Error>
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        let mut books: HashMap<String, BookState> = self
+            .symbols
+            .iter()
+            .map(|symbol| {
+                let state = BookState {
+                    book: ChecksumBook::new(),
+                    last_seq_id: None,
+                };
+                (symbol.to_uppercase(), state)
+            })
+            .collect();
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(Message::Text("ping".into())).await {
+                        error!("OKX orderbook stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) if text.as_str() == "pong" => (),
+                        Some(Ok(Message::Text(text))) => {
+                            let msg = match serde_json::from_str::<OkxDepthMessage>(&text) {
+                                Ok(msg) => msg,
+                                Err(e) => {
+                                    match serde_json::from_str::<OkxEvent>(&text) {
+                                        Ok(event) if event.event == "error" => {
+                                            error!("OKX orderbook stream: {:?} {:?}", event.code, event.msg)
+                                        }
+                                        Ok(event) => info!("OKX orderbook stream: {}", event.event),
+                                        Err(_) => {
+                                            error!("OKX orderbook stream: Failed to parse message: {}", e);
+                                            self.monitor.incr("parse_failures");
+                                        }
+                                    }
+                                    continue;
+                                }
+                            };
+                            let Some(symbol) = msg.arg.instId.as_deref() else {
+                                continue;
+                            };
+                            let Some(state) = books.get_mut(symbol) else {
+                                continue;
+                            };
+                            let Some(depth) = msg.data.first() else {
+                                continue;
+                            };
+                            // Sizes are in contracts. Without the contract value the book cannot be kept
+                            let Some(&contract_value) = self.contract_values.get(symbol) else {
+                                error!("OKX orderbook stream: {} has no contract value", symbol);
+                                self.monitor.incr("parse_failures");
+                                continue;
+                            };
+
+                            match (msg.action.as_str(), state.last_seq_id) {
+                                ("snapshot", _) => state.book.clear(),
+                                ("update", None) => continue, // Waiting for a snapshot
+                                ("update", Some(last)) if depth.prevSeqId == last => (),
+                                ("update", Some(last)) => {
+                                    warn!(
+                                        "OKX orderbook stream: {} sequence gap (last {}, prev {}) - resubscribing",
+                                        symbol, last, depth.prevSeqId
+                                    );
+                                    self.monitor.incr("resyncs");
+                                    if let Err(e) = self.resubscribe(&mut write, state, symbol).await {
+                                        error!("OKX orderbook stream: Failed to resubscribe: {}", e);
+                                        break;
+                                    }
+                                    continue;
+                                }
+                                _ => {
+                                    error!("OKX orderbook stream: Unknown action {}", msg.action);
+                                    continue;
+                                }
+                            }
+
+                            // Parse everything before the book changes, so that an applied message always goes out
+                            let Some(parsed) = ParsedDepth::parse(depth) else {
+                                error!("OKX orderbook stream: {} malformed message - resubscribing", symbol);
+                                self.monitor.incr("parse_failures");
+                                if let Err(e) = self.resubscribe(&mut write, state, symbol).await {
+                                    error!("OKX orderbook stream: Failed to resubscribe: {}", e);
+                                    break;
+                                }
+                                continue;
+                            };
+
+                            let valid = state.book.apply(&parsed.raw_bids, &parsed.raw_asks)
+                                && state.book.checksum(CHECKSUM_DEPTH) == depth.checksum as i32;
+                            if !valid {
+                                error!("OKX orderbook stream: {} checksum mismatch - resubscribing", symbol);
+                                self.monitor.incr("checksum_failures");
+                                if let Err(e) = self.resubscribe(&mut write, state, symbol).await {
+                                    error!("OKX orderbook stream: Failed to resubscribe: {}", e);
+                                    break;
+                                }
+                                continue;
+                            }
+                            state.last_seq_id = Some(depth.seqId);
+
+                            let update = self.generate_orderbook_update(&msg, symbol, contract_value, parsed);
+                            if self.tx.send(update).await.is_err() {
+                                error!("OKX orderbook stream: Failed to send update");
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("OKX orderbook stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("OKX orderbook stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("OKX orderbook stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("OKX orderbook stream: WebSocket error: {}", e);
+                            return Err(e);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the local book and subscribe again to receive a fresh snapshot
+    async fn resubscribe<S>(
+        &self,
+        write: &mut S,
+        state: &mut BookState,
+        symbol: &str,
+    ) -> Result<(), S::Error>
+    where
+        S: SinkExt<Message> + Unpin,
+    {
+        state.book.clear();
+        state.last_seq_id = None;
+
+        let arg = OkxStreamArg::instrument("books", symbol);
+        send_operation(write, "unsubscribe", vec![arg.clone()]).await?;
+        send_operation(write, "subscribe", vec![arg]).await
+    }
+
+    fn generate_orderbook_update(
+        &self,
+        update: &OkxDepthMessage,
+        symbol: &str,
+        contract_value: Decimal,
+        depth: ParsedDepth,
+    ) -> OrderbookUpdateStream {
+        let to_coins = |levels: Vec<(Decimal, Decimal)>| -> Vec<(Decimal, Decimal)> {
+            levels
+                .into_iter()
+                .map(|(price, size)| (price, size * contract_value))
+                .collect()
+        };
+
+        OrderbookUpdateStream {
+            symbol: symbol.to_string(),
+            bids: to_coins(depth.bids),
+            asks: to_coins(depth.asks),
+            kind: match update.action.as_str() {
+                "snapshot" => OrderbookUpdateKind::Snapshot,
+                _ => OrderbookUpdateKind::Delta,
+            },
+            trade_time: depth.ts,
+            event_time: depth.ts,
+            update_id: None,
+            received: self.monitor.stamp(self.recorder.now_millis(), depth.ts),
+            last_update_exchange: "OKX".to_string(),
+        }
+    }
+}
+
+// Levels of a message as sent, for the checksum, and parsed, for the engine
+struct ParsedDepth {
+    raw_bids: Vec<(String, String)>,
+    raw_asks: Vec<(String, String)>,
+    bids: Vec<(Decimal, Decimal)>, // Sizes in contracts
+    asks: Vec<(Decimal, Decimal)>,
+    ts: u64,
+}
+
+impl ParsedDepth {
+    fn parse(depth: &DepthData) -> Option<Self> {
+        let raw_bids = price_levels(&depth.bids)?;
+        let raw_asks = price_levels(&depth.asks)?;
+        Some(Self {
+            bids: parse_levels(&raw_bids)?,
+            asks: parse_levels(&raw_asks)?,
+            ts: depth.ts.parse().ok()?,
+            raw_bids,
+            raw_asks,
+        })
+    }
+}
+
+// (price, size) of every raw level
+fn price_levels(levels: &[Vec<String>]) -> Option<Vec<(String, String)>> {
+    levels
+        .iter()
+        .map(|level| Some((level.first()?.clone(), level.get(1)?.clone())))
+        .collect()
+}
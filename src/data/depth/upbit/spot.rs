@@ -1,13 +1,19 @@
-use crate::data::{depth::OrderbookUpdateStream, stream::StreamHandler};
+use crate::data::endpoints::Endpoint;
+use crate::data::fault::FaultInjector;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    depth::{OrderbookUpdateKind, OrderbookUpdateStream},
+    health::StreamMonitor,
+    router::SymbolRouter,
+    stream::StreamHandler,
+    upbit::subscription_message,
+};
 use futures::{SinkExt, StreamExt};
 use log::{error, info};
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use std::future::Future;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{self, Bytes, Message},
-};
+use tokio_tungstenite::tungstenite::{self, Bytes, Message};
 
 /* Upbit Orderbook Stream */
 
@@ -16,10 +22,10 @@ use tokio_tungstenite::{
 pub struct UpbitWebsocketSpotOrderbook {
     #[serde(rename = "type")]
     pub type_fields: String, // orderbook
-    pub code: String,        // Currency pair
-    pub timestamp: u64,      // When the message was sent
-    pub total_ask_size: f32, // Total ask size
-    pub total_bid_size: f32, // Total bid size
+    pub code: String,            // Currency pair
+    pub timestamp: u64,          // When the message was sent
+    pub total_ask_size: Decimal, // Total ask size
+    pub total_bid_size: Decimal, // Total bid size
     pub orderbook_units: Vec<OrderbookUnit>,
     pub stream_type: String,
 }
@@ -27,65 +33,98 @@ pub struct UpbitWebsocketSpotOrderbook {
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct OrderbookUnit {
-    pub ask_price: f32,
-    pub ask_size: f32,
-    pub bid_price: f32,
-    pub bid_size: f32,
+    pub ask_price: Decimal,
+    pub ask_size: Decimal,
+    pub bid_price: Decimal,
+    pub bid_size: Decimal,
 }
 
 pub struct UpbitSpotOrderbookStreamHandler {
     streams: String,
-    pub symbol: String,
-    pub tx: mpsc::Sender<OrderbookUpdateStream>,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<OrderbookUpdateStream>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
 }
 
 impl StreamHandler for UpbitSpotOrderbookStreamHandler {
     fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
-        let symbol = self.symbol.clone();
+        let symbols = self.symbols.clone();
         let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let faults = self.faults.clone();
+        let recorder = self.recorder.clone();
 
         Box::new(Box::pin(async move {
-            let ws_url = "wss://api.upbit.com/websocket/v1".to_string();
-            let (ws_stream, _) = connect_async(ws_url).await?;
-            let (write, read) = ws_stream.split();
+            let (read, write) = recorder.connect(&endpoint.ws).await?;
+            let (read, write) = faults.wrap(read, write);
 
             let handler = UpbitSpotOrderbookStreamHandler {
-                symbol,
+                symbols,
                 streams: "orderbook".to_string(),
                 tx,
+                endpoint,
+                monitor,
+                faults,
+                recorder,
             };
 
-            handler.handle_orderbook(read, write).await;
+            handler.monitor.live();
 
-            Ok(())
+            handler.handle_orderbook(read, write).await
         }))
     }
 }
 
 impl UpbitSpotOrderbookStreamHandler {
-    pub fn new(symbol: String, tx: mpsc::Sender<OrderbookUpdateStream>) -> Self {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<OrderbookUpdateStream>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
         Self {
-            symbol,
+            symbols,
             streams: "orderbook".to_string(),
             tx,
+            endpoint,
+            monitor,
+            faults: FaultInjector::disabled(),
+            recorder: FrameRecorder::disabled(),
         }
     }
 
-    pub async fn handle_orderbook<R, S>(&self, mut read: R, mut write: S)
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Inject faults into the socket
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub async fn handle_orderbook<R, S>(
+        &self,
+        mut read: R,
+        mut write: S,
+    ) -> Result<(), tungstenite::Error>
     where
         R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
         S: SinkExt<Message> + Unpin,
         S::Error: std::fmt::Display,
     {
         // Send subscribe message
-        let subscribe_message = format!(
-            r#"[{{"ticket":"UNIQUE_TICKET"}},{{"type":{},"codes":["{}"]}}]"#,
-            self.streams, self.symbol
-        );
+        let subscribe_message = subscription_message(&self.streams, &self.symbols);
 
         if let Err(e) = write.send(Message::Text(subscribe_message.into())).await {
             error!("Failed to send subscription message: {}", e);
-            return;
+            return Ok(());
         }
 
         // Upbit requires a ping every 60 seconds
@@ -107,22 +146,16 @@ impl UpbitSpotOrderbookStreamHandler {
                         Some(Ok(Message::Binary(binary))) => {
                             match serde_json::from_slice::<UpbitWebsocketSpotOrderbook>(&binary) {
                                 Ok(orderbook) => {
-                                    // Snapshot handling
-                                    if orderbook.stream_type == "SNAPSHOT" {
-                                        // Send processed upbit update to tx
-                                        let update = self.generate_orderbook_update(&orderbook);
-                                        if let Err(e) = self.tx.send(update).await {
-                                            error!("Failed to send orderbook update: {}", e);
-                                        }
-                                    } else {
-                                        // Real time
-                                        let update = self.generate_orderbook_update(&orderbook);
-                                        if let Err(e) = self.tx.send(update).await {
-                                            error!("Failed to send orderbook update: {}", e);
-                                        }
+                                    // Both SNAPSHOT and REALTIME messages carry the full top-N book
+                                    let update = self.generate_orderbook_update(&orderbook);
+                                    if let Err(e) = self.tx.send(update).await {
+                                        error!("Failed to send orderbook update: {}", e);
                                     }
                                 }
-                                Err(e) => error!("Failed to parse binary orderbook data: {}", e),
+                                Err(e) => {
+                                    error!("Failed to parse binary orderbook data: {}", e);
+                                    self.monitor.incr("parse_failures");
+                                }
                             }
                         }
                         Some(Ok(Message::Ping(payload))) => {
@@ -135,11 +168,17 @@ impl UpbitSpotOrderbookStreamHandler {
                             info!("Upbit orderbook stream: Connection closed");
                             break;
                         }
-                        _ => (), // Ignore other message types
+                        Some(Ok(_)) => (), // Ignore other message types
+                        Some(Err(e)) => {
+                            error!("WebSocket error: {}", e);
+                            return Err(e);
+                        }
+                        None => break,
                     }
                 }
             }
         }
+        Ok(())
     }
 
     fn generate_orderbook_update(
@@ -147,18 +186,24 @@ impl UpbitSpotOrderbookStreamHandler {
         update: &UpbitWebsocketSpotOrderbook,
     ) -> OrderbookUpdateStream {
         OrderbookUpdateStream {
+            symbol: update.code.clone(),
             bids: update
                 .orderbook_units
                 .iter()
-                .map(|unit| (unit.bid_price.to_string(), unit.bid_size.to_string()))
+                .map(|unit| (unit.bid_price, unit.bid_size))
                 .collect(),
             asks: update
                 .orderbook_units
                 .iter()
-                .map(|unit| (unit.ask_price.to_string(), unit.ask_size.to_string()))
+                .map(|unit| (unit.ask_price, unit.ask_size))
                 .collect(),
+            kind: OrderbookUpdateKind::Snapshot,
             trade_time: update.timestamp,
             event_time: update.timestamp,
+            update_id: None,
+            received: self
+                .monitor
+                .stamp(self.recorder.now_millis(), update.timestamp),
             last_update_exchange: "Upbit".to_string(),
         }
     }
@@ -0,0 +1,82 @@
+// Base URLs of every venue. Overridable from the environment so that the whole pipeline
+// can run against a testnet or a local stand-in (`ws://` and `http://` work as well)
+
+/// WebSocket and REST base of one venue.
+/// Binance bases are hosts, paths such as `/stream` and `/fapi/v1` are appended.
+/// The other venues take the full WebSocket URL
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub ws: String,
+    pub rest: String,
+}
+
+impl Endpoint {
+    pub fn new(ws: &str, rest: &str) -> Self {
+        Self {
+            ws: ws.trim_end_matches('/').to_string(),
+            rest: rest.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BinanceEndpoints {
+    pub future: Endpoint, // USDⓈ-M
+    pub coin: Endpoint,   // COIN-M
+    pub spot: Endpoint,
+}
+
+impl BinanceEndpoints {
+    pub fn mainnet() -> Self {
+        Self {
+            future: Endpoint::new("wss://fstream.binance.com", "https://fapi.binance.com"),
+            coin: Endpoint::new("wss://dstream.binance.com", "https://dapi.binance.com"),
+            spot: Endpoint::new("wss://stream.binance.com:443", "https://api.binance.com"),
+        }
+    }
+
+    // https://developers.binance.com/docs/derivatives/usds-margined-futures/general-info
+    // https://developers.binance.com/docs/binance-spot-api-docs/testnet
+    pub fn testnet() -> Self {
+        Self {
+            future: Endpoint::new(
+                "wss://stream.binancefuture.com",
+                "https://testnet.binancefuture.com",
+            ),
+            coin: Endpoint::new(
+                "wss://dstream.binancefuture.com",
+                "https://testnet.binancefuture.com",
+            ),
+            spot: Endpoint::new(
+                "wss://stream.testnet.binance.vision",
+                "https://testnet.binance.vision",
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub binance: BinanceEndpoints,
+    pub upbit: Endpoint,
+    pub bithumb: Endpoint,
+    pub bitget: Endpoint,
+    pub bybit: Endpoint,
+    pub okx: Endpoint,
+}
+
+impl Endpoints {
+    pub fn mainnet() -> Self {
+        Self {
+            binance: BinanceEndpoints::mainnet(),
+            upbit: Endpoint::new("wss://api.upbit.com/websocket/v1", "https://api.upbit.com"),
+            bithumb: Endpoint::new("wss://pubwss.bithumb.com/pub/ws", "https://api.bithumb.com"),
+            bitget: Endpoint::new("wss://ws.bitget.com/v2/ws/public", "https://api.bitget.com"),
+            bybit: Endpoint::new(
+                "wss://stream.bybit.com/v5/public/linear",
+                "https://api.bybit.com",
+            ),
+            okx: Endpoint::new("wss://ws.okx.com:8443/ws/v5/public", "https://www.okx.com"),
+        }
+    }
+}
@@ -1,17 +1,86 @@
+use crate::channel::{FutureChannel, SpotChannel};
 use crate::data::{
-    depth::OrderbookUpdateStream, liquidation::LiquidationData, market::MarketData,
-    markprice::MarkPriceData,
+    bbo::BboData, depth::OrderbookUpdateStream, liquidation::LiquidationData, market::MarketData,
+    markprice::MarkPriceData, router::SymbolRouter, stats::FuturesStatsData,
 };
-use tokio::sync::mpsc;
 
+#[derive(Clone, Default)]
 pub struct FutureDataChannels {
-    pub ob_out: mpsc::Sender<OrderbookUpdateStream>,
-    pub agg_out: mpsc::Sender<MarketData>,
-    pub liq_out: mpsc::Sender<LiquidationData>,
-    pub mark_out: mpsc::Sender<MarkPriceData>,
+    pub ob_out: SymbolRouter<OrderbookUpdateStream>,
+    pub bbo_out: SymbolRouter<BboData>,
+    pub agg_out: SymbolRouter<MarketData>,
+    pub liq_out: SymbolRouter<LiquidationData>,
+    pub mark_out: SymbolRouter<MarkPriceData>,
+    pub stats_out: SymbolRouter<FuturesStatsData>, // REST pollers
 }
 
+#[derive(Clone, Default)]
 pub struct SpotDataChannels {
-    pub ob_out: mpsc::Sender<OrderbookUpdateStream>,
-    pub agg_out: mpsc::Sender<MarketData>,
+    pub ob_out: SymbolRouter<OrderbookUpdateStream>,
+    pub bbo_out: SymbolRouter<BboData>,
+    pub agg_out: SymbolRouter<MarketData>,
+}
+
+impl FutureDataChannels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route every message of `symbol` into the engine side of `channel`
+    pub fn add_route(&mut self, symbol: &str, channel: &FutureChannel) {
+        self.ob_out.insert(symbol, channel.ob.0.clone());
+        self.bbo_out.insert(symbol, channel.bbo.0.clone());
+        self.agg_out.insert(symbol, channel.agg.0.clone());
+        self.liq_out
+            .insert(symbol, channel.additional.liq.0.clone());
+        self.mark_out
+            .insert(symbol, channel.additional.mark.0.clone());
+        self.stats_out
+            .insert(symbol, channel.additional.stats.0.clone());
+    }
+
+    /// Whether every engine channel is empty
+    pub fn is_drained(&self) -> bool {
+        self.ob_out.is_drained()
+            && self.bbo_out.is_drained()
+            && self.agg_out.is_drained()
+            && self.liq_out.is_drained()
+            && self.mark_out.is_drained()
+            && self.stats_out.is_drained()
+    }
+
+    /// Wait until every engine channel is empty
+    pub async fn drained(&self) {
+        self.ob_out.drained().await;
+        self.bbo_out.drained().await;
+        self.agg_out.drained().await;
+        self.liq_out.drained().await;
+        self.mark_out.drained().await;
+        self.stats_out.drained().await;
+    }
+}
+
+impl SpotDataChannels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route every message of `symbol` into the engine side of `channel`
+    pub fn add_route(&mut self, symbol: &str, channel: &SpotChannel) {
+        self.ob_out.insert(symbol, channel.ob.0.clone());
+        self.bbo_out.insert(symbol, channel.bbo.0.clone());
+        self.agg_out.insert(symbol, channel.agg.0.clone());
+    }
+
+    /// Whether every engine channel is empty
+    pub fn is_drained(&self) -> bool {
+        self.ob_out.is_drained() && self.bbo_out.is_drained() && self.agg_out.is_drained()
+    }
+
+    /// Wait until every engine channel is empty
+    pub async fn drained(&self) {
+        self.ob_out.drained().await;
+        self.bbo_out.drained().await;
+        self.agg_out.drained().await;
+    }
 }
@@ -0,0 +1,409 @@
+use crate::data::health::StreamMonitor;
+use crate::util::fnv1a;
+use futures::{Sink, Stream, StreamExt};
+use log::warn;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Sleep;
+use tokio_tungstenite::tungstenite::{self, error::ProtocolError, Message};
+
+/* Fault Injection */
+
+// Failures seen in production, replayed on purpose to exercise reconnects and resyncs.
+// Every data frame (text or binary) read from a wrapped socket draws at most one fault:
+// - Drop: The frame is lost
+// - Duplicate: The frame is delivered twice
+// - Reorder: The frame is held back and delivered after the next one
+// - Stall: Nothing is read for `stall` (pings go unanswered too), then reading resumes
+// - Close: The socket is reset without a close handshake. Writes fail from then on
+// REST snapshots can time out as well. Control frames are never touched.
+// Each stream draws from its own generator, seeded with `seed` and the stream name,
+// so a run with the same seed and the same frames injects the same faults
+
+/// How long a timed out REST request hangs before failing, as the real client timeout
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct FaultConfig {
+    pub seed: u64,
+    pub streams: Vec<String>, // Stream name prefixes, e.g. "binance/future/orderbook". Empty: Every stream
+    // Probabilities per data frame
+    pub drop: f64,
+    pub duplicate: f64,
+    pub reorder: f64,
+    pub stall: f64,
+    pub close: f64,
+    // Probability per REST snapshot request
+    pub snapshot_timeout: f64,
+    pub stall_duration: Duration,
+}
+
+impl FaultConfig {
+    fn applies_to(&self, name: &str) -> bool {
+        self.streams.is_empty() || self.streams.iter().any(|prefix| name.starts_with(prefix))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    Drop,
+    Duplicate,
+    Reorder,
+    Stall,
+    Close,
+}
+
+struct FaultState {
+    config: FaultConfig,
+    rng: Mutex<StdRng>,
+    monitor: StreamMonitor, // Injected faults are counted in the stream health
+}
+
+/// Per-stream fault schedule. Disabled by default, in which case wrapping is a pass-through.
+/// Clones share the generator, so the schedule carries on across reconnects
+#[derive(Clone, Default)]
+pub struct FaultInjector {
+    state: Option<Arc<FaultState>>,
+}
+
+impl FaultInjector {
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Faults for the stream of `monitor`. Disabled if the config does not cover it
+    pub fn new(config: &FaultConfig, monitor: &StreamMonitor) -> Self {
+        if !config.applies_to(monitor.name()) {
+            return Self::disabled();
+        }
+        warn!("{}: Fault injection enabled ({:?})", monitor.name(), config);
+        let seed = config.seed ^ fnv1a(monitor.name());
+        Self {
+            state: Some(Arc::new(FaultState {
+                config: config.clone(),
+                rng: Mutex::new(StdRng::seed_from_u64(seed)),
+                monitor: monitor.clone(),
+            })),
+        }
+    }
+
+    /// Wrap the halves of a socket. Both share the injected close
+    pub fn wrap<R, S>(&self, read: R, write: S) -> (FaultyStream<R>, FaultySink<S>) {
+        let closed = Arc::new(AtomicBool::new(false));
+        let read = FaultyStream {
+            inner: read,
+            faults: self.clone(),
+            pending: VecDeque::new(),
+            held: None,
+            stall: None,
+            closed: closed.clone(),
+        };
+        let write = FaultySink {
+            inner: write,
+            closed,
+        };
+        (read, write)
+    }
+
+    /// Whether the next REST snapshot request times out
+    pub fn snapshot_timeout(&self) -> bool {
+        let Some(state) = &self.state else {
+            return false;
+        };
+        let timeout = state
+            .rng
+            .lock()
+            .unwrap()
+            .random_bool(state.config.snapshot_timeout);
+        if timeout {
+            state.monitor.incr("faults_snapshot_timeout");
+        }
+        timeout
+    }
+
+    fn next_fault(&self) -> Option<Fault> {
+        let state = self.state.as_ref()?;
+        let config = &state.config;
+        let draw: f64 = state.rng.lock().unwrap().random();
+
+        let mut threshold = 0.0;
+        for (fault, probability) in [
+            (Fault::Drop, config.drop),
+            (Fault::Duplicate, config.duplicate),
+            (Fault::Reorder, config.reorder),
+            (Fault::Stall, config.stall),
+            (Fault::Close, config.close),
+        ] {
+            threshold += probability;
+            if draw < threshold {
+                state.monitor.incr(match fault {
+                    Fault::Drop => "faults_drop",
+                    Fault::Duplicate => "faults_duplicate",
+                    Fault::Reorder => "faults_reorder",
+                    Fault::Stall => "faults_stall",
+                    Fault::Close => "faults_close",
+                });
+                return Some(fault);
+            }
+        }
+        None
+    }
+
+    fn stall_duration(&self) -> Duration {
+        self.state
+            .as_ref()
+            .map_or(Duration::ZERO, |state| state.config.stall_duration)
+    }
+}
+
+/// Stands in for a REST request that never answered
+#[derive(Debug)]
+pub struct InjectedTimeout;
+
+impl std::fmt::Display for InjectedTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "injected timeout after {:?}", REQUEST_TIMEOUT)
+    }
+}
+
+impl std::error::Error for InjectedTimeout {}
+
+/* Faulty Socket Halves */
+
+pub struct FaultyStream<R> {
+    inner: R,
+    faults: FaultInjector,
+    pending: VecDeque<Message>, // Ready to be delivered before reading on
+    held: Option<Message>,      // Reordered, delivered after the next frame
+    stall: Option<Pin<Box<Sleep>>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl<R> Stream for FaultyStream<R>
+where
+    R: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+{
+    type Item = Result<Message, tungstenite::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.closed.load(Ordering::Relaxed) {
+                return Poll::Ready(None);
+            }
+            if let Some(stall) = this.stall.as_mut() {
+                if stall.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.stall = None;
+            }
+            if let Some(msg) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(msg)));
+            }
+
+            let msg = match this.inner.poll_next_unpin(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(msg))) if msg.is_text() || msg.is_binary() => msg,
+                // The stream ended. Whatever was held back is still delivered
+                Poll::Ready(None) => return Poll::Ready(this.held.take().map(Ok)),
+                Poll::Ready(other) => return Poll::Ready(other),
+            };
+
+            match this.faults.next_fault() {
+                Some(Fault::Drop) => continue,
+                Some(Fault::Duplicate) => this.pending.push_back(msg.clone()),
+                Some(Fault::Reorder) if this.held.is_none() => {
+                    this.held = Some(msg);
+                    continue;
+                }
+                Some(Fault::Stall) => {
+                    this.pending.push_back(msg);
+                    this.stall = Some(Box::pin(tokio::time::sleep(this.faults.stall_duration())));
+                    continue;
+                }
+                Some(Fault::Close) => {
+                    this.closed.store(true, Ordering::Relaxed);
+                    return Poll::Ready(Some(Err(tungstenite::Error::Protocol(
+                        ProtocolError::ResetWithoutClosingHandshake,
+                    ))));
+                }
+                Some(Fault::Reorder) | None => (),
+            }
+            if let Some(held) = this.held.take() {
+                this.pending.push_back(held);
+            }
+            return Poll::Ready(Some(Ok(msg)));
+        }
+    }
+}
+
+pub struct FaultySink<S> {
+    inner: S,
+    closed: Arc<AtomicBool>,
+}
+
+impl<S> FaultySink<S> {
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+impl<S> Sink<Message> for FaultySink<S>
+where
+    S: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    type Error = tungstenite::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.is_closed() {
+            return Poll::Ready(Err(tungstenite::Error::AlreadyClosed));
+        }
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        if self.is_closed() {
+            return Err(tungstenite::Error::AlreadyClosed);
+        }
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.is_closed() {
+            return Poll::Ready(Err(tungstenite::Error::AlreadyClosed));
+        }
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::health::StreamHealth;
+    use futures::{sink, stream, SinkExt};
+    use std::convert::Infallible;
+
+    fn config(seed: u64) -> FaultConfig {
+        FaultConfig {
+            seed,
+            streams: Vec::new(),
+            drop: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            stall: 0.0,
+            close: 0.0,
+            snapshot_timeout: 0.0,
+            stall_duration: Duration::from_secs(5),
+        }
+    }
+
+    fn frames(count: usize) -> impl Stream<Item = Result<Message, tungstenite::Error>> + Unpin {
+        let texts: Vec<Message> = (0..count)
+            .map(|i| Message::Text(i.to_string().into()))
+            .collect();
+        stream::iter(texts.into_iter().map(Ok))
+    }
+
+    // Text of every frame read until the stream ends or fails
+    async fn read_all(config: &FaultConfig, count: usize) -> Vec<String> {
+        let monitor = StreamHealth::new().monitor("test/orderbook/0");
+        let faults = FaultInjector::new(config, &monitor);
+        let (mut read, _) = faults.wrap(frames(count), ());
+        let mut texts = Vec::new();
+        while let Some(Ok(msg)) = read.next().await {
+            texts.push(msg.into_text().unwrap().to_string());
+        }
+        texts
+    }
+
+    #[tokio::test]
+    async fn disabled_passes_every_frame_through() {
+        let (mut read, _) = FaultInjector::disabled().wrap(frames(3), ());
+        let mut texts = Vec::new();
+        while let Some(Ok(msg)) = read.next().await {
+            texts.push(msg.into_text().unwrap().to_string());
+        }
+        assert_eq!(texts, ["0", "1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn drop_loses_frames() {
+        let config = FaultConfig {
+            drop: 1.0,
+            ..config(0)
+        };
+        assert!(read_all(&config, 5).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn duplicate_delivers_frames_twice() {
+        let config = FaultConfig {
+            duplicate: 1.0,
+            ..config(0)
+        };
+        assert_eq!(read_all(&config, 2).await, ["0", "0", "1", "1"]);
+    }
+
+    #[tokio::test]
+    async fn reorder_swaps_with_the_next_frame() {
+        let config = FaultConfig {
+            reorder: 1.0,
+            ..config(0)
+        };
+        // A held frame goes out after the next one, the last one when the stream ends
+        assert_eq!(read_all(&config, 5).await, ["1", "0", "3", "2", "4"]);
+    }
+
+    #[tokio::test]
+    async fn close_fails_the_read_and_the_writes() {
+        let config = FaultConfig {
+            close: 1.0,
+            ..config(0)
+        };
+        let monitor = StreamHealth::new().monitor("test/orderbook/0");
+        let faults = FaultInjector::new(&config, &monitor);
+        let drain = sink::drain().sink_map_err(|never: Infallible| match never {});
+        let (mut read, mut write) = faults.wrap(frames(3), drain);
+
+        assert!(matches!(read.next().await, Some(Err(_))));
+        assert!(read.next().await.is_none());
+        assert!(write.send(Message::Text("ping".into())).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn same_seed_same_faults() {
+        let config = FaultConfig {
+            drop: 0.2,
+            duplicate: 0.2,
+            reorder: 0.2,
+            ..config(7)
+        };
+        let first = read_all(&config, 200).await;
+        assert_eq!(first, read_all(&config, 200).await);
+        assert_ne!(
+            first,
+            read_all(&FaultConfig { seed: 8, ..config }, 200).await
+        );
+    }
+
+    #[test]
+    fn covers_the_configured_streams_only() {
+        let config = FaultConfig {
+            streams: vec!["binance/future/orderbook".to_string()],
+            ..config(0)
+        };
+        assert!(config.applies_to("binance/future/orderbook/0"));
+        assert!(!config.applies_to("binance/spot/orderbook/0"));
+    }
+}
@@ -0,0 +1,291 @@
+use crate::data::latency::{ClockOffset, LatencyCounts, LatencyHistogram, ReceiveStamp};
+use log::info;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+/* Stream Health */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamState {
+    Connecting, // Socket is being opened (first attempt or after a backoff)
+    Live,       // Socket is open and data is flowing
+    Degraded,   // Socket dropped. Waiting for the next reconnect attempt
+    Failed,     // Retry budget is exhausted. The supervisor gave up
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamStatus {
+    pub state: StreamState,
+    pub last_error: Option<String>,
+    pub reconnect_count: u64,      // Total reconnects since startup
+    pub consecutive_failures: u32, // Failures since the stream last stayed up
+    pub last_change: u64,          // Unix millis of the last state change
+    pub counters: BTreeMap<&'static str, u64>, // Stream specific metrics (e.g. checksum failures)
+    pub latency: LatencyHistogram, // Exchange to local latency of the messages
+    pub uncorrected: LatencyHistogram, // Same while the clock offset is unknown. Skew included
+}
+
+impl StreamStatus {
+    fn new() -> Self {
+        Self {
+            state: StreamState::Connecting,
+            last_error: None,
+            reconnect_count: 0,
+            consecutive_failures: 0,
+            last_change: now_millis(),
+            counters: BTreeMap::new(),
+            latency: LatencyHistogram::default(),
+            uncorrected: LatencyHistogram::default(),
+        }
+    }
+}
+
+/// Shared registry of every supervised stream, keyed by stream name.
+/// Cheap to clone. Any part of the engine can hold one and query it.
+#[derive(Debug, Clone, Default)]
+pub struct StreamHealth {
+    inner: Arc<RwLock<HashMap<String, StreamStatus>>>,
+    offsets: Arc<RwLock<HashMap<String, ClockOffset>>>, // Exchange clock offset per venue
+}
+
+impl StreamHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a stream and return the handle used to report its state
+    pub fn monitor(&self, name: &str) -> StreamMonitor {
+        let status = StreamStatus::new();
+        let latency = status.latency.clone();
+        let uncorrected = status.uncorrected.clone();
+        self.inner.write().unwrap().insert(name.to_string(), status);
+
+        StreamMonitor {
+            name: name.to_string(),
+            health: self.clone(),
+            latency,
+            uncorrected,
+            offset: self.clock_offset(clock(name)),
+        }
+    }
+
+    /// Clock offset of an exchange clock, e.g. "binance/future" or "upbit".
+    /// Unknown until a clock sync estimates it
+    pub fn clock_offset(&self, clock: &str) -> ClockOffset {
+        self.offsets
+            .write()
+            .unwrap()
+            .entry(clock.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Log the latency of every exchange clock and stream that received messages
+    pub fn log_latency(&self) {
+        let streams = self.snapshot();
+        let corrected = |status: &StreamStatus| status.latency.counts();
+        let uncorrected = |status: &StreamStatus| status.uncorrected.counts();
+
+        for (name, counts) in per_clock(&streams, corrected) {
+            let offset = match self.clock_offset(&name).get() {
+                Some(offset) => format!("{} ms", offset),
+                None => "unknown".to_string(),
+            };
+            info!("Latency {}: {}, clock offset {}", name, counts, offset);
+        }
+        for (name, counts) in per_clock(&streams, uncorrected) {
+            info!("Uncorrected latency {}: {}", name, counts);
+        }
+        for (name, status) in streams.iter() {
+            for (title, counts) in [
+                ("Latency", corrected(status)),
+                ("Uncorrected latency", uncorrected(status)),
+            ] {
+                if counts.count() > 0 {
+                    info!("{} {}: {}", title, name, counts);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<StreamStatus> {
+        self.inner.read().unwrap().get(name).cloned()
+    }
+
+    pub fn snapshot(&self) -> Vec<(String, StreamStatus)> {
+        let mut streams: Vec<(String, StreamStatus)> = self
+            .inner
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, status)| (name.clone(), status.clone()))
+            .collect();
+        streams.sort_by(|a, b| a.0.cmp(&b.0));
+        streams
+    }
+
+    fn modify<F: FnOnce(&mut StreamStatus)>(&self, name: &str, f: F) {
+        if let Some(status) = self.inner.write().unwrap().get_mut(name) {
+            f(status);
+        }
+    }
+}
+
+/// Per-stream handle. Handlers report `live` once their socket is open,
+/// the supervisor reports everything else.
+#[derive(Debug, Clone)]
+pub struct StreamMonitor {
+    name: String,
+    health: StreamHealth,
+    latency: LatencyHistogram,
+    uncorrected: LatencyHistogram,
+    offset: ClockOffset, // Of the stream's exchange clock
+}
+
+impl StreamMonitor {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> StreamState {
+        self.health
+            .get(&self.name)
+            .map(|status| status.state)
+            .unwrap_or(StreamState::Failed)
+    }
+
+    pub fn connecting(&self) {
+        self.health.modify(&self.name, |status| {
+            status.state = StreamState::Connecting;
+            status.last_change = now_millis();
+        });
+    }
+
+    pub fn live(&self) {
+        self.health.modify(&self.name, |status| {
+            status.state = StreamState::Live;
+            status.last_change = now_millis();
+        });
+    }
+
+    /// The connection stayed up long enough to clear the failures before it
+    pub fn stable(&self) {
+        self.health.modify(&self.name, |status| {
+            status.consecutive_failures = 0;
+        });
+    }
+
+    /// Record a dropped connection. Returns the number of consecutive failures
+    pub fn degraded(&self, error: String) -> u32 {
+        let mut failures = 0;
+        self.health.modify(&self.name, |status| {
+            status.state = StreamState::Degraded;
+            status.last_error = Some(error);
+            status.reconnect_count += 1;
+            status.consecutive_failures += 1;
+            status.last_change = now_millis();
+            failures = status.consecutive_failures;
+        });
+        failures
+    }
+
+    /// Bump a stream specific counter
+    pub fn incr(&self, counter: &'static str) {
+        self.add(counter, 1);
+    }
+
+    pub fn add(&self, counter: &'static str, value: u64) {
+        self.health.modify(&self.name, |status| {
+            *status.counters.entry(counter).or_insert(0) += value;
+        });
+    }
+
+    /// Stamp a message received at `local_time` that the exchange sent at `exchange_time`
+    /// and record its latency
+    /// Messages received before the clock offset is known carry no latency, theirs goes into
+    /// the uncorrected histogram
+    pub fn stamp(&self, local_time: u64, exchange_time: u64) -> ReceiveStamp {
+        let raw = local_time as i64 - exchange_time as i64;
+        let Some(offset) = self.offset.get() else {
+            self.uncorrected.record(raw);
+            return ReceiveStamp::local(local_time);
+        };
+        let latency = raw + offset;
+        self.latency.record(latency);
+        ReceiveStamp {
+            local_time,
+            latency: Some(latency),
+        }
+    }
+
+    pub fn failed(&self) {
+        self.health.modify(&self.name, |status| {
+            status.state = StreamState::Failed;
+            status.last_change = now_millis();
+        });
+    }
+}
+
+// Stream names start with their venue, e.g. "binance/future/orderbook/0". Binance runs one
+// clock per market, the other venues one for all their streams
+fn clock(name: &str) -> &str {
+    let mut parts = name.splitn(3, '/');
+    match (parts.next(), parts.next()) {
+        (Some("binance"), Some(market)) => &name[..8 + market.len()],
+        (Some(venue), _) => venue,
+        _ => name,
+    }
+}
+
+// Latency of the streams that received messages, summed per exchange clock
+fn per_clock(
+    streams: &[(String, StreamStatus)],
+    counts: impl Fn(&StreamStatus) -> LatencyCounts,
+) -> BTreeMap<String, LatencyCounts> {
+    let mut clocks: BTreeMap<String, LatencyCounts> = BTreeMap::new();
+    for (name, status) in streams.iter() {
+        let counts = counts(status);
+        if counts.count() > 0 {
+            clocks
+                .entry(clock(name).to_string())
+                .or_default()
+                .add(&counts);
+        }
+    }
+    clocks
+}
+
+fn now_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uncorrected_latency_is_kept_apart() {
+        let health = StreamHealth::new();
+        let monitor = health.monitor("binance/future/orderbook/0");
+
+        // Offset unknown: 50 ms of which some is clock skew
+        assert_eq!(monitor.stamp(1_050, 1_000), ReceiveStamp::local(1_050));
+
+        health.clock_offset("binance/future").set(-30);
+        let stamp = monitor.stamp(2_050, 2_000);
+        assert_eq!(stamp.latency, Some(20));
+
+        let status = &health.snapshot()[0].1;
+        assert_eq!(status.uncorrected.counts().count(), 1);
+        assert_eq!(status.latency.counts().count(), 1);
+        assert_eq!(status.latency.counts().quantile(1.0), Some(20));
+    }
+
+    #[test]
+    fn binance_markets_run_their_own_clock() {
+        assert_eq!(clock("binance/future/orderbook/0"), "binance/future");
+        assert_eq!(clock("binance/spot/aggtrade/1"), "binance/spot");
+        assert_eq!(clock("upbit/krw/trade/0"), "upbit");
+        assert_eq!(clock("okx"), "okx");
+    }
+}
@@ -0,0 +1,379 @@
+use crate::data::endpoints::Endpoint;
+use log::{error, info};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/* Receive Stamps */
+
+/// When a message was received (unix millis, the capture's time on replay) and how long after
+/// the exchange sent it. The latency is corrected by the exchange clock offset, and left out
+/// until that offset is known
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReceiveStamp {
+    pub local_time: u64,
+    pub latency: Option<i64>, // Millis. None without an exchange time or clock offset
+}
+
+impl ReceiveStamp {
+    /// Received without an exchange send time to compare with
+    pub fn local(local_time: u64) -> Self {
+        Self {
+            local_time,
+            latency: None,
+        }
+    }
+}
+
+/* Latency Histogram */
+
+// Upper bounds of the buckets, in millis. One more bucket takes everything above
+const BUCKETS_MS: [i64; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+
+/// Latencies of one stream. Cheap to clone, recorded without locking
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    inner: Arc<Buckets>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    counts: [AtomicU64; BUCKETS_MS.len() + 1],
+    negative: AtomicU64, // Exchange time ahead of ours even after the offset correction
+    sum: AtomicI64,
+    max: AtomicI64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, latency: i64) {
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| latency <= bound)
+            .unwrap_or(BUCKETS_MS.len());
+        self.inner.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        if latency < 0 {
+            self.inner.negative.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.sum.fetch_add(latency, Ordering::Relaxed);
+        self.inner.max.fetch_max(latency, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> LatencyCounts {
+        LatencyCounts {
+            buckets: std::array::from_fn(|i| self.inner.counts[i].load(Ordering::Relaxed)),
+            negative: self.inner.negative.load(Ordering::Relaxed),
+            sum: self.inner.sum.load(Ordering::Relaxed),
+            max: self.inner.max.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Point in time copy of one or more histograms
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LatencyCounts {
+    buckets: [u64; BUCKETS_MS.len() + 1],
+    negative: u64,
+    sum: i64,
+    max: i64,
+}
+
+impl LatencyCounts {
+    /// Merge the counts of another stream, e.g. into the total of its exchange
+    pub fn add(&mut self, other: &LatencyCounts) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += count;
+        }
+        self.negative += other.negative;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile. None above the last bound
+    pub fn quantile(&self, q: f64) -> Option<i64> {
+        let rank = (self.count() as f64 * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return BUCKETS_MS.get(i).copied();
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for LatencyCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.count();
+        if count == 0 {
+            return write!(f, "no messages");
+        }
+        let bound = |q: f64| match self.quantile(q) {
+            Some(bound) => format!("<= {} ms", bound),
+            None => format!("> {} ms", BUCKETS_MS[BUCKETS_MS.len() - 1]),
+        };
+        write!(
+            f,
+            "{} messages, mean {} ms, p50 {}, p99 {}, max {} ms, {} negative",
+            count,
+            self.sum / count as i64,
+            bound(0.5),
+            bound(0.99),
+            self.max,
+            self.negative
+        )
+    }
+}
+
+/* Clock Offset */
+
+/// Offset of an exchange clock from ours: exchange time = local time + offset, in millis.
+/// Shared by the clock sync of the exchange and its stream monitors
+#[derive(Debug, Clone)]
+pub struct ClockOffset {
+    millis: Arc<AtomicI64>, // i64::MIN until the first estimate
+}
+
+impl Default for ClockOffset {
+    fn default() -> Self {
+        Self {
+            millis: Arc::new(AtomicI64::new(i64::MIN)),
+        }
+    }
+}
+
+impl ClockOffset {
+    pub fn get(&self) -> Option<i64> {
+        let millis = self.millis.load(Ordering::Relaxed);
+        (millis != i64::MIN).then_some(millis)
+    }
+
+    pub(crate) fn set(&self, millis: i64) {
+        self.millis.store(millis, Ordering::Relaxed);
+    }
+}
+
+/* Clock Sync */
+
+// Every sample bounds the offset between the send and the receive of its request (and the
+// second the `Date` header rounds down). The bounds of the recent samples are intersected,
+// the offset is the middle of what is left
+const SAMPLES_PER_ROUND: usize = 4;
+const SAMPLE_SPACING: Duration = Duration::from_millis(260); // Spreads Upbit samples over a second
+const SAMPLE_WINDOW: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerTime {
+    BinanceFuture, // `/fapi/v1/time`, millis
+    BinanceCoin,   // `/dapi/v1/time`, millis
+    BinanceSpot,   // `/api/v3/time`, millis
+    Upbit,         // `Date` header of a quotation request, seconds
+}
+
+impl ServerTime {
+    /// Exchange clock whose streams the offset corrects, the start of their names
+    pub fn clock(&self) -> &'static str {
+        match self {
+            ServerTime::BinanceFuture => "binance/future",
+            ServerTime::BinanceCoin => "binance/coin",
+            ServerTime::BinanceSpot => "binance/spot",
+            ServerTime::Upbit => "upbit",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            ServerTime::BinanceFuture => "Binance future",
+            ServerTime::BinanceCoin => "Binance coin",
+            ServerTime::BinanceSpot => "Binance spot",
+            ServerTime::Upbit => "Upbit",
+        }
+    }
+
+    fn url(&self, endpoint: &Endpoint) -> String {
+        match self {
+            // https://developers.binance.com/docs/derivatives/usds-margined-futures/market-data/rest-api/Check-Server-Time
+            ServerTime::BinanceFuture => format!("{}/fapi/v1/time", endpoint.rest),
+            // https://developers.binance.com/docs/derivatives/coin-margined-futures/market-data/rest-api/Check-Server-time
+            ServerTime::BinanceCoin => format!("{}/dapi/v1/time", endpoint.rest),
+            // https://developers.binance.com/docs/binance-spot-api-docs/rest-api/general-endpoints#check-server-time
+            ServerTime::BinanceSpot => format!("{}/api/v3/time", endpoint.rest),
+            // Upbit has no time endpoint. Any response carries the server time in `Date`
+            ServerTime::Upbit => format!("{}/v1/ticker?markets=KRW-BTC", endpoint.rest),
+        }
+    }
+
+    /// Server time of a response as (earliest, latest) unix millis
+    async fn parse(&self, response: reqwest::Response) -> Result<(i64, i64), String> {
+        match self {
+            ServerTime::BinanceFuture | ServerTime::BinanceCoin | ServerTime::BinanceSpot => {
+                #[allow(non_snake_case)]
+                #[derive(serde::Deserialize)]
+                struct Time {
+                    serverTime: i64,
+                }
+                let time = response.json::<Time>().await.map_err(|e| e.to_string())?;
+                Ok((time.serverTime, time.serverTime))
+            }
+            ServerTime::Upbit => {
+                let date = response
+                    .headers()
+                    .get(reqwest::header::DATE)
+                    .and_then(|date| date.to_str().ok())
+                    .ok_or("No Date header")?;
+                let date = chrono::DateTime::parse_from_rfc2822(date).map_err(|e| e.to_string())?;
+                let second = date.timestamp_millis();
+                Ok((second, second + 999))
+            }
+        }
+    }
+}
+
+/// Estimates the clock offset of an exchange every `interval`
+pub struct ClockSync {
+    source: ServerTime,
+    url: String,
+    interval: Duration,
+    offset: ClockOffset,
+    client: reqwest::Client,
+}
+
+impl ClockSync {
+    pub fn new(
+        source: ServerTime,
+        endpoint: &Endpoint,
+        interval: Duration,
+        offset: ClockOffset,
+    ) -> Self {
+        Self {
+            source,
+            url: source.url(endpoint),
+            interval,
+            offset,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // (lowest, highest) offset per sample
+        let mut bounds: VecDeque<(i64, i64)> = VecDeque::new();
+
+        loop {
+            interval.tick().await;
+
+            for _ in 0..SAMPLES_PER_ROUND {
+                match self.sample().await {
+                    Ok(sample) => {
+                        if bounds.len() == SAMPLE_WINDOW {
+                            bounds.pop_front();
+                        }
+                        bounds.push_back(sample);
+                    }
+                    Err(e) => error!("{} clock sync: Request failed: {}", self.source.title(), e),
+                }
+                tokio::time::sleep(SAMPLE_SPACING).await;
+            }
+
+            let Some(&latest) = bounds.back() else {
+                continue;
+            };
+            let (low, high) = bounds.iter().fold((i64::MIN, i64::MAX), |(low, high), b| {
+                (low.max(b.0), high.min(b.1))
+            });
+            let (low, high) = match low <= high {
+                true => (low, high),
+                false => {
+                    // The clocks stepped or drifted apart. Start over from the latest sample
+                    bounds.clear();
+                    bounds.push_back(latest);
+                    latest
+                }
+            };
+
+            let offset = low + (high - low) / 2;
+            if self.offset.get().is_none() {
+                info!(
+                    "{} clock sync: Offset {} ms (+/- {} ms)",
+                    self.source.title(),
+                    offset,
+                    (high - low) / 2
+                );
+            }
+            self.offset.set(offset);
+        }
+    }
+
+    async fn sample(&self) -> Result<(i64, i64), String> {
+        let sent = chrono::Utc::now().timestamp_millis();
+        let response = self
+            .client
+            .get(&self.url)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let received = chrono::Utc::now().timestamp_millis();
+        let (earliest, latest) = self.source.parse(response).await?;
+
+        // The server read its clock somewhere between our send and receive
+        Ok((earliest - received, latest - sent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(latencies: &[i64]) -> LatencyCounts {
+        let histogram = LatencyHistogram::default();
+        for &latency in latencies {
+            histogram.record(latency);
+        }
+        histogram.counts()
+    }
+
+    #[test]
+    fn quantile_is_the_bound_of_its_bucket() {
+        // Buckets <= 5, <= 10, <= 10, <= 50
+        let counts = counts(&[3, 7, 8, 40]);
+        assert_eq!(counts.quantile(0.25), Some(5));
+        assert_eq!(counts.quantile(0.5), Some(10));
+        assert_eq!(counts.quantile(0.75), Some(10));
+        assert_eq!(counts.quantile(1.0), Some(50));
+    }
+
+    #[test]
+    fn quantile_above_the_last_bound() {
+        let counts = counts(&[1, 6000]);
+        assert_eq!(counts.quantile(0.5), Some(1));
+        assert_eq!(counts.quantile(0.99), None);
+    }
+
+    #[test]
+    fn quantile_of_negative_latencies() {
+        let counts = counts(&[-20, -3, 2]);
+        assert_eq!(counts.quantile(0.0), Some(1));
+        assert_eq!(counts.quantile(0.5), Some(1));
+        assert_eq!(counts.quantile(1.0), Some(2));
+        assert_eq!(counts.negative, 2);
+    }
+
+    #[test]
+    fn added_counts_merge_the_buckets() {
+        let mut total = counts(&[1, 1, 1]);
+        total.add(&counts(&[300]));
+        assert_eq!(total.count(), 4);
+        assert_eq!(total.quantile(0.75), Some(1));
+        assert_eq!(total.quantile(1.0), Some(500));
+        assert_eq!(total.max, 300);
+    }
+}
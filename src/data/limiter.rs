@@ -0,0 +1,121 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Request weight budget of an exchange rate limit, shared by every request that draws from it:
+/// pollers, depth snapshots and trade backfills. Fixed windows, like the exchange counters
+/// themselves. `acquire` waits for the next window once the budget is spent.
+#[derive(Debug, Clone)]
+pub struct WeightLimiter {
+    limit: u32,
+    period: Duration,
+    window: Option<Arc<Mutex<(Instant, u32)>>>, // (window start, weight used). None: Unlimited
+}
+
+impl WeightLimiter {
+    pub fn new(limit: u32, period: Duration) -> Self {
+        Self {
+            limit: limit.max(1),
+            period,
+            window: Some(Arc::new(Mutex::new((Instant::now(), 0)))),
+        }
+    }
+
+    /// Never waits. Replays do not reach the exchange, and waiting would break their determinism
+    pub fn unlimited() -> Self {
+        Self {
+            limit: u32::MAX,
+            period: Duration::from_secs(60),
+            window: None,
+        }
+    }
+
+    pub async fn acquire(&self, weight: u32) {
+        let Some(window) = &self.window else {
+            return;
+        };
+        let weight = weight.min(self.limit);
+        loop {
+            let wait = {
+                let mut window = window.lock().unwrap();
+                let now = Instant::now();
+                if now >= window.0 + self.period {
+                    *window = (now, 0);
+                }
+                if window.1 + weight <= self.limit {
+                    window.1 += weight;
+                    return;
+                }
+                window.0 + self.period - now
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Weight `requests` of `weight` every `interval` would use per window
+    pub fn demand(&self, requests: usize, weight: u32, interval: Duration) -> u64 {
+        let per_window = self.period.as_secs_f64() / interval.as_secs_f64().max(0.001);
+        (requests as f64 * weight as f64 * per_window).ceil() as u64
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_the_next_window_once_spent() {
+        let limiter = WeightLimiter::new(50, Duration::from_secs(60));
+        let start = Instant::now();
+
+        limiter.acquire(20).await;
+        limiter.acquire(20).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // 40 + 20 is over the budget of 50
+        limiter.acquire(20).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clones_share_the_budget() {
+        let limiter = WeightLimiter::new(20, Duration::from_secs(60));
+        let other = limiter.clone();
+        let start = Instant::now();
+
+        limiter.acquire(20).await;
+        other.acquire(1).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caps_a_request_heavier_than_the_limit() {
+        let limiter = WeightLimiter::new(10, Duration::from_secs(60));
+        let start = Instant::now();
+
+        limiter.acquire(20).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited_never_waits() {
+        let limiter = WeightLimiter::unlimited();
+        let start = Instant::now();
+
+        for _ in 0..1000 {
+            limiter.acquire(u32::MAX).await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn demand_per_window() {
+        let limiter = WeightLimiter::new(2400, Duration::from_secs(60));
+        // 100 symbols of weight 1 every 10 seconds, 6 rounds a minute
+        assert_eq!(limiter.demand(100, 1, Duration::from_secs(10)), 600);
+    }
+}
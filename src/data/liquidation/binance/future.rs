@@ -1,14 +1,18 @@
-use crate::data::liquidation::LiquidationData;
+use crate::data::binance::{
+    combined_streams, fetch_contract_sizes, BinanceFutureMarket, ContractSizes,
+};
+use crate::data::endpoints::Endpoint;
+use crate::data::health::StreamMonitor;
+use crate::data::liquidation::{rolling::MarketLiquidations, LiquidationData};
+use crate::data::recorder::FrameRecorder;
+use crate::data::router::SymbolRouter;
 use crate::data::stream::StreamHandler;
 use futures::{SinkExt, StreamExt};
 use log::{error, info};
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use std::future::Future;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{self, Message},
-};
+use tokio_tungstenite::tungstenite::{self, Message};
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
@@ -28,44 +32,65 @@ pub struct FutureLiquidationEvent {
 #[allow(dead_code, non_snake_case)]
 #[derive(Debug, Deserialize)]
 pub struct FutureLiquidationOrder {
-    pub s: String,  // Symbol
-    pub S: String,  // Side
-    pub o: String,  // Order Type
-    pub f: String,  // Time in Force
-    pub q: String,  // Original Quantity
-    pub p: String,  // Price
-    pub ap: String, // Average Price
-    pub X: String,  // Order Status
-    pub l: String,  // Order Last Filled Quantity
-    pub z: String,  // Order Filled Accumulated Quantity
-    pub T: u64,     // Order Trade Time
+    pub s: String,   // Symbol
+    pub S: String,   // Side
+    pub o: String,   // Order Type
+    pub f: String,   // Time in Force
+    pub q: Decimal,  // Original Quantity
+    pub p: String,   // Price
+    pub ap: Decimal, // Average Price
+    pub X: String,   // Order Status
+    pub l: String,   // Order Last Filled Quantity
+    pub z: String,   // Order Filled Accumulated Quantity
+    pub T: u64,      // Order Trade Time
 }
 
 pub struct BinanceFutureLiquidationStreamHandler {
+    market: BinanceFutureMarket,
     pub streams: String,
-    pub symbol: String,
-    pub tx: mpsc::Sender<LiquidationData>,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<LiquidationData>,
+    contract_sizes: ContractSizes,
+    market_liquidations: Option<MarketLiquidations>, // All-market mode
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
 }
 
 impl StreamHandler for BinanceFutureLiquidationStreamHandler {
     fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
-        let symbol = self.symbol.clone();
+        let market = self.market;
+        let symbols = self.symbols.clone();
         let streams = self.streams.clone();
         let tx = self.tx.clone();
+        let market_liquidations = self.market_liquidations.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
 
         Box::new(Box::pin(async move {
-            let ws_url = format!(
-                "wss://fstream.binance.com/stream?streams={}@{}",
-                symbol, streams
-            );
-            let (ws_stream, _) = connect_async(&ws_url).await?;
-            let (write, read) = ws_stream.split();
+            let contract_sizes =
+                fetch_contract_sizes(market, &endpoint, &recorder, &monitor).await?;
+
+            let path = match market_liquidations {
+                Some(_) => streams.clone(),
+                None => combined_streams(&symbols, &streams),
+            };
+            let ws_url = format!("{}?streams={}", market.ws_url(&endpoint), path);
+            let (read, write) = recorder.connect(&ws_url).await?;
 
             let handler = BinanceFutureLiquidationStreamHandler {
-                symbol,
+                market,
+                symbols,
                 streams,
                 tx,
+                contract_sizes,
+                market_liquidations,
+                endpoint,
+                monitor,
+                recorder,
             };
+            handler.monitor.live();
             handler.handle_liquidation(read, write).await;
 
             Ok(())
@@ -74,14 +99,41 @@ impl StreamHandler for BinanceFutureLiquidationStreamHandler {
 }
 
 impl BinanceFutureLiquidationStreamHandler {
-    pub fn new(symbol: String, tx: mpsc::Sender<LiquidationData>) -> Self {
+    pub fn new(
+        market: BinanceFutureMarket,
+        symbols: Vec<String>,
+        tx: SymbolRouter<LiquidationData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
         Self {
-            symbol,
+            market,
+            symbols,
             streams: "forceOrder".to_string(),
             tx,
+            contract_sizes: ContractSizes::default(),
+            market_liquidations: None,
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
         }
     }
 
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Consume the liquidations of every symbol (`!forceOrder@arr`) and record them
+    /// into `market_liquidations`. Only `symbols` are routed to their cores.
+    /// Binance pushes at most one liquidation per symbol every 1000ms
+    pub fn all_market(mut self, market_liquidations: MarketLiquidations) -> Self {
+        self.streams = "!forceOrder@arr".to_string();
+        self.market_liquidations = Some(market_liquidations);
+        self
+    }
+
     pub async fn handle_liquidation<R, S>(&self, mut read: R, mut write: S)
     where
         R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
@@ -94,12 +146,23 @@ impl BinanceFutureLiquidationStreamHandler {
                     match serde_json::from_str::<BinanceWebsocketFutureLiquidation>(&text) {
                         Ok(liquidation) => {
                             let update = self.generate_liquidation_update(&liquidation);
+                            if let Some(market_liquidations) = &self.market_liquidations {
+                                market_liquidations.record(&update);
+                                if !self
+                                    .symbols
+                                    .iter()
+                                    .any(|symbol| symbol.eq_ignore_ascii_case(&update.symbol))
+                                {
+                                    continue;
+                                }
+                            }
                             if self.tx.send(update).await.is_err() {
                                 error!("Binance liquidation stream: Failed to send update");
                             }
                         }
                         Err(e) => {
                             error!("Binance liquidation stream: Failed to parse message: {}", e);
+                            self.monitor.incr("parse_failures");
                         }
                     }
                 }
@@ -122,12 +185,17 @@ impl BinanceFutureLiquidationStreamHandler {
         &self,
         update: &BinanceWebsocketFutureLiquidation,
     ) -> LiquidationData {
+        let order = &update.data.o;
         LiquidationData {
-            side: update.data.o.S.clone(),
-            avg_price: update.data.o.ap.clone(),
-            quantity: update.data.o.q.clone(),
-            trade_time: update.data.o.T,
+            symbol: order.s.clone(),
+            side: order.S.clone(),
+            avg_price: order.ap,
+            quantity: self.contract_sizes.to_coins(&order.s, order.ap, order.q),
+            trade_time: order.T,
             event_time: update.data.E,
+            received: self
+                .monitor
+                .stamp(self.recorder.now_millis(), update.data.E),
         }
     }
 }
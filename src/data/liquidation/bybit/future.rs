@@ -0,0 +1,176 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    bybit::{ping_message, send_operation, BybitOpResponse, PING_INTERVAL_SECS},
+    health::StreamMonitor,
+    liquidation::LiquidationData,
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/* Bybit Liquidation Stream */
+
+// The legacy `liquidation` topic pushed at most one order per second and is deprecated.
+// `allLiquidation` pushes every liquidation of the symbol.
+// https://bybit-exchange.github.io/docs/v5/websocket/public/all-liquidation
+
+#[derive(Debug, Deserialize)]
+pub struct BybitLiquidationMessage {
+    pub ts: u64, // When the message was sent
+    pub data: Vec<LiquidationEvent>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct LiquidationEvent {
+    pub T: u64,     // Update time
+    pub s: String,  // Symbol
+    pub S: String,  // Position side. "Buy": a long position was liquidated
+    pub v: Decimal, // Executed size
+    pub p: Decimal, // Bankruptcy price
+}
+
+pub struct BybitFutureLiquidationStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<LiquidationData>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for BybitFutureLiquidationStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let (read, mut write) = recorder.connect(&endpoint.ws).await?;
+
+            send_operation(&mut write, "subscribe", "allLiquidation", &symbols).await?;
+
+            let handler = BybitFutureLiquidationStreamHandler {
+                symbols,
+                tx,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_liquidation(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+impl BybitFutureLiquidationStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<LiquidationData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_liquidation<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(ping_message()).await {
+                        error!("Bybit liquidation stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<BybitLiquidationMessage>(&text) {
+                                Ok(liquidation) => {
+                                    for data in liquidation.data.iter() {
+                                        let update = self.generate_liquidation_update(data, liquidation.ts);
+                                        if self.tx.send(update).await.is_err() {
+                                            error!("Bybit liquidation stream: Failed to send update");
+                                        }
+                                    }
+                                }
+                                Err(e) => match serde_json::from_str::<BybitOpResponse>(&text) {
+                                    Ok(response) if response.success => (),
+                                    Ok(response) => error!("Bybit liquidation stream: {} failed: {}", response.op, response.ret_msg),
+                                    Err(_) => {
+                                        error!("Bybit liquidation stream: Failed to parse message: {}", e);
+                                        self.monitor.incr("parse_failures");
+                                    }
+                                },
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Bybit liquidation stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Bybit liquidation stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Bybit liquidation stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("Bybit liquidation stream: WebSocket error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_liquidation_update(
+        &self,
+        update: &LiquidationEvent,
+        event_time: u64,
+    ) -> LiquidationData {
+        LiquidationData {
+            symbol: update.s.clone(),
+            // Side of the liquidation order, like Binance: a long is closed by a SELL
+            side: match update.S.as_str() {
+                "Buy" => "SELL".to_string(),
+                _ => "BUY".to_string(),
+            },
+            avg_price: update.p,
+            quantity: update.v,
+            trade_time: update.T,
+            event_time,
+            received: self.monitor.stamp(self.recorder.now_millis(), event_time),
+        }
+    }
+}
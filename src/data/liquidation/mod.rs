@@ -1,10 +1,18 @@
 pub mod binance;
+pub mod bybit;
+pub mod okx;
+pub mod rolling;
+
+use crate::data::latency::ReceiveStamp;
+use rust_decimal::Decimal;
 
 #[derive(Debug)]
 pub struct LiquidationData {
+    pub symbol: String,
     pub side: String,
-    pub avg_price: String,
-    pub quantity: String,
+    pub avg_price: Decimal,
+    pub quantity: Decimal,
     pub trade_time: u64,
     pub event_time: u64,
+    pub received: ReceiveStamp,
 }
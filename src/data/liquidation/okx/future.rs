@@ -0,0 +1,201 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    health::StreamMonitor,
+    liquidation::LiquidationData,
+    okx::{fetch_contract_values, send_operation, OkxEvent, OkxStreamArg, PING_INTERVAL_SECS},
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/* OKX Liquidation Stream */
+
+// `liquidation-orders` is published for a whole instrument type. Every SWAP liquidation
+// is received and the ones outside of our symbols are dropped.
+
+#[derive(Debug, Deserialize)]
+pub struct OkxLiquidationMessage {
+    pub data: Vec<LiquidationOrder>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct LiquidationOrder {
+    pub instId: String,
+    pub details: Vec<LiquidationDetail>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct LiquidationDetail {
+    pub side: String, // Side of the liquidation order: "buy" or "sell"
+    pub bkPx: String, // Bankruptcy price
+    pub sz: String,   // Quantity in contracts
+    pub ts: String,   // Liquidation time
+}
+
+pub struct OkxFutureLiquidationStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<LiquidationData>,
+    contract_values: HashMap<String, Decimal>, // Also the set of symbols to keep
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for OkxFutureLiquidationStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let contract_values = fetch_contract_values(&endpoint, &symbols, &recorder).await?;
+
+            let (read, mut write) = recorder.connect(&endpoint.ws).await?;
+
+            let arg = OkxStreamArg {
+                channel: "liquidation-orders".to_string(),
+                instId: None,
+                instType: Some("SWAP".to_string()),
+            };
+            send_operation(&mut write, "subscribe", vec![arg]).await?;
+
+            let handler = OkxFutureLiquidationStreamHandler {
+                symbols,
+                tx,
+                contract_values,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_liquidation(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+impl OkxFutureLiquidationStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<LiquidationData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            contract_values: HashMap::new(),
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_liquidation<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(Message::Text("ping".into())).await {
+                        error!("OKX liquidation stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) if text.as_str() == "pong" => (),
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<OkxLiquidationMessage>(&text) {
+                                Ok(liquidation) => {
+                                    for order in liquidation.data.iter() {
+                                        let Some(contract_value) = self.contract_values.get(&order.instId) else {
+                                            continue; // Not one of our symbols
+                                        };
+                                        for detail in order.details.iter() {
+                                            let Some(update) = self.generate_liquidation_update(order, detail, *contract_value) else {
+                                                error!("OKX liquidation stream: Invalid value in {:?}", detail);
+                                                self.monitor.incr("parse_failures");
+                                                continue;
+                                            };
+                                            if self.tx.send(update).await.is_err() {
+                                                error!("OKX liquidation stream: Failed to send update");
+                                            }
+                                        }
+                                    }
+                                }
+                                Err(e) => match serde_json::from_str::<OkxEvent>(&text) {
+                                    Ok(event) if event.event == "error" => {
+                                        error!("OKX liquidation stream: {:?} {:?}", event.code, event.msg)
+                                    }
+                                    Ok(event) => info!("OKX liquidation stream: {}", event.event),
+                                    Err(_) => {
+                                        error!("OKX liquidation stream: Failed to parse message: {}", e);
+                                        self.monitor.incr("parse_failures");
+                                    }
+                                },
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("OKX liquidation stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("OKX liquidation stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("OKX liquidation stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("OKX liquidation stream: WebSocket error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_liquidation_update(
+        &self,
+        order: &LiquidationOrder,
+        detail: &LiquidationDetail,
+        contract_value: Decimal,
+    ) -> Option<LiquidationData> {
+        let ts = detail.ts.parse::<u64>().ok()?;
+        Some(LiquidationData {
+            symbol: order.instId.clone(),
+            side: detail.side.to_uppercase(),
+            avg_price: detail.bkPx.parse::<Decimal>().ok()?,
+            quantity: detail.sz.parse::<Decimal>().ok()? * contract_value,
+            trade_time: ts,
+            event_time: ts,
+            received: self.monitor.stamp(self.recorder.now_millis(), ts),
+        })
+    }
+}
@@ -0,0 +1,180 @@
+use crate::data::liquidation::LiquidationData;
+use rust_decimal::Decimal;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Liquidations of every symbol of a venue over a rolling window.
+// Cascades usually start in other coins, so cores read the market-wide figures next to their own.
+
+/// Liquidated notional (price * quantity) by order side
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LiquidationNotional {
+    pub buy: Decimal,  // Shorts liquidated
+    pub sell: Decimal, // Longs liquidated
+}
+
+impl LiquidationNotional {
+    fn add(&mut self, side: &str, notional: Decimal) {
+        match side {
+            "BUY" => self.buy += notional,
+            _ => self.sell += notional,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Liquidation {
+    symbol: String,
+    side: String,
+    notional: Decimal,
+    time: u64,
+}
+
+/// Market-wide liquidations, cheap to clone and shared by the stream and the cores.
+/// Times are exchange millis, so the window follows the feed rather than the local clock.
+/// Only the feed prunes. Cores read at their own event time, which may trail the feed
+#[derive(Debug, Clone)]
+pub struct MarketLiquidations {
+    window: u64,                              // Millis
+    inner: Arc<Mutex<VecDeque<Liquidation>>>, // In arrival order
+}
+
+impl MarketLiquidations {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: window.as_millis() as u64,
+            inner: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn record(&self, liquidation: &LiquidationData) {
+        let mut liquidations = self.inner.lock().unwrap();
+        liquidations.push_back(Liquidation {
+            symbol: liquidation.symbol.clone(),
+            side: liquidation.side.clone(),
+            notional: liquidation.avg_price * liquidation.quantity,
+            time: liquidation.event_time,
+        });
+
+        // Whatever is older than the window of the latest event is of no use to any reader
+        let from = liquidation.event_time.saturating_sub(self.window);
+        while liquidations
+            .front()
+            .is_some_and(|oldest| oldest.time < from)
+        {
+            liquidations.pop_front();
+        }
+    }
+
+    /// Notional of every symbol within the window ending at `now`
+    pub fn total(&self, now: u64) -> LiquidationNotional {
+        let mut total = LiquidationNotional::default();
+        self.within(now, |liquidation| {
+            total.add(&liquidation.side, liquidation.notional)
+        });
+        total
+    }
+
+    /// The `count` symbols with the largest notional within the window ending at `now`,
+    /// largest first
+    pub fn top_symbols(&self, now: u64, count: usize) -> Vec<(String, LiquidationNotional)> {
+        let mut by_symbol: BTreeMap<String, LiquidationNotional> = BTreeMap::new();
+        self.within(now, |liquidation| {
+            by_symbol
+                .entry(liquidation.symbol.clone())
+                .or_default()
+                .add(&liquidation.side, liquidation.notional)
+        });
+
+        let mut symbols: Vec<(String, LiquidationNotional)> = by_symbol.into_iter().collect();
+        symbols.sort_by_key(|(_, notional)| Reverse(notional.buy + notional.sell));
+        symbols.truncate(count);
+        symbols
+    }
+
+    fn within(&self, now: u64, mut visit: impl FnMut(&Liquidation)) {
+        let from = now.saturating_sub(self.window);
+        let liquidations = self.inner.lock().unwrap();
+        for liquidation in liquidations.iter() {
+            if (from..=now).contains(&liquidation.time) {
+                visit(liquidation);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::latency::ReceiveStamp;
+
+    fn liquidation(symbol: &str, side: &str, notional: i64, time: u64) -> LiquidationData {
+        LiquidationData {
+            symbol: symbol.to_string(),
+            side: side.to_string(),
+            quantity: Decimal::from(1),
+            avg_price: Decimal::from(notional),
+            trade_time: time,
+            event_time: time,
+            received: ReceiveStamp::default(),
+        }
+    }
+
+    fn notional(buy: i64, sell: i64) -> LiquidationNotional {
+        LiquidationNotional {
+            buy: Decimal::from(buy),
+            sell: Decimal::from(sell),
+        }
+    }
+
+    #[test]
+    fn totals_the_window_ending_at_the_reader_time() {
+        let liquidations = MarketLiquidations::new(Duration::from_secs(10));
+        liquidations.record(&liquidation("BTCUSDT", "BUY", 100, 2_500));
+        liquidations.record(&liquidation("ETHUSDT", "SELL", 50, 5_000));
+        liquidations.record(&liquidation("BTCUSDT", "SELL", 20, 12_000));
+
+        assert_eq!(liquidations.total(12_000), notional(100, 70));
+        // Newer than the reader
+        assert_eq!(liquidations.total(11_000), notional(100, 50));
+    }
+
+    #[test]
+    fn a_trailing_reader_does_not_prune_for_the_others() {
+        let liquidations = MarketLiquidations::new(Duration::from_secs(10));
+        liquidations.record(&liquidation("BTCUSDT", "BUY", 100, 1_000));
+
+        // A core far ahead sees nothing, one at the feed's time still sees it
+        assert_eq!(liquidations.total(60_000), notional(0, 0));
+        assert_eq!(liquidations.total(2_000), notional(100, 0));
+    }
+
+    #[test]
+    fn the_feed_prunes_what_left_its_window() {
+        let liquidations = MarketLiquidations::new(Duration::from_secs(10));
+        liquidations.record(&liquidation("BTCUSDT", "BUY", 100, 1_000));
+        liquidations.record(&liquidation("ETHUSDT", "BUY", 10, 30_000));
+
+        assert_eq!(liquidations.inner.lock().unwrap().len(), 1);
+        assert_eq!(liquidations.total(2_000), notional(0, 0));
+    }
+
+    #[test]
+    fn top_symbols_largest_first() {
+        let liquidations = MarketLiquidations::new(Duration::from_secs(10));
+        liquidations.record(&liquidation("BTCUSDT", "BUY", 100, 1_000));
+        liquidations.record(&liquidation("ETHUSDT", "SELL", 300, 2_000));
+        liquidations.record(&liquidation("XRPUSDT", "SELL", 10, 3_000));
+        liquidations.record(&liquidation("BTCUSDT", "SELL", 150, 4_000));
+
+        assert_eq!(
+            liquidations.top_symbols(5_000, 2),
+            vec![
+                ("ETHUSDT".to_string(), notional(0, 300)),
+                ("BTCUSDT".to_string(), notional(100, 150)),
+            ]
+        );
+    }
+}
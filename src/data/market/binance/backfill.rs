@@ -0,0 +1,133 @@
+use crate::data::latency::ReceiveStamp;
+use crate::data::limiter::WeightLimiter;
+use crate::data::market::MarketData;
+use crate::data::recorder::{FrameRecorder, RestError};
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Aggregate trade ids are consecutive per symbol. A jump in `a` means trades were missed,
+// either on the wire or while the stream was reconnecting. Missing trades are fetched from
+// the REST `aggTrades` endpoint and replayed before the live trade that revealed the gap.
+// https://developers.binance.com/docs/binance-spot-api-docs/rest-api/market-data-endpoints#compressedaggregate-trades-list
+// https://developers.binance.com/docs/derivatives/usds-margined-futures/market-data/rest-api/Compressed-Aggregate-Trades-List
+
+const BACKFILL_LIMIT: u64 = 1000; // Max trades per request
+const MAX_BACKFILL_PAGES: u64 = 10; // Longer outages are not replayed in full
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct RestAggTrade {
+    pub a: u64,     // Aggregate trade ID
+    pub p: Decimal, // Price
+    pub q: Decimal, // Quantity
+    pub T: u64,     // Trade time
+    pub m: bool,    // Is the buyer the market maker?
+}
+
+impl RestAggTrade {
+    pub fn to_market_data(&self, symbol: &str, local_time: u64) -> MarketData {
+        MarketData {
+            symbol: symbol.to_uppercase(),
+            price: self.p,
+            quantity: self.q,
+            buyer_market_maker: self.m,
+            trade_time: self.T,
+            event_time: self.T, // REST trades have no event time
+            received: ReceiveStamp::local(local_time),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeCheck {
+    First,                         // No trade seen yet for the symbol
+    Next,                          // Directly follows the last trade
+    Duplicate,                     // Already delivered (e.g. by a backfill)
+    Gap { from: u64, until: u64 }, // Trades `from..until` are missing
+}
+
+/// Last delivered aggregate trade id per symbol.
+/// Shared by every connection of a handler so that it survives reconnects.
+#[derive(Debug, Clone, Default)]
+pub struct AggTradeTracker {
+    last_ids: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl AggTradeTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn last(&self, symbol: &str) -> Option<u64> {
+        self.last_ids.lock().unwrap().get(symbol).copied()
+    }
+
+    pub fn check(&self, symbol: &str, id: u64) -> TradeCheck {
+        match self.last(symbol) {
+            None => TradeCheck::First,
+            Some(last) if id <= last => TradeCheck::Duplicate,
+            Some(last) if id == last + 1 => TradeCheck::Next,
+            Some(last) => TradeCheck::Gap {
+                from: last + 1,
+                until: id,
+            },
+        }
+    }
+
+    pub fn record(&self, symbol: &str, id: u64) {
+        self.last_ids.lock().unwrap().insert(symbol.to_string(), id);
+    }
+}
+
+/// Fetch aggregate trades with ids `from..until`.
+/// Every page draws `weight` from `limiter`
+pub async fn fetch_agg_trades(
+    url: &str,
+    symbol: &str,
+    from: u64,
+    until: u64,
+    recorder: &FrameRecorder,
+    limiter: &WeightLimiter,
+    weight: u32,
+) -> Result<Vec<RestAggTrade>, RestError> {
+    let client = reqwest::Client::new();
+    let mut trades = Vec::new();
+    let mut next = from;
+
+    for _ in 0..MAX_BACKFILL_PAGES {
+        if until <= next {
+            break;
+        }
+        let limit = (until - next).min(BACKFILL_LIMIT);
+
+        limiter.acquire(weight).await;
+        let request = client
+            .get(url)
+            .query(&[
+                ("symbol", symbol.to_uppercase()),
+                ("fromId", next.to_string()),
+                ("limit", limit.to_string()),
+            ])
+            .timeout(std::time::Duration::from_secs(10));
+        let body = recorder.fetch(request).await?;
+        let page = serde_json::from_slice::<Vec<RestAggTrade>>(&body)?;
+
+        let fetched = page.len() as u64;
+        trades.extend(page.into_iter().filter(|trade| trade.a >= next));
+        match trades.last() {
+            Some(trade) if fetched == limit => next = trade.a + 1,
+            _ => return Ok(trades), // Caught up with the latest trade
+        }
+    }
+
+    if next < until {
+        warn!(
+            "Binance aggtrade backfill: {} gap too large, stopped at id {}",
+            symbol, next
+        );
+    }
+    Ok(trades)
+}
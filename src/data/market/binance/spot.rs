@@ -1,107 +1,285 @@
+use crate::data::binance::combined_streams;
+use crate::data::endpoints::Endpoint;
+use crate::data::fault::FaultInjector;
+use crate::data::health::StreamMonitor;
+use crate::data::limiter::WeightLimiter;
+use crate::data::market::binance::backfill::{
+    fetch_agg_trades, AggTradeTracker, RestAggTrade, TradeCheck,
+};
 use crate::data::market::MarketData;
+use crate::data::recorder::{FrameRecorder, RestError};
+use crate::data::router::SymbolRouter;
 use crate::data::stream::StreamHandler;
-use futures::{SinkExt, StreamExt};
-use log::{error, info};
+use futures::{stream::FuturesUnordered, SinkExt, StreamExt};
+use log::{error, info, warn};
+use rust_decimal::Decimal;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::future::Future;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{self, Message},
-};
+use tokio_tungstenite::tungstenite::{self, Message};
 
 /* Binance AggTrade Stream */
 
+const AGG_TRADES_WEIGHT: u32 = 4; // Per backfill page
+
+#[derive(Debug, Deserialize)]
+pub struct BinanceWebsocketSpotAggTrade {
+    pub data: SpotAggTradeEvent,
+}
+
 #[allow(dead_code, non_snake_case)]
 #[derive(Debug, Deserialize)]
 pub struct SpotAggTradeEvent {
-    pub e: String, // Event type
-    pub E: u64,    // Event time
-    pub s: String, // Symbol
-    pub a: u64,    // Aggregate trade ID
-    pub p: String, // Price
-    pub q: String, // Quantity
-    pub f: u64,    // First trade ID
-    pub l: u64,    // Last trade ID
-    pub T: u64,    // Trade time
-    pub m: bool,   // Is the buyer the market maker?
+    pub e: String,  // Event type
+    pub E: u64,     // Event time
+    pub s: String,  // Symbol
+    pub a: u64,     // Aggregate trade ID
+    pub p: Decimal, // Price
+    pub q: Decimal, // Quantity
+    pub f: u64,     // First trade ID
+    pub l: u64,     // Last trade ID
+    pub T: u64,     // Trade time
+    pub m: bool,    // Is the buyer the market maker?
 }
 
 pub struct BinanceSpotAggTradeStreamHandler {
     pub stream: String,
-    pub symbol: String,
-    pub tx: mpsc::Sender<MarketData>,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarketData>,
+    tracker: AggTradeTracker, // Last delivered trade per symbol, kept across reconnects
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
+    limiter: WeightLimiter,
 }
 
 impl StreamHandler for BinanceSpotAggTradeStreamHandler {
     fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
-        let symbol = self.symbol.clone();
+        let symbols = self.symbols.clone();
         let stream = self.stream.clone();
         let tx = self.tx.clone();
+        let tracker = self.tracker.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let faults = self.faults.clone();
+        let recorder = self.recorder.clone();
+        let limiter = self.limiter.clone();
 
         Box::new(Box::pin(async move {
-            let ws_url = format!("wss://stream.binance.com:443/ws/{}@aggTrade", symbol);
-            let (ws_stream, _) = connect_async(ws_url).await?;
-            let (write, read) = ws_stream.split();
-
-            let handler = BinanceSpotAggTradeStreamHandler { symbol, stream, tx };
-            handler.handle_aggtrade(read, write).await;
+            let ws_url = format!(
+                "{}/stream?streams={}",
+                endpoint.ws,
+                combined_streams(&symbols, &stream)
+            );
+            let (read, write) = recorder.connect(&ws_url).await?;
+            let (read, write) = faults.wrap(read, write);
 
-            Ok(())
+            let handler = BinanceSpotAggTradeStreamHandler {
+                symbols,
+                stream,
+                tx,
+                tracker,
+                endpoint,
+                monitor,
+                faults,
+                recorder,
+                limiter,
+            };
+            handler.monitor.live();
+            handler.handle_aggtrade(read, write).await
         }))
     }
 }
 
 impl BinanceSpotAggTradeStreamHandler {
-    pub fn new(symbol: String, tx: mpsc::Sender<MarketData>) -> Self {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarketData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
         Self {
-            symbol,
+            symbols,
             stream: "aggTrade".to_string(),
             tx,
+            tracker: AggTradeTracker::new(),
+            endpoint,
+            monitor,
+            faults: FaultInjector::disabled(),
+            recorder: FrameRecorder::disabled(),
+            limiter: WeightLimiter::unlimited(),
         }
     }
 
-    pub async fn handle_aggtrade<R, S>(&self, mut read: R, mut write: S)
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Inject faults into the socket
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    /// Draw the backfills from the weight budget of the REST host
+    pub fn with_limiter(mut self, limiter: WeightLimiter) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    pub async fn handle_aggtrade<R, S>(
+        &self,
+        mut read: R,
+        mut write: S,
+    ) -> Result<(), tungstenite::Error>
     where
         R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
         S: SinkExt<Message> + Unpin,
         S::Error: std::fmt::Display,
     {
-        while let Some(msg) = read.next().await {
-            match msg {
-                Ok(Message::Text(text)) => match serde_json::from_str::<SpotAggTradeEvent>(&text) {
-                    Ok(aggtrade) => {
-                        let update = self.generate_aggtrade_update(&aggtrade);
-                        if self.tx.send(update).await.is_err() {
-                            error!("Binance aggtrade stream: Failed to send update");
+        // Backfills run next to the socket. Live trades of a symbol being backfilled are held
+        // until its missing trades went out. Trades missed while reconnecting show up as a gap
+        // before the first live trade of the symbol. Held trades are stamped on arrival,
+        // the wait for the backfill is not latency
+        let mut backfills = FuturesUnordered::new();
+        let mut held: HashMap<String, Vec<(u64, MarketData)>> = HashMap::new(); // (trade id, update)
+
+        loop {
+            tokio::select! {
+                Some((symbol, result)) = backfills.next() => {
+                    let symbol: String = symbol;
+                    self.send_backfill(&symbol, result).await?;
+                    for (id, update) in held.remove(&symbol).unwrap_or_default() {
+                        // Still behind if the backfill failed or stopped short. Do not retry
+                        if self.tracker.check(&symbol, id) != TradeCheck::Duplicate {
+                            self.send_trade(id, update).await;
                         }
                     }
-                    Err(e) => {
-                        error!("Binance aggtrade stream: Failed to parse message: {}", e);
-                    }
-                },
-                Ok(Message::Ping(payload)) => {
-                    if let Err(e) = write.send(Message::Pong(payload)).await {
-                        error!("Binance aggtrade stream: Failed to send Pong: {}", e);
-                    }
                 }
-                Ok(Message::Pong(_)) => info!("Binance aggtrade stream: Pong received"),
-                Ok(Message::Close(_)) => {
-                    info!("Binance aggtrade stream: Connection closed");
-                    break;
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<BinanceWebsocketSpotAggTrade>(&text) {
+                                Ok(aggtrade) => {
+                                    let (symbol, id) = (aggtrade.data.s.clone(), aggtrade.data.a);
+                                    let update = self.generate_aggtrade_update(&aggtrade);
+                                    if let Some(waiting) = held.get_mut(&symbol) {
+                                        waiting.push((id, update));
+                                        continue;
+                                    }
+                                    match self.tracker.check(&symbol, id) {
+                                        TradeCheck::First | TradeCheck::Next => self.send_trade(id, update).await,
+                                        TradeCheck::Duplicate => (),
+                                        TradeCheck::Gap { from, until } => {
+                                            warn!("Binance aggtrade stream: {} missed trades {}..{}", symbol, from, until);
+                                            self.monitor.incr("trade_gaps");
+                                            backfills.push(self.fetch_backfill(symbol.clone(), from, until));
+                                            held.insert(symbol, vec![(id, update)]);
+                                        }
+                                    }
+                                }
+                                Err(e) => {
+                                    error!("Binance aggtrade stream: Failed to parse message: {}", e);
+                                    self.monitor.incr("parse_failures");
+                                }
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Binance aggtrade stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Binance aggtrade stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Binance aggtrade stream: Connection closed");
+                            return Ok(());
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("Binance aggtrade stream: WebSocket error: {}", e);
+                            return Err(e);
+                        }
+                    }
                 }
-                _ => (),
             }
         }
     }
 
-    fn generate_aggtrade_update(&self, update: &SpotAggTradeEvent) -> MarketData {
+    async fn send_trade(&self, id: u64, update: MarketData) {
+        let symbol = update.symbol.clone();
+        if self.tx.send(update).await.is_err() {
+            error!("Binance aggtrade stream: Failed to send update");
+        }
+        self.tracker.record(&symbol, id);
+    }
+
+    /// Fetch the missing trades `from..until`
+    async fn fetch_backfill(
+        &self,
+        symbol: String,
+        from: u64,
+        until: u64,
+    ) -> (String, Result<Vec<RestAggTrade>, RestError>) {
+        let url = format!("{}/api/v3/aggTrades", self.endpoint.rest);
+        let result = fetch_agg_trades(
+            &url,
+            &symbol,
+            from,
+            until,
+            &self.recorder,
+            &self.limiter,
+            AGG_TRADES_WEIGHT,
+        )
+        .await;
+        (symbol, result)
+    }
+
+    /// Send the backfilled trades in order. A rate limit or ban is handed back to end the
+    /// connection, the supervisor waits it out
+    async fn send_backfill(
+        &self,
+        symbol: &str,
+        result: Result<Vec<RestAggTrade>, RestError>,
+    ) -> Result<(), tungstenite::Error> {
+        let trades = match result {
+            Ok(trades) => trades,
+            Err(e) => {
+                error!("Binance aggtrade stream: {} backfill failed: {}", symbol, e);
+                self.monitor.incr("backfill_failures");
+                return e.ban().map_or(Ok(()), Err);
+            }
+        };
+
+        self.monitor.add("backfilled_trades", trades.len() as u64);
+        for trade in trades.iter() {
+            if self
+                .tx
+                .send(trade.to_market_data(symbol, self.recorder.now_millis()))
+                .await
+                .is_err()
+            {
+                error!("Binance aggtrade stream: Failed to send update");
+            }
+            self.tracker.record(symbol, trade.a);
+        }
+        Ok(())
+    }
+
+    fn generate_aggtrade_update(&self, update: &BinanceWebsocketSpotAggTrade) -> MarketData {
         MarketData {
-            price: update.p.clone(),
-            quantity: update.q.clone(),
-            buyer_market_maker: update.m,
-            trade_time: update.T,
-            event_time: update.E,
+            symbol: update.data.s.clone(),
+            price: update.data.p,
+            quantity: update.data.q,
+            buyer_market_maker: update.data.m,
+            trade_time: update.data.T,
+            event_time: update.data.E,
+            received: self
+                .monitor
+                .stamp(self.recorder.now_millis(), update.data.E),
         }
     }
 }
@@ -0,0 +1,172 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    depth::bitget::future::{bitget_operation, BitgetStreamArg, BitgetStreamConfirm},
+    health::StreamMonitor,
+    market::MarketData,
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use rust_decimal::prelude::FromStr;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/* Bitget Trade Stream */
+
+#[derive(Debug, Deserialize)]
+pub struct BitgetTradeMessage {
+    pub action: String, // "snapshot" (recent trades on subscribe) or "update"
+    pub arg: BitgetStreamArg,
+    pub data: Vec<TradeData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct TradeData {
+    pub ts: String,    // Trade time
+    pub price: String, // Price
+    pub size: String,  // Quantity
+    pub side: String,  // Taker side: "buy" or "sell"
+}
+
+pub struct BitgetFutureTradeStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarketData>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for BitgetFutureTradeStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let (read, mut write) = recorder.connect(&endpoint.ws).await?;
+
+            let subscription = bitget_operation("subscribe", "trade", &symbols);
+            write.send(Message::Text(subscription.into())).await?;
+
+            let handler = BitgetFutureTradeStreamHandler {
+                symbols,
+                tx,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_trade(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+impl BitgetFutureTradeStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarketData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_trade<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        // Bitget drops connections without a "ping" for 2 minutes
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(30));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(Message::Text("ping".into())).await {
+                        error!("Bitget trade stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) if text.as_str() == "pong" => (),
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<BitgetTradeMessage>(&text) {
+                                // The snapshot replays trades from before we subscribed
+                                Ok(trade) if trade.action == "snapshot" => (),
+                                Ok(trade) => {
+                                    // Bitget sends the newest trade first
+                                    for data in trade.data.iter().rev() {
+                                        let Some(update) = self.generate_trade_update(&trade.arg.instId, data) else {
+                                            error!("Bitget trade stream: Invalid value in {:?}", data);
+                                            self.monitor.incr("parse_failures");
+                                            continue;
+                                        };
+                                        if self.tx.send(update).await.is_err() {
+                                            error!("Bitget trade stream: Failed to send update");
+                                        }
+                                    }
+                                }
+                                Err(_) => match serde_json::from_str::<BitgetStreamConfirm>(&text) {
+                                    Ok(confirm) => info!("Bitget trade stream: {}", confirm.event),
+                                    Err(e) => error!("Bitget trade stream: Failed to parse message: {}", e),
+                                },
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Bitget trade stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Bitget trade stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Bitget trade stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("Bitget trade stream: WebSocket error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_trade_update(&self, symbol: &str, update: &TradeData) -> Option<MarketData> {
+        let ts = update.ts.parse::<u64>().ok()?;
+        Some(MarketData {
+            symbol: symbol.to_string(),
+            price: Decimal::from_str(&update.price).ok()?,
+            quantity: Decimal::from_str(&update.size).ok()?,
+            buyer_market_maker: update.side == "sell",
+            trade_time: ts,
+            event_time: ts,
+            received: self.monitor.stamp(self.recorder.now_millis(), ts),
+        })
+    }
+}
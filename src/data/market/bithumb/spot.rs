@@ -0,0 +1,198 @@
+use crate::data::bithumb::{subscription_message, BithumbStatus, PING_INTERVAL_SECS};
+use crate::data::endpoints::Endpoint;
+use crate::data::health::StreamMonitor;
+use crate::data::market::MarketData;
+use crate::data::recorder::FrameRecorder;
+use crate::data::router::SymbolRouter;
+use crate::data::stream::StreamHandler;
+use chrono::{FixedOffset, NaiveDateTime};
+use futures::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Bytes, Message};
+
+/* Bithumb Trade(Transaction) Stream */
+
+#[derive(Debug, Deserialize)]
+pub struct BithumbWebsocketTransaction {
+    pub content: TransactionContent,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionContent {
+    pub list: Vec<Transaction>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct Transaction {
+    pub symbol: String,
+    pub buySellGb: String, // "1": Sell taker, "2": Buy taker
+    pub contPrice: Decimal,
+    pub contQty: Decimal,
+    pub contDtm: String, // "2020-01-29 12:24:18.830039" in KST
+}
+
+pub struct BithumbSpotTradeStreamHandler {
+    pub streams: String,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarketData>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for BithumbSpotTradeStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let (read, write) = recorder.connect(&endpoint.ws).await?;
+
+            let handler = BithumbSpotTradeStreamHandler {
+                symbols,
+                streams: "transaction".to_string(),
+                tx,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_trade(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+impl BithumbSpotTradeStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarketData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            streams: "transaction".to_string(),
+            tx,
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_trade<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        let subscribe_message = subscription_message(&self.streams, &self.symbols);
+        if let Err(e) = write.send(Message::Text(subscribe_message.into())).await {
+            error!(
+                "Bithumb trade stream: Failed to send subscription message: {}",
+                e
+            );
+            return;
+        }
+
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    if let Err(e) = write.send(Message::Ping(Bytes::from_static(&[]))).await {
+                        error!("Bithumb trade stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<BithumbWebsocketTransaction>(&text) {
+                                Ok(transactions) => {
+                                    // Transactions carry no event time. Stamp with the receive time
+                                    let now = self.recorder.now_millis();
+                                    for transaction in transactions.content.list.iter() {
+                                        let Some(update) = self.generate_trade_update(transaction, now) else {
+                                            error!("Bithumb trade stream: Failed to parse time {}", transaction.contDtm);
+                                            self.monitor.incr("parse_failures");
+                                            continue;
+                                        };
+                                        if self.tx.send(update).await.is_err() {
+                                            error!("Bithumb trade stream: Failed to send update");
+                                        }
+                                    }
+                                }
+                                Err(e) => match serde_json::from_str::<BithumbStatus>(&text) {
+                                    Ok(status) if status.status == "0000" => {
+                                        info!("Bithumb trade stream: {}", status.resmsg)
+                                    }
+                                    Ok(status) => warn!(
+                                        "Bithumb trade stream: {} ({})",
+                                        status.resmsg, status.status
+                                    ),
+                                    Err(_) => {
+                                        error!("Bithumb trade stream: Failed to parse message: {}", e);
+                                        self.monitor.incr("parse_failures");
+                                    }
+                                },
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Bithumb trade stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Bithumb trade stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Bithumb trade stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("Bithumb trade stream: WebSocket error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_trade_update(&self, update: &Transaction, event_time: u64) -> Option<MarketData> {
+        let trade_time = kst_millis(&update.contDtm)?;
+        Some(MarketData {
+            symbol: update.symbol.clone(),
+            price: update.contPrice,
+            quantity: update.contQty,
+            buyer_market_maker: update.buySellGb == "1",
+            trade_time,
+            event_time,
+            received: self.monitor.stamp(self.recorder.now_millis(), trade_time),
+        })
+    }
+}
+
+// "2020-01-29 12:24:18.830039" (KST) to unix millis
+fn kst_millis(time: &str) -> Option<u64> {
+    let kst = FixedOffset::east_opt(9 * 3600)?;
+    let local = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S%.f").ok()?;
+    let time = local.and_local_timezone(kst).single()?;
+    u64::try_from(time.timestamp_millis()).ok()
+}
@@ -0,0 +1,165 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    bybit::{ping_message, send_operation, BybitOpResponse, PING_INTERVAL_SECS},
+    health::StreamMonitor,
+    market::MarketData,
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/* Bybit Trade Stream */
+
+#[derive(Debug, Deserialize)]
+pub struct BybitTradeMessage {
+    pub ts: u64, // When the message was sent
+    pub data: Vec<TradeData>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct TradeData {
+    pub T: u64,     // Trade time
+    pub s: String,  // Symbol
+    pub S: String,  // Taker side: "Buy" or "Sell"
+    pub v: Decimal, // Quantity
+    pub p: Decimal, // Price
+}
+
+pub struct BybitFutureTradeStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarketData>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for BybitFutureTradeStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let (read, mut write) = recorder.connect(&endpoint.ws).await?;
+
+            send_operation(&mut write, "subscribe", "publicTrade", &symbols).await?;
+
+            let handler = BybitFutureTradeStreamHandler {
+                symbols,
+                tx,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_trade(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+impl BybitFutureTradeStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarketData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_trade<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(ping_message()).await {
+                        error!("Bybit trade stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<BybitTradeMessage>(&text) {
+                                Ok(trade) => {
+                                    // Trades of a message are sorted oldest first
+                                    for data in trade.data.iter() {
+                                        let update = self.generate_trade_update(data, trade.ts);
+                                        if self.tx.send(update).await.is_err() {
+                                            error!("Bybit trade stream: Failed to send update");
+                                        }
+                                    }
+                                }
+                                Err(e) => match serde_json::from_str::<BybitOpResponse>(&text) {
+                                    Ok(response) if response.success => (),
+                                    Ok(response) => error!("Bybit trade stream: {} failed: {}", response.op, response.ret_msg),
+                                    Err(_) => {
+                                        error!("Bybit trade stream: Failed to parse message: {}", e);
+                                        self.monitor.incr("parse_failures");
+                                    }
+                                },
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Bybit trade stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Bybit trade stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Bybit trade stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("Bybit trade stream: WebSocket error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_trade_update(&self, update: &TradeData, event_time: u64) -> MarketData {
+        MarketData {
+            symbol: update.s.clone(),
+            price: update.p,
+            quantity: update.v,
+            buyer_market_maker: update.S == "Sell",
+            trade_time: update.T,
+            event_time,
+            received: self.monitor.stamp(self.recorder.now_millis(), event_time),
+        }
+    }
+}
@@ -1,13 +1,22 @@
 pub mod binance;
+pub mod bitget;
+pub mod bithumb;
+pub mod bybit;
+pub mod okx;
 pub mod upbit;
 
+use crate::data::latency::ReceiveStamp;
+use rust_decimal::Decimal;
+
 #[derive(Debug, Clone)]
 pub struct MarketData {
-    pub price: String,
-    pub quantity: String,
+    pub symbol: String,
+    pub price: Decimal,
+    pub quantity: Decimal,
     pub buyer_market_maker: bool, // true: SELL ORDER, false: BUY ORDER
     pub trade_time: u64,
     pub event_time: u64,
+    pub received: ReceiveStamp,
 }
 
 // Why buyer_market_maker is true: SELL ORDER, false: BUY ORDER
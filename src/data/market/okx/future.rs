@@ -0,0 +1,182 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    health::StreamMonitor,
+    market::MarketData,
+    okx::{fetch_contract_values, send_operation, OkxEvent, OkxStreamArg, PING_INTERVAL_SECS},
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/* OKX Trade Stream */
+
+#[derive(Debug, Deserialize)]
+pub struct OkxTradeMessage {
+    pub data: Vec<TradeData>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct TradeData {
+    pub instId: String, // Symbol
+    pub px: String,     // Price
+    pub sz: String,     // Quantity in contracts
+    pub side: String,   // Taker side: "buy" or "sell"
+    pub ts: String,     // Trade time
+}
+
+pub struct OkxFutureTradeStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarketData>,
+    contract_values: HashMap<String, Decimal>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for OkxFutureTradeStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let contract_values = fetch_contract_values(&endpoint, &symbols, &recorder).await?;
+
+            let (read, mut write) = recorder.connect(&endpoint.ws).await?;
+
+            let args = symbols
+                .iter()
+                .map(|symbol| OkxStreamArg::instrument("trades", symbol))
+                .collect();
+            send_operation(&mut write, "subscribe", args).await?;
+
+            let handler = OkxFutureTradeStreamHandler {
+                symbols,
+                tx,
+                contract_values,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_trade(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+impl OkxFutureTradeStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarketData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            contract_values: HashMap::new(),
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_trade<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(Message::Text("ping".into())).await {
+                        error!("OKX trade stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) if text.as_str() == "pong" => (),
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<OkxTradeMessage>(&text) {
+                                Ok(trade) => {
+                                    for data in trade.data.iter() {
+                                        let Some(update) = self.generate_trade_update(data) else {
+                                            error!("OKX trade stream: Invalid value in {:?}", data);
+                                            self.monitor.incr("parse_failures");
+                                            continue;
+                                        };
+                                        if self.tx.send(update).await.is_err() {
+                                            error!("OKX trade stream: Failed to send update");
+                                        }
+                                    }
+                                }
+                                Err(e) => match serde_json::from_str::<OkxEvent>(&text) {
+                                    Ok(event) if event.event == "error" => {
+                                        error!("OKX trade stream: {:?} {:?}", event.code, event.msg)
+                                    }
+                                    Ok(event) => info!("OKX trade stream: {}", event.event),
+                                    Err(_) => {
+                                        error!("OKX trade stream: Failed to parse message: {}", e);
+                                        self.monitor.incr("parse_failures");
+                                    }
+                                },
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("OKX trade stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("OKX trade stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("OKX trade stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("OKX trade stream: WebSocket error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_trade_update(&self, update: &TradeData) -> Option<MarketData> {
+        let contract_value = self.contract_values.get(&update.instId)?;
+        let ts = update.ts.parse::<u64>().ok()?;
+        Some(MarketData {
+            symbol: update.instId.clone(),
+            price: update.px.parse::<Decimal>().ok()?,
+            quantity: update.sz.parse::<Decimal>().ok()? * contract_value,
+            buyer_market_maker: update.side == "sell",
+            trade_time: ts,
+            event_time: ts,
+            received: self.monitor.stamp(self.recorder.now_millis(), ts),
+        })
+    }
+}
@@ -0,0 +1,184 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::latency::ReceiveStamp;
+use crate::data::market::MarketData;
+use crate::data::recorder::{FrameRecorder, RestError};
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// Upbit `sequential_id` grows with every trade of a market but is not contiguous,
+// so a missing trade cannot be spotted between two live messages. Trades can only be
+// lost while the socket is down. The first trade of a market after a reconnect is
+// compared with the highest delivered one and everything in between is fetched
+// from `GET /v1/trades/ticks`, which pages backwards from a `cursor` (a sequential id).
+
+const BACKFILL_LIMIT: usize = 500; // Max trades per request
+const MAX_BACKFILL_PAGES: usize = 10; // Quotation API allows 10 requests per second
+const RECENT_IDS: usize = 1024; // Delivered ids kept per market to drop replays
+
+#[derive(Debug, Deserialize)]
+pub struct RestTradeTick {
+    pub market: String,
+    pub timestamp: u64, // When the trade was executed
+    pub trade_price: Decimal,
+    pub trade_volume: Decimal,
+    pub ask_bid: String,
+    pub sequential_id: u64,
+}
+
+impl RestTradeTick {
+    pub fn to_market_data(&self, local_time: u64) -> MarketData {
+        MarketData {
+            symbol: self.market.clone(),
+            price: self.trade_price,
+            quantity: self.trade_volume,
+            buyer_market_maker: self.ask_bid == "ASK",
+            trade_time: self.timestamp,
+            event_time: self.timestamp, // REST trades have no event time
+            received: ReceiveStamp::local(local_time),
+        }
+    }
+}
+
+/// Delivered `sequential_id`s per market: the highest, and the recent ones to drop replays.
+/// Trades are not guaranteed to arrive in id order, so an id below the highest may still be new.
+/// Shared by every connection of a handler so that it survives reconnects.
+#[derive(Debug, Clone, Default)]
+pub struct TradeSequence {
+    markets: Arc<Mutex<HashMap<String, RecentIds>>>,
+}
+
+#[derive(Debug, Default)]
+struct RecentIds {
+    highest: u64,
+    order: VecDeque<u64>, // Delivery order, oldest first
+    ids: HashSet<u64>,
+    forgotten: Option<u64>, // Highest id dropped from the window. Anything up to it counts as delivered
+}
+
+impl TradeSequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Highest delivered id
+    pub fn last(&self, symbol: &str) -> Option<u64> {
+        self.markets
+            .lock()
+            .unwrap()
+            .get(symbol)
+            .map(|recent| recent.highest)
+    }
+
+    /// Mark `id` delivered. False if it already was
+    pub fn record(&self, symbol: &str, id: u64) -> bool {
+        let mut markets = self.markets.lock().unwrap();
+        let recent = markets.entry(symbol.to_string()).or_default();
+        if recent.forgotten.is_some_and(|forgotten| id <= forgotten) || !recent.ids.insert(id) {
+            return false;
+        }
+        recent.order.push_back(id);
+        recent.highest = recent.highest.max(id);
+        if recent.order.len() > RECENT_IDS {
+            if let Some(oldest) = recent.order.pop_front() {
+                recent.ids.remove(&oldest);
+                recent.forgotten = recent.forgotten.max(Some(oldest));
+            }
+        }
+        true
+    }
+}
+
+/// Fetch the trades of `symbol` strictly between the sequential ids `after` and `before`, oldest first
+pub async fn fetch_trade_ticks(
+    endpoint: &Endpoint,
+    symbol: &str,
+    after: u64,
+    before: u64,
+    recorder: &FrameRecorder,
+) -> Result<Vec<RestTradeTick>, RestError> {
+    let client = reqwest::Client::new();
+    let mut trades = Vec::new();
+    let mut cursor = before;
+
+    for _ in 0..MAX_BACKFILL_PAGES {
+        // Newest first, every trade is older than `cursor`
+        let request = client
+            .get(format!("{}/v1/trades/ticks", endpoint.rest))
+            .query(&[
+                ("market", symbol.to_string()),
+                ("count", BACKFILL_LIMIT.to_string()),
+                ("cursor", cursor.to_string()),
+            ])
+            .timeout(std::time::Duration::from_secs(10));
+        let body = recorder.fetch(request).await?;
+        let page = serde_json::from_slice::<Vec<RestTradeTick>>(&body)?;
+
+        let full = page.len() == BACKFILL_LIMIT;
+        let oldest = page.last().map(|trade| trade.sequential_id);
+        trades.extend(
+            page.into_iter()
+                .filter(|trade| trade.sequential_id > after && trade.sequential_id < before),
+        );
+
+        match oldest {
+            Some(oldest) if full && oldest > after => cursor = oldest,
+            _ => {
+                trades.reverse();
+                return Ok(trades);
+            }
+        }
+    }
+
+    warn!(
+        "Upbit trade backfill: {} gap too large, replaying from id {}",
+        symbol, cursor
+    );
+    trades.reverse();
+    Ok(trades)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_replayed_ids() {
+        let sequence = TradeSequence::new();
+        assert!(sequence.record("KRW-BTC", 10));
+        assert!(sequence.record("KRW-BTC", 20));
+        assert!(!sequence.record("KRW-BTC", 10));
+        assert!(!sequence.record("KRW-BTC", 20));
+        assert_eq!(sequence.last("KRW-BTC"), Some(20));
+    }
+
+    #[test]
+    fn delivers_ids_out_of_order() {
+        let sequence = TradeSequence::new();
+        assert!(sequence.record("KRW-BTC", 20));
+        assert!(sequence.record("KRW-BTC", 15));
+        assert_eq!(sequence.last("KRW-BTC"), Some(20));
+    }
+
+    #[test]
+    fn markets_are_apart() {
+        let sequence = TradeSequence::new();
+        assert!(sequence.record("KRW-BTC", 10));
+        assert!(sequence.record("KRW-ETH", 10));
+        assert_eq!(sequence.last("KRW-XRP"), None);
+    }
+
+    #[test]
+    fn ids_older_than_the_window_count_as_delivered() {
+        let sequence = TradeSequence::new();
+        for id in 0..RECENT_IDS as u64 + 2 {
+            assert!(sequence.record("KRW-BTC", id * 10));
+        }
+        // 0 and 10 left the window, 5 was never seen but is older than what is kept
+        assert!(!sequence.record("KRW-BTC", 0));
+        assert!(!sequence.record("KRW-BTC", 5));
+        assert!(sequence.record("KRW-BTC", 15));
+    }
+}
@@ -1 +1,2 @@
+pub mod backfill;
 pub mod spot;
@@ -1,14 +1,19 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::fault::FaultInjector;
+use crate::data::health::StreamMonitor;
+use crate::data::market::upbit::backfill::{fetch_trade_ticks, RestTradeTick, TradeSequence};
 use crate::data::market::MarketData;
+use crate::data::recorder::FrameRecorder;
+use crate::data::router::SymbolRouter;
 use crate::data::stream::StreamHandler;
+use crate::data::upbit::subscription_message;
 use futures::{SinkExt, StreamExt};
 use log::{error, info};
+use rust_decimal::Decimal;
 use serde::Deserialize;
+use std::collections::HashSet;
 use std::future::Future;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{self, Bytes, Message},
-};
+use tokio_tungstenite::tungstenite::{self, Bytes, Message};
 
 /* Upbit AggTrade(Trade) Stream */
 
@@ -22,65 +27,103 @@ pub struct UpbitWebsocketSpotAggTrade {
     pub trade_date: String,   // YYYY-MM-DD
     pub trade_time: String,   // HH:MM:SS
     pub trade_timestamp: u64, // When the trade was executed
-    pub trade_price: f32,
-    pub trade_volume: f32,
+    pub trade_price: Decimal,
+    pub trade_volume: Decimal,
     pub ask_bid: String,
+    pub sequential_id: u64, // Unique and increasing per market, not contiguous
     pub stream_type: String,
 }
 
 pub struct UpbitSpotAggTradeStreamHandler {
     pub streams: String,
-    pub symbol: String,
-    pub tx: mpsc::Sender<MarketData>,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarketData>,
+    sequence: TradeSequence, // Delivered trades per market, kept across reconnects
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    faults: FaultInjector,
+    recorder: FrameRecorder,
 }
 
 impl StreamHandler for UpbitSpotAggTradeStreamHandler {
     fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
-        let symbol = self.symbol.clone();
+        let symbols = self.symbols.clone();
         let tx = self.tx.clone();
+        let sequence = self.sequence.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let faults = self.faults.clone();
+        let recorder = self.recorder.clone();
 
         Box::new(Box::pin(async move {
-            let ws_url = "wss://api.upbit.com/websocket/v1".to_string();
-            let (ws_stream, _) = connect_async(ws_url).await?;
-            let (write, read) = ws_stream.split();
+            let (read, write) = recorder.connect(&endpoint.ws).await?;
+            let (read, write) = faults.wrap(read, write);
 
             let handler = UpbitSpotAggTradeStreamHandler {
-                symbol,
+                symbols,
                 streams: "trade".to_string(),
                 tx,
+                sequence,
+                endpoint,
+                monitor,
+                faults,
+                recorder,
             };
 
-            handler.handle_aggtrade(read, write).await;
+            handler.monitor.live();
 
-            Ok(())
+            handler.handle_aggtrade(read, write).await
         }))
     }
 }
 
 impl UpbitSpotAggTradeStreamHandler {
-    pub fn new(symbol: String, tx: mpsc::Sender<MarketData>) -> Self {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarketData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
         Self {
-            symbol,
+            symbols,
             streams: "trade".to_string(),
             tx,
+            sequence: TradeSequence::new(),
+            endpoint,
+            monitor,
+            faults: FaultInjector::disabled(),
+            recorder: FrameRecorder::disabled(),
         }
     }
 
-    pub async fn handle_aggtrade<R, S>(&self, mut read: R, mut write: S)
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    /// Inject faults into the socket
+    pub fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = faults;
+        self
+    }
+
+    pub async fn handle_aggtrade<R, S>(
+        &self,
+        mut read: R,
+        mut write: S,
+    ) -> Result<(), tungstenite::Error>
     where
         R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
         S: SinkExt<Message> + Unpin,
         S::Error: std::fmt::Display,
     {
         // Send subscription message
-        let subscribe_msg = format!(
-            r#"[{{"ticket":"UNIQUE_TICKET"}},{{"type":{},"codes":["{}"]}}]"#,
-            self.streams, self.symbol
-        );
+        let subscribe_msg = subscription_message(&self.streams, &self.symbols);
 
         if let Err(e) = write.send(Message::Text(subscribe_msg.into())).await {
             error!("Failed to send subscription message: {}", e);
-            return;
+            return Ok(());
         }
 
         // Upbit requires a ping every 60 seconds
@@ -88,6 +131,9 @@ impl UpbitSpotAggTradeStreamHandler {
         // https://docs.upbit.com/reference/connection
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
 
+        // Markets whose first trade on this connection is still pending
+        let mut resuming: HashSet<String> = self.symbols.iter().map(|s| s.to_uppercase()).collect();
+
         loop {
             tokio::select! {
                 _ = interval.tick() => {
@@ -101,12 +147,31 @@ impl UpbitSpotAggTradeStreamHandler {
                         Some(Ok(Message::Binary(binary))) => {
                             match serde_json::from_slice::<UpbitWebsocketSpotAggTrade>(&binary) {
                                 Ok(trade) => {
+                                    let last = self.sequence.last(&trade.code);
+                                    if !self.sequence.record(&trade.code, trade.sequential_id) {
+                                        // Replayed on (re)subscribe
+                                        self.monitor.incr("duplicate_trades");
+                                        continue;
+                                    }
+                                    let resumed = resuming.remove(&trade.code);
+                                    let backfilled = match last {
+                                        Some(last) if resumed && trade.sequential_id > last => {
+                                            self.backfill(&trade.code, last, trade.sequential_id).await
+                                        }
+                                        _ => Ok(()),
+                                    };
+
                                     let update = self.generate_aggtrade_update(&trade);
                                     if let Err(e) = self.tx.send(update).await {
                                         error!("Failed to send trade update: {}", e);
                                     }
+                                    // The trade is recorded already. Deliver it before handing a ban over
+                                    backfilled?;
+                                }
+                                Err(e) => {
+                                    error!("Failed to parse binary trade data: {}", e);
+                                    self.monitor.incr("parse_failures");
                                 }
-                                Err(e) => error!("Failed to parse binary trade data: {}", e),
                             }
                         }
                         Some(Ok(Message::Ping(payload))) => {
@@ -118,22 +183,73 @@ impl UpbitSpotAggTradeStreamHandler {
                         Some(Ok(_)) => {} // Ignore other message types
                         Some(Err(e)) => {
                             error!("WebSocket error: {}", e);
-                            break;
+                            return Err(e);
                         }
                         None => break,
                     }
                 }
             }
         }
+
+        Ok(())
+    }
+
+    /// Send the trades missed between the sequential ids `after` and `before` in order.
+    /// A rate limit or ban is handed back to end the connection, the supervisor waits it out
+    async fn backfill(
+        &self,
+        symbol: &str,
+        after: u64,
+        before: u64,
+    ) -> Result<(), tungstenite::Error> {
+        let trades =
+            match fetch_trade_ticks(&self.endpoint, symbol, after, before, &self.recorder).await {
+                Ok(trades) => trades,
+                Err(e) => {
+                    error!("Upbit aggtrade stream: {} backfill failed: {}", symbol, e);
+                    self.monitor.incr("backfill_failures");
+                    return e.ban().map_or(Ok(()), Err);
+                }
+            };
+        // Trades that made it over the socket out of order are already delivered
+        let trades: Vec<RestTradeTick> = trades
+            .into_iter()
+            .filter(|trade| self.sequence.record(symbol, trade.sequential_id))
+            .collect();
+        if trades.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "Upbit aggtrade stream: {} replaying {} missed trades",
+            symbol,
+            trades.len()
+        );
+        self.monitor.incr("trade_gaps");
+        self.monitor.add("backfilled_trades", trades.len() as u64);
+        for trade in trades.iter() {
+            if let Err(e) = self
+                .tx
+                .send(trade.to_market_data(self.recorder.now_millis()))
+                .await
+            {
+                error!("Failed to send trade update: {}", e);
+            }
+        }
+        Ok(())
     }
 
     fn generate_aggtrade_update(&self, update: &UpbitWebsocketSpotAggTrade) -> MarketData {
         MarketData {
-            price: update.trade_price.to_string(),
-            quantity: update.trade_volume.to_string(),
+            symbol: update.code.clone(),
+            price: update.trade_price,
+            quantity: update.trade_volume,
             buyer_market_maker: update.ask_bid == "ASK",
             trade_time: update.trade_timestamp,
             event_time: update.timestamp,
+            received: self
+                .monitor
+                .stamp(self.recorder.now_millis(), update.timestamp),
         }
     }
 }
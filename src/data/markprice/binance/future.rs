@@ -1,13 +1,15 @@
-use crate::data::{markprice::MarkPriceData, stream::StreamHandler};
+use crate::data::binance::{combined_streams, BinanceFutureMarket};
+use crate::data::endpoints::Endpoint;
+use crate::data::recorder::FrameRecorder;
+use crate::data::router::SymbolRouter;
+use crate::data::{health::StreamMonitor, markprice::MarkPriceData, stream::StreamHandler};
 use futures::{SinkExt, StreamExt};
 use log::{error, info};
+use rust_decimal::prelude::FromStr;
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use std::future::Future;
-use tokio::sync::mpsc;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{self, Message},
-};
+use tokio_tungstenite::tungstenite::{self, Message};
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
@@ -19,41 +21,54 @@ pub struct BinanceWebsocketFutureMarkPrice {
 #[allow(dead_code, non_snake_case)]
 #[derive(Debug, Deserialize)]
 pub struct FutureMarkPriceEvent {
-    pub e: String, // Event type
-    pub E: u64,    // Event time
-    pub s: String, // Symbol
-    pub p: String, // Mark price
-    pub i: String, // Index price
+    pub e: String,  // Event type
+    pub E: u64,     // Event time
+    pub s: String,  // Symbol
+    pub p: Decimal, // Mark price
+    pub i: Decimal, // Index price
     pub P: String, // Estimated Settle Price, only useful in the last hour before the settlement starts
-    pub r: String, // Funding rate
-    pub T: u64,    // Next funding time
+    pub r: String, // Funding rate. Empty for delivery contracts
+    pub T: u64,    // Next funding time. 0 for delivery contracts
 }
 
 pub struct BinanceFutureMarkPriceStreamHandler {
+    market: BinanceFutureMarket,
     pub streams: String,
-    pub symbol: String,
-    pub tx: mpsc::Sender<MarkPriceData>,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarkPriceData>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
 }
 
 impl StreamHandler for BinanceFutureMarkPriceStreamHandler {
     fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
-        let symbol = self.symbol.clone();
+        let market = self.market;
+        let symbols = self.symbols.clone();
         let streams = self.streams.clone();
         let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
 
         Box::new(Box::pin(async move {
             let ws_url = format!(
-                "wss://fstream.binance.com/stream?streams={}@{}",
-                symbol, streams
+                "{}?streams={}",
+                market.ws_url(&endpoint),
+                combined_streams(&symbols, &streams)
             );
-            let (ws_stream, _) = connect_async(&ws_url).await?;
-            let (write, read) = ws_stream.split();
+            let (read, write) = recorder.connect(&ws_url).await?;
 
             let handler = BinanceFutureMarkPriceStreamHandler {
-                symbol,
+                market,
+                symbols,
                 streams,
                 tx,
+                endpoint,
+                monitor,
+                recorder,
             };
+            handler.monitor.live();
             handler.handle_markprice(read, write).await;
 
             Ok(())
@@ -62,14 +77,30 @@ impl StreamHandler for BinanceFutureMarkPriceStreamHandler {
 }
 
 impl BinanceFutureMarkPriceStreamHandler {
-    pub fn new(symbol: String, tx: mpsc::Sender<MarkPriceData>) -> Self {
+    pub fn new(
+        market: BinanceFutureMarket,
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarkPriceData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
         Self {
-            symbol,
+            market,
+            symbols,
             streams: "markPrice".to_string(),
             tx,
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
         }
     }
 
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
     pub async fn handle_markprice<R, S>(&self, mut read: R, mut write: S)
     where
         R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
@@ -81,13 +112,21 @@ impl BinanceFutureMarkPriceStreamHandler {
                 Ok(Message::Text(text)) => {
                     match serde_json::from_str::<BinanceWebsocketFutureMarkPrice>(&text) {
                         Ok(markprice) => {
-                            let update = self.generate_markprice_update(&markprice);
+                            let Some(update) = self.generate_markprice_update(&markprice) else {
+                                error!(
+                                    "Binance mark price stream: Invalid funding rate {}",
+                                    markprice.data.r
+                                );
+                                self.monitor.incr("parse_failures");
+                                continue;
+                            };
                             if self.tx.send(update).await.is_err() {
                                 error!("Binance mark price stream: Failed to send update");
                             }
                         }
                         Err(e) => {
                             error!("Binance mark price stream: Failed to parse message: {}", e);
+                            self.monitor.incr("parse_failures");
                         }
                     }
                 }
@@ -106,13 +145,26 @@ impl BinanceFutureMarkPriceStreamHandler {
         }
     }
 
-    fn generate_markprice_update(&self, update: &BinanceWebsocketFutureMarkPrice) -> MarkPriceData {
-        MarkPriceData {
-            mark_price: update.data.p.clone(),
-            index_price: update.data.i.clone(),
-            funding_rate: update.data.r.clone(),
+    fn generate_markprice_update(
+        &self,
+        update: &BinanceWebsocketFutureMarkPrice,
+    ) -> Option<MarkPriceData> {
+        // Delivery contracts have no funding
+        let funding_rate = match update.data.r.as_str() {
+            "" => Decimal::ZERO,
+            rate => Decimal::from_str(rate).ok()?,
+        };
+
+        Some(MarkPriceData {
+            symbol: update.data.s.clone(),
+            mark_price: update.data.p,
+            index_price: update.data.i,
+            funding_rate,
             next_funding_time: update.data.T,
             event_time: update.data.E,
-        }
+            received: self
+                .monitor
+                .stamp(self.recorder.now_millis(), update.data.E),
+        })
     }
 }
@@ -0,0 +1,169 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    depth::bitget::future::{bitget_operation, BitgetStreamConfirm},
+    health::StreamMonitor,
+    markprice::MarkPriceData,
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use rust_decimal::prelude::FromStr;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/* Bitget Ticker Stream (Mark price, index price, funding) */
+
+#[derive(Debug, Deserialize)]
+pub struct BitgetTickerMessage {
+    pub data: Vec<TickerData>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(non_snake_case)]
+pub struct TickerData {
+    pub instId: String,          // Symbol
+    pub markPrice: String,       // Mark price
+    pub indexPrice: String,      // Index price
+    pub fundingRate: String,     // Current funding rate
+    pub nextFundingTime: String, // Next funding time
+    pub ts: String,              // System time
+}
+
+pub struct BitgetFutureTickerStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarkPriceData>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for BitgetFutureTickerStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let (read, mut write) = recorder.connect(&endpoint.ws).await?;
+
+            let subscription = bitget_operation("subscribe", "ticker", &symbols);
+            write.send(Message::Text(subscription.into())).await?;
+
+            let handler = BitgetFutureTickerStreamHandler {
+                symbols,
+                tx,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_ticker(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+impl BitgetFutureTickerStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarkPriceData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_ticker<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        // Bitget drops connections without a "ping" for 2 minutes
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(30));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(Message::Text("ping".into())).await {
+                        error!("Bitget ticker stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) if text.as_str() == "pong" => (),
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<BitgetTickerMessage>(&text) {
+                                Ok(ticker) => {
+                                    for data in ticker.data.iter() {
+                                        let Some(update) = self.generate_markprice_update(data) else {
+                                            error!("Bitget ticker stream: Invalid value in {:?}", data);
+                                            self.monitor.incr("parse_failures");
+                                            continue;
+                                        };
+                                        if self.tx.send(update).await.is_err() {
+                                            error!("Bitget ticker stream: Failed to send update");
+                                        }
+                                    }
+                                }
+                                Err(_) => match serde_json::from_str::<BitgetStreamConfirm>(&text) {
+                                    Ok(confirm) => info!("Bitget ticker stream: {}", confirm.event),
+                                    Err(e) => error!("Bitget ticker stream: Failed to parse message: {}", e),
+                                },
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Bitget ticker stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Bitget ticker stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Bitget ticker stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("Bitget ticker stream: WebSocket error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_markprice_update(&self, update: &TickerData) -> Option<MarkPriceData> {
+        let event_time = update.ts.parse::<u64>().ok()?;
+        Some(MarkPriceData {
+            symbol: update.instId.clone(),
+            mark_price: Decimal::from_str(&update.markPrice).ok()?,
+            index_price: Decimal::from_str(&update.indexPrice).ok()?,
+            funding_rate: Decimal::from_str(&update.fundingRate).ok()?,
+            next_funding_time: update.nextFundingTime.parse::<u64>().ok()?,
+            event_time,
+            received: self.monitor.stamp(self.recorder.now_millis(), event_time),
+        })
+    }
+}
@@ -0,0 +1,213 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    bybit::{ping_message, send_operation, BybitOpResponse, PING_INTERVAL_SECS},
+    health::StreamMonitor,
+    markprice::MarkPriceData,
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use rust_decimal::prelude::FromStr;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/* Bybit Ticker Stream (Mark price, index price, funding) */
+
+// The first message of a symbol is a "snapshot" with every field.
+// Following "delta" messages only carry the fields that changed.
+
+#[derive(Debug, Deserialize)]
+pub struct BybitTickerMessage {
+    #[serde(rename = "type")]
+    pub type_fields: String, // "snapshot" or "delta"
+    pub ts: u64, // When the message was sent
+    pub data: TickerData,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct TickerData {
+    pub symbol: String,
+    pub markPrice: Option<String>,
+    pub indexPrice: Option<String>,
+    pub fundingRate: Option<String>,
+    pub nextFundingTime: Option<String>,
+}
+
+// Latest known value of every field of a symbol
+#[derive(Debug, Default)]
+struct TickerState {
+    mark_price: Option<Decimal>,
+    index_price: Option<Decimal>,
+    funding_rate: Option<Decimal>,
+    next_funding_time: Option<u64>,
+}
+
+impl TickerState {
+    /// Merge a snapshot or delta. `None` if a field fails to parse
+    fn merge(&mut self, data: &TickerData) -> Option<()> {
+        fn field<T: FromStr>(value: &Option<String>, current: &mut Option<T>) -> Option<()> {
+            if let Some(value) = value.as_deref().filter(|value| !value.is_empty()) {
+                *current = Some(value.parse::<T>().ok()?);
+            }
+            Some(())
+        }
+
+        field(&data.markPrice, &mut self.mark_price)?;
+        field(&data.indexPrice, &mut self.index_price)?;
+        field(&data.fundingRate, &mut self.funding_rate)?;
+        field(&data.nextFundingTime, &mut self.next_funding_time)
+    }
+}
+
+pub struct BybitFutureTickerStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarkPriceData>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for BybitFutureTickerStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let (read, mut write) = recorder.connect(&endpoint.ws).await?;
+
+            send_operation(&mut write, "subscribe", "tickers", &symbols).await?;
+
+            let handler = BybitFutureTickerStreamHandler {
+                symbols,
+                tx,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_ticker(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+impl BybitFutureTickerStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarkPriceData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_ticker<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        let mut tickers: HashMap<String, TickerState> = HashMap::new();
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(ping_message()).await {
+                        error!("Bybit ticker stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            match serde_json::from_str::<BybitTickerMessage>(&text) {
+                                Ok(ticker) => {
+                                    let state = tickers.entry(ticker.data.symbol.clone()).or_default();
+                                    if ticker.type_fields == "snapshot" {
+                                        *state = TickerState::default();
+                                    }
+                                    if state.merge(&ticker.data).is_none() {
+                                        error!("Bybit ticker stream: Invalid value in {:?}", ticker.data);
+                                        self.monitor.incr("parse_failures");
+                                        continue;
+                                    }
+
+                                    // Nothing to send until every field is known
+                                    let Some(update) = self.generate_markprice_update(&ticker, state) else {
+                                        continue;
+                                    };
+                                    if self.tx.send(update).await.is_err() {
+                                        error!("Bybit ticker stream: Failed to send update");
+                                    }
+                                }
+                                Err(e) => match serde_json::from_str::<BybitOpResponse>(&text) {
+                                    Ok(response) if response.success => (),
+                                    Ok(response) => error!("Bybit ticker stream: {} failed: {}", response.op, response.ret_msg),
+                                    Err(_) => {
+                                        error!("Bybit ticker stream: Failed to parse message: {}", e);
+                                        self.monitor.incr("parse_failures");
+                                    }
+                                },
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("Bybit ticker stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("Bybit ticker stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("Bybit ticker stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("Bybit ticker stream: WebSocket error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn generate_markprice_update(
+        &self,
+        update: &BybitTickerMessage,
+        state: &TickerState,
+    ) -> Option<MarkPriceData> {
+        Some(MarkPriceData {
+            symbol: update.data.symbol.clone(),
+            mark_price: state.mark_price?,
+            index_price: state.index_price?,
+            funding_rate: state.funding_rate?,
+            next_funding_time: state.next_funding_time?,
+            event_time: update.ts,
+            received: self.monitor.stamp(self.recorder.now_millis(), update.ts),
+        })
+    }
+}
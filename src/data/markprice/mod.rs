@@ -1,10 +1,18 @@
 pub mod binance;
+pub mod bitget;
+pub mod bybit;
+pub mod okx;
+
+use crate::data::latency::ReceiveStamp;
+use rust_decimal::Decimal;
 
 #[derive(Debug)]
 pub struct MarkPriceData {
-    pub mark_price: String,
-    pub index_price: String,
-    pub funding_rate: String,
+    pub symbol: String,
+    pub mark_price: Decimal,
+    pub index_price: Decimal,
+    pub funding_rate: Decimal,
     pub next_funding_time: u64,
     pub event_time: u64,
+    pub received: ReceiveStamp,
 }
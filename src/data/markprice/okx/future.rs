@@ -0,0 +1,277 @@
+use crate::data::endpoints::Endpoint;
+use crate::data::recorder::FrameRecorder;
+use crate::data::{
+    health::StreamMonitor,
+    markprice::MarkPriceData,
+    okx::{send_operation, OkxEvent, OkxStreamArg, PING_INTERVAL_SECS},
+    router::SymbolRouter,
+    stream::StreamHandler,
+};
+use futures::{SinkExt, StreamExt};
+use log::{error, info};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/* OKX Mark Price Stream (Mark price, index price, funding) */
+
+// OKX splits what Binance sends in `markPrice` into three channels:
+// - mark-price:    markPx of the swap
+// - funding-rate:  fundingRate and fundingTime (next settlement) of the swap
+// - index-tickers: idxPx of the underlying index, e.g. "BTC-USDT" for "BTC-USDT-SWAP"
+// They are merged per swap and sent once every field is known.
+
+#[derive(Debug, Deserialize)]
+pub struct OkxChannelHeader {
+    pub arg: OkxStreamArg,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OkxDataMessage<T> {
+    pub arg: OkxStreamArg,
+    pub data: Vec<T>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct MarkPxData {
+    pub instId: String,
+    pub markPx: String,
+    pub ts: String,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct FundingRateData {
+    pub instId: String,
+    pub fundingRate: String,
+    pub fundingTime: String, // Next settlement time
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct IndexTickerData {
+    pub idxPx: String,
+}
+
+// Latest known value of every field of a swap
+#[derive(Debug, Default)]
+struct MarkPriceState {
+    mark_price: Option<Decimal>,
+    index_price: Option<Decimal>,
+    funding_rate: Option<Decimal>,
+    next_funding_time: Option<u64>,
+    event_time: Option<u64>,
+}
+
+pub struct OkxFutureMarkPriceStreamHandler {
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<MarkPriceData>,
+    endpoint: Endpoint,
+    monitor: StreamMonitor,
+    recorder: FrameRecorder,
+}
+
+impl StreamHandler for OkxFutureMarkPriceStreamHandler {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let symbols = self.symbols.clone();
+        let tx = self.tx.clone();
+        let endpoint = self.endpoint.clone();
+        let monitor = self.monitor.clone();
+        let recorder = self.recorder.clone();
+
+        Box::new(Box::pin(async move {
+            let (read, mut write) = recorder.connect(&endpoint.ws).await?;
+
+            let mut args = Vec::new();
+            for symbol in symbols.iter() {
+                args.push(OkxStreamArg::instrument("mark-price", symbol));
+                args.push(OkxStreamArg::instrument("funding-rate", symbol));
+                args.push(OkxStreamArg::instrument("index-tickers", &index_of(symbol)));
+            }
+            send_operation(&mut write, "subscribe", args).await?;
+
+            let handler = OkxFutureMarkPriceStreamHandler {
+                symbols,
+                tx,
+                endpoint,
+                monitor,
+                recorder,
+            };
+            handler.monitor.live();
+            handler.handle_markprice(read, write).await;
+
+            Ok(())
+        }))
+    }
+}
+
+/// Index of a swap, e.g. "BTC-USDT-SWAP" -> "BTC-USDT"
+fn index_of(symbol: &str) -> String {
+    let symbol = symbol.to_uppercase();
+    match symbol.strip_suffix("-SWAP") {
+        Some(index) => index.to_string(),
+        None => symbol,
+    }
+}
+
+impl OkxFutureMarkPriceStreamHandler {
+    pub fn new(
+        symbols: Vec<String>,
+        tx: SymbolRouter<MarkPriceData>,
+        endpoint: Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            symbols,
+            tx,
+            endpoint,
+            monitor,
+            recorder: FrameRecorder::disabled(),
+        }
+    }
+
+    /// Record the socket and REST traffic, or play it back from a capture
+    pub fn with_recorder(mut self, recorder: FrameRecorder) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
+    pub async fn handle_markprice<R, S>(&self, mut read: R, mut write: S)
+    where
+        R: StreamExt<Item = Result<Message, tungstenite::Error>> + Unpin,
+        S: SinkExt<Message> + Unpin,
+        S::Error: std::fmt::Display,
+    {
+        let mut states: HashMap<String, MarkPriceState> = HashMap::new();
+        let mut ping = tokio::time::interval(tokio::time::Duration::from_secs(PING_INTERVAL_SECS));
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if let Err(e) = write.send(Message::Text("ping".into())).await {
+                        error!("OKX mark price stream: Failed to send ping: {}", e);
+                        break;
+                    }
+                }
+
+                msg = read.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) if text.as_str() == "pong" => (),
+                        Some(Ok(Message::Text(text))) => {
+                            let header = match serde_json::from_str::<OkxChannelHeader>(&text) {
+                                Ok(header) => header,
+                                Err(e) => {
+                                    match serde_json::from_str::<OkxEvent>(&text) {
+                                        Ok(event) if event.event == "error" => {
+                                            error!("OKX mark price stream: {:?} {:?}", event.code, event.msg)
+                                        }
+                                        Ok(event) => info!("OKX mark price stream: {}", event.event),
+                                        Err(_) => {
+                                            error!("OKX mark price stream: Failed to parse message: {}", e);
+                                            self.monitor.incr("parse_failures");
+                                        }
+                                    }
+                                    continue;
+                                }
+                            };
+
+                            let Some(symbols) = self.merge(&mut states, &header.arg.channel, &text) else {
+                                error!("OKX mark price stream: Invalid message {}", text);
+                                self.monitor.incr("parse_failures");
+                                continue;
+                            };
+
+                            for symbol in symbols {
+                                let Some(update) = self.generate_markprice_update(&symbol, &states[&symbol]) else {
+                                    continue; // Not every field is known yet
+                                };
+                                if self.tx.send(update).await.is_err() {
+                                    error!("OKX mark price stream: Failed to send update");
+                                }
+                            }
+                        }
+                        Some(Ok(Message::Ping(payload))) => {
+                            if let Err(e) = write.send(Message::Pong(payload)).await {
+                                error!("OKX mark price stream: Failed to send Pong: {}", e);
+                            }
+                        }
+                        Some(Ok(Message::Pong(_))) => info!("OKX mark price stream: Pong received"),
+                        Some(Ok(Message::Close(_))) | None => {
+                            info!("OKX mark price stream: Connection closed");
+                            break;
+                        }
+                        Some(Ok(_)) => (),
+                        Some(Err(e)) => {
+                            error!("OKX mark price stream: WebSocket error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Merge a channel message into the states. Returns the swaps that changed,
+    /// `None` if the message is malformed.
+    fn merge(
+        &self,
+        states: &mut HashMap<String, MarkPriceState>,
+        channel: &str,
+        text: &str,
+    ) -> Option<Vec<String>> {
+        let mut changed = Vec::new();
+        match channel {
+            "mark-price" => {
+                let msg = serde_json::from_str::<OkxDataMessage<MarkPxData>>(text).ok()?;
+                for data in msg.data {
+                    let state = states.entry(data.instId.clone()).or_default();
+                    state.mark_price = Some(data.markPx.parse().ok()?);
+                    state.event_time = Some(data.ts.parse().ok()?);
+                    changed.push(data.instId);
+                }
+            }
+            "funding-rate" => {
+                let msg = serde_json::from_str::<OkxDataMessage<FundingRateData>>(text).ok()?;
+                for data in msg.data {
+                    let state = states.entry(data.instId.clone()).or_default();
+                    state.funding_rate = Some(data.fundingRate.parse().ok()?);
+                    state.next_funding_time = Some(data.fundingTime.parse().ok()?);
+                    changed.push(data.instId);
+                }
+            }
+            "index-tickers" => {
+                let msg = serde_json::from_str::<OkxDataMessage<IndexTickerData>>(text).ok()?;
+                let index_price = msg.data.first()?.idxPx.parse().ok()?;
+                // An index can back several of our swaps
+                for symbol in self.symbols.iter().map(|symbol| symbol.to_uppercase()) {
+                    if index_of(&symbol) == msg.arg.instId.clone()? {
+                        states.entry(symbol.clone()).or_default().index_price = Some(index_price);
+                        changed.push(symbol);
+                    }
+                }
+            }
+            _ => (),
+        }
+        Some(changed)
+    }
+
+    fn generate_markprice_update(
+        &self,
+        symbol: &str,
+        state: &MarkPriceState,
+    ) -> Option<MarkPriceData> {
+        let event_time = state.event_time?;
+        Some(MarkPriceData {
+            symbol: symbol.to_string(),
+            mark_price: state.mark_price?,
+            index_price: state.index_price?,
+            funding_rate: state.funding_rate?,
+            next_funding_time: state.next_funding_time?,
+            event_time,
+            received: self.monitor.stamp(self.recorder.now_millis(), event_time),
+        })
+    }
+}
@@ -1,8 +1,24 @@
+pub mod bbo;
 pub mod binance;
+pub mod bitget;
+pub mod bithumb;
+pub mod bybit;
+pub mod clock;
 pub mod depth;
+pub mod endpoints;
 pub mod exchanges;
+pub mod fault;
+pub mod health;
+pub mod latency;
+pub mod limiter;
 pub mod liquidation;
 pub mod market;
 pub mod markprice;
+pub mod okx;
+pub mod recorder;
+pub mod replay;
+pub mod router;
+pub mod stats;
 pub mod stream;
+pub mod supervisor;
 pub mod upbit;
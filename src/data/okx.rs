@@ -0,0 +1,195 @@
+use crate::data::{
+    depth::okx::future::OkxFutureOrderbookStreamHandler, endpoints::Endpoint,
+    exchanges::FutureDataChannels, liquidation::okx::future::OkxFutureLiquidationStreamHandler,
+    market::okx::future::OkxFutureTradeStreamHandler,
+    markprice::okx::future::OkxFutureMarkPriceStreamHandler, recorder::FrameRecorder,
+    stream::io_error, supervisor::Supervisor,
+};
+use futures::{Sink, SinkExt};
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::task::JoinSet;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+// OKX v5 public streams for USDT swaps. Symbols are instrument ids, e.g. "BTC-USDT-SWAP"
+// https://www.okx.com/docs-v5/en/#overview-websocket
+
+// OKX closes connections without traffic for 30 seconds
+pub const PING_INTERVAL_SECS: u64 = 25;
+
+const SYMBOLS_PER_CONNECTION: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(non_snake_case)]
+pub struct OkxStreamArg {
+    pub channel: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instId: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instType: Option<String>,
+}
+
+impl OkxStreamArg {
+    pub fn instrument(channel: &str, symbol: &str) -> Self {
+        Self {
+            channel: channel.to_string(),
+            instId: Some(symbol.to_uppercase()),
+            instType: None,
+        }
+    }
+}
+
+/// Reply to `subscribe` and `unsubscribe`, or an error
+#[derive(Debug, Deserialize)]
+pub struct OkxEvent {
+    pub event: String, // "subscribe", "unsubscribe" or "error"
+    pub code: Option<String>,
+    pub msg: Option<String>,
+}
+
+pub struct OkxThreads {
+    future: FutureDataChannels,
+    supervisor: Supervisor,
+    endpoint: Endpoint,
+}
+
+impl OkxThreads {
+    pub fn new(future: FutureDataChannels, supervisor: Supervisor, endpoint: Endpoint) -> Self {
+        Self {
+            future,
+            supervisor,
+            endpoint,
+        }
+    }
+
+    pub fn spawn_streams(self, tasks: &mut JoinSet<()>, future_symbols: Vec<String>) {
+        if future_symbols.is_empty() {
+            warn!("No symbols specified, skipping OKX streams");
+            return;
+        }
+
+        // Future Streams
+        for (shard, symbols) in future_symbols.chunks(SYMBOLS_PER_CONNECTION).enumerate() {
+            info!("Starting OKX Future Streams for {:?}", symbols);
+            self.supervisor
+                .spawn(tasks, &format!("okx/future/trade/{}", shard), |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    OkxFutureTradeStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.agg_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                });
+            self.supervisor.spawn(
+                tasks,
+                &format!("okx/future/orderbook/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    let faults = self.supervisor.faults(&monitor);
+                    OkxFutureOrderbookStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.ob_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                    .with_faults(faults)
+                },
+            );
+            self.supervisor.spawn(
+                tasks,
+                &format!("okx/future/markprice/{}", shard),
+                |monitor| {
+                    let recorder = self.supervisor.recorder(&monitor);
+                    OkxFutureMarkPriceStreamHandler::new(
+                        symbols.to_vec(),
+                        self.future.mark_out.clone(),
+                        self.endpoint.clone(),
+                        monitor,
+                    )
+                    .with_recorder(recorder)
+                },
+            );
+        }
+
+        // Liquidations are only published for the whole instrument type
+        self.supervisor
+            .spawn(tasks, "okx/future/liquidation", |monitor| {
+                let recorder = self.supervisor.recorder(&monitor);
+                OkxFutureLiquidationStreamHandler::new(
+                    future_symbols,
+                    self.future.liq_out,
+                    self.endpoint.clone(),
+                    monitor,
+                )
+                .with_recorder(recorder)
+            });
+    }
+}
+
+pub async fn send_operation<S>(
+    write: &mut S,
+    op: &str,
+    args: Vec<OkxStreamArg>,
+) -> Result<(), S::Error>
+where
+    S: Sink<Message> + Unpin,
+{
+    let request = serde_json::json!({ "op": op, "args": args }).to_string();
+    write.send(Message::Text(request.into())).await
+}
+
+/* Contract Values */
+
+// Swap sizes are in contracts. One contract is `ctVal` coins (e.g. 0.01 BTC for BTC-USDT-SWAP),
+// sizes are converted so that OKX quantities compare with the other venues.
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct Instrument {
+    instId: String,
+    ctVal: Decimal,
+}
+
+#[derive(Debug, Deserialize)]
+struct InstrumentsResponse {
+    code: String,
+    msg: String,
+    data: Vec<Instrument>,
+}
+
+/// Contract value of every symbol, keyed by instrument id
+pub async fn fetch_contract_values(
+    endpoint: &Endpoint,
+    symbols: &[String],
+    recorder: &FrameRecorder,
+) -> Result<HashMap<String, Decimal>, tungstenite::Error> {
+    // https://www.okx.com/docs-v5/en/#public-data-rest-api-get-instruments
+    let request = reqwest::Client::new()
+        .get(format!(
+            "{}/api/v5/public/instruments?instType=SWAP",
+            endpoint.rest
+        ))
+        .timeout(std::time::Duration::from_secs(10));
+    let body = recorder.fetch(request).await?;
+    let response = serde_json::from_slice::<InstrumentsResponse>(&body).map_err(io_error)?;
+
+    if response.code != "0" {
+        return Err(tungstenite::Error::Io(std::io::Error::other(response.msg)));
+    }
+
+    Ok(response
+        .data
+        .into_iter()
+        .filter(|instrument| {
+            symbols
+                .iter()
+                .any(|symbol| symbol.eq_ignore_ascii_case(&instrument.instId))
+        })
+        .map(|instrument| (instrument.instId, instrument.ctVal))
+        .collect())
+}
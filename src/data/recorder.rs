@@ -0,0 +1,564 @@
+use crate::data::health::StreamMonitor;
+use crate::data::replay::{ReplayConnection, ReplaySlot};
+use crate::data::stream::io_error;
+use crate::data::supervisor::rate_limited;
+use chrono::DateTime;
+use flate2::{write::GzEncoder, Compression};
+use futures::{stream::SplitSink, stream::SplitStream, Sink, Stream, StreamExt};
+use log::{error, info};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{self, Bytes, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+/* Raw Frame Recorder */
+
+// Everything a recorded stream receives is appended, as received, to
+//      <dir>/<stream name>/<YYYY-MM-DDTHH>.frames.gz    One file per UTC hour of receive time
+// A file is a series of gzip members, one per `BLOCK_INTERVAL` of traffic, so `zcat` reads
+// the whole hour and a reader can start decoding at any member. Inside, every frame is
+//      u64 LE receive time (unix nanos) | u8 kind | u32 LE length | payload
+// Kinds:
+//      1: Text frame       2: Binary frame
+//      3: REST response, "<path>?<query>\n<body>"    4: Socket opened, "<url>"
+// The index next to it, <YYYY-MM-DDTHH>.index, has one line per member:
+//      <receive time of its first frame> <byte offset of the member> <frames in the member>
+// Frames are recorded before fault injection. Files are only appended to, so a restart within
+// the hour carries on the same file.
+// When replaying, the recorder plays the capture back instead: the socket and the REST
+// responses come from the capture, and the clock is the receive time of the replayed frame
+
+const BLOCK_INTERVAL: Duration = Duration::from_secs(1);
+const QUEUE_CAPACITY: usize = 65536; // Frames waiting for the writer. Beyond that they are dropped
+const NANOS_PER_HOUR: u64 = 3_600_000_000_000;
+
+pub const FRAME_TEXT: u8 = 1;
+pub const FRAME_BINARY: u8 = 2;
+pub const FRAME_REST: u8 = 3;
+pub const FRAME_CONNECT: u8 = 4;
+
+type LiveSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    pub streams: Vec<String>, // Stream name prefixes, e.g. "upbit/krw". Empty: Every stream
+}
+
+impl RecorderConfig {
+    fn applies_to(&self, name: &str) -> bool {
+        self.streams.is_empty() || self.streams.iter().any(|prefix| name.starts_with(prefix))
+    }
+}
+
+struct RecordedFrame {
+    received_ns: u64,
+    kind: u8,
+    payload: Bytes,
+}
+
+/// Writer tasks of every recorded stream. Closing them writes out the frames they still hold
+#[derive(Debug, Clone)]
+pub struct RecorderWriters {
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    closing: Arc<watch::Sender<bool>>,
+}
+
+impl Default for RecorderWriters {
+    fn default() -> Self {
+        Self {
+            handles: Arc::default(),
+            closing: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl RecorderWriters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn spawn(&self, dir: PathBuf, rx: mpsc::Receiver<RecordedFrame>, monitor: &StreamMonitor) {
+        let closing = self.closing.subscribe();
+        let handle = tokio::spawn(write_frames(dir, rx, closing, monitor.clone()));
+        self.handles.lock().unwrap().push(handle);
+    }
+
+    /// Stop taking frames and wait until every writer has flushed the ones it was sent
+    pub async fn close(&self) {
+        self.closing.send_replace(true);
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        for handle in handles {
+            if let Err(e) = handle.await {
+                error!("Recorder: Writer failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Per-stream recorder. Disabled by default, in which case sockets and requests go
+/// out untouched. Clones feed the same writer, so a stream keeps its files across reconnects
+#[derive(Clone, Default)]
+pub struct FrameRecorder {
+    tx: Option<mpsc::Sender<RecordedFrame>>,
+    monitor: Option<StreamMonitor>,
+    replay: Option<ReplaySlot>,
+}
+
+impl FrameRecorder {
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Spawn the writer of the stream of `monitor` into `writers`.
+    /// Disabled if the config does not cover it
+    pub fn new(
+        config: &RecorderConfig,
+        monitor: &StreamMonitor,
+        writers: &RecorderWriters,
+    ) -> Self {
+        if !config.applies_to(monitor.name()) {
+            return Self::disabled();
+        }
+        let dir = config.dir.join(monitor.name());
+        info!("{}: Recording frames to {}", monitor.name(), dir.display());
+
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        writers.spawn(dir, rx, monitor);
+        Self {
+            tx: Some(tx),
+            monitor: Some(monitor.clone()),
+            replay: None,
+        }
+    }
+
+    /// Play the capture of `slot` back instead of going to the exchange
+    pub fn replaying(slot: ReplaySlot) -> Self {
+        Self {
+            tx: None,
+            monitor: None,
+            replay: Some(slot),
+        }
+    }
+
+    /// Open the socket, recording every frame read from it
+    pub async fn connect(
+        &self,
+        url: &str,
+    ) -> Result<(SocketRead, SocketWrite), tungstenite::Error> {
+        if let Some(slot) = &self.replay {
+            return Ok((SocketRead::Replay(slot.connect()), SocketWrite::Replay));
+        }
+        let (ws_stream, _) = connect_async(url).await?;
+        self.record(FRAME_CONNECT, Bytes::copy_from_slice(url.as_bytes()));
+        let (write, read) = ws_stream.split();
+        let read = SocketRead::Live {
+            inner: read,
+            recorder: self.clone(),
+        };
+        Ok((read, SocketWrite::Live(write)))
+    }
+
+    /// Send `request` and return the response body, recorded next to the frames.
+    /// Replays hand out the recorded body of the same path and query instead
+    pub async fn fetch(&self, request: reqwest::RequestBuilder) -> Result<Bytes, RestError> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let target = match request.url().query() {
+            Some(query) => format!("{}?{}", request.url().path(), query),
+            None => request.url().path().to_string(),
+        };
+        if let Some(slot) = &self.replay {
+            return Ok(slot.fetch(target).await);
+        }
+
+        let response = client.execute(request).await?;
+        // 429: Rate limited, 418: IP banned. The supervisor backs off as for a rejected socket
+        let status = response.status().as_u16();
+        if status == 429 || status == 418 {
+            return Err(RestError::RateLimited {
+                status,
+                retry_after: response
+                    .headers()
+                    .get("retry-after")
+                    .map(|value| value.as_bytes().to_vec()),
+            });
+        }
+        let body = response.error_for_status()?.bytes().await?;
+        if self.tx.is_some() {
+            let mut payload = Vec::with_capacity(target.len() + 1 + body.len());
+            payload.extend_from_slice(target.as_bytes());
+            payload.push(b'\n');
+            payload.extend_from_slice(&body);
+            self.record(FRAME_REST, Bytes::from(payload));
+        }
+        Ok(body)
+    }
+
+    /// Whether the traffic comes from a capture. Replays skip waits between requests:
+    /// responses are handed out when the capture reaches them
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Receive time in unix millis, for messages the exchange does not stamp.
+    /// Replays use the receive time of the frame being replayed
+    pub fn now_millis(&self) -> u64 {
+        match &self.replay {
+            Some(slot) => slot.now_millis(),
+            None => now_nanos() / 1_000_000,
+        }
+    }
+
+    fn record_message(&self, msg: &Message) {
+        let kind = match msg {
+            Message::Text(_) => FRAME_TEXT,
+            Message::Binary(_) => FRAME_BINARY,
+            _ => return,
+        };
+        self.record(kind, msg.clone().into_data());
+    }
+
+    fn record(&self, kind: u8, payload: Bytes) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let frame = RecordedFrame {
+            received_ns: now_nanos(),
+            kind,
+            payload,
+        };
+        // Never hold the socket up for the disk
+        if tx.try_send(frame).is_err() {
+            if let Some(monitor) = &self.monitor {
+                monitor.incr("record_dropped");
+            }
+        }
+    }
+}
+
+/// REST failures: the request itself, a rate limit or ban, or a body that does not parse
+#[derive(Debug)]
+pub enum RestError {
+    Request(reqwest::Error),
+    RateLimited {
+        status: u16,
+        retry_after: Option<Vec<u8>>,
+    },
+    Parse(serde_json::Error),
+}
+
+impl RestError {
+    /// The rate limit or ban as the supervisor reads it, None for any other failure.
+    /// Handlers that retry REST themselves end the connection with it instead
+    pub fn ban(&self) -> Option<tungstenite::Error> {
+        match self {
+            RestError::RateLimited {
+                status,
+                retry_after,
+            } => Some(rate_limited(*status, retry_after.as_deref())),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for RestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestError::Request(e) => write!(f, "{}", e),
+            RestError::RateLimited { status, .. } => write!(f, "rate limited ({})", status),
+            RestError::Parse(e) => write!(f, "invalid response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RestError {}
+
+impl From<RestError> for tungstenite::Error {
+    fn from(e: RestError) -> Self {
+        e.ban().unwrap_or_else(|| io_error(e))
+    }
+}
+
+impl From<reqwest::Error> for RestError {
+    fn from(e: reqwest::Error) -> Self {
+        RestError::Request(e)
+    }
+}
+
+impl From<serde_json::Error> for RestError {
+    fn from(e: serde_json::Error) -> Self {
+        RestError::Parse(e)
+    }
+}
+
+/* Socket Halves */
+
+pub enum SocketRead {
+    Live {
+        inner: SplitStream<LiveSocket>,
+        recorder: FrameRecorder,
+    },
+    Replay(ReplayConnection),
+}
+
+impl Stream for SocketRead {
+    type Item = Result<Message, tungstenite::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match &mut *self {
+            SocketRead::Live { inner, recorder } => {
+                let poll = inner.poll_next_unpin(cx);
+                if let Poll::Ready(Some(Ok(msg))) = &poll {
+                    recorder.record_message(msg);
+                }
+                poll
+            }
+            SocketRead::Replay(connection) => connection.poll_next_unpin(cx),
+        }
+    }
+}
+
+/// Replayed sockets swallow whatever the handler sends
+pub enum SocketWrite {
+    Live(SplitSink<LiveSocket, Message>),
+    Replay,
+}
+
+impl Sink<Message> for SocketWrite {
+    type Error = tungstenite::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match &mut *self {
+            SocketWrite::Live(inner) => Pin::new(inner).poll_ready(cx),
+            SocketWrite::Replay => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        match &mut *self {
+            SocketWrite::Live(inner) => Pin::new(inner).start_send(item),
+            SocketWrite::Replay => Ok(()),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match &mut *self {
+            SocketWrite::Live(inner) => Pin::new(inner).poll_flush(cx),
+            SocketWrite::Replay => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match &mut *self {
+            SocketWrite::Live(inner) => Pin::new(inner).poll_close(cx),
+            SocketWrite::Replay => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+/* Capture Files */
+
+// Frames of the current member, compressed as they come
+struct Block {
+    encoder: GzEncoder<Vec<u8>>,
+    first_ns: u64,
+    frames: u64,
+}
+
+impl Block {
+    fn new() -> Self {
+        Self {
+            encoder: GzEncoder::new(Vec::new(), Compression::default()),
+            first_ns: 0,
+            frames: 0,
+        }
+    }
+
+    fn push(&mut self, frame: &RecordedFrame) -> std::io::Result<()> {
+        if self.frames == 0 {
+            self.first_ns = frame.received_ns;
+        }
+        self.encoder.write_all(&frame.received_ns.to_le_bytes())?;
+        self.encoder.write_all(&[frame.kind])?;
+        self.encoder
+            .write_all(&(frame.payload.len() as u32).to_le_bytes())?;
+        self.encoder.write_all(&frame.payload)?;
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+struct CaptureFile {
+    hour: u64, // Hours since the epoch
+    frames_path: PathBuf,
+    index_path: PathBuf,
+    offset: u64, // Current length of the frames file
+}
+
+impl CaptureFile {
+    async fn open(dir: &Path, hour: u64) -> std::io::Result<Self> {
+        fs::create_dir_all(dir).await?;
+        let name = DateTime::from_timestamp_nanos((hour * NANOS_PER_HOUR) as i64)
+            .format("%Y-%m-%dT%H")
+            .to_string();
+        let frames_path = dir.join(format!("{}.frames.gz", name));
+        let index_path = dir.join(format!("{}.index", name));
+        let offset = match fs::metadata(&frames_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        Ok(Self {
+            hour,
+            frames_path,
+            index_path,
+            offset,
+        })
+    }
+
+    /// Append `block` as one gzip member and index it
+    async fn append(&mut self, block: Block) -> std::io::Result<()> {
+        let (first_ns, frames) = (block.first_ns, block.frames);
+        let member = block.encoder.finish()?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.frames_path)
+            .await?;
+        file.write_all(&member).await?;
+        file.flush().await?;
+
+        let mut index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.index_path)
+            .await?;
+        index
+            .write_all(format!("{} {} {}\n", first_ns, self.offset, frames).as_bytes())
+            .await?;
+        index.flush().await?;
+
+        self.offset += member.len() as u64;
+        Ok(())
+    }
+}
+
+async fn write_frames(
+    dir: PathBuf,
+    mut rx: mpsc::Receiver<RecordedFrame>,
+    mut closing: watch::Receiver<bool>,
+    monitor: StreamMonitor,
+) {
+    let mut capture: Option<CaptureFile> = None;
+    let mut block = Block::new();
+    let mut interval = tokio::time::interval(BLOCK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            // Frames already queued are still received, then `recv` ends the loop
+            _ = closing.changed(), if !rx.is_closed() => rx.close(),
+            frame = rx.recv() => {
+                let Some(frame) = frame else {
+                    break;
+                };
+                // Hourly rotation, by receive time
+                let hour = frame.received_ns / NANOS_PER_HOUR;
+                if capture.as_ref().is_none_or(|capture| capture.hour != hour) {
+                    flush_block(&mut capture, &mut block, &monitor).await;
+                    capture = match CaptureFile::open(&dir, hour).await {
+                        Ok(capture) => Some(capture),
+                        Err(e) => {
+                            error!("{} recorder: Failed to open capture file in {}: {}", monitor.name(), dir.display(), e);
+                            None
+                        }
+                    };
+                }
+                if let Err(e) = block.push(&frame) {
+                    error!("{} recorder: Failed to compress frame: {}", monitor.name(), e);
+                    monitor.incr("record_dropped");
+                }
+            }
+            _ = interval.tick() => flush_block(&mut capture, &mut block, &monitor).await,
+        }
+    }
+    flush_block(&mut capture, &mut block, &monitor).await;
+}
+
+async fn flush_block(
+    capture: &mut Option<CaptureFile>,
+    block: &mut Block,
+    monitor: &StreamMonitor,
+) {
+    if block.frames == 0 {
+        return;
+    }
+    let full = std::mem::replace(block, Block::new());
+    let frames = full.frames;
+    let Some(capture) = capture.as_mut() else {
+        monitor.add("record_dropped", frames);
+        return;
+    };
+    match capture.append(full).await {
+        Ok(()) => monitor.add("recorded_frames", frames),
+        Err(e) => {
+            error!(
+                "{} recorder: Failed to write {}: {}",
+                monitor.name(),
+                capture.frames_path.display(),
+                e
+            );
+            monitor.add("record_dropped", frames);
+        }
+    }
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::health::StreamHealth;
+
+    #[tokio::test]
+    async fn close_writes_out_the_queued_frames() {
+        let dir = std::env::temp_dir().join(format!("recorder-test-{}", now_nanos()));
+        let config = RecorderConfig {
+            dir: dir.clone(),
+            streams: Vec::new(),
+        };
+        let health = StreamHealth::new();
+        let monitor = health.monitor("binance/future/orderbook/0");
+        let writers = RecorderWriters::new();
+        let recorder = FrameRecorder::new(&config, &monitor, &writers);
+
+        for i in 0..3 {
+            recorder.record(FRAME_TEXT, Bytes::from(format!("frame {}", i)));
+        }
+        // The recorder is still alive, closing does not wait for it to go
+        writers.close().await;
+
+        let counters = &health.snapshot()[0].1.counters;
+        assert_eq!(counters.get("recorded_frames"), Some(&3));
+        let files = std::fs::read_dir(dir.join(monitor.name())).unwrap().count();
+        assert_eq!(files, 2); // Frames and index
+        drop(recorder);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
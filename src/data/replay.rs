@@ -0,0 +1,632 @@
+use crate::data::clock::Clock;
+use crate::data::exchanges::{FutureDataChannels, SpotDataChannels};
+use crate::data::recorder::{FRAME_BINARY, FRAME_CONNECT, FRAME_REST, FRAME_TEXT};
+use flate2::read::MultiGzDecoder;
+use futures::Stream;
+use log::{error, info, warn};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::{self, Bytes, Message, Utf8Bytes};
+
+/* Capture Replay */
+
+// Plays captures written by the recorder back through the live handlers, so frames take
+// the same parse path into the engine channels. Every stream of the capture directory is
+// matched by name with a configured stream; the symbols must be the ones of the recording.
+// The captures are merged by receive time and released one entry at a time:
+// - Socket opened: The previous connection of the stream ends and the handler reconnects
+// - Frame: Read by the handler from its socket
+// - REST response: Handed to the request of the same path and query, in recorded order
+// The next entry only goes out once the handler is back to reading (or waits for a REST
+// response still to come) and every engine channel is empty. Replays run on a single
+// thread, so the same capture always reaches the engine in the same order.
+// Polled REST stats are not captured and do not run while replaying
+
+// A handler that neither reads nor waits on REST for this long fails the replay. Going on
+// without it would hand the engine a different stream depending on the machine's pace
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+const FRAME_HEADER_LEN: usize = 13; // u64 receive time | u8 kind | u32 length
+
+/// A stream stopped taking what the capture hands it. The replay ends there
+#[derive(Debug)]
+pub struct ReplayStalled {
+    pub stream: String,
+    pub waiting_for: &'static str,
+}
+
+impl std::fmt::Display for ReplayStalled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} stalled waiting for the {}",
+            self.stream, self.waiting_for
+        )
+    }
+}
+
+impl std::error::Error for ReplayStalled {}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReplaySpeed {
+    Max,         // As fast as the engine keeps up
+    Factor(f64), // 1.0 is real time
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    pub dir: PathBuf, // Root of the capture, as in `RECORD_DIR`
+    pub speed: ReplaySpeed,
+}
+
+struct Shared {
+    clock: Clock, // Receive time of the entry being replayed
+    slots: Mutex<BTreeMap<String, ReplaySlot>>,
+}
+
+/// Replay of one capture directory. Clones share the streams
+#[derive(Clone)]
+pub struct Replay {
+    config: ReplayConfig,
+    shared: Arc<Shared>,
+}
+
+impl std::fmt::Debug for Replay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Replay")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl Replay {
+    pub fn new(config: ReplayConfig) -> Self {
+        Self {
+            config,
+            shared: Arc::new(Shared {
+                clock: Clock::manual(0),
+                slots: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.config.dir
+    }
+
+    /// Receive time of the entry being replayed, for the cores
+    pub fn clock(&self) -> Clock {
+        self.shared.clock.clone()
+    }
+
+    /// Register the stream `name`. Its handler reads the capture through the slot
+    pub fn slot(&self, name: &str) -> ReplaySlot {
+        self.shared
+            .slots
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| ReplaySlot {
+                name: name.to_string(),
+                state: Arc::new(Mutex::new(SlotState::default())),
+                progress: Arc::new(Notify::new()),
+                shared: self.shared.clone(),
+            })
+            .clone()
+    }
+
+    /// Play the whole capture back. `future_routes` and `spot_routes` are the engine
+    /// channels the handlers feed, they must be empty before the next entry goes out.
+    /// Fails on the first stream that stalls, the engine state is then incomplete
+    pub async fn run(
+        self,
+        future_routes: Vec<FutureDataChannels>,
+        spot_routes: Vec<SpotDataChannels>,
+    ) -> Result<(), ReplayStalled> {
+        let engine = Engine {
+            future_routes: &future_routes,
+            spot_routes: &spot_routes,
+        };
+
+        let slots = self.shared.slots.lock().unwrap().clone();
+        let mut readers = Vec::new();
+        for (name, dir) in capture_streams(&self.config.dir) {
+            let Some(slot) = slots.get(&name) else {
+                warn!("Replay: {} is not configured, skipping its capture", name);
+                continue;
+            };
+            match CaptureReader::open(&name, &dir) {
+                Ok(reader) => readers.push((slot.clone(), reader, 0u64)), // (slot, reader, sockets opened)
+                Err(e) => error!("Replay: Failed to read {}: {}", dir.display(), e),
+            }
+        }
+        for name in slots.keys() {
+            if !readers.iter().any(|(slot, _, _)| &slot.name == name) {
+                info!("Replay: {} has no capture and stays idle", name);
+            }
+        }
+
+        // Oldest entry first. Ties go to the stream that sorts first
+        let mut heads = BinaryHeap::new();
+        let mut pending: Vec<Option<CapturedFrame>> = Vec::new();
+        for (index, (_, reader, _)) in readers.iter_mut().enumerate() {
+            let frame = reader.next();
+            if let Some(frame) = &frame {
+                heads.push(Reverse((frame.received_ns, index)));
+            }
+            pending.push(frame);
+        }
+
+        let started = Instant::now();
+        let mut first_ns = None;
+        let (mut frames, mut responses, mut connects) = (0u64, 0u64, 0u64);
+
+        while let Some(Reverse((_, index))) = heads.pop() {
+            let Some(frame) = pending[index].take() else {
+                continue;
+            };
+            if let Some(next) = readers[index].1.next() {
+                heads.push(Reverse((next.received_ns, index)));
+                pending[index] = Some(next);
+            }
+            let (slot, _, opened) = &mut readers[index];
+
+            if let ReplaySpeed::Factor(speed) = self.config.speed {
+                let first_ns = *first_ns.get_or_insert(frame.received_ns);
+                let offset = Duration::from_nanos(frame.received_ns.saturating_sub(first_ns));
+                tokio::time::sleep_until(started + offset.div_f64(speed)).await;
+            }
+            self.shared.clock.set(frame.received_ns / 1_000_000);
+
+            match frame.kind {
+                FRAME_CONNECT => {
+                    *opened += 1;
+                    slot.reopen(*opened);
+                    let sockets = *opened;
+                    let ready = || slot.connections() >= sockets && slot.is_settled();
+                    wait_for(slot, "reconnect", ready, Some(&engine)).await?;
+                    connects += 1;
+                }
+                FRAME_TEXT | FRAME_BINARY => {
+                    let msg = match frame.kind {
+                        FRAME_TEXT => match Utf8Bytes::try_from(frame.payload) {
+                            Ok(text) => Message::Text(text),
+                            Err(e) => {
+                                error!("Replay: {} invalid text frame: {}", slot.name, e);
+                                continue;
+                            }
+                        },
+                        _ => Message::Binary(frame.payload),
+                    };
+                    // Captures without socket markers are a single connection
+                    let sockets = (*opened).max(1);
+                    let connected = || slot.is_connected(sockets);
+                    wait_for(slot, "connection", connected, None).await?;
+                    slot.push_frame(msg);
+                    wait_for(slot, "frame", || slot.is_settled(), Some(&engine)).await?;
+                    frames += 1;
+                }
+                FRAME_REST => {
+                    let Some(split) = frame.payload.iter().position(|&byte| byte == b'\n') else {
+                        error!("Replay: {} invalid REST response", slot.name);
+                        continue;
+                    };
+                    let target = String::from_utf8_lossy(&frame.payload[..split]).to_string();
+                    slot.push_response(target, frame.payload.slice(split + 1..));
+                    wait_for(slot, "REST response", || slot.is_settled(), Some(&engine)).await?;
+                    responses += 1;
+                }
+                kind => error!("Replay: {} unknown frame kind {}", slot.name, kind),
+            }
+        }
+
+        info!(
+            "Replay: Finished {} frames, {} REST responses and {} connections of {} streams in {:.1}s",
+            frames,
+            responses,
+            connects,
+            readers.len(),
+            started.elapsed().as_secs_f64()
+        );
+        Ok(())
+    }
+}
+
+/// The engine channels the handlers feed
+struct Engine<'a> {
+    future_routes: &'a [FutureDataChannels],
+    spot_routes: &'a [SpotDataChannels],
+}
+
+impl Engine<'_> {
+    fn is_drained(&self) -> bool {
+        self.future_routes
+            .iter()
+            .all(FutureDataChannels::is_drained)
+            && self.spot_routes.iter().all(SpotDataChannels::is_drained)
+    }
+
+    async fn drained(&self) {
+        for routes in self.future_routes {
+            routes.drained().await;
+        }
+        for routes in self.spot_routes {
+            routes.drained().await;
+        }
+    }
+}
+
+/// Wait until `ready` holds and, given the `engine`, it has drained its channels.
+/// Woken by the handler of `slot` as it reads, reconnects or requests, and by the drains
+async fn wait_for<F>(
+    slot: &ReplaySlot,
+    waiting_for: &'static str,
+    ready: F,
+    engine: Option<&Engine<'_>>,
+) -> Result<(), ReplayStalled>
+where
+    F: Fn() -> bool,
+{
+    let settled = async {
+        loop {
+            let progress = slot.progress.notified();
+            tokio::pin!(progress);
+            // Registered before the check, so that progress made meanwhile still wakes us
+            progress.as_mut().enable();
+            if !ready() {
+                progress.await;
+                continue;
+            }
+            match engine {
+                Some(engine) if !engine.is_drained() => engine.drained().await,
+                _ => return,
+            }
+        }
+    };
+    tokio::time::timeout(STALL_TIMEOUT, settled)
+        .await
+        .map_err(|_| ReplayStalled {
+            stream: slot.name.clone(),
+            waiting_for,
+        })
+}
+
+/* Stream Slots */
+
+#[derive(Default)]
+struct SlotState {
+    connects: u64, // Sockets opened by the handler so far
+    closed: u64,   // Sockets up to this one were closed by the replay
+    frames: VecDeque<Message>,
+    reader: Option<Waker>,
+    polls: u64,                                  // Reads by the handler, across sockets
+    last_work: u64, // `polls` when the handler last took a frame or a REST response
+    responses: HashMap<String, VecDeque<Bytes>>, // By path and query
+    requests: HashMap<u64, (String, Waker)>, // Requests waiting for their response
+    next_request: u64,
+}
+
+impl SlotState {
+    /// Whether the handler is done with everything it was given
+    fn is_settled(&self) -> bool {
+        let deliverable = self.requests.values().any(|(target, _)| {
+            self.responses
+                .get(target)
+                .is_some_and(|responses| !responses.is_empty())
+        });
+        if deliverable {
+            return false;
+        }
+        let reading = self.frames.is_empty() && self.polls > self.last_work;
+        // Stuck on a response that comes later in the capture, without reading meanwhile
+        let waiting = !self.requests.is_empty() && self.polls == self.last_work;
+        reading || waiting
+    }
+}
+
+/// Replay side of one stream, handed to its recorder
+#[derive(Clone)]
+pub struct ReplaySlot {
+    name: String,
+    state: Arc<Mutex<SlotState>>,
+    progress: Arc<Notify>, // The handler did something the replay may be waiting for
+    shared: Arc<Shared>,
+}
+
+impl ReplaySlot {
+    /// Open a socket. Frames of the capture are read from it until the replay closes it
+    pub fn connect(&self) -> ReplayConnection {
+        let mut state = self.state.lock().unwrap();
+        state.connects += 1;
+        state.frames.clear();
+        self.progress.notify_waiters();
+        ReplayConnection {
+            slot: self.clone(),
+            id: state.connects,
+        }
+    }
+
+    /// Recorded response of the request to `target`. Waits until the replay reaches it
+    pub fn fetch(&self, target: String) -> ReplayFetch {
+        let mut state = self.state.lock().unwrap();
+        state.next_request += 1;
+        ReplayFetch {
+            slot: self.clone(),
+            target,
+            id: state.next_request,
+        }
+    }
+
+    pub fn now_millis(&self) -> u64 {
+        self.shared.clock.now_millis()
+    }
+
+    fn connections(&self) -> u64 {
+        self.state.lock().unwrap().connects
+    }
+
+    fn is_connected(&self, sockets: u64) -> bool {
+        let state = self.state.lock().unwrap();
+        state.connects >= sockets && state.connects > state.closed
+    }
+
+    fn is_settled(&self) -> bool {
+        self.state.lock().unwrap().is_settled()
+    }
+
+    /// The `socket`th socket of the capture was opened. Unless the handler already
+    /// reconnected on its own, its current socket ends
+    fn reopen(&self, socket: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.connects >= socket {
+            return;
+        }
+        state.closed = state.connects;
+        if let Some(reader) = state.reader.take() {
+            reader.wake();
+        }
+    }
+
+    fn push_frame(&self, msg: Message) {
+        let mut state = self.state.lock().unwrap();
+        state.frames.push_back(msg);
+        if let Some(reader) = state.reader.take() {
+            reader.wake();
+        }
+    }
+
+    fn push_response(&self, target: String, body: Bytes) {
+        let mut state = self.state.lock().unwrap();
+        for (waiting, waker) in state.requests.values() {
+            if *waiting == target {
+                waker.wake_by_ref();
+            }
+        }
+        state.responses.entry(target).or_default().push_back(body);
+    }
+}
+
+pub struct ReplayConnection {
+    slot: ReplaySlot,
+    id: u64,
+}
+
+impl Stream for ReplayConnection {
+    type Item = Result<Message, tungstenite::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut state = self.slot.state.lock().unwrap();
+        state.polls += 1;
+        self.slot.progress.notify_waiters();
+        if self.id <= state.closed || self.id != state.connects {
+            return Poll::Ready(None);
+        }
+        if let Some(msg) = state.frames.pop_front() {
+            state.last_work = state.polls;
+            return Poll::Ready(Some(Ok(msg)));
+        }
+        state.reader = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+pub struct ReplayFetch {
+    slot: ReplaySlot,
+    target: String,
+    id: u64,
+}
+
+impl Future for ReplayFetch {
+    type Output = Bytes;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Bytes> {
+        let mut state = self.slot.state.lock().unwrap();
+        self.slot.progress.notify_waiters();
+        let response = state
+            .responses
+            .get_mut(&self.target)
+            .and_then(|responses| responses.pop_front());
+        match response {
+            Some(body) => {
+                state.requests.remove(&self.id);
+                state.last_work = state.polls;
+                Poll::Ready(body)
+            }
+            None => {
+                state
+                    .requests
+                    .insert(self.id, (self.target.clone(), cx.waker().clone()));
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for ReplayFetch {
+    fn drop(&mut self) {
+        self.slot.state.lock().unwrap().requests.remove(&self.id);
+        self.slot.progress.notify_waiters();
+    }
+}
+
+/* Capture Files */
+
+struct CapturedFrame {
+    received_ns: u64,
+    kind: u8,
+    payload: Bytes,
+}
+
+/// Frames of one stream, oldest first, across its hourly files
+struct CaptureReader {
+    name: String,
+    files: VecDeque<PathBuf>,
+    decoder: Option<MultiGzDecoder<BufReader<File>>>,
+}
+
+impl CaptureReader {
+    fn open(name: &str, dir: &Path) -> std::io::Result<Self> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_capture_file(path))
+            .collect();
+        // Hours sort by name
+        files.sort();
+        Ok(Self {
+            name: name.to_string(),
+            files: files.into(),
+            decoder: None,
+        })
+    }
+
+    fn next(&mut self) -> Option<CapturedFrame> {
+        loop {
+            if self.decoder.is_none() {
+                let path = self.files.pop_front()?;
+                match File::open(&path) {
+                    Ok(file) => self.decoder = Some(MultiGzDecoder::new(BufReader::new(file))),
+                    Err(e) => {
+                        error!("Replay: Failed to open {}: {}", path.display(), e);
+                        continue;
+                    }
+                }
+            }
+            let decoder = self.decoder.as_mut()?;
+            match read_frame(decoder) {
+                Ok(frame) => return Some(frame),
+                Err(e) => {
+                    // The end of the file, or a member cut short by a crash
+                    if e.kind() != ErrorKind::UnexpectedEof {
+                        error!("Replay: {} capture is corrupt: {}", self.name, e);
+                    }
+                    self.decoder = None;
+                }
+            }
+        }
+    }
+}
+
+fn read_frame<R: Read>(reader: &mut R) -> std::io::Result<CapturedFrame> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    let received_ns = u64::from_le_bytes(header[0..8].try_into().unwrap());
+    let kind = header[8];
+    let len = u32::from_le_bytes(header[9..13].try_into().unwrap()) as usize;
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Ok(CapturedFrame {
+        received_ns,
+        kind,
+        payload: Bytes::from(payload),
+    })
+}
+
+fn is_capture_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(".frames.gz"))
+}
+
+/// Every directory under `root` holding capture files, keyed by stream name
+fn capture_streams(root: &Path) -> BTreeMap<String, PathBuf> {
+    let mut streams = BTreeMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("Replay: Failed to read {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_capture_file(&path) {
+                let Ok(relative) = dir.strip_prefix(root) else {
+                    continue;
+                };
+                let name = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                streams.insert(name, dir.clone());
+            }
+        }
+    }
+    streams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn replay() -> Replay {
+        Replay::new(ReplayConfig {
+            dir: PathBuf::new(),
+            speed: ReplaySpeed::Max,
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wakes_when_the_handler_reads() {
+        let slot = replay().slot("binance/future/aggtrade/0");
+        let mut connection = slot.connect();
+        slot.push_frame(Message::Text("frame".into()));
+
+        let handler = tokio::spawn(async move { while connection.next().await.is_some() {} });
+        let started = Instant::now();
+        wait_for(&slot, "frame", || slot.is_settled(), None)
+            .await
+            .unwrap();
+        // Woken by the reads, not by a timer
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        handler.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn handler_that_stops_reading_stalls_the_replay() {
+        let slot = replay().slot("binance/future/aggtrade/0");
+        let _connection = slot.connect();
+        slot.push_frame(Message::Text("frame".into()));
+
+        let started = Instant::now();
+        let stalled = wait_for(&slot, "frame", || slot.is_settled(), None)
+            .await
+            .unwrap_err();
+        assert_eq!(stalled.waiting_for, "frame");
+        assert_eq!(started.elapsed(), STALL_TIMEOUT);
+    }
+}
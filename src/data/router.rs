@@ -0,0 +1,100 @@
+use crate::data::{
+    bbo::BboData, depth::OrderbookUpdateStream, liquidation::LiquidationData, market::MarketData,
+    markprice::MarkPriceData, stats::FuturesStatsData,
+};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+/// Messages that carry the exchange symbol they belong to
+pub trait Routable {
+    fn symbol(&self) -> &str;
+}
+
+impl Routable for OrderbookUpdateStream {
+    fn symbol(&self) -> &str {
+        &self.symbol
+    }
+}
+
+impl Routable for BboData {
+    fn symbol(&self) -> &str {
+        &self.symbol
+    }
+}
+
+impl Routable for MarketData {
+    fn symbol(&self) -> &str {
+        &self.symbol
+    }
+}
+
+impl Routable for MarkPriceData {
+    fn symbol(&self) -> &str {
+        &self.symbol
+    }
+}
+
+impl Routable for LiquidationData {
+    fn symbol(&self) -> &str {
+        &self.symbol
+    }
+}
+
+impl Routable for FuturesStatsData {
+    fn symbol(&self) -> &str {
+        &self.symbol
+    }
+}
+
+/// Fans one multi-symbol stream out to the engine channel of each symbol.
+/// Keys are upper case, the way exchanges echo symbols back in their messages.
+#[derive(Debug)]
+pub struct SymbolRouter<T> {
+    routes: HashMap<String, mpsc::Sender<T>>,
+}
+
+impl<T> Clone for SymbolRouter<T> {
+    fn clone(&self) -> Self {
+        Self {
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+impl<T> Default for SymbolRouter<T> {
+    fn default() -> Self {
+        Self {
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Routable> SymbolRouter<T> {
+    pub fn insert(&mut self, symbol: &str, tx: mpsc::Sender<T>) {
+        self.routes.insert(symbol.to_uppercase(), tx);
+    }
+
+    /// Send to the channel of the message's symbol.
+    /// Messages for symbols without a route are handed back like a closed channel.
+    pub async fn send(&self, item: T) -> Result<(), mpsc::error::SendError<T>> {
+        match self.routes.get(item.symbol()) {
+            Some(tx) => tx.send(item).await,
+            None => Err(mpsc::error::SendError(item)),
+        }
+    }
+
+    /// Whether the engine has taken every message sent so far
+    pub fn is_drained(&self) -> bool {
+        self.routes
+            .values()
+            .all(|tx| tx.capacity() == tx.max_capacity())
+    }
+
+    /// Wait until the engine has taken every message sent so far
+    pub async fn drained(&self) {
+        for tx in self.routes.values() {
+            // Every permit is back once the channel is empty. A closed channel is drained too
+            let _ = tx.reserve_many(tx.max_capacity()).await;
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use crate::data::{
+    endpoints::Endpoint,
+    health::StreamMonitor,
+    limiter::WeightLimiter,
+    router::SymbolRouter,
+    stats::{FuturesStat, FuturesStatsData},
+    stream::StreamHandler,
+    supervisor::rate_limited,
+};
+use log::{error, warn};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite;
+
+/* Binance Futures Statistics */
+
+// https://developers.binance.com/docs/derivatives/usds-margined-futures/market-data/rest-api/Open-Interest
+// https://developers.binance.com/docs/derivatives/usds-margined-futures/market-data/rest-api/Long-Short-Ratio
+// https://developers.binance.com/docs/derivatives/usds-margined-futures/market-data/rest-api/Top-Trader-Long-Short-Ratio
+// https://developers.binance.com/docs/derivatives/usds-margined-futures/market-data/rest-api/Taker-BuySell-Volume
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinanceStatsEndpoint {
+    OpenInterest,
+    GlobalLongShortAccountRatio,
+    TopLongShortPositionRatio,
+    TakerLongShortRatio,
+}
+
+impl BinanceStatsEndpoint {
+    /// Used in stream names, e.g. "binance/future/stats/open_interest"
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinanceStatsEndpoint::OpenInterest => "open_interest",
+            BinanceStatsEndpoint::GlobalLongShortAccountRatio => "account_ratio",
+            BinanceStatsEndpoint::TopLongShortPositionRatio => "position_ratio",
+            BinanceStatsEndpoint::TakerLongShortRatio => "taker_volume",
+        }
+    }
+
+    fn url(&self, rest: &str, symbol: &str) -> String {
+        // The `futures/data` figures are published every 5 minutes at the shortest period
+        let path = match self {
+            BinanceStatsEndpoint::OpenInterest => "fapi/v1/openInterest?",
+            BinanceStatsEndpoint::GlobalLongShortAccountRatio => {
+                "futures/data/globalLongShortAccountRatio?period=5m&limit=1&"
+            }
+            BinanceStatsEndpoint::TopLongShortPositionRatio => {
+                "futures/data/topLongShortPositionRatio?period=5m&limit=1&"
+            }
+            BinanceStatsEndpoint::TakerLongShortRatio => {
+                "futures/data/takerlongshortRatio?period=5m&limit=1&"
+            }
+        };
+        format!("{}/{}symbol={}", rest, path, symbol.to_uppercase())
+    }
+
+    /// Weight of one request
+    pub fn weight(&self) -> u32 {
+        1
+    }
+
+    /// `futures/data` is limited to 1000 requests per 5 minutes apart from the `fapi` weight
+    pub fn is_futures_data(&self) -> bool {
+        *self != BinanceStatsEndpoint::OpenInterest
+    }
+
+    fn parse(
+        &self,
+        symbol: &str,
+        body: &str,
+    ) -> Result<Option<FuturesStatsData>, serde_json::Error> {
+        let data = match self {
+            BinanceStatsEndpoint::OpenInterest => {
+                let open_interest = serde_json::from_str::<OpenInterest>(body)?;
+                Some((
+                    FuturesStat::OpenInterest(open_interest.openInterest),
+                    open_interest.time,
+                ))
+            }
+            BinanceStatsEndpoint::GlobalLongShortAccountRatio => {
+                serde_json::from_str::<Vec<LongShortRatio>>(body)?
+                    .pop()
+                    .and_then(|ratio| {
+                        let stat = FuturesStat::LongShortAccountRatio {
+                            long: ratio.longAccount,
+                            short: ratio.shortAccount,
+                            ratio: ratio.longShortRatio,
+                        };
+                        Some((stat, ratio.timestamp.to_u64()?))
+                    })
+            }
+            BinanceStatsEndpoint::TopLongShortPositionRatio => {
+                serde_json::from_str::<Vec<LongShortRatio>>(body)?
+                    .pop()
+                    .and_then(|ratio| {
+                        // `longAccount` and `shortAccount` carry the position shares here
+                        let stat = FuturesStat::TopLongShortPositionRatio {
+                            long: ratio.longAccount,
+                            short: ratio.shortAccount,
+                            ratio: ratio.longShortRatio,
+                        };
+                        Some((stat, ratio.timestamp.to_u64()?))
+                    })
+            }
+            BinanceStatsEndpoint::TakerLongShortRatio => {
+                serde_json::from_str::<Vec<TakerVolume>>(body)?
+                    .pop()
+                    .and_then(|volume| {
+                        let stat = FuturesStat::TakerVolume {
+                            buy: volume.buyVol,
+                            sell: volume.sellVol,
+                            ratio: volume.buySellRatio,
+                        };
+                        Some((stat, volume.timestamp.to_u64()?))
+                    })
+            }
+        };
+
+        Ok(data.map(|(stat, event_time)| FuturesStatsData {
+            symbol: symbol.to_uppercase(),
+            stat,
+            event_time,
+        }))
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct OpenInterest {
+    openInterest: Decimal,
+    time: u64,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct LongShortRatio {
+    longShortRatio: Decimal,
+    longAccount: Decimal,
+    shortAccount: Decimal,
+    timestamp: Decimal, // Millis. Sent as a string or a number
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct TakerVolume {
+    buySellRatio: Decimal,
+    buyVol: Decimal,
+    sellVol: Decimal,
+    timestamp: Decimal, // Millis. Sent as a string or a number
+}
+
+/* Binance Futures Statistics Poller */
+
+/// Polls one endpoint for every symbol each `interval`
+pub struct BinanceFutureStatsPoller {
+    endpoint: BinanceStatsEndpoint,
+    interval: Duration,
+    pub symbols: Vec<String>,
+    pub tx: SymbolRouter<FuturesStatsData>,
+    limiter: WeightLimiter,
+    client: reqwest::Client,
+    rest: String, // REST base, e.g. "https://fapi.binance.com"
+    monitor: StreamMonitor,
+}
+
+impl StreamHandler for BinanceFutureStatsPoller {
+    fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+        let handler = BinanceFutureStatsPoller {
+            endpoint: self.endpoint,
+            interval: self.interval,
+            symbols: self.symbols.clone(),
+            tx: self.tx.clone(),
+            limiter: self.limiter.clone(),
+            client: self.client.clone(),
+            rest: self.rest.clone(),
+            monitor: self.monitor.clone(),
+        };
+
+        Box::new(Box::pin(async move {
+            handler.monitor.live();
+            handler.poll().await
+        }))
+    }
+}
+
+impl BinanceFutureStatsPoller {
+    pub fn new(
+        endpoint: BinanceStatsEndpoint,
+        interval: Duration,
+        symbols: Vec<String>,
+        tx: SymbolRouter<FuturesStatsData>,
+        limiter: WeightLimiter,
+        rest: &Endpoint,
+        monitor: StreamMonitor,
+    ) -> Self {
+        Self {
+            endpoint,
+            interval,
+            symbols,
+            tx,
+            limiter,
+            client: reqwest::Client::new(),
+            rest: rest.rest.clone(),
+            monitor,
+        }
+    }
+
+    /// Runs until the exchange rate limits us. The supervisor then backs off
+    pub async fn poll(&self) -> Result<(), tungstenite::Error> {
+        let mut interval = tokio::time::interval(self.interval);
+        // The limiter may hold a round past its tick. Keep the spacing instead of bursting
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        // Last figure sent per symbol. `futures/data` repeats the same period until the next one
+        let mut last: HashMap<String, u64> = HashMap::new();
+
+        loop {
+            interval.tick().await;
+
+            for symbol in self.symbols.iter() {
+                self.limiter.acquire(self.endpoint.weight()).await;
+
+                let body = match self.fetch(symbol).await {
+                    Ok(body) => body,
+                    Err(PollError::RateLimited(e)) => return Err(e),
+                    Err(PollError::Request(e)) => {
+                        error!(
+                            "Binance {} poller: {} request failed: {}",
+                            self.endpoint.name(),
+                            symbol,
+                            e
+                        );
+                        self.monitor.incr("poll_failures");
+                        continue;
+                    }
+                };
+
+                let data = match self.endpoint.parse(symbol, &body) {
+                    Ok(Some(data)) => data,
+                    Ok(None) => continue, // No figure for the period yet
+                    Err(e) => {
+                        error!(
+                            "Binance {} poller: Failed to parse {}: {}",
+                            self.endpoint.name(),
+                            symbol,
+                            e
+                        );
+                        self.monitor.incr("parse_failures");
+                        continue;
+                    }
+                };
+                if last.insert(data.symbol.clone(), data.event_time) == Some(data.event_time) {
+                    continue;
+                }
+
+                if self.tx.send(data).await.is_err() {
+                    error!(
+                        "Binance {} poller: Failed to send update",
+                        self.endpoint.name()
+                    );
+                }
+            }
+        }
+    }
+
+    async fn fetch(&self, symbol: &str) -> Result<String, PollError> {
+        let response = self
+            .client
+            .get(self.endpoint.url(&self.rest, symbol))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .map_err(PollError::Request)?;
+
+        // 429: Rate limited, 418: IP banned. Hand over to the supervisor like a rejected socket
+        let status = response.status().as_u16();
+        if status == 429 || status == 418 {
+            warn!(
+                "Binance {} poller: Rate limited ({})",
+                self.endpoint.name(),
+                status
+            );
+            let retry_after = response.headers().get("retry-after");
+            return Err(PollError::RateLimited(rate_limited(
+                status,
+                retry_after.map(|value| value.as_bytes()),
+            )));
+        }
+
+        response
+            .error_for_status()
+            .map_err(PollError::Request)?
+            .text()
+            .await
+            .map_err(PollError::Request)
+    }
+}
+
+enum PollError {
+    RateLimited(tungstenite::Error),
+    Request(reqwest::Error),
+}
@@ -0,0 +1,35 @@
+pub mod binance;
+
+use rust_decimal::Decimal;
+
+// Futures context that is only published over REST and has to be polled
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FuturesStat {
+    OpenInterest(Decimal), // In coins
+    // Share of accounts net long and net short
+    LongShortAccountRatio {
+        long: Decimal,
+        short: Decimal,
+        ratio: Decimal,
+    },
+    // Share of long and short positions of the top 20% traders by margin
+    TopLongShortPositionRatio {
+        long: Decimal,
+        short: Decimal,
+        ratio: Decimal,
+    },
+    // Taker volume of the last period, in coins
+    TakerVolume {
+        buy: Decimal,
+        sell: Decimal,
+        ratio: Decimal,
+    },
+}
+
+#[derive(Debug)]
+pub struct FuturesStatsData {
+    pub symbol: String,
+    pub stat: FuturesStat,
+    pub event_time: u64, // Exchange time of the figure
+}
@@ -4,3 +4,11 @@ use tokio_tungstenite::tungstenite;
 pub trait StreamHandler {
     fn connect(&self) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin>;
 }
+
+/// Surface REST failures through the stream's error type so the supervisor retries them
+pub fn io_error<E>(e: E) -> tungstenite::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    tungstenite::Error::Io(std::io::Error::other(e))
+}
@@ -0,0 +1,327 @@
+use crate::data::{
+    fault::{FaultConfig, FaultInjector},
+    health::{StreamHealth, StreamMonitor, StreamState},
+    recorder::{FrameRecorder, RecorderConfig, RecorderWriters},
+    replay::Replay,
+    stream::StreamHandler,
+};
+use log::{error, warn};
+use rand::Rng;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::{self, http};
+
+/* Reconnect Policy */
+
+// A socket that opens and then drops sooner than this still counts as a failure,
+// so a stream that keeps closing right after connecting backs off and runs out of retries
+const STABLE_UPTIME: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub max_retries: Option<u32>, // Consecutive failures allowed. `None` retries forever
+}
+
+impl RetryPolicy {
+    pub fn new(base_delay_ms: u64, max_delay_ms: u64, max_retries: u32) -> Self {
+        Self {
+            base_delay: Duration::from_millis(base_delay_ms),
+            max_delay: Duration::from_millis(max_delay_ms.max(base_delay_ms)),
+            max_retries: (max_retries > 0).then_some(max_retries),
+        }
+    }
+
+    /// Exponential backoff with equal jitter: half of the delay is fixed,
+    /// the other half is random so that streams do not reconnect in lockstep.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let exp = failures.saturating_sub(1).min(16);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exp)
+            .min(self.max_delay)
+            .as_millis() as u64;
+
+        let half = delay / 2;
+        let jitter = rand::rng().random_range(0..=half);
+        Duration::from_millis(half + jitter)
+    }
+
+    fn exhausted(&self, failures: u32) -> bool {
+        self.max_retries.is_some_and(|max| failures > max)
+    }
+}
+
+/* Stream Supervisor */
+
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    pub health: StreamHealth,
+    pub policy: RetryPolicy,
+    faults: Option<FaultConfig>,
+    recorder: Option<RecorderConfig>,
+    writers: RecorderWriters,
+    replay: Option<Replay>,
+}
+
+impl Supervisor {
+    pub fn new(health: StreamHealth, policy: RetryPolicy) -> Self {
+        Self {
+            health,
+            policy,
+            faults: None,
+            recorder: None,
+            writers: RecorderWriters::new(),
+            replay: None,
+        }
+    }
+
+    /// Inject faults into the sockets and snapshots of the streams `config` covers
+    pub fn with_faults(mut self, config: FaultConfig) -> Self {
+        self.faults = Some(config);
+        self
+    }
+
+    /// Record the raw frames of the streams `config` covers
+    pub fn with_recorder(mut self, config: RecorderConfig) -> Self {
+        self.recorder = Some(config);
+        self
+    }
+
+    /// Feed the streams from `replay` instead of the exchanges
+    pub fn with_replay(mut self, replay: Replay) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    /// Frame recorder of the stream of `monitor`. Disabled unless recording is on.
+    /// Replays hand out the recorder playing the stream's capture back
+    pub fn recorder(&self, monitor: &StreamMonitor) -> FrameRecorder {
+        if let Some(replay) = &self.replay {
+            return FrameRecorder::replaying(replay.slot(monitor.name()));
+        }
+        match &self.recorder {
+            Some(config) => FrameRecorder::new(config, monitor, &self.writers),
+            None => FrameRecorder::disabled(),
+        }
+    }
+
+    /// Flush the recorded frames to disk. Call once the streams are gone
+    pub async fn close_recorders(&self) {
+        self.writers.close().await;
+    }
+
+    /// Fault schedule of the stream of `monitor`. Disabled unless fault injection is on
+    pub fn faults(&self, monitor: &StreamMonitor) -> FaultInjector {
+        match &self.faults {
+            Some(config) => FaultInjector::new(config, monitor),
+            None => FaultInjector::disabled(),
+        }
+    }
+
+    /// Register `name` in the health registry, build the handler with its monitor
+    /// and keep it connected on the task set.
+    pub fn spawn<H, F>(&self, tasks: &mut JoinSet<()>, name: &str, build: F)
+    where
+        H: StreamHandler + Send + 'static,
+        F: FnOnce(StreamMonitor) -> H,
+    {
+        let monitor = self.health.monitor(name);
+        let handler = build(monitor.clone());
+        tasks.spawn(supervise(handler, monitor, self.policy));
+    }
+}
+
+/// Keep `handler` connected until the retry budget runs out.
+/// Returns only when the stream is marked as failed.
+pub async fn supervise<H: StreamHandler>(handler: H, monitor: StreamMonitor, policy: RetryPolicy) {
+    loop {
+        monitor.connecting();
+        warn!("Attempting to connect to {}", monitor.name());
+
+        let started = Instant::now();
+        let result = handler.connect().await;
+        if monitor.state() == StreamState::Live && started.elapsed() >= STABLE_UPTIME {
+            monitor.stable();
+        }
+
+        let (reason, ban) = match result {
+            Ok(()) => ("connection closed".to_string(), None),
+            Err(e) => {
+                error!("{} connection error: {}", monitor.name(), e);
+                let ban = ban_delay(&e, &policy);
+                (e.to_string(), ban)
+            }
+        };
+        let failures = monitor.degraded(reason);
+
+        if policy.exhausted(failures) {
+            error!(
+                "{}: Giving up after {} consecutive failures",
+                monitor.name(),
+                failures
+            );
+            monitor.failed();
+            return;
+        }
+
+        let delay = ban.unwrap_or_else(|| policy.backoff(failures));
+        warn!(
+            "{}: Retrying in {} ms (attempt {})",
+            monitor.name(),
+            delay.as_millis(),
+            failures
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// The error a 429 or 418 REST response hands to the supervisor, shaped like a socket
+/// handshake the exchange rejected so `ban_delay` reads its `Retry-After`
+pub fn rate_limited(status: u16, retry_after: Option<&[u8]>) -> tungstenite::Error {
+    let mut rejected = http::Response::builder().status(status);
+    if let Some(retry_after) = retry_after {
+        rejected = rejected.header("retry-after", retry_after);
+    }
+    match rejected.body(None) {
+        Ok(rejected) => tungstenite::Error::Http(rejected),
+        Err(e) => tungstenite::Error::HttpFormat(e),
+    }
+}
+
+/// Binance answers 429 when we are rate limited and 418 once the IP is banned.
+/// Honour `Retry-After` when it is given, otherwise back off for the maximum delay.
+fn ban_delay(err: &tungstenite::Error, policy: &RetryPolicy) -> Option<Duration> {
+    let tungstenite::Error::Http(response) = err else {
+        return None;
+    };
+
+    match response.status().as_u16() {
+        418 | 429 => {
+            let retry_after = response
+                .headers()
+                .get("retry-after")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+                .map(Duration::from_secs);
+
+            Some(retry_after.unwrap_or(policy.max_delay))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::future::Future;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn backoff_doubles_with_jitter_up_to_the_maximum() {
+        let policy = RetryPolicy::new(100, 1_000, 0);
+        for (failures, full) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1_000),
+            (40, 1_000),
+        ] {
+            for _ in 0..50 {
+                let delay = policy.backoff(failures).as_millis() as u64;
+                assert!(
+                    (full / 2..=full).contains(&delay),
+                    "{} failures: {} ms",
+                    failures,
+                    delay
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn exhausted_after_the_retry_budget() {
+        let policy = RetryPolicy::new(100, 1_000, 3);
+        assert!(!policy.exhausted(3));
+        assert!(policy.exhausted(4));
+
+        // No budget retries forever
+        assert!(!RetryPolicy::new(100, 1_000, 0).exhausted(u32::MAX));
+    }
+
+    #[test]
+    fn bans_wait_for_retry_after_or_the_maximum_delay() {
+        let policy = RetryPolicy::new(100, 60_000, 0);
+        assert_eq!(
+            ban_delay(&rate_limited(429, Some(b"7")), &policy),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            ban_delay(&rate_limited(418, None), &policy),
+            Some(policy.max_delay)
+        );
+        assert_eq!(
+            ban_delay(&rate_limited(418, Some(b"soon")), &policy),
+            Some(policy.max_delay)
+        );
+
+        // Anything else backs off as usual
+        assert_eq!(ban_delay(&rate_limited(503, Some(b"7")), &policy), None);
+        assert_eq!(
+            ban_delay(&tungstenite::Error::ConnectionClosed, &policy),
+            None
+        );
+    }
+
+    /// Goes live on every connection and closes it after the next uptime
+    struct Uptimes {
+        uptimes: Arc<Mutex<VecDeque<Duration>>>,
+        monitor: StreamMonitor,
+    }
+
+    impl StreamHandler for Uptimes {
+        fn connect(
+            &self,
+        ) -> Box<dyn Future<Output = Result<(), tungstenite::Error>> + Send + Unpin> {
+            let uptime = self.uptimes.lock().unwrap().pop_front().unwrap();
+            let monitor = self.monitor.clone();
+            Box::new(Box::pin(async move {
+                monitor.live();
+                tokio::time::sleep(uptime).await;
+                Ok(())
+            }))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stable_connection_clears_the_failures() {
+        let short = Duration::from_secs(1);
+        let uptimes = Arc::new(Mutex::new(VecDeque::from([
+            short,
+            short,
+            STABLE_UPTIME,
+            short,
+            short,
+            short,
+        ])));
+        let health = StreamHealth::new();
+        let monitor = health.monitor("test/orderbook/0");
+        let handler = Uptimes {
+            uptimes: uptimes.clone(),
+            monitor: monitor.clone(),
+        };
+
+        supervise(handler, monitor, RetryPolicy::new(10, 100, 2)).await;
+
+        // Two short ones, then the stable one starts over: it and two more short ones
+        let (_, status) = health.snapshot().remove(0);
+        assert_eq!(status.state, StreamState::Failed);
+        assert_eq!(status.reconnect_count, 5);
+        assert_eq!(status.consecutive_failures, 3);
+        assert_eq!(uptimes.lock().unwrap().len(), 1);
+    }
+}
@@ -1,54 +1,88 @@
 use crate::data::{
-    depth::upbit::spot::UpbitSpotOrderbookStreamHandler, exchanges::SpotDataChannels,
-    market::upbit::spot::UpbitSpotAggTradeStreamHandler, stream::StreamHandler,
+    depth::upbit::spot::UpbitSpotOrderbookStreamHandler, endpoints::Endpoint,
+    exchanges::SpotDataChannels, market::upbit::spot::UpbitSpotAggTradeStreamHandler,
+    supervisor::Supervisor,
 };
-use log::{error, info, warn};
+use log::{info, warn};
 use tokio::task::JoinSet;
 
 pub struct UpbitThreads {
+    market: String, // Quote market, e.g. "krw"
     spot: SpotDataChannels,
+    supervisor: Supervisor,
+    endpoint: Endpoint,
 }
 
 impl UpbitThreads {
-    pub fn new(spot: SpotDataChannels) -> Self {
-        Self { spot }
+    pub fn new(
+        market: &str,
+        spot: SpotDataChannels,
+        supervisor: Supervisor,
+        endpoint: Endpoint,
+    ) -> Self {
+        Self {
+            market: market.to_lowercase(),
+            spot,
+            supervisor,
+            endpoint,
+        }
     }
 
-    pub fn spawn_streams(self, tasks: &mut JoinSet<()>, symbols: String) {
-        if symbols == "NO_SYMBOL" {
-            warn!("No symbols specified, skipping Upbit streams");
+    pub fn spawn_streams(self, tasks: &mut JoinSet<()>, symbols: Vec<String>) {
+        if symbols.is_empty() {
+            warn!(
+                "No symbols specified, skipping Upbit {} streams",
+                self.market
+            );
             return;
         }
 
         // Spot Streams
-        info!("Starting Upbit Streams for {}", symbols);
-        tasks.spawn(spawn_spot_aggtrade_task(
-            UpbitSpotAggTradeStreamHandler::new(symbols.clone(), self.spot.agg_out),
-        ));
-        tasks.spawn(spawn_spot_orderbook_task(
-            UpbitSpotOrderbookStreamHandler::new(symbols.clone(), self.spot.ob_out),
-        ));
+        // One connection takes every code of a stream type
+        info!("Starting Upbit {} Streams for {:?}", self.market, symbols);
+        self.supervisor.spawn(
+            tasks,
+            &format!("upbit/{}/aggtrade", self.market),
+            |monitor| {
+                let recorder = self.supervisor.recorder(&monitor);
+                let faults = self.supervisor.faults(&monitor);
+                UpbitSpotAggTradeStreamHandler::new(
+                    symbols.clone(),
+                    self.spot.agg_out,
+                    self.endpoint.clone(),
+                    monitor,
+                )
+                .with_recorder(recorder)
+                .with_faults(faults)
+            },
+        );
+        self.supervisor.spawn(
+            tasks,
+            &format!("upbit/{}/orderbook", self.market),
+            |monitor| {
+                let recorder = self.supervisor.recorder(&monitor);
+                let faults = self.supervisor.faults(&monitor);
+                UpbitSpotOrderbookStreamHandler::new(
+                    symbols.clone(),
+                    self.spot.ob_out,
+                    self.endpoint.clone(),
+                    monitor,
+                )
+                .with_recorder(recorder)
+                .with_faults(faults)
+            },
+        );
     }
 }
 
-async fn spawn_spot_aggtrade_task(handler: UpbitSpotAggTradeStreamHandler) {
-    loop {
-        warn!("Attempting to connect to Upbit Aggtrades");
-        if let Err(e) = handler.connect().await {
-            error!("Upbit Aggtrades connection error: {}", e);
-        }
-        warn!("Upbit Aggtrades: Retrying in 5 seconds");
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-    }
-}
+/// Subscription message of `stream` for every code
+/// https://docs.upbit.com/reference/websocket-request-format
+pub fn subscription_message(stream: &str, symbols: &[String]) -> String {
+    let codes: Vec<String> = symbols.iter().map(|symbol| symbol.to_uppercase()).collect();
 
-async fn spawn_spot_orderbook_task(handler: UpbitSpotOrderbookStreamHandler) {
-    loop {
-        warn!("Attempting to connect to Upbit Spot");
-        if let Err(e) = handler.connect().await {
-            error!("Upbit Spot connection error: {}", e);
-        }
-        warn!("Upbit Spot: Retrying in 5 seconds");
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-    }
+    serde_json::json!([
+        { "ticket": "UNIQUE_TICKET" },
+        { "type": stream, "codes": codes },
+    ])
+    .to_string()
 }
@@ -0,0 +1,5 @@
+pub mod channel;
+pub mod data;
+pub mod database;
+pub mod prism;
+pub mod util;
@@ -1,20 +1,37 @@
-use crate::data::{
-    binance::BinanceThreads,
+use config::{read_env_config, PrismEnvConfig};
+use cryptoquant::channel::{FutureChannel, SpotChannel};
+use cryptoquant::data::{
+    binance::{delivery_time, BinanceThreads},
+    bitget::BitgetThreads,
+    bithumb::BithumbThreads,
+    bybit::BybitThreads,
+    clock::Clock,
     exchanges::{FutureDataChannels, SpotDataChannels},
+    health::StreamHealth,
+    latency::{ClockSync, ServerTime},
+    liquidation::rolling::MarketLiquidations,
+    okx::OkxThreads,
+    replay::Replay,
+    stats::binance::future::BinanceStatsEndpoint,
+    supervisor::{RetryPolicy, Supervisor},
     upbit::UpbitThreads,
 };
-use channel::{FutureChannel, SpotChannel};
-use config::read_env_config;
-use database::postgres::connect_to_timescale;
+use cryptoquant::prism::core::{
+    event::Venue,
+    future::FutureCore,
+    quote::KrwRates,
+    sequencer::{Sequencer, SequencerReport},
+    spot::SpotCore,
+    term_structure::TermStructure,
+    Core,
+};
 use log::{error, info, warn};
-use prism::core::{future::FutureCore, spot::SpotCore, Core};
+use std::process::ExitCode;
+use std::time::Duration;
 use tokio::signal;
+use tokio::task::JoinSet;
 
-mod channel;
 mod config;
-mod data;
-mod database;
-mod prism;
 
 /*
 (1) Data flows like:
@@ -27,34 +44,176 @@ mod prism;
      The channel name will be: tx(rx)_(fut/spt)_exec
 */
 
-#[tokio::main]
-async fn main() {
+fn main() -> ExitCode {
     /* Initialize logger */
     env_logger::init();
     let env_var = read_env_config();
 
+    // A replay runs every task on one thread, so that a capture always plays out the same way
+    let mut runtime = match env_var.replay {
+        Some(_) => tokio::runtime::Builder::new_current_thread(),
+        None => tokio::runtime::Builder::new_multi_thread(),
+    };
+    let runtime = runtime
+        .enable_all()
+        .build()
+        .expect("Failed to build the Tokio runtime");
+    runtime.block_on(run(env_var))
+}
+
+async fn run(env_var: PrismEnvConfig) -> ExitCode {
     /* Create threaded task set */
-    let mut tasks = tokio::task::JoinSet::new();
+    let mut tasks = JoinSet::new();
+    let mut cores = JoinSet::new();
+
+    // On replay the cores read the capture's time instead of the wall
+    let replay = env_var.replay.clone().map(Replay::new);
+    let timing = CoreTiming {
+        clock: replay.as_ref().map_or_else(Clock::wall, Replay::clock),
+        lateness: (env_var.sequencer_lateness_ms > 0)
+            .then(|| Duration::from_millis(env_var.sequencer_lateness_ms)),
+        sequencers: SequencerReport::new(),
+    };
 
     /* Create channels and data managers for thread communication */
-    let binance_fut = FutureChannel::new(env_var.channel_capacity);
-    let mut binance_future_core = Core::<FutureCore>::new(
-        binance_fut.ob.1,
-        binance_fut.agg.1,
-        binance_fut.additional.mark.1,
-        binance_fut.additional.liq.1,
-    );
+    // Every symbol gets its own channel and core. Streams route messages by symbol
+    // Delivery contracts (e.g. BTCUSDT_250627) publish their basis to the term structure
+    let term_structure = TermStructure::new();
+    // Liquidations of every Binance futures symbol, when `!forceOrder@arr` is enabled
+    let market_liquidations = env_var
+        .binance_all_market_liquidations
+        .then(|| MarketLiquidations::new(Duration::from_secs(env_var.liquidation_window_secs)));
+    let mut binance_future_routes = FutureDataChannels::new();
+    let mut binance_coin_routes = FutureDataChannels::new();
+    for (market, routes, symbols) in [
+        (
+            "binance/future",
+            &mut binance_future_routes,
+            &env_var.symbol_binance_fut,
+        ),
+        (
+            "binance/coin",
+            &mut binance_coin_routes,
+            &env_var.symbol_binance_coin,
+        ),
+    ] {
+        for symbol in symbols {
+            let channel = FutureChannel::new(env_var.channel_capacity);
+            routes.add_route(symbol, &channel);
+            let mut core =
+                timing.setup(future_core(Venue::Binance, symbol, channel), market, symbol);
+            if let Some(delivery_time) = delivery_time(symbol) {
+                core = core.with_term_structure(term_structure.clone(), delivery_time);
+            }
+            if let Some(market_liquidations) = &market_liquidations {
+                core = core.with_market_liquidations(market_liquidations.clone());
+            }
+            /* Feature Creation Engine */
+            cores.spawn(async move { core.work().await });
+        }
+    }
 
-    let binance_spt = SpotChannel::new(env_var.channel_capacity);
-    let mut binance_spot_core = Core::<SpotCore>::new(binance_spt.ob.1, binance_spt.agg.1);
+    let mut bitget_future_routes = FutureDataChannels::new();
+    let mut bybit_future_routes = FutureDataChannels::new();
+    let mut okx_future_routes = FutureDataChannels::new();
+    for (venue, market, routes, symbols) in [
+        (
+            Venue::Bitget,
+            "bitget",
+            &mut bitget_future_routes,
+            &env_var.symbol_bitget_fut,
+        ),
+        (
+            Venue::Bybit,
+            "bybit",
+            &mut bybit_future_routes,
+            &env_var.symbol_bybit_fut,
+        ),
+        (
+            Venue::Okx,
+            "okx",
+            &mut okx_future_routes,
+            &env_var.symbol_okx_fut,
+        ),
+    ] {
+        for symbol in symbols {
+            let channel = FutureChannel::new(env_var.channel_capacity);
+            routes.add_route(symbol, &channel);
+            let mut core = timing.setup(future_core(venue, symbol, channel), market, symbol);
+            cores.spawn(async move { core.work().await });
+        }
+    }
 
-    let upbit_spt_krw = SpotChannel::new(env_var.channel_capacity);
-    let mut upbit_spot_core = Core::<SpotCore>::new(upbit_spt_krw.ob.1, upbit_spt_krw.agg.1);
+    let mut binance_spot_routes = SpotDataChannels::new();
+    let mut bithumb_krw_routes = SpotDataChannels::new();
+    for (venue, market, routes, symbols) in [
+        (
+            Venue::Binance,
+            "binance/spot",
+            &mut binance_spot_routes,
+            &env_var.symbol_binance_spt,
+        ),
+        (
+            Venue::Bithumb,
+            "bithumb",
+            &mut bithumb_krw_routes,
+            &env_var.symbol_bithumb_krw,
+        ),
+    ] {
+        for symbol in symbols {
+            let channel = SpotChannel::new(env_var.channel_capacity);
+            routes.add_route(symbol, &channel);
+            let mut core = timing.setup(spot_core(venue, symbol, channel), market, symbol);
+            cores.spawn(async move { core.work().await });
+        }
+    }
 
-    /* Feature Creation Engine */
-    tasks.spawn(async move { binance_future_core.work().await });
-    tasks.spawn(async move { binance_spot_core.work().await });
-    tasks.spawn(async move { upbit_spot_core.work().await });
+    // BTC and USDT quoted markets are priced in KRW through the KRW-BTC and KRW-USDT books
+    let krw_rates = KrwRates::new();
+    let mut upbit_krw_symbols = env_var.symbol_upbit_krw.clone();
+    for (quote, symbols) in [
+        ("KRW-BTC", &env_var.symbol_upbit_btc),
+        ("KRW-USDT", &env_var.symbol_upbit_usdt),
+    ] {
+        if !symbols.is_empty() && !upbit_krw_symbols.iter().any(|symbol| symbol == quote) {
+            upbit_krw_symbols.push(quote.to_string());
+        }
+    }
+
+    let mut upbit_markets = Vec::new();
+    for (market, symbols) in [
+        ("krw", upbit_krw_symbols),
+        ("btc", env_var.symbol_upbit_btc.clone()),
+        ("usdt", env_var.symbol_upbit_usdt.clone()),
+    ] {
+        let mut routes = SpotDataChannels::new();
+        for symbol in &symbols {
+            let channel = SpotChannel::new(env_var.channel_capacity);
+            routes.add_route(symbol, &channel);
+            let core = spot_core(Venue::Upbit, symbol, channel).with_krw_rates(krw_rates.clone());
+            let mut core = timing.setup(core, &format!("upbit/{}", market), symbol);
+            cores.spawn(async move { core.work().await });
+        }
+        upbit_markets.push((market, symbols, routes));
+    }
+
+    // A replay waits for the engine to drain its channels before every frame
+    let mut replay_routes = (Vec::new(), Vec::new());
+    if replay.is_some() {
+        replay_routes.0.extend([
+            binance_future_routes.clone(),
+            binance_coin_routes.clone(),
+            bitget_future_routes.clone(),
+            bybit_future_routes.clone(),
+            okx_future_routes.clone(),
+        ]);
+        replay_routes
+            .1
+            .extend([binance_spot_routes.clone(), bithumb_krw_routes.clone()]);
+        replay_routes
+            .1
+            .extend(upbit_markets.iter().map(|(_, _, routes)| routes.clone()));
+    }
 
     // /* Start Data Manager */
     // let mut core_config = TradeConfig::default();
@@ -89,47 +248,252 @@ async fn main() {
     // }
 
     /* Start Data Streams */
-    let binance_streams = BinanceThreads::new(
-        FutureDataChannels {
-            ob_out: binance_fut.ob.0,
-            agg_out: binance_fut.agg.0,
-            liq_out: binance_fut.additional.liq.0,
-            mark_out: binance_fut.additional.mark.0,
-        },
-        SpotDataChannels {
-            ob_out: binance_spt.ob.0,
-            agg_out: binance_spt.agg.0,
-        },
+    let policy = match replay {
+        // Replayed sockets close when the capture says so. Reconnect at once
+        Some(_) => RetryPolicy::new(0, 0, 0),
+        None => RetryPolicy::new(
+            env_var.reconnect_base_ms,
+            env_var.reconnect_max_ms,
+            env_var.reconnect_max_retries,
+        ),
+    };
+    let health = StreamHealth::new();
+    let mut supervisor = Supervisor::new(health.clone(), policy);
+    if let Some(replay) = &replay {
+        info!("Replaying the capture in {}", replay.dir().display());
+        supervisor = supervisor.with_replay(replay.clone());
+    } else if let Some(recorder) = env_var.recorder.clone() {
+        info!("Recording raw frames to {}", recorder.dir.display());
+        supervisor = supervisor.with_recorder(recorder);
+    }
+    if let Some(faults) = env_var.faults.clone() {
+        warn!("Fault injection is on. Do not use this run for trading");
+        supervisor = supervisor.with_faults(faults);
+    }
+
+    let mut binance_streams = BinanceThreads::new(
+        binance_future_routes,
+        binance_coin_routes,
+        binance_spot_routes,
+        supervisor.clone(),
+        env_var.endpoints.binance.clone(),
+        env_var.binance_streams_per_connection,
     );
+    if let Some(market_liquidations) = market_liquidations {
+        binance_streams = binance_streams.with_market_liquidations(market_liquidations);
+    }
+    let stats_pollers = [
+        (
+            BinanceStatsEndpoint::OpenInterest,
+            env_var.poll_open_interest_secs,
+        ),
+        (
+            BinanceStatsEndpoint::GlobalLongShortAccountRatio,
+            env_var.poll_account_ratio_secs,
+        ),
+        (
+            BinanceStatsEndpoint::TopLongShortPositionRatio,
+            env_var.poll_position_ratio_secs,
+        ),
+        (
+            BinanceStatsEndpoint::TakerLongShortRatio,
+            env_var.poll_taker_volume_secs,
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, secs)| *secs > 0)
+    .map(|(endpoint, secs)| (endpoint, Duration::from_secs(secs)))
+    .collect();
+    // Polled stats are not captured, replays run without them.
+    // Replayed requests do not reach the exchange and do not wait for weight either
+    if replay.is_none() {
+        binance_streams = binance_streams
+            .with_weight_limit(env_var.binance_weight_per_minute)
+            .with_stats_pollers(stats_pollers);
+    }
     binance_streams.spawn_streams(
         &mut tasks,
         env_var.symbol_binance_fut.clone(),
+        env_var.symbol_binance_coin.clone(),
         env_var.symbol_binance_spt.clone(),
     );
 
-    let upbit_krw_streams = UpbitThreads::new(SpotDataChannels {
-        ob_out: upbit_spt_krw.ob.0,
-        agg_out: upbit_spt_krw.agg.0,
-    });
-    upbit_krw_streams.spawn_streams(&mut tasks, env_var.symbol_upbit_krw.clone());
+    let bitget_streams = BitgetThreads::new(
+        bitget_future_routes,
+        supervisor.clone(),
+        env_var.endpoints.bitget.clone(),
+    );
+    bitget_streams.spawn_streams(&mut tasks, env_var.symbol_bitget_fut.clone());
+
+    let bybit_streams = BybitThreads::new(
+        bybit_future_routes,
+        supervisor.clone(),
+        env_var.endpoints.bybit.clone(),
+    );
+    bybit_streams.spawn_streams(&mut tasks, env_var.symbol_bybit_fut.clone());
+
+    let okx_streams = OkxThreads::new(
+        okx_future_routes,
+        supervisor.clone(),
+        env_var.endpoints.okx.clone(),
+    );
+    okx_streams.spawn_streams(&mut tasks, env_var.symbol_okx_fut.clone());
+
+    for (market, symbols, routes) in upbit_markets {
+        let upbit_streams = UpbitThreads::new(
+            market,
+            routes,
+            supervisor.clone(),
+            env_var.endpoints.upbit.clone(),
+        );
+        upbit_streams.spawn_streams(&mut tasks, symbols);
+    }
+
+    let bithumb_streams = BithumbThreads::new(
+        bithumb_krw_routes,
+        supervisor.clone(),
+        env_var.endpoints.bithumb.clone(),
+    );
+    bithumb_streams.spawn_streams(&mut tasks, env_var.symbol_bithumb_krw.clone());
+
+    /* Latency */
+    // Exchange clock offsets correct the latency of their streams. Replays run no clock sync:
+    // their latencies stay uncorrected
+    if replay.is_none() && env_var.clock_sync_secs > 0 {
+        let interval = Duration::from_secs(env_var.clock_sync_secs);
+        for (source, endpoint, enabled) in [
+            (
+                ServerTime::BinanceFuture,
+                &env_var.endpoints.binance.future,
+                !env_var.symbol_binance_fut.is_empty(),
+            ),
+            (
+                ServerTime::BinanceCoin,
+                &env_var.endpoints.binance.coin,
+                !env_var.symbol_binance_coin.is_empty(),
+            ),
+            (
+                ServerTime::BinanceSpot,
+                &env_var.endpoints.binance.spot,
+                !env_var.symbol_binance_spt.is_empty(),
+            ),
+            (
+                ServerTime::Upbit,
+                &env_var.endpoints.upbit,
+                !(env_var.symbol_upbit_krw.is_empty()
+                    && env_var.symbol_upbit_btc.is_empty()
+                    && env_var.symbol_upbit_usdt.is_empty()),
+            ),
+        ] {
+            if enabled {
+                let offset = health.clock_offset(source.clock());
+                let clock_sync = ClockSync::new(source, endpoint, interval, offset);
+                tasks.spawn(clock_sync.run());
+            }
+        }
+    }
+    if env_var.latency_report_secs > 0 {
+        let health = health.clone();
+        let sequencers = timing.sequencers.clone();
+        let period = Duration::from_secs(env_var.latency_report_secs);
+        tasks.spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                health.log_latency();
+                sequencers.log();
+            }
+        });
+    }
+
+    let replay_done = async move {
+        match replay {
+            Some(replay) => replay.run(replay_routes.0, replay_routes.1).await,
+            None => std::future::pending().await,
+        }
+    };
+    let mut exit = ExitCode::SUCCESS;
 
     /* Graceful Shutdown */
     tokio::select! {
         _ = signal::ctrl_c() => {
             info!("Received Ctrl+C, shutting down...");
         }
+        res = replay_done => {
+            match res {
+                Ok(()) => info!("Replay complete, shutting down..."),
+                Err(e) => {
+                    // Whatever the cores hold now is not what the capture plays out to
+                    error!("Replay failed: {}, the state digests are incomplete", e);
+                    exit = ExitCode::FAILURE;
+                }
+            }
+        }
         Some(res) = tasks.join_next() => {
             match res {
                 Ok(_) => warn!("A task completed unexpectedly"),
                 Err(e) => error!("A task failed: {}", e),
             }
         }
+        Some(res) = cores.join_next() => {
+            match res {
+                Ok(_) => warn!("A core completed unexpectedly"),
+                Err(e) => error!("A core failed: {}", e),
+            }
+        }
     }
 
-    // Abort all remaining tasks
+    // Abort all remaining streams
     tasks.abort_all();
     while tasks.join_next().await.is_some() {
         // Wait for all tasks to complete or be aborted
     }
+    // Write out the frames the recorders still hold
+    supervisor.close_recorders().await;
+    // Cores stop once every stream feeding them is gone
+    while cores.join_next().await.is_some() {}
+    health.log_latency();
     info!("Shutdown complete");
+    exit
+}
+
+/// How every core keeps time and orders its streams
+struct CoreTiming {
+    clock: Clock,               // Wall time, or the capture's time on replay
+    lateness: Option<Duration>, // Events are held up to this long to apply them in event time order
+    sequencers: SequencerReport,
+}
+
+impl CoreTiming {
+    /// Put the core of `symbol` on the clock and, when sequencing, behind a sequencer
+    /// reported under `market`
+    fn setup<Rx>(&self, core: Core<Rx>, market: &str, symbol: &str) -> Core<Rx> {
+        let core = core.with_clock(self.clock.clone());
+        match self.lateness {
+            Some(lateness) => core.with_sequencer(Sequencer::new(lateness).with_report(
+                &self.sequencers,
+                market,
+                symbol,
+            )),
+            None => core,
+        }
+    }
+}
+
+fn future_core(venue: Venue, symbol: &str, channel: FutureChannel) -> Core<FutureCore> {
+    Core::<FutureCore>::new(
+        venue,
+        symbol,
+        channel.ob.1,
+        channel.agg.1,
+        channel.bbo.1,
+        channel.additional.mark.1,
+        channel.additional.liq.1,
+        channel.additional.stats.1,
+    )
+}
+
+fn spot_core(venue: Venue, symbol: &str, channel: SpotChannel) -> Core<SpotCore> {
+    Core::<SpotCore>::new(venue, symbol, channel.ob.1, channel.agg.1, channel.bbo.1)
 }
@@ -1,5 +1,4 @@
 use super::Bar;
-use rust_decimal::Decimal;
 
 #[derive(Debug, Clone)]
 pub struct TickImbalanceBar {
@@ -0,0 +1,97 @@
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+// bookTicker is pushed on every change of the top of book, so the top at diff book update id
+// `u` is the last bookTicker with an id up to `u`. Tickers newer than the book wait here
+// until the book catches up
+const MAX_PENDING: usize = 4096;
+
+/// Recent bookTicker prices by orderbook update id, to compare with the diff book at the same id
+#[derive(Debug, Default)]
+pub struct BboHistory {
+    tickers: VecDeque<(u64, Decimal, Decimal)>, // (update id, bid, ask), ascending ids
+}
+
+impl BboHistory {
+    pub fn push(&mut self, update_id: u64, bid: Decimal, ask: Decimal) {
+        if self
+            .tickers
+            .back()
+            .is_some_and(|&(last, _, _)| update_id <= last)
+        {
+            return;
+        }
+        if self.tickers.len() == MAX_PENDING {
+            self.tickers.pop_front();
+        }
+        self.tickers.push_back((update_id, bid, ask));
+    }
+
+    /// (bid, ask) of bookTicker at the book's update id. None until bookTicker reached the id,
+    /// or when the ticker before it is already gone
+    pub fn at(&mut self, book_id: u64) -> Option<(Decimal, Decimal)> {
+        let &(newest, _, _) = self.tickers.back()?;
+        if newest < book_id {
+            // Tickers up to the book's id may still be in flight
+            return None;
+        }
+        let after = self.tickers.partition_point(|&(id, _, _)| id <= book_id);
+        if after == 0 {
+            return None;
+        }
+        // Older tickers are superseded for this and every later book
+        self.tickers.drain(..after - 1);
+        self.tickers.front().map(|&(_, bid, ask)| (bid, ask))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prices(bid: i64, ask: i64) -> (Decimal, Decimal) {
+        (Decimal::from(bid), Decimal::from(ask))
+    }
+
+    #[test]
+    fn book_matches_the_last_ticker_up_to_its_id() {
+        let mut history = BboHistory::default();
+        history.push(10, Decimal::from(100), Decimal::from(101));
+        history.push(14, Decimal::from(99), Decimal::from(101));
+        history.push(20, Decimal::from(98), Decimal::from(100));
+
+        assert_eq!(history.at(13), Some(prices(100, 101)));
+        assert_eq!(history.at(14), Some(prices(99, 101)));
+        assert_eq!(history.at(19), Some(prices(99, 101)));
+        assert_eq!(history.at(20), Some(prices(98, 100)));
+    }
+
+    #[test]
+    fn waits_for_the_ticker_to_reach_the_book() {
+        let mut history = BboHistory::default();
+        assert_eq!(history.at(5), None);
+
+        history.push(10, Decimal::from(100), Decimal::from(101));
+        // A ticker between 10 and 12 may still arrive
+        assert_eq!(history.at(12), None);
+
+        history.push(12, Decimal::from(99), Decimal::from(101));
+        assert_eq!(history.at(12), Some(prices(99, 101)));
+    }
+
+    #[test]
+    fn book_older_than_every_ticker_is_unknown() {
+        let mut history = BboHistory::default();
+        history.push(10, Decimal::from(100), Decimal::from(101));
+        assert_eq!(history.at(9), None);
+    }
+
+    #[test]
+    fn ignores_stale_tickers() {
+        let mut history = BboHistory::default();
+        history.push(10, Decimal::from(100), Decimal::from(101));
+        history.push(8, Decimal::from(1), Decimal::from(2));
+        history.push(10, Decimal::from(1), Decimal::from(2));
+        assert_eq!(history.at(10), Some(prices(100, 101)));
+    }
+}
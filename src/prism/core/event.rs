@@ -0,0 +1,109 @@
+use crate::data::{
+    bbo::BboData, depth::OrderbookUpdateStream, latency::ReceiveStamp,
+    liquidation::LiquidationData, market::MarketData, markprice::MarkPriceData,
+    stats::FuturesStatsData,
+};
+
+/* Market Events */
+
+// Everything a core consumes, normalized into one type. The channels of a core are turned
+// into events by its `work` loop; tests and backtests hand events to `Core::on_event` directly
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Venue {
+    Binance,
+    Bitget,
+    Bithumb,
+    Bybit,
+    Okx,
+    Upbit,
+}
+
+impl Venue {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Venue::Binance => "binance",
+            Venue::Bitget => "bitget",
+            Venue::Bithumb => "bithumb",
+            Venue::Bybit => "bybit",
+            Venue::Okx => "okx",
+            Venue::Upbit => "upbit",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MarketEvent {
+    Trade {
+        venue: Venue,
+        trade: MarketData,
+    },
+    Depth {
+        venue: Venue,
+        update: OrderbookUpdateStream,
+    },
+    Bbo {
+        venue: Venue,
+        bbo: BboData,
+    },
+    Mark {
+        venue: Venue,
+        mark: MarkPriceData,
+    },
+    Liquidation {
+        venue: Venue,
+        liquidation: LiquidationData,
+    },
+    Stats {
+        venue: Venue,
+        stats: FuturesStatsData, // Polled over REST
+    },
+}
+
+impl MarketEvent {
+    pub fn venue(&self) -> Venue {
+        match self {
+            MarketEvent::Trade { venue, .. }
+            | MarketEvent::Depth { venue, .. }
+            | MarketEvent::Bbo { venue, .. }
+            | MarketEvent::Mark { venue, .. }
+            | MarketEvent::Liquidation { venue, .. }
+            | MarketEvent::Stats { venue, .. } => *venue,
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        match self {
+            MarketEvent::Trade { trade, .. } => &trade.symbol,
+            MarketEvent::Depth { update, .. } => &update.symbol,
+            MarketEvent::Bbo { bbo, .. } => &bbo.symbol,
+            MarketEvent::Mark { mark, .. } => &mark.symbol,
+            MarketEvent::Liquidation { liquidation, .. } => &liquidation.symbol,
+            MarketEvent::Stats { stats, .. } => &stats.symbol,
+        }
+    }
+
+    /// When the event was received and its offset corrected latency. None for polled stats
+    pub fn received(&self) -> Option<ReceiveStamp> {
+        match self {
+            MarketEvent::Trade { trade, .. } => Some(trade.received),
+            MarketEvent::Depth { update, .. } => Some(update.received),
+            MarketEvent::Bbo { bbo, .. } => Some(bbo.received),
+            MarketEvent::Mark { mark, .. } => Some(mark.received),
+            MarketEvent::Liquidation { liquidation, .. } => Some(liquidation.received),
+            MarketEvent::Stats { .. } => None,
+        }
+    }
+
+    /// Exchange time of the event, in unix millis
+    pub fn event_time(&self) -> u64 {
+        match self {
+            MarketEvent::Trade { trade, .. } => trade.event_time,
+            MarketEvent::Depth { update, .. } => update.event_time,
+            MarketEvent::Bbo { bbo, .. } => bbo.event_time,
+            MarketEvent::Mark { mark, .. } => mark.event_time,
+            MarketEvent::Liquidation { liquidation, .. } => liquidation.event_time,
+            MarketEvent::Stats { stats, .. } => stats.event_time,
+        }
+    }
+}
@@ -1,82 +1,452 @@
-use crate::data::{depth, liquidation, market, markprice};
-use crate::prism::core::{Core, MarketState};
+use crate::data::{
+    bbo,
+    clock::Clock,
+    depth,
+    liquidation::{self, rolling::MarketLiquidations},
+    market, markprice,
+    stats::{FuturesStat, FuturesStatsData},
+};
+use crate::prism::core::{
+    bbo_check::BboHistory,
+    event::{MarketEvent, Venue},
+    is_live,
+    term_structure::{basis, underlying, BasisPoint, TermStructure},
+    Core, MarketState,
+};
 use crate::prism::orderbook::Orderbook;
-use rust_decimal::prelude::FromStr;
-use rust_decimal::Decimal;
 use tokio::sync::mpsc;
 
+const TOP_LIQUIDATED_SYMBOLS: usize = 5; // Kept in the market state next to the market-wide total
+
 pub struct FutureCore {
     pub mark: mpsc::Receiver<markprice::MarkPriceData>,
     pub liq: mpsc::Receiver<liquidation::LiquidationData>,
+    pub stats: mpsc::Receiver<FuturesStatsData>,
+    pub term_structure: Option<(TermStructure, u64)>, // Delivery contracts: (curve, delivery time)
+    pub market_liquidations: Option<MarketLiquidations>,
 }
 
 impl Core<FutureCore> {
+    #[allow(clippy::too_many_arguments)] // One receiver per stream
     pub fn new(
+        venue: Venue,
+        symbol: &str,
         ob: mpsc::Receiver<depth::OrderbookUpdateStream>,
         agg: mpsc::Receiver<market::MarketData>,
+        bbo: mpsc::Receiver<bbo::BboData>,
         mark: mpsc::Receiver<markprice::MarkPriceData>,
         liq: mpsc::Receiver<liquidation::LiquidationData>,
+        stats: mpsc::Receiver<FuturesStatsData>,
     ) -> Self {
         Self {
             ob,
             agg,
-            additional: FutureCore { mark, liq },
-            market_state: MarketState::new(),
+            bbo,
+            additional: FutureCore {
+                mark,
+                liq,
+                stats,
+                term_structure: None,
+                market_liquidations: None,
+            },
+            venue,
+            clock: Clock::default(),
+            sequencer: None,
+            market_state: MarketState::new(symbol),
             total_orderbook: Orderbook::new(),
+            bbo_history: BboHistory::default(),
         }
     }
 
+    /// Track the liquidations of the whole venue next to the symbol's own
+    pub fn with_market_liquidations(mut self, market_liquidations: MarketLiquidations) -> Self {
+        self.additional.market_liquidations = Some(market_liquidations);
+        self
+    }
+
+    /// Publish the basis of a delivery contract into the term structure of its underlying
+    pub fn with_term_structure(
+        mut self,
+        term_structure: TermStructure,
+        delivery_time: u64,
+    ) -> Self {
+        self.additional.term_structure = Some((term_structure, delivery_time));
+        self
+    }
+
     pub async fn work(&mut self) {
+        let venue = self.venue;
         loop {
-            tokio::select! {
-                Some(market) = self.agg.recv() => {
-                    // Update price
-                    self.market_state.event_time = market.event_time;
-                    self.market_state.transaction_time = market.trade_time;
-                    self.market_state.price = Decimal::from_str(&market.price).unwrap();
-
-                    match market.buyer_market_maker {
-                        true => {
-                            self.market_state.sell_quantity = Decimal::from_str(&market.quantity).unwrap();
-                            self.market_state.buy_quantity = Decimal::from(0);
-                        }
-                        false => {
-                            self.market_state.buy_quantity = Decimal::from_str(&market.quantity).unwrap();
-                            self.market_state.sell_quantity = Decimal::from(0);
-                        }
-                    }
-                    self.debug();
+            // Held events to release, unless every stream is gone and they go out at once
+            let due = self.flush_due().filter(|_| !self.is_closed());
+            let event = tokio::select! {
+                // Polled in this order, so the same arrivals always apply in the same order
+                biased;
+
+                // A stream that ends wakes the loop like a flush tick, so that a flush waiting
+                // on a clock nobody moves any more cannot hold the core up
+                trade = self.agg.recv(), if is_live(&self.agg) => {
+                    trade.map(|trade| MarketEvent::Trade { venue, trade })
+                }
+                mark = self.additional.mark.recv(), if is_live(&self.additional.mark) => {
+                    mark.map(|mark| MarketEvent::Mark { venue, mark })
+                }
+                liquidation = self.additional.liq.recv(), if is_live(&self.additional.liq) => {
+                    liquidation.map(|liquidation| MarketEvent::Liquidation { venue, liquidation })
+                }
+                stats = self.additional.stats.recv(), if is_live(&self.additional.stats) => {
+                    stats.map(|stats| MarketEvent::Stats { venue, stats })
+                }
+                bbo = self.bbo.recv(), if is_live(&self.bbo) => {
+                    bbo.map(|bbo| MarketEvent::Bbo { venue, bbo })
+                }
+                update = self.ob.recv(), if is_live(&self.ob) => {
+                    update.map(|update| MarketEvent::Depth { venue, update })
                 }
 
-                Some(mark) = self.additional.mark.recv() => {
-                    self.market_state.event_time = mark.event_time;
-                    // Update mark price
-                    self.market_state.mark_price = Some(Decimal::from_str(&mark.mark_price).unwrap());
-                    self.market_state.index_price = Some(Decimal::from_str(&mark.index_price).unwrap());
-                    self.market_state.funding_rate = Some(Decimal::from_str(&mark.funding_rate).unwrap());
-                    self.market_state.next_funding_time = Some(mark.next_funding_time);
+                // Release the held events that waited out the lateness
+                _ = self.clock.sleep_until(due.unwrap_or_default()), if due.is_some() => None,
 
-                    self.debug();
-                }
+                // Every stream of this symbol is gone
+                else => break,
+            };
+            self.dispatch(event, Self::on_event);
+        }
+        self.finish(Self::on_event);
+    }
 
-                Some(liq) = self.additional.liq.recv() => {
-                    // Update liquidation
-                    self.market_state.liq_quantity = Decimal::from_str(&liq.quantity).unwrap();
-                    self.market_state.liq_price = Decimal::from_str(&liq.avg_price).unwrap();
-                    self.market_state.liq_side = liq.side;
+    fn is_closed(&self) -> bool {
+        !is_live(&self.ob)
+            && !is_live(&self.agg)
+            && !is_live(&self.bbo)
+            && !is_live(&self.additional.mark)
+            && !is_live(&self.additional.liq)
+            && !is_live(&self.additional.stats)
+    }
 
-                    self.debug();
-                }
+    /// Apply one event to the market state and the book
+    pub fn on_event(&mut self, event: MarketEvent) {
+        self.apply_latency(&event);
+        match event {
+            MarketEvent::Trade { trade, .. } => {
+                // Update price
+                self.apply_trade(&trade);
+                self.update_market_liquidations();
+            }
 
-                Some(ob) = self.ob.recv() => {
-                    // Update orderbook
-                    self.total_orderbook.update(&ob).await;
+            MarketEvent::Mark { mark, .. } => {
+                self.market_state.event_time = mark.event_time;
+                // Update mark price
+                self.market_state.mark_price = Some(mark.mark_price);
+                self.market_state.index_price = Some(mark.index_price);
+                self.market_state.funding_rate = Some(mark.funding_rate);
+                self.market_state.next_funding_time = Some(mark.next_funding_time);
+                self.update_basis(&mark);
+            }
 
-                    self.debug();
+            MarketEvent::Liquidation { liquidation, .. } => {
+                // Update liquidation
+                self.market_state.liq_quantity = liquidation.quantity;
+                self.market_state.liq_price = liquidation.avg_price;
+                self.market_state.liq_side = liquidation.side;
+                self.update_market_liquidations();
+            }
+
+            MarketEvent::Stats { stats, .. } => {
+                // Update positioning
+                match stats.stat {
+                    FuturesStat::OpenInterest(open_interest) => {
+                        self.market_state.open_interest = Some(open_interest);
+                    }
+                    FuturesStat::LongShortAccountRatio { ratio, .. } => {
+                        self.market_state.long_short_account_ratio = Some(ratio);
+                    }
+                    FuturesStat::TopLongShortPositionRatio { ratio, .. } => {
+                        self.market_state.top_long_short_position_ratio = Some(ratio);
+                    }
+                    FuturesStat::TakerVolume { buy, sell, .. } => {
+                        self.market_state.taker_buy_volume = Some(buy);
+                        self.market_state.taker_sell_volume = Some(sell);
+                    }
                 }
+            }
 
-                else => {}
+            MarketEvent::Bbo { bbo, .. } => {
+                // Update best bid/offer
+                self.update_bbo(&bbo);
             }
+
+            MarketEvent::Depth { update, .. } => {
+                // Update orderbook
+                self.apply_depth(&update);
+            }
+        }
+
+        self.debug();
+    }
+
+    fn update_market_liquidations(&mut self) {
+        let Some(market_liquidations) = &self.additional.market_liquidations else {
+            return;
+        };
+        let now = self.market_state.event_time;
+        self.market_state.market_liquidations = Some(market_liquidations.total(now));
+        self.market_state.top_liquidated =
+            market_liquidations.top_symbols(now, TOP_LIQUIDATED_SYMBOLS);
+    }
+
+    fn update_basis(&mut self, mark: &markprice::MarkPriceData) {
+        let Some((term_structure, delivery_time)) = &self.additional.term_structure else {
+            return;
+        };
+
+        let Some((basis, annualized_basis)) = basis(
+            mark.mark_price,
+            mark.index_price,
+            mark.event_time,
+            *delivery_time,
+        ) else {
+            // Delivered
+            self.market_state.basis = None;
+            self.market_state.annualized_basis = None;
+            term_structure.remove(&self.market_state.symbol, *delivery_time);
+            self.market_state.basis_curve =
+                term_structure.curve(underlying(&self.market_state.symbol));
+            return;
+        };
+        self.market_state.basis = Some(basis);
+        self.market_state.annualized_basis = Some(annualized_basis);
+
+        term_structure.set(BasisPoint {
+            symbol: self.market_state.symbol.clone(),
+            delivery_time: *delivery_time,
+            basis,
+            annualized_basis,
+            event_time: mark.event_time,
+        });
+        self.market_state.basis_curve = term_structure.curve(underlying(&self.market_state.symbol));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{depth::OrderbookUpdateKind, latency::ReceiveStamp};
+    use rust_decimal::Decimal;
+    use std::time::Duration;
+
+    const SYMBOL: &str = "BTCUSDT_250627";
+    const YEAR_MILLIS: u64 = 365 * 24 * 60 * 60 * 1000;
+
+    fn core() -> Core<FutureCore> {
+        // The senders are dropped, events are handed to `on_event` directly
+        Core::<FutureCore>::new(
+            Venue::Binance,
+            SYMBOL,
+            mpsc::channel(1).1,
+            mpsc::channel(1).1,
+            mpsc::channel(1).1,
+            mpsc::channel(1).1,
+            mpsc::channel(1).1,
+            mpsc::channel(1).1,
+        )
+    }
+
+    fn trade(price: i64, quantity: i64, buyer_market_maker: bool, time: u64) -> MarketEvent {
+        MarketEvent::Trade {
+            venue: Venue::Binance,
+            trade: market::MarketData {
+                symbol: SYMBOL.to_string(),
+                price: Decimal::from(price),
+                quantity: Decimal::from(quantity),
+                buyer_market_maker,
+                trade_time: time,
+                event_time: time,
+                received: ReceiveStamp::local(time),
+            },
+        }
+    }
+
+    fn depth(
+        kind: OrderbookUpdateKind,
+        update_id: u64,
+        bids: &[(i64, i64)],
+        asks: &[(i64, i64)],
+    ) -> MarketEvent {
+        let levels = |levels: &[(i64, i64)]| {
+            levels
+                .iter()
+                .map(|&(price, quantity)| (Decimal::from(price), Decimal::from(quantity)))
+                .collect()
+        };
+        MarketEvent::Depth {
+            venue: Venue::Binance,
+            update: depth::OrderbookUpdateStream {
+                symbol: SYMBOL.to_string(),
+                bids: levels(bids),
+                asks: levels(asks),
+                kind,
+                trade_time: 1_000 + update_id,
+                event_time: 1_000 + update_id,
+                update_id: Some(update_id),
+                last_update_exchange: "binance".to_string(),
+                received: ReceiveStamp::local(1_000 + update_id),
+            },
+        }
+    }
+
+    fn bbo(update_id: u64, bid: i64, ask: i64) -> MarketEvent {
+        MarketEvent::Bbo {
+            venue: Venue::Binance,
+            bbo: bbo::BboData {
+                symbol: SYMBOL.to_string(),
+                bid_price: Decimal::from(bid),
+                bid_quantity: Decimal::ONE,
+                ask_price: Decimal::from(ask),
+                ask_quantity: Decimal::ONE,
+                update_id,
+                trade_time: 1_000 + update_id,
+                event_time: 1_000 + update_id,
+                received: ReceiveStamp::local(1_000 + update_id),
+            },
+        }
+    }
+
+    fn mark(mark_price: i64, index_price: i64, time: u64) -> MarketEvent {
+        MarketEvent::Mark {
+            venue: Venue::Binance,
+            mark: markprice::MarkPriceData {
+                symbol: SYMBOL.to_string(),
+                mark_price: Decimal::from(mark_price),
+                index_price: Decimal::from(index_price),
+                funding_rate: Decimal::ZERO,
+                next_funding_time: 0,
+                event_time: time,
+                received: ReceiveStamp::local(time),
+            },
         }
     }
+
+    fn liquidation(
+        side: &str,
+        price: i64,
+        quantity: i64,
+        time: u64,
+    ) -> liquidation::LiquidationData {
+        liquidation::LiquidationData {
+            symbol: SYMBOL.to_string(),
+            side: side.to_string(),
+            avg_price: Decimal::from(price),
+            quantity: Decimal::from(quantity),
+            trade_time: time,
+            event_time: time,
+            received: ReceiveStamp::local(time),
+        }
+    }
+
+    #[test]
+    fn trades_and_depth_move_the_price_and_the_book() {
+        let mut core = core();
+        core.on_event(trade(100, 2, false, 1_000));
+        core.on_event(trade(101, 3, true, 1_001));
+        assert_eq!(core.market_state.price, Decimal::from(101));
+        assert_eq!(core.market_state.sell_quantity, Decimal::from(3));
+        assert_eq!(core.market_state.buy_quantity, Decimal::ZERO);
+        assert_eq!(core.market_state.transaction_time, 1_001);
+
+        core.on_event(depth(
+            OrderbookUpdateKind::Snapshot,
+            10,
+            &[(99, 1), (98, 2)],
+            &[(102, 1)],
+        ));
+        core.on_event(depth(
+            OrderbookUpdateKind::Delta,
+            11,
+            &[(99, 0)],
+            &[(101, 4)],
+        ));
+        let book = &core.total_orderbook;
+        assert_eq!(book.best_bid(), Some((Decimal::from(98), Decimal::from(2))));
+        assert_eq!(
+            book.best_ask(),
+            Some((Decimal::from(101), Decimal::from(4)))
+        );
+        assert_eq!(book.update_id, Some(11));
+    }
+
+    #[test]
+    fn bbo_mismatch_follows_the_book_at_the_same_update_id() {
+        let mut core = core();
+        core.on_event(depth(
+            OrderbookUpdateKind::Snapshot,
+            10,
+            &[(100, 1)],
+            &[(101, 1)],
+        ));
+        core.on_event(bbo(10, 100, 101));
+        assert!(!core.market_state.bbo_mismatch);
+        assert_eq!(
+            core.market_state.best_ask,
+            Some((Decimal::from(101), Decimal::ONE))
+        );
+
+        // bookTicker moved the ask at 11, the diff book did not
+        core.on_event(bbo(11, 100, 102));
+        core.on_event(depth(OrderbookUpdateKind::Delta, 11, &[(100, 2)], &[]));
+        assert!(core.market_state.bbo_mismatch);
+
+        core.on_event(bbo(12, 100, 102));
+        core.on_event(depth(
+            OrderbookUpdateKind::Delta,
+            12,
+            &[],
+            &[(101, 0), (102, 1)],
+        ));
+        assert!(!core.market_state.bbo_mismatch);
+    }
+
+    #[test]
+    fn mark_sets_the_basis_and_publishes_it_to_the_curve() {
+        let term_structure = TermStructure::new();
+        let delivery_time = 1_000 + YEAR_MILLIS / 2;
+        let mut core = core().with_term_structure(term_structure.clone(), delivery_time);
+
+        core.on_event(mark(101, 100, 1_000));
+        let state = &core.market_state;
+        assert_eq!(state.mark_price, Some(Decimal::from(101)));
+        assert_eq!(state.index_price, Some(Decimal::from(100)));
+        assert_eq!(state.basis, Some(Decimal::new(1, 2)));
+        // Half a year to delivery doubles it
+        assert_eq!(state.annualized_basis, Some(Decimal::new(2, 2)));
+        assert_eq!(state.basis_curve.len(), 1);
+        assert_eq!(term_structure.curve("BTCUSDT")[0].symbol, SYMBOL);
+
+        // Delivered: off the curve
+        core.on_event(mark(100, 100, delivery_time));
+        assert_eq!(core.market_state.basis, None);
+        assert!(core.market_state.basis_curve.is_empty());
+        assert!(term_structure.curve("BTCUSDT").is_empty());
+    }
+
+    #[test]
+    fn liquidations_update_the_symbol_and_the_market() {
+        let market_liquidations = MarketLiquidations::new(Duration::from_secs(60));
+        let mut core = core().with_market_liquidations(market_liquidations.clone());
+        core.on_event(trade(100, 1, false, 1_000));
+
+        let liquidated = liquidation("SELL", 100, 2, 1_000);
+        market_liquidations.record(&liquidated);
+        core.on_event(MarketEvent::Liquidation {
+            venue: Venue::Binance,
+            liquidation: liquidated,
+        });
+        let state = &core.market_state;
+        assert_eq!(state.liq_quantity, Decimal::from(2));
+        assert_eq!(state.liq_price, Decimal::from(100));
+        assert_eq!(state.liq_side, "SELL");
+        let total = state.market_liquidations.clone().unwrap();
+        assert_eq!(total.sell, Decimal::from(200));
+        assert_eq!(state.top_liquidated[0].0, SYMBOL);
+    }
 }
@@ -1,15 +1,25 @@
+use crate::data::liquidation::rolling::LiquidationNotional;
+use crate::prism::core::term_structure::BasisPoint;
 use rust_decimal::Decimal;
 
 #[derive(Debug)]
 pub struct MarketState {
+    pub symbol: String,
     // Time: Websocket Received Time
     pub event_time: u64,
     // Time: Transaction Time
     pub transaction_time: u64,
+    // Latency: Exchange to local of the last stream event, offset corrected (millis)
+    pub latency: Option<i64>,
     // Price
     pub price: Decimal,
     pub index_price: Option<Decimal>,
     pub vwap: Option<Decimal>,
+    pub krw_price: Option<Decimal>, // Upbit: price converted to KRW through the quote market
+    // Best Bid/Offer: bookTicker
+    pub best_bid: Option<(Decimal, Decimal)>, // (price, quantity)
+    pub best_ask: Option<(Decimal, Decimal)>, // (price, quantity)
+    pub bbo_mismatch: bool, // bookTicker and the diff book disagree at the same update id
     // Quantity
     pub sell_quantity: Decimal,
     pub buy_quantity: Decimal,
@@ -17,28 +27,56 @@ pub struct MarketState {
     pub mark_price: Option<Decimal>,
     pub funding_rate: Option<Decimal>,
     pub next_funding_time: Option<u64>,
+    // Basis: Delivery contracts only
+    pub basis: Option<Decimal>,
+    pub annualized_basis: Option<Decimal>,
+    pub basis_curve: Vec<BasisPoint>, // Every delivery contract of the underlying, nearest first
+    // Positioning: Polled over REST
+    pub open_interest: Option<Decimal>,
+    pub long_short_account_ratio: Option<Decimal>,
+    pub top_long_short_position_ratio: Option<Decimal>,
+    pub taker_buy_volume: Option<Decimal>,
+    pub taker_sell_volume: Option<Decimal>,
     // Liquidation
     pub liq_quantity: Decimal,
     pub liq_price: Decimal,
     pub liq_side: String,
+    pub market_liquidations: Option<LiquidationNotional>, // Every symbol of the venue over the rolling window
+    pub top_liquidated: Vec<(String, LiquidationNotional)>, // Largest symbols of the same window
 }
 
 impl MarketState {
-    pub fn new() -> Self {
+    pub fn new(symbol: &str) -> Self {
         Self {
+            symbol: symbol.to_uppercase(),
             event_time: 0,
             transaction_time: 0,
+            latency: None,
             price: Decimal::from(0),
             index_price: None,
             vwap: None,
+            krw_price: None,
+            best_bid: None,
+            best_ask: None,
+            bbo_mismatch: false,
             sell_quantity: Decimal::from(0),
             buy_quantity: Decimal::from(0),
             mark_price: None,
             funding_rate: None,
             next_funding_time: None,
+            basis: None,
+            annualized_basis: None,
+            basis_curve: Vec::new(),
+            open_interest: None,
+            long_short_account_ratio: None,
+            top_long_short_position_ratio: None,
+            taker_buy_volume: None,
+            taker_sell_volume: None,
             liq_quantity: Decimal::from(0),
             liq_price: Decimal::from(0),
             liq_side: String::from(""),
+            market_liquidations: None,
+            top_liquidated: Vec::new(),
         }
     }
 }
@@ -1,29 +1,66 @@
+pub mod bbo_check;
+pub mod event;
 pub mod future;
 pub mod market_state;
+pub mod quote;
+pub mod sequencer;
 pub mod spot;
+pub mod term_structure;
 
-use crate::data::{depth, market};
+use crate::data::{bbo, clock::Clock, depth, market};
 use crate::prism::orderbook::Orderbook;
-use log::debug;
+use crate::util::fnv1a;
+use bbo_check::BboHistory;
+use event::{MarketEvent, Venue};
+use log::{debug, info, warn};
 use market_state::MarketState;
+use rust_decimal::Decimal;
+use sequencer::Sequencer;
 use tokio::sync::mpsc;
 
+/// Whether `rx` can still hand out events: a sender is left, or events are buffered
+fn is_live<T>(rx: &mpsc::Receiver<T>) -> bool {
+    !rx.is_closed() || !rx.is_empty()
+}
+
 pub struct Core<Rx> {
     // Data Channel
     ob: mpsc::Receiver<depth::OrderbookUpdateStream>,
     agg: mpsc::Receiver<market::MarketData>,
+    bbo: mpsc::Receiver<bbo::BboData>,
 
     additional: Rx,
 
+    venue: Venue,
+    // Wall time, or the capture's time on replay
+    clock: Clock,
+    // Orders the streams by event time. Without it events apply in arrival order
+    sequencer: Option<Sequencer>,
+
     // Market State
     pub market_state: MarketState,
     // Orderbook
     pub total_orderbook: Orderbook,
     // pub filtered_orderbook: Orderbook,
     // Bars
+
+    // bookTicker prices the diff book has not caught up with
+    bbo_history: BboHistory,
 }
 
 impl<Rx> Core<Rx> {
+    /// Read the time from `clock` instead of the wall
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Apply the events of every stream in exchange event time order
+    pub fn with_sequencer(mut self, sequencer: Sequencer) -> Self {
+        self.sequencer = Some(sequencer);
+        self
+    }
+
     pub fn debug(&self) {
         debug!("Market State: {:?}", self.market_state);
         debug!(
@@ -35,4 +72,140 @@ impl<Rx> Core<Rx> {
             self.total_orderbook.best_bid()
         );
     }
+
+    /// Fingerprint of the market state and the whole book.
+    /// Two runs over the same capture end with the same digest
+    pub fn state_digest(&self) -> u64 {
+        fnv1a(&format!(
+            "{:?} {:?}",
+            self.market_state, self.total_orderbook
+        ))
+    }
+
+    /// Time on the core's clock at which held events have waited out the lateness.
+    /// None while nothing is held
+    fn flush_due(&self) -> Option<u64> {
+        self.sequencer.as_ref().and_then(Sequencer::next_due)
+    }
+
+    /// Hand a received event (`None` on a flush tick) to the sequencer and apply what it
+    /// releases. Without a sequencer the event is applied at once
+    fn dispatch(&mut self, event: Option<MarketEvent>, apply: fn(&mut Self, MarketEvent)) {
+        let now = self.clock.now_millis();
+        let Some(sequencer) = &mut self.sequencer else {
+            if let Some(event) = event {
+                apply(self, event);
+            }
+            return;
+        };
+        match event {
+            Some(event) => sequencer.push(event, now),
+            None => sequencer.tick(now),
+        }
+        self.apply_released(apply);
+    }
+
+    /// Apply the held events and report. Every stream is gone
+    fn finish(&mut self, apply: fn(&mut Self, MarketEvent)) {
+        if let Some(sequencer) = &mut self.sequencer {
+            sequencer.finish();
+        }
+        self.apply_released(apply);
+        self.report();
+    }
+
+    fn apply_released(&mut self, apply: fn(&mut Self, MarketEvent)) {
+        while let Some(event) = self.sequencer.as_mut().and_then(Sequencer::pop) {
+            apply(self, event);
+        }
+    }
+
+    fn report(&self) {
+        info!(
+            "{} {}: Streams closed. Best bid {:?}, best ask {:?}, state digest {:016x}",
+            self.venue.as_str(),
+            self.market_state.symbol,
+            self.total_orderbook.best_bid(),
+            self.total_orderbook.best_ask(),
+            self.state_digest()
+        );
+        if let Some(sequencer) = &self.sequencer {
+            let stats = sequencer.stats();
+            info!(
+                "{} {}: Sequencer released {} events, {} reordered, {} late (worst {} ms)",
+                self.venue.as_str(),
+                self.market_state.symbol,
+                stats.released,
+                stats.reordered,
+                stats.late,
+                stats.max_lateness
+            );
+        }
+    }
+
+    fn apply_latency(&mut self, event: &MarketEvent) {
+        if let Some(received) = event.received() {
+            self.market_state.latency = received.latency;
+        }
+    }
+
+    fn apply_trade(&mut self, market: &market::MarketData) {
+        self.market_state.event_time = market.event_time;
+        self.market_state.transaction_time = market.trade_time;
+        self.market_state.price = market.price;
+
+        match market.buyer_market_maker {
+            true => {
+                self.market_state.sell_quantity = market.quantity;
+                self.market_state.buy_quantity = Decimal::from(0);
+            }
+            false => {
+                self.market_state.buy_quantity = market.quantity;
+                self.market_state.sell_quantity = Decimal::from(0);
+            }
+        }
+    }
+
+    fn apply_depth(&mut self, update: &depth::OrderbookUpdateStream) {
+        self.total_orderbook.update(update);
+        self.cross_check_bbo();
+    }
+
+    fn update_bbo(&mut self, bbo: &bbo::BboData) {
+        self.market_state.best_bid = Some((bbo.bid_price, bbo.bid_quantity));
+        self.market_state.best_ask = Some((bbo.ask_price, bbo.ask_quantity));
+        self.bbo_history
+            .push(bbo.update_id, bbo.bid_price, bbo.ask_price);
+        self.cross_check_bbo();
+    }
+
+    /// Compare the diff book with bookTicker at the same orderbook update id
+    fn cross_check_bbo(&mut self) {
+        let Some(book_id) = self.total_orderbook.update_id else {
+            return;
+        };
+        let Some((bbo_bid, bbo_ask)) = self.bbo_history.at(book_id) else {
+            return;
+        };
+        let (Some((book_bid, _)), Some((book_ask, _))) = (
+            self.total_orderbook.best_bid(),
+            self.total_orderbook.best_ask(),
+        ) else {
+            return;
+        };
+
+        let mismatch = bbo_bid != book_bid || bbo_ask != book_ask;
+        match (mismatch, self.market_state.bbo_mismatch) {
+            (true, false) => warn!(
+                "{}: Diff book {} / {} disagrees with bookTicker {} / {} at update {}",
+                self.market_state.symbol, book_bid, book_ask, bbo_bid, bbo_ask, book_id
+            ),
+            (false, true) => info!(
+                "{}: Diff book agrees with bookTicker again",
+                self.market_state.symbol
+            ),
+            _ => (),
+        }
+        self.market_state.bbo_mismatch = mismatch;
+    }
 }
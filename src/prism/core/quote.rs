@@ -0,0 +1,35 @@
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// Upbit lists the same coin against KRW, BTC and USDT (e.g. KRW-ETH, BTC-ETH, USDT-ETH).
+// To compare them, BTC and USDT quoted prices are converted with the live
+// KRW-BTC and KRW-USDT books, which are published here by their KRW cores.
+
+/// Live KRW price of every Upbit quote currency, keyed by currency (e.g. "BTC")
+#[derive(Debug, Clone, Default)]
+pub struct KrwRates {
+    inner: Arc<RwLock<HashMap<String, Decimal>>>,
+}
+
+impl KrwRates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, currency: &str, price: Decimal) {
+        self.inner
+            .write()
+            .unwrap()
+            .insert(currency.to_string(), price);
+    }
+
+    pub fn get(&self, currency: &str) -> Option<Decimal> {
+        self.inner.read().unwrap().get(currency).copied()
+    }
+}
+
+/// Split an Upbit market code into (quote, base), e.g. "BTC-ETH" -> ("BTC", "ETH")
+pub fn upbit_market(symbol: &str) -> Option<(&str, &str)> {
+    symbol.split_once('-')
+}
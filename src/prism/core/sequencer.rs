@@ -0,0 +1,321 @@
+use crate::prism::core::event::MarketEvent;
+use log::{debug, info};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/* Event Sequencer */
+
+// The streams of a symbol arrive on separate sockets. The sequencer holds their events and
+// releases them in exchange event time order:
+// - The watermark trails the newest event time seen by the lateness. Events at or below it go out
+// - An event held for the lateness on the local clock pushes the watermark up to its own time,
+//   so a quiet stream does not hold the others back
+// - An event older than one already released is late. It goes out at once, out of order
+// Polled stats carry the time of the figure, not of the message, and are passed through
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SequencerStats {
+    pub released: u64,
+    pub reordered: u64,    // Arrived out of order, released in order
+    pub late: u64,         // Arrived after the watermark passed, released out of order
+    pub max_lateness: u64, // Worst late event, in millis behind the released time
+}
+
+struct Held {
+    event_time: u64,
+    seq: u64, // Arrival order, ties keep it
+    event: MarketEvent,
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    // Reversed: the heap pops the oldest event first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.event_time, other.seq).cmp(&(self.event_time, self.seq))
+    }
+}
+
+type SharedStats = Arc<Mutex<SequencerStats>>;
+
+/// Counts of the sequencers of every core, logged next to the latency report
+#[derive(Debug, Clone, Default)]
+pub struct SequencerReport {
+    sequencers: Arc<Mutex<Vec<(String, SharedStats)>>>, // (market and symbol, counts)
+}
+
+impl SequencerReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn log(&self) {
+        for (name, stats) in self.sequencers.lock().unwrap().iter() {
+            let stats = *stats.lock().unwrap();
+            info!(
+                "{}: Sequencer released {} events, {} reordered, {} late (worst {} ms)",
+                name, stats.released, stats.reordered, stats.late, stats.max_lateness
+            );
+        }
+    }
+}
+
+pub struct Sequencer {
+    lateness: u64, // Millis
+    held: BinaryHeap<Held>,
+    arrivals: VecDeque<(u64, u64)>, // (local arrival millis, event time) in arrival order
+    ready: VecDeque<MarketEvent>,
+    newest: u64, // Newest event time seen
+    watermark: u64,
+    released: u64, // Newest event time released
+    seq: u64,
+    stats: SharedStats, // Also read by the report
+}
+
+impl Sequencer {
+    pub fn new(lateness: Duration) -> Self {
+        Self {
+            lateness: lateness.as_millis() as u64,
+            held: BinaryHeap::new(),
+            arrivals: VecDeque::new(),
+            ready: VecDeque::new(),
+            newest: 0,
+            watermark: 0,
+            released: 0,
+            seq: 0,
+            stats: Arc::new(Mutex::new(SequencerStats::default())),
+        }
+    }
+
+    pub fn lateness(&self) -> Duration {
+        Duration::from_millis(self.lateness)
+    }
+
+    /// Publish the counts to `report`, under the stream prefix and symbol of the core
+    pub fn with_report(self, report: &SequencerReport, market: &str, symbol: &str) -> Self {
+        report
+            .sequencers
+            .lock()
+            .unwrap()
+            .push((format!("{} {}", market, symbol), self.stats.clone()));
+        self
+    }
+
+    pub fn stats(&self) -> SequencerStats {
+        *self.stats.lock().unwrap()
+    }
+
+    pub fn is_holding(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// Take an event received at `now` (local millis)
+    pub fn push(&mut self, event: MarketEvent, now: u64) {
+        // Release what the time alone made due, before the new event can jump ahead of it
+        self.tick(now);
+
+        if let MarketEvent::Stats { .. } = event {
+            self.ready.push_back(event);
+            return;
+        }
+
+        let event_time = event.event_time();
+        if event_time < self.released {
+            let lateness = self.released - event_time;
+            debug!(
+                "{} {}: Event {} ms behind the released stream",
+                event.venue().as_str(),
+                event.symbol(),
+                lateness
+            );
+            let mut stats = self.stats.lock().unwrap();
+            stats.late += 1;
+            stats.max_lateness = stats.max_lateness.max(lateness);
+            stats.released += 1;
+            drop(stats);
+            self.ready.push_back(event);
+            return;
+        }
+        if event_time < self.newest {
+            self.stats.lock().unwrap().reordered += 1;
+        }
+
+        self.held.push(Held {
+            event_time,
+            seq: self.seq,
+            event,
+        });
+        self.seq += 1;
+        self.arrivals.push_back((now, event_time));
+        self.newest = self.newest.max(event_time);
+        self.watermark = self
+            .watermark
+            .max(self.newest.saturating_sub(self.lateness));
+        self.release();
+    }
+
+    /// Local millis at which the oldest arrival has waited out the lateness. None while
+    /// nothing is held
+    pub fn next_due(&self) -> Option<u64> {
+        if !self.is_holding() {
+            return None;
+        }
+        self.arrivals
+            .front()
+            .map(|&(arrival, _)| arrival + self.lateness)
+    }
+
+    /// Release the events that waited out the lateness by `now`
+    pub fn tick(&mut self, now: u64) {
+        while let Some(&(arrival, event_time)) = self.arrivals.front() {
+            if arrival + self.lateness > now {
+                break;
+            }
+            self.watermark = self.watermark.max(event_time);
+            self.arrivals.pop_front();
+        }
+        self.release();
+    }
+
+    /// Release everything. The streams are gone
+    pub fn finish(&mut self) {
+        self.watermark = u64::MAX;
+        self.arrivals.clear();
+        self.release();
+    }
+
+    /// Next event in sequence
+    pub fn pop(&mut self) -> Option<MarketEvent> {
+        self.ready.pop_front()
+    }
+
+    fn release(&mut self) {
+        let mut stats = self.stats.lock().unwrap();
+        while self
+            .held
+            .peek()
+            .is_some_and(|held| held.event_time <= self.watermark)
+        {
+            let Some(held) = self.held.pop() else {
+                break;
+            };
+            self.released = self.released.max(held.event_time);
+            stats.released += 1;
+            self.ready.push_back(held.event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{latency::ReceiveStamp, market::MarketData};
+    use crate::prism::core::event::Venue;
+    use rust_decimal::Decimal;
+
+    fn trade(event_time: u64) -> MarketEvent {
+        MarketEvent::Trade {
+            venue: Venue::Binance,
+            trade: MarketData {
+                symbol: "BTCUSDT".to_string(),
+                price: Decimal::ONE,
+                quantity: Decimal::ONE,
+                buyer_market_maker: false,
+                trade_time: event_time,
+                event_time,
+                received: ReceiveStamp::local(0),
+            },
+        }
+    }
+
+    fn released(sequencer: &mut Sequencer) -> Vec<u64> {
+        std::iter::from_fn(|| sequencer.pop())
+            .map(|event| event.event_time())
+            .collect()
+    }
+
+    #[test]
+    fn releases_in_event_time_order_behind_the_watermark() {
+        let mut sequencer = Sequencer::new(Duration::from_millis(100));
+        sequencer.push(trade(1000), 0);
+        sequencer.push(trade(980), 10);
+        assert!(released(&mut sequencer).is_empty());
+
+        // The watermark moves to 1101 - 100
+        sequencer.push(trade(1101), 20);
+        assert_eq!(released(&mut sequencer), vec![980, 1000]);
+        assert!(sequencer.is_holding());
+
+        let stats = sequencer.stats();
+        assert_eq!((stats.released, stats.reordered, stats.late), (2, 1, 0));
+    }
+
+    #[test]
+    fn local_clock_releases_a_quiet_stream() {
+        let mut sequencer = Sequencer::new(Duration::from_millis(100));
+        sequencer.push(trade(1000), 0);
+        assert_eq!(sequencer.next_due(), Some(100));
+
+        sequencer.tick(99);
+        assert!(released(&mut sequencer).is_empty());
+        sequencer.tick(100);
+        assert_eq!(released(&mut sequencer), vec![1000]);
+        assert!(!sequencer.is_holding());
+        assert_eq!(sequencer.next_due(), None);
+    }
+
+    #[test]
+    fn late_events_go_out_at_once() {
+        let mut sequencer = Sequencer::new(Duration::from_millis(100));
+        sequencer.push(trade(1000), 0);
+        sequencer.tick(100);
+        sequencer.push(trade(900), 110);
+        sequencer.push(trade(950), 120);
+        assert_eq!(released(&mut sequencer), vec![1000, 900, 950]);
+
+        let stats = sequencer.stats();
+        assert_eq!((stats.late, stats.max_lateness), (2, 100));
+    }
+
+    #[test]
+    fn finish_releases_everything_held() {
+        let mut sequencer = Sequencer::new(Duration::from_secs(60));
+        for event_time in [3000, 1000, 2000] {
+            sequencer.push(trade(event_time), 0);
+        }
+        assert!(released(&mut sequencer).is_empty());
+
+        sequencer.finish();
+        assert_eq!(released(&mut sequencer), vec![1000, 2000, 3000]);
+    }
+
+    #[test]
+    fn report_reads_the_live_counts() {
+        let report = SequencerReport::new();
+        let mut sequencer = Sequencer::new(Duration::from_millis(100)).with_report(
+            &report,
+            "binance/future",
+            "BTCUSDT",
+        );
+        sequencer.push(trade(1000), 0);
+        sequencer.finish();
+
+        let sequencers = report.sequencers.lock().unwrap();
+        assert_eq!(sequencers[0].0, "binance/future BTCUSDT");
+        assert_eq!(sequencers[0].1.lock().unwrap().released, 1);
+    }
+}
@@ -1,57 +1,256 @@
-use crate::data::{depth, market};
-use crate::prism::core::{Core, MarketState};
+use crate::data::{bbo, clock::Clock, depth, market};
+use crate::prism::core::{
+    bbo_check::BboHistory,
+    event::{MarketEvent, Venue},
+    is_live,
+    quote::{upbit_market, KrwRates},
+    Core, MarketState,
+};
 use crate::prism::orderbook::Orderbook;
-use rust_decimal::prelude::FromStr;
-use rust_decimal::Decimal;
 use tokio::sync::mpsc;
 
-pub struct SpotCore;
+pub struct SpotCore {
+    pub krw_rates: Option<KrwRates>, // Upbit only
+}
 
 impl Core<SpotCore> {
     pub fn new(
+        venue: Venue,
+        symbol: &str,
         ob: mpsc::Receiver<depth::OrderbookUpdateStream>,
         agg: mpsc::Receiver<market::MarketData>,
+        bbo: mpsc::Receiver<bbo::BboData>,
     ) -> Self {
         Self {
             ob,
             agg,
-            additional: SpotCore,
-            market_state: MarketState::new(),
+            bbo,
+            additional: SpotCore { krw_rates: None },
+            venue,
+            clock: Clock::default(),
+            sequencer: None,
+            market_state: MarketState::new(symbol),
             total_orderbook: Orderbook::new(),
+            bbo_history: BboHistory::default(),
         }
     }
 
+    /// Track a KRW-equivalent price of an Upbit market
+    pub fn with_krw_rates(mut self, krw_rates: KrwRates) -> Self {
+        self.additional.krw_rates = Some(krw_rates);
+        self
+    }
+
     pub async fn work(&mut self) {
+        let venue = self.venue;
         loop {
-            tokio::select! {
-                Some(market) = self.agg.recv() => {
-                    // Update price
-                    self.market_state.event_time = market.event_time;
-                    self.market_state.transaction_time = market.trade_time;
-                    self.market_state.price = Decimal::from_str(&market.price).unwrap();
-
-                    match market.buyer_market_maker {
-                        true => {
-                            self.market_state.sell_quantity = Decimal::from_str(&market.quantity).unwrap();
-                            self.market_state.buy_quantity = Decimal::from(0);
-                        }
-                        false => {
-                            self.market_state.buy_quantity = Decimal::from_str(&market.quantity).unwrap();
-                            self.market_state.sell_quantity = Decimal::from(0);
-                        }
-                    }
-                    self.debug();
+            // Held events to release, unless every stream is gone and they go out at once
+            let due = self.flush_due().filter(|_| !self.is_closed());
+            let event = tokio::select! {
+                // Polled in this order, so the same arrivals always apply in the same order
+                biased;
+
+                // A stream that ends wakes the loop like a flush tick, so that a flush waiting
+                // on a clock nobody moves any more cannot hold the core up
+                trade = self.agg.recv(), if is_live(&self.agg) => {
+                    trade.map(|trade| MarketEvent::Trade { venue, trade })
+                }
+                bbo = self.bbo.recv(), if is_live(&self.bbo) => {
+                    bbo.map(|bbo| MarketEvent::Bbo { venue, bbo })
+                }
+                update = self.ob.recv(), if is_live(&self.ob) => {
+                    update.map(|update| MarketEvent::Depth { venue, update })
                 }
 
-                Some(ob) = self.ob.recv() => {
-                    // Update orderbook
-                    self.total_orderbook.update(&ob).await;
+                // Release the held events that waited out the lateness
+                _ = self.clock.sleep_until(due.unwrap_or_default()), if due.is_some() => None,
 
-                    self.debug();
-                }
+                // Every stream of this symbol is gone
+                else => break,
+            };
+            self.dispatch(event, Self::on_event);
+        }
+        self.finish(Self::on_event);
+    }
+
+    fn is_closed(&self) -> bool {
+        !is_live(&self.ob) && !is_live(&self.agg) && !is_live(&self.bbo)
+    }
+
+    /// Apply one event to the market state and the book.
+    /// Futures only events (mark, liquidation, stats) are ignored
+    pub fn on_event(&mut self, event: MarketEvent) {
+        self.apply_latency(&event);
+        match event {
+            MarketEvent::Trade { trade, .. } => {
+                // Update price
+                self.apply_trade(&trade);
+                self.update_krw_price();
+            }
 
-                else => {}
+            MarketEvent::Bbo { bbo, .. } => {
+                // Update best bid/offer
+                self.update_bbo(&bbo);
+            }
+
+            MarketEvent::Depth { update, .. } => {
+                // Update orderbook
+                self.apply_depth(&update);
+                self.update_krw_price();
+            }
+
+            MarketEvent::Mark { .. }
+            | MarketEvent::Liquidation { .. }
+            | MarketEvent::Stats { .. } => {
+                return;
+            }
+        }
+
+        self.debug();
+    }
+
+    fn update_krw_price(&mut self) {
+        let Some(krw_rates) = &self.additional.krw_rates else {
+            return;
+        };
+        let Some((quote, base)) = upbit_market(&self.market_state.symbol) else {
+            return;
+        };
+
+        if quote == "KRW" {
+            // KRW-BTC and KRW-USDT books price the BTC and USDT markets
+            if base == "BTC" || base == "USDT" {
+                if let Some(mid_price) = self.total_orderbook.mid_price() {
+                    krw_rates.set(base, mid_price);
+                }
             }
         }
+
+        let price = self.market_state.price;
+        if price.is_zero() {
+            return;
+        }
+        self.market_state.krw_price = match quote {
+            "KRW" => Some(price),
+            _ => krw_rates.get(quote).map(|rate| price * rate),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{clock::Clock, depth::OrderbookUpdateKind, latency::ReceiveStamp};
+    use crate::prism::core::sequencer::Sequencer;
+    use rust_decimal::Decimal;
+    use std::time::Duration;
+
+    fn trade(symbol: &str, price: i64, event_time: u64) -> market::MarketData {
+        market::MarketData {
+            symbol: symbol.to_string(),
+            price: Decimal::from(price),
+            quantity: Decimal::ONE,
+            buyer_market_maker: false,
+            trade_time: event_time,
+            event_time,
+            received: ReceiveStamp::local(event_time),
+        }
+    }
+
+    fn snapshot(symbol: &str, bid: i64, ask: i64) -> MarketEvent {
+        MarketEvent::Depth {
+            venue: Venue::Upbit,
+            update: depth::OrderbookUpdateStream {
+                symbol: symbol.to_string(),
+                bids: vec![(Decimal::from(bid), Decimal::ONE)],
+                asks: vec![(Decimal::from(ask), Decimal::ONE)],
+                kind: OrderbookUpdateKind::Snapshot,
+                trade_time: 1_000,
+                event_time: 1_000,
+                update_id: None,
+                last_update_exchange: "upbit".to_string(),
+                received: ReceiveStamp::local(1_000),
+            },
+        }
+    }
+
+    fn core(symbol: &str) -> Core<SpotCore> {
+        Core::<SpotCore>::new(
+            Venue::Upbit,
+            symbol,
+            mpsc::channel(1).1,
+            mpsc::channel(1).1,
+            mpsc::channel(1).1,
+        )
+    }
+
+    #[test]
+    fn krw_books_price_the_other_quote_markets() {
+        let krw_rates = KrwRates::new();
+        let mut krw_btc = core("KRW-BTC").with_krw_rates(krw_rates.clone());
+        let mut btc_eth = core("BTC-ETH").with_krw_rates(krw_rates.clone());
+
+        krw_btc.on_event(snapshot("KRW-BTC", 99, 101));
+        assert_eq!(krw_rates.get("BTC"), Some(Decimal::from(100)));
+
+        btc_eth.on_event(MarketEvent::Trade {
+            venue: Venue::Upbit,
+            trade: trade("BTC-ETH", 3, 1_000),
+        });
+        assert_eq!(btc_eth.market_state.price, Decimal::from(3));
+        assert_eq!(btc_eth.market_state.krw_price, Some(Decimal::from(300)));
+    }
+
+    #[test]
+    fn futures_events_leave_the_state_alone() {
+        let mut core = core("KRW-BTC");
+        core.on_event(MarketEvent::Liquidation {
+            venue: Venue::Upbit,
+            liquidation: crate::data::liquidation::LiquidationData {
+                symbol: "KRW-BTC".to_string(),
+                side: "SELL".to_string(),
+                avg_price: Decimal::ONE,
+                quantity: Decimal::ONE,
+                trade_time: 1_000,
+                event_time: 1_000,
+                received: ReceiveStamp::local(1_000),
+            },
+        });
+        assert_eq!(core.state_digest(), self::core("KRW-BTC").state_digest());
+    }
+
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn sequencer_flushes_on_the_core_clock() {
+        let clock = Clock::manual(0);
+        let (agg_tx, agg) = mpsc::channel(8);
+        let (ob_tx, ob) = mpsc::channel(8);
+        let (bbo_tx, bbo) = mpsc::channel(8);
+        let mut core = Core::<SpotCore>::new(Venue::Upbit, "KRW-BTC", ob, agg, bbo)
+            .with_clock(clock.clone())
+            .with_sequencer(Sequencer::new(Duration::from_millis(100)));
+        let core = tokio::spawn(async move {
+            core.work().await;
+            core
+        });
+
+        agg_tx.send(trade("KRW-BTC", 1, 2_000)).await.unwrap();
+        settle().await;
+        // Held for the lateness on the core's clock, then released by the flush
+        clock.set(100);
+        settle().await;
+        // Older than the released trade: it goes out at once, after it
+        agg_tx.send(trade("KRW-BTC", 2, 1_000)).await.unwrap();
+        settle().await;
+
+        drop((agg_tx, ob_tx, bbo_tx));
+        let core = core.await.unwrap();
+        // Without the flush both are held and released in event time order, ending at 1
+        assert_eq!(core.market_state.price, Decimal::from(2));
     }
 }
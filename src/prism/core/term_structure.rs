@@ -0,0 +1,145 @@
+use rust_decimal::Decimal;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+// Dated futures (e.g. BTCUSDT_250627) trade at a basis to the index that converges at delivery.
+// Every delivery contract core publishes its basis here, so that the curve of an
+// underlying (e.g. BTCUSDT: 250627, 250926) can be read by any part of the engine.
+
+const YEAR_MILLIS: i64 = 365 * 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone)]
+pub struct BasisPoint {
+    pub symbol: String,
+    pub delivery_time: u64,
+    pub basis: Decimal,            // (mark - index) / index
+    pub annualized_basis: Decimal, // basis scaled to a year by the time left to delivery
+    pub event_time: u64,
+}
+
+/// Latest basis of every delivery contract, keyed by underlying and then delivery time
+#[derive(Debug, Clone, Default)]
+pub struct TermStructure {
+    inner: Arc<RwLock<HashMap<String, BTreeMap<u64, BasisPoint>>>>,
+}
+
+impl TermStructure {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, point: BasisPoint) {
+        self.inner
+            .write()
+            .unwrap()
+            .entry(underlying(&point.symbol).to_string())
+            .or_default()
+            .insert(point.delivery_time, point);
+    }
+
+    /// Take a delivered contract off the curve of its underlying
+    pub fn remove(&self, symbol: &str, delivery_time: u64) {
+        let mut inner = self.inner.write().unwrap();
+        let underlying = underlying(symbol);
+        let Some(curve) = inner.get_mut(underlying) else {
+            return;
+        };
+        curve.remove(&delivery_time);
+        if curve.is_empty() {
+            inner.remove(underlying);
+        }
+    }
+
+    /// Contracts of `underlying` from the nearest delivery to the furthest
+    pub fn curve(&self, underlying: &str) -> Vec<BasisPoint> {
+        self.inner
+            .read()
+            .unwrap()
+            .get(underlying)
+            .map(|curve| curve.values().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Underlying of a dated contract, e.g. "BTCUSDT_250627" -> "BTCUSDT"
+pub fn underlying(symbol: &str) -> &str {
+    symbol.split_once('_').map_or(symbol, |(pair, _)| pair)
+}
+
+/// (basis, annualized basis) of a contract marked at `mark` against `index`.
+/// `None` once the contract is delivered
+pub fn basis(
+    mark: Decimal,
+    index: Decimal,
+    event_time: u64,
+    delivery_time: u64,
+) -> Option<(Decimal, Decimal)> {
+    let time_left = delivery_time as i64 - event_time as i64;
+    if index.is_zero() || time_left <= 0 {
+        return None;
+    }
+
+    let basis = (mark - index) / index;
+    let annualized = basis * Decimal::from(YEAR_MILLIS) / Decimal::from(time_left);
+    Some((basis, annualized))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(symbol: &str, delivery_time: u64) -> BasisPoint {
+        BasisPoint {
+            symbol: symbol.to_string(),
+            delivery_time,
+            basis: Decimal::ZERO,
+            annualized_basis: Decimal::ZERO,
+            event_time: 0,
+        }
+    }
+
+    #[test]
+    fn underlying_drops_the_delivery_date() {
+        assert_eq!(underlying("BTCUSDT_250627"), "BTCUSDT");
+        assert_eq!(underlying("BTCUSD_PERP"), "BTCUSD");
+        assert_eq!(underlying("BTCUSDT"), "BTCUSDT");
+    }
+
+    #[test]
+    fn basis_is_annualized_by_the_time_left() {
+        let (mark, index) = (Decimal::from(101), Decimal::from(100));
+        let quarter = (YEAR_MILLIS / 4) as u64;
+        assert_eq!(
+            basis(mark, index, 1_000, 1_000 + quarter),
+            Some((Decimal::new(1, 2), Decimal::new(4, 2)))
+        );
+
+        // Delivered, or no index to compare with
+        assert_eq!(basis(mark, index, 1_000, 1_000), None);
+        assert_eq!(basis(mark, index, 2_000, 1_000), None);
+        assert_eq!(basis(mark, Decimal::ZERO, 1_000, 1_000 + quarter), None);
+    }
+
+    #[test]
+    fn curve_runs_from_the_nearest_delivery_until_removed() {
+        let term_structure = TermStructure::new();
+        term_structure.set(point("BTCUSDT_250926", 2_000));
+        term_structure.set(point("BTCUSDT_250627", 1_000));
+        term_structure.set(point("ETHUSDT_250627", 1_000));
+
+        let symbols = |underlying: &str| -> Vec<String> {
+            term_structure
+                .curve(underlying)
+                .into_iter()
+                .map(|point| point.symbol)
+                .collect()
+        };
+        assert_eq!(symbols("BTCUSDT"), ["BTCUSDT_250627", "BTCUSDT_250926"]);
+
+        term_structure.remove("BTCUSDT_250627", 1_000);
+        assert_eq!(symbols("BTCUSDT"), ["BTCUSDT_250926"]);
+        assert_eq!(symbols("ETHUSDT"), ["ETHUSDT_250627"]);
+        term_structure.remove("BTCUSDT_250926", 2_000);
+        assert!(symbols("BTCUSDT").is_empty());
+    }
+}
@@ -2,7 +2,7 @@ use rust_decimal::Decimal;
 
 use super::vwap::Vwap;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Candle {
     pub ts: Option<u64>,
     pub te: Option<u64>,
@@ -1,6 +1,6 @@
 use rust_decimal::Decimal;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Vwap {
     pub cumul_price_volume: Decimal,
     pub cumul_volume: Decimal,
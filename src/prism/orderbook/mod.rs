@@ -1,15 +1,15 @@
-use crate::data::depth::OrderbookUpdateStream;
-use rust_decimal::prelude::FromStr;
+use crate::data::depth::{OrderbookUpdateKind, OrderbookUpdateStream};
 use rust_decimal::Decimal;
 use std::collections::BTreeMap;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Orderbook {
     pub bids: BTreeMap<Decimal, Decimal>,
     pub asks: BTreeMap<Decimal, Decimal>,
 
     pub trade_time: u64,
     pub event_time: u64,
+    pub update_id: Option<u64>, // Of the last update, where the venue numbers them
     pub last_source: Option<String>,
 }
 
@@ -20,32 +20,40 @@ impl Orderbook {
             asks: BTreeMap::new(),
             trade_time: 0,
             event_time: 0,
+            update_id: None,
             last_source: None,
         }
     }
 
-    pub async fn update(&mut self, update: &OrderbookUpdateStream) {
+    pub fn update(&mut self, update: &OrderbookUpdateStream) {
         if update.trade_time == 0 || update.event_time == 0 {
             return;
         }
 
-        for bid in update.bids.clone() {
-            self.update_bid(&bid.0, &bid.1);
+        match update.kind {
+            OrderbookUpdateKind::Snapshot => {
+                // Full picture of the book. Levels missing from the snapshot are gone
+                self.bids.clear();
+                self.asks.clear();
+            }
+            OrderbookUpdateKind::Delta => (),
         }
 
-        for ask in update.asks.clone() {
-            self.update_ask(&ask.0, &ask.1);
+        for &(price, volume) in update.bids.iter() {
+            self.update_bid(price, volume);
+        }
+
+        for &(price, volume) in update.asks.iter() {
+            self.update_ask(price, volume);
         }
 
         self.trade_time = update.trade_time;
         self.event_time = update.event_time;
+        self.update_id = update.update_id;
         self.last_source = Some(update.last_update_exchange.clone());
     }
 
-    fn update_bid(&mut self, price: &str, volume: &str) {
-        let price = Decimal::from_str(price).unwrap();
-        let volume = Decimal::from_str(volume).unwrap();
-
+    fn update_bid(&mut self, price: Decimal, volume: Decimal) {
         if volume.is_zero() {
             self.bids.remove(&price);
         } else {
@@ -53,10 +61,7 @@ impl Orderbook {
         }
     }
 
-    fn update_ask(&mut self, price: &str, volume: &str) {
-        let price = Decimal::from_str(price).unwrap();
-        let volume = Decimal::from_str(volume).unwrap();
-
+    fn update_ask(&mut self, price: Decimal, volume: Decimal) {
         if volume.is_zero() {
             self.asks.remove(&price);
         } else {
@@ -75,4 +80,126 @@ impl Orderbook {
             .first_key_value()
             .map(|(price, volume)| (*price, *volume))
     }
+
+    pub fn mid_price(&self) -> Option<Decimal> {
+        let (bid, _) = self.best_bid()?;
+        let (ask, _) = self.best_ask()?;
+        Some((bid + ask) / Decimal::from(2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::latency::ReceiveStamp;
+
+    fn levels(levels: &[(i64, i64)]) -> Vec<(Decimal, Decimal)> {
+        levels
+            .iter()
+            .map(|&(price, volume)| (Decimal::from(price), Decimal::from(volume)))
+            .collect()
+    }
+
+    fn update(
+        kind: OrderbookUpdateKind,
+        time: u64,
+        bids: &[(i64, i64)],
+        asks: &[(i64, i64)],
+    ) -> OrderbookUpdateStream {
+        OrderbookUpdateStream {
+            symbol: "BTCUSDT".to_string(),
+            bids: levels(bids),
+            asks: levels(asks),
+            kind,
+            trade_time: time,
+            event_time: time,
+            update_id: Some(time),
+            last_update_exchange: "binance".to_string(),
+            received: ReceiveStamp::local(time),
+        }
+    }
+
+    fn side(levels: &[(i64, i64)]) -> BTreeMap<Decimal, Decimal> {
+        self::levels(levels).into_iter().collect()
+    }
+
+    #[test]
+    fn snapshot_drops_the_levels_it_does_not_list() {
+        let mut orderbook = Orderbook::new();
+        orderbook.update(&update(
+            OrderbookUpdateKind::Snapshot,
+            1,
+            &[(98, 1), (99, 2)],
+            &[(101, 3), (102, 4)],
+        ));
+        orderbook.update(&update(
+            OrderbookUpdateKind::Snapshot,
+            2,
+            &[(97, 5)],
+            &[(102, 6)],
+        ));
+
+        assert_eq!(orderbook.bids, side(&[(97, 5)]));
+        assert_eq!(orderbook.asks, side(&[(102, 6)]));
+        assert_eq!(orderbook.update_id, Some(2));
+    }
+
+    #[test]
+    fn delta_merges_into_the_book() {
+        let mut orderbook = Orderbook::new();
+        orderbook.update(&update(
+            OrderbookUpdateKind::Snapshot,
+            1,
+            &[(98, 1), (99, 2)],
+            &[(101, 3)],
+        ));
+        orderbook.update(&update(
+            OrderbookUpdateKind::Delta,
+            2,
+            &[(99, 7), (97, 1)],
+            &[(100, 2)],
+        ));
+
+        assert_eq!(orderbook.bids, side(&[(97, 1), (98, 1), (99, 7)]));
+        assert_eq!(orderbook.asks, side(&[(100, 2), (101, 3)]));
+        assert_eq!(
+            orderbook.mid_price(),
+            Some(Decimal::new(995, 1)) // (99 + 100) / 2
+        );
+    }
+
+    #[test]
+    fn zero_volume_removes_the_level() {
+        let mut orderbook = Orderbook::new();
+        orderbook.update(&update(
+            OrderbookUpdateKind::Snapshot,
+            1,
+            &[(98, 1), (99, 2)],
+            &[(101, 3), (102, 4)],
+        ));
+        orderbook.update(&update(
+            OrderbookUpdateKind::Delta,
+            2,
+            &[(99, 0), (50, 0)],
+            &[(101, 0)],
+        ));
+
+        assert_eq!(
+            orderbook.best_bid(),
+            Some((Decimal::from(98), Decimal::ONE))
+        );
+        assert_eq!(
+            orderbook.best_ask(),
+            Some((Decimal::from(102), Decimal::from(4)))
+        );
+        assert_eq!(orderbook.bids, side(&[(98, 1)]));
+    }
+
+    #[test]
+    fn updates_without_a_time_are_ignored() {
+        let mut orderbook = Orderbook::new();
+        orderbook.update(&update(OrderbookUpdateKind::Snapshot, 0, &[(98, 1)], &[]));
+        assert!(orderbook.bids.is_empty());
+        assert_eq!(orderbook.update_id, None);
+    }
 }
@@ -0,0 +1,253 @@
+// The Binance futures orderbook handler reads a scripted depth feed through the fault
+// injector, resyncing from a local REST stand-in. Whatever the faults, the book it builds
+// must be the fault-free book at the same update id.
+
+use cryptoquant::data::binance::BinanceFutureMarket;
+use cryptoquant::data::depth::binance::future::BinanceFutureOrderbookStreamHandler;
+use cryptoquant::data::endpoints::Endpoint;
+use cryptoquant::data::fault::{FaultConfig, FaultInjector};
+use cryptoquant::data::health::StreamHealth;
+use cryptoquant::data::router::SymbolRouter;
+use cryptoquant::prism::orderbook::Orderbook;
+use futures::{sink, stream, SinkExt, Stream};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_decimal::Decimal;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+const SYMBOL: &str = "BTCUSDT";
+const EVENTS: usize = 300;
+const FRAME_SPACING: Duration = Duration::from_millis(2);
+
+type Levels = BTreeMap<Decimal, Decimal>;
+
+/// Depth events of one market and the book after each of them
+struct Feed {
+    frames: Vec<String>,
+    books: Vec<(u64, Levels, Levels)>, // (final update id, bids, asks). The initial book first
+}
+
+impl Feed {
+    fn generate(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let level = |price: i64, quantity: i64| (Decimal::new(price, 2), Decimal::new(quantity, 3));
+        let mut bids: Levels = (1..=10).map(|i| level(10000 - i, 1000)).collect();
+        let mut asks: Levels = (1..=10).map(|i| level(10000 + i, 1000)).collect();
+        let mut books = vec![(1, bids.clone(), asks.clone())];
+        let mut frames = Vec::new();
+
+        let mut last = 1;
+        for i in 0..EVENTS {
+            let mut changes = (Vec::new(), Vec::new());
+            for _ in 0..rng.random_range(1..=3) {
+                let quantity = rng.random_range(0..3) * rng.random_range(1..5000);
+                match rng.random_bool(0.5) {
+                    true => changes
+                        .0
+                        .push(level(10000 - rng.random_range(1..=15), quantity)),
+                    false => changes
+                        .1
+                        .push(level(10000 + rng.random_range(1..=15), quantity)),
+                }
+            }
+            for (side, levels) in [(&mut bids, &changes.0), (&mut asks, &changes.1)] {
+                for &(price, quantity) in levels {
+                    match quantity.is_zero() {
+                        true => side.remove(&price),
+                        false => side.insert(price, quantity),
+                    };
+                }
+            }
+
+            // At least two ids per event, so that a snapshot can claim one inside the next
+            let (first, next) = (last + 1, last + 1 + rng.random_range(1..=4));
+            let time = 1_700_000_000_000 + i as u64;
+            let render = |levels: &Vec<(Decimal, Decimal)>| {
+                levels
+                    .iter()
+                    .map(|(price, quantity)| [price.to_string(), quantity.to_string()])
+                    .collect::<Vec<_>>()
+            };
+            frames.push(
+                json!({
+                    "stream": "btcusdt@depth",
+                    "data": {
+                        "e": "depthUpdate", "E": time, "T": time, "s": SYMBOL,
+                        "U": first, "u": next, "pu": last,
+                        "b": render(&changes.0), "a": render(&changes.1),
+                    },
+                })
+                .to_string(),
+            );
+            books.push((next, bids.clone(), asks.clone()));
+            last = next;
+        }
+        Self { frames, books }
+    }
+
+    /// Book after the last event up to `update_id`
+    fn book_at(&self, update_id: u64) -> &(u64, Levels, Levels) {
+        let after = self.books.partition_point(|(id, _, _)| *id <= update_id);
+        &self.books[after.max(1) - 1]
+    }
+}
+
+/// Answers every depth request with the book after the last frame the socket has sent
+async fn serve_snapshots(feed: Arc<Feed>, sent: Arc<AtomicUsize>) -> Endpoint {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                match socket.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buffer[..n]),
+                }
+            }
+
+            let (update_id, bids, asks) = &feed.books[sent.load(Ordering::SeqCst)];
+            let render = |levels: &Levels| {
+                levels
+                    .iter()
+                    .map(|(price, quantity)| [price.to_string(), quantity.to_string()])
+                    .collect::<Vec<_>>()
+            };
+            let body = json!({
+                "lastUpdateId": update_id + 1,
+                "E": 1_700_000_000_000u64,
+                "T": 1_700_000_000_000u64,
+                "bids": render(bids),
+                "asks": render(asks),
+            })
+            .to_string();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+    Endpoint::new("ws://127.0.0.1:0", &format!("http://{}", addr))
+}
+
+/// Plays the frames one by one, counting the ones sent
+fn socket(
+    feed: Arc<Feed>,
+    sent: Arc<AtomicUsize>,
+) -> impl Stream<Item = Result<Message, tungstenite::Error>> + Unpin {
+    Box::pin(stream::unfold(0, move |i| {
+        let (feed, sent) = (feed.clone(), sent.clone());
+        async move {
+            let frame = feed.frames.get(i)?;
+            tokio::time::sleep(FRAME_SPACING).await;
+            sent.store(i + 1, Ordering::SeqCst);
+            Some((Ok(Message::Text(frame.clone().into())), i + 1))
+        }
+    }))
+}
+
+/// Book the handler builds from the feed, with the counters of its stream
+async fn run(
+    feed: Arc<Feed>,
+    faults: Option<FaultConfig>,
+) -> (Orderbook, BTreeMap<&'static str, u64>) {
+    let sent = Arc::new(AtomicUsize::new(0));
+    let endpoint = serve_snapshots(feed.clone(), sent.clone()).await;
+
+    let health = StreamHealth::new();
+    let monitor = health.monitor("binance/future/orderbook/0");
+    let (tx, mut rx) = mpsc::channel(1024);
+    let mut router = SymbolRouter::default();
+    router.insert(SYMBOL, tx);
+    let mut handler = BinanceFutureOrderbookStreamHandler::new(
+        BinanceFutureMarket::UsdM,
+        vec![SYMBOL.to_lowercase()],
+        router,
+        endpoint,
+        monitor.clone(),
+    );
+    let faults = match faults {
+        Some(config) => FaultInjector::new(&config, &monitor),
+        None => FaultInjector::disabled(),
+    };
+    handler = handler.with_faults(faults.clone());
+
+    let book = tokio::spawn(async move {
+        let mut book = Orderbook::new();
+        while let Some(update) = rx.recv().await {
+            book.update(&update);
+        }
+        book
+    });
+
+    let write = sink::drain().sink_map_err(|never: Infallible| match never {});
+    let (read, write) = faults.wrap(socket(feed, sent), write);
+    handler.handle_orderbook(read, write).await.unwrap();
+    drop(handler);
+
+    let counters = health
+        .snapshot()
+        .into_iter()
+        .map(|(_, status)| status.counters)
+        .next()
+        .unwrap_or_default();
+    (book.await.unwrap(), counters)
+}
+
+fn fault_config(seed: u64) -> FaultConfig {
+    FaultConfig {
+        seed,
+        streams: Vec::new(),
+        drop: 0.01,
+        duplicate: 0.01,
+        reorder: 0.01,
+        stall: 0.0,
+        close: 0.0,
+        snapshot_timeout: 0.0,
+        stall_duration: Duration::ZERO,
+    }
+}
+
+fn faults_injected(counters: &BTreeMap<&'static str, u64>) -> HashMap<&'static str, u64> {
+    counters
+        .iter()
+        .filter(|(name, _)| name.starts_with("faults_"))
+        .map(|(name, count)| (*name, *count))
+        .collect()
+}
+
+#[tokio::test]
+async fn book_under_faults_matches_the_fault_free_book() {
+    let feed = Arc::new(Feed::generate(1));
+
+    let (clean, _) = run(feed.clone(), None).await;
+    let (last_id, bids, asks) = feed.books.last().unwrap();
+    assert_eq!(clean.update_id, Some(*last_id));
+    assert_eq!((&clean.bids, &clean.asks), (bids, asks));
+
+    let (faulty, counters) = run(feed.clone(), Some(fault_config(42))).await;
+    assert!(
+        counters.get("resyncs").copied().unwrap_or(0) > 0,
+        "{:?}",
+        counters
+    );
+    let faulty_id = faulty.update_id.expect("the faulty book never synced");
+    let (_, bids, asks) = feed.book_at(faulty_id);
+    assert_eq!((&faulty.bids, &faulty.asks), (bids, asks));
+
+    // Same seed and same frames, same faults
+    let (_, again) = run(feed, Some(fault_config(42))).await;
+    assert_eq!(faults_injected(&counters), faults_injected(&again));
+}
@@ -0,0 +1,288 @@
+// The Binance and Upbit streams run against the mock exchange binary, which drops every socket
+// now and then. What reaches the core channels must be every trade of the mock exactly once,
+// with the trades missed while reconnecting backfilled and COIN-M contracts turned into coins.
+
+use cryptoquant::channel::{FutureChannel, SpotChannel};
+use cryptoquant::data::binance::BinanceThreads;
+use cryptoquant::data::depth::OrderbookUpdateStream;
+use cryptoquant::data::endpoints::{BinanceEndpoints, Endpoint};
+use cryptoquant::data::exchanges::{FutureDataChannels, SpotDataChannels};
+use cryptoquant::data::health::StreamHealth;
+use cryptoquant::data::market::MarketData;
+use cryptoquant::data::supervisor::{RetryPolicy, Supervisor};
+use cryptoquant::data::upbit::UpbitThreads;
+use cryptoquant::prism::orderbook::Orderbook;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+const FUTURE: &str = "BTCUSDT";
+const COIN: &str = "BTCUSD_PERP";
+const UPBIT: &str = "KRW-BTC";
+const BTC_CONTRACT_SIZE: i64 = 100; // USD, as listed by the mock's COIN-M exchangeInfo
+
+// Every aggTrade socket must have dropped and backfilled by then, also on a slow machine
+const DEADLINE: Duration = Duration::from_secs(30);
+const AGGTRADE_STREAMS: [&str; 3] = [
+    "binance/future/aggtrade/0",
+    "binance/coin/aggtrade/0",
+    "upbit/krw/aggtrade",
+];
+
+/// The mock exchange binary on a free local port, killed on drop
+struct Mock {
+    child: Child,
+    addr: String,
+}
+
+impl Mock {
+    async fn start() -> Self {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .to_string();
+        let child = Command::new(env!("CARGO_BIN_EXE_mock_exchange"))
+            .env("MOCK_ADDR", &addr)
+            .env("MOCK_TICK_MS", "10")
+            .env("MOCK_SOCKET_LIFETIME_MS", "800")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        // Killed on drop from here on, also when it never listens
+        let mock = Self { child, addr };
+
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(&mock.addr).await.is_ok() {
+                return mock;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("the mock exchange did not listen on {}", mock.addr);
+    }
+
+    fn http(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(&self, path: &str) -> T {
+        reqwest::get(format!("{}{}", self.http(), path))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap()
+    }
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// What the core of one symbol received
+#[derive(Default)]
+struct Received {
+    book: Orderbook,
+    depth_updates: usize,
+    trades: Vec<MarketData>,
+}
+
+/// Collects the depth and trade channels of a symbol until every sender is gone
+fn collect(
+    mut ob: mpsc::Receiver<OrderbookUpdateStream>,
+    mut agg: mpsc::Receiver<MarketData>,
+) -> tokio::task::JoinHandle<Received> {
+    tokio::spawn(async move {
+        let mut received = Received::default();
+        loop {
+            tokio::select! {
+                Some(update) = ob.recv() => {
+                    received.book.update(&update);
+                    received.depth_updates += 1;
+                }
+                Some(trade) = agg.recv() => received.trades.push(trade),
+                else => break,
+            }
+        }
+        received
+    })
+}
+
+fn future_route(
+    routes: &mut FutureDataChannels,
+    symbol: &str,
+) -> tokio::task::JoinHandle<Received> {
+    let channel = FutureChannel::new(4096);
+    routes.add_route(symbol, &channel);
+    collect(channel.ob.1, channel.agg.1)
+}
+
+fn spot_route(routes: &mut SpotDataChannels, symbol: &str) -> tokio::task::JoinHandle<Received> {
+    let channel = SpotChannel::new(4096);
+    routes.add_route(symbol, &channel);
+    collect(channel.ob.1, channel.agg.1)
+}
+
+#[derive(Debug, Deserialize)]
+struct RestAggTrade {
+    p: Decimal,
+    q: Decimal,
+    #[serde(rename = "T")]
+    time: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RestTradeTick {
+    timestamp: u64,
+    trade_price: Decimal,
+    trade_volume: Decimal,
+}
+
+/// (trade time, price, quantity) of every trade, oldest first
+type Trades = Vec<(u64, Decimal, Decimal)>;
+
+fn trades(received: &Received) -> Trades {
+    let mut trades: Trades = received
+        .trades
+        .iter()
+        .map(|trade| (trade.trade_time, trade.price, trade.quantity))
+        .collect();
+    trades.sort();
+    trades
+}
+
+/// The mock's trades over the span of `received`. Each of them must have arrived once
+fn assert_every_trade_once(symbol: &str, received: &Trades, mut expected: Trades) {
+    let (first, last) = match (received.first(), received.last()) {
+        (Some(first), Some(last)) => (first.0, last.0),
+        _ => panic!("{} received no trades", symbol),
+    };
+    expected.retain(|(time, _, _)| (first..=last).contains(time));
+    expected.sort();
+    assert_eq!(received, &expected, "{}", symbol);
+}
+
+fn counter(health: &StreamHealth, stream: &str, name: &str) -> u64 {
+    health
+        .snapshot()
+        .into_iter()
+        .find(|(stream_name, _)| stream_name == stream)
+        .and_then(|(_, status)| status.counters.get(name).copied())
+        .unwrap_or(0)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn streams_deliver_every_mock_trade_once() {
+    let mock = Mock::start().await;
+    let ws = format!("ws://{}", mock.addr);
+    let endpoint = Endpoint::new(&ws, &mock.http());
+
+    let (mut future, mut coin, mut upbit) = (
+        FutureDataChannels::new(),
+        FutureDataChannels::new(),
+        SpotDataChannels::new(),
+    );
+    let future_received = future_route(&mut future, FUTURE);
+    let coin_received = future_route(&mut coin, COIN);
+    let upbit_received = spot_route(&mut upbit, UPBIT);
+
+    let health = StreamHealth::new();
+    let supervisor = Supervisor::new(health.clone(), RetryPolicy::new(50, 200, 0));
+    let mut tasks = JoinSet::new();
+    BinanceThreads::new(
+        future,
+        coin,
+        SpotDataChannels::new(),
+        supervisor.clone(),
+        BinanceEndpoints {
+            future: endpoint.clone(),
+            coin: endpoint.clone(),
+            spot: endpoint.clone(),
+        },
+        10,
+    )
+    .spawn_streams(
+        &mut tasks,
+        vec![FUTURE.to_string()],
+        vec![COIN.to_string()],
+        Vec::new(),
+    );
+    UpbitThreads::new(
+        "krw",
+        upbit,
+        supervisor,
+        Endpoint::new(&format!("{}/websocket/v1", ws), &mock.http()),
+    )
+    .spawn_streams(&mut tasks, vec![UPBIT.to_string()]);
+
+    // Every socket was dropped at least once, the trades missed meanwhile were fetched
+    let deadline = tokio::time::Instant::now() + DEADLINE;
+    for stream in AGGTRADE_STREAMS {
+        while counter(&health, stream, "backfilled_trades") == 0 {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "{} backfilled nothing",
+                stream
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+    tasks.abort_all();
+    while tasks.join_next().await.is_some() {}
+    let (future_received, coin_received, upbit_received) = (
+        future_received.await.unwrap(),
+        coin_received.await.unwrap(),
+        upbit_received.await.unwrap(),
+    );
+
+    for (symbol, received) in [
+        (FUTURE, &future_received),
+        (COIN, &coin_received),
+        (UPBIT, &upbit_received),
+    ] {
+        assert!(received.depth_updates > 0, "{} received no depth", symbol);
+        let (bid, ask) = (received.book.best_bid(), received.book.best_ask());
+        assert!(
+            matches!((bid, ask), (Some(bid), Some(ask)) if bid.0 < ask.0),
+            "{} book {:?} {:?}",
+            symbol,
+            bid,
+            ask
+        );
+    }
+
+    let rest: Vec<RestAggTrade> = mock
+        .get(&format!("/fapi/v1/aggTrades?symbol={}&limit=1000", FUTURE))
+        .await;
+    let expected = rest
+        .iter()
+        .map(|trade| (trade.time, trade.p, trade.q))
+        .collect();
+    assert_every_trade_once(FUTURE, &trades(&future_received), expected);
+
+    // COIN-M quantities are contracts. The core gets coins
+    let rest: Vec<RestAggTrade> = mock
+        .get(&format!("/dapi/v1/aggTrades?symbol={}&limit=1000", COIN))
+        .await;
+    let size = Decimal::from(BTC_CONTRACT_SIZE);
+    let expected = rest
+        .iter()
+        .map(|trade| (trade.time, trade.p, trade.q * size / trade.p))
+        .collect();
+    assert_every_trade_once(COIN, &trades(&coin_received), expected);
+
+    let rest: Vec<RestTradeTick> = mock
+        .get(&format!("/v1/trades/ticks?market={}&count=500", UPBIT))
+        .await;
+    let expected = rest
+        .iter()
+        .map(|tick| (tick.timestamp, tick.trade_price, tick.trade_volume))
+        .collect();
+    assert_every_trade_once(UPBIT, &trades(&upbit_received), expected);
+}
@@ -0,0 +1,244 @@
+// A small Binance futures capture, written in the recorder's format, is replayed twice through
+// the live handlers into a core. Both replays must end in the same state.
+
+use cryptoquant::channel::FutureChannel;
+use cryptoquant::data::binance::BinanceThreads;
+use cryptoquant::data::endpoints::BinanceEndpoints;
+use cryptoquant::data::exchanges::{FutureDataChannels, SpotDataChannels};
+use cryptoquant::data::health::StreamHealth;
+use cryptoquant::data::replay::{Replay, ReplayConfig, ReplaySpeed};
+use cryptoquant::data::supervisor::{RetryPolicy, Supervisor};
+use cryptoquant::prism::core::{event::Venue, future::FutureCore, Core};
+use flate2::{write::GzEncoder, Compression};
+use rust_decimal::Decimal;
+use serde_json::json;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::JoinSet;
+
+const SYMBOL: &str = "BTCUSDT";
+const START_MS: u64 = 1_700_000_000_000; // 2023-11-14T22:13:20Z
+const DEPTH_EVENTS: u64 = 40;
+const SNAPSHOT_AFTER: u64 = 3; // Depth events received before the snapshot response
+
+// Frame kinds of the capture format
+const FRAME_TEXT: u8 = 1;
+const FRAME_REST: u8 = 3;
+const FRAME_CONNECT: u8 = 4;
+
+/// Frames of one captured stream, in receive order
+#[derive(Default)]
+struct CapturedStream {
+    frames: Vec<(u64, u8, Vec<u8>)>, // (receive time in unix millis, kind, payload)
+}
+
+impl CapturedStream {
+    fn push(&mut self, received_ms: u64, kind: u8, payload: impl Into<Vec<u8>>) {
+        self.frames.push((received_ms, kind, payload.into()));
+    }
+
+    fn rest(&mut self, received_ms: u64, target: &str, body: serde_json::Value) {
+        self.push(received_ms, FRAME_REST, format!("{}\n{}", target, body));
+    }
+
+    /// Write the stream to `<root>/<name>/<hour>.frames.gz`
+    fn write(&self, root: &Path, name: &str) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        let file = File::create(dir.join("2023-11-14T22.frames.gz")).unwrap();
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        for (received_ms, kind, payload) in &self.frames {
+            encoder
+                .write_all(&(received_ms * 1_000_000).to_le_bytes())
+                .unwrap();
+            encoder.write_all(&[*kind]).unwrap();
+            encoder
+                .write_all(&(payload.len() as u32).to_le_bytes())
+                .unwrap();
+            encoder.write_all(payload).unwrap();
+        }
+        encoder.finish().unwrap();
+    }
+}
+
+/// Depth updates chained by `pu`, with the REST snapshot taken in the middle of them
+fn orderbook_capture() -> CapturedStream {
+    let mut capture = CapturedStream::default();
+    capture.push(
+        START_MS,
+        FRAME_CONNECT,
+        "wss://fstream.binance.com/stream?streams=btcusdt@depth",
+    );
+
+    let level = |price: u64, quantity: u64| [format!("{}.0", price), format!("{}.000", quantity)];
+    let mut last = 1;
+    for i in 0..DEPTH_EVENTS {
+        let received = START_MS + 10 * (i + 1);
+        let (first, next) = (last + 1, last + 3);
+        capture.push(
+            received,
+            FRAME_TEXT,
+            json!({
+                "stream": "btcusdt@depth",
+                "data": {
+                    "e": "depthUpdate", "E": received - 2, "T": received - 3, "s": SYMBOL,
+                    "U": first, "u": next, "pu": last,
+                    "b": [level(36990 - i % 5, i % 3), level(36980, i + 1)],
+                    "a": [level(37010 + i % 5, (i + 1) % 3), level(37020, i + 2)],
+                },
+            })
+            .to_string(),
+        );
+        if i + 1 == SNAPSHOT_AFTER {
+            // Claims an id inside the next event, which the handler applies first
+            capture.rest(
+                received + 5,
+                &format!("/fapi/v1/depth?symbol={}&limit=1000", SYMBOL),
+                json!({
+                    "lastUpdateId": next + 1,
+                    "E": received,
+                    "T": received,
+                    "bids": [level(36990, 1), level(36985, 2), level(36980, 3)],
+                    "asks": [level(37010, 1), level(37015, 2), level(37020, 3)],
+                }),
+            );
+        }
+        last = next;
+    }
+    capture
+}
+
+/// Aggregate trades with a gap, filled by a backfill response that arrives later
+fn aggtrade_capture() -> CapturedStream {
+    let mut capture = CapturedStream::default();
+    capture.push(
+        START_MS + 1,
+        FRAME_CONNECT,
+        "wss://fstream.binance.com/stream?streams=btcusdt@aggTrade",
+    );
+
+    let trade = |id: u64, time: u64| {
+        json!({
+            "a": id, "p": format!("{}.5", 37000 + id % 7), "q": format!("0.{:03}", id),
+            "T": time, "m": id.is_multiple_of(2),
+        })
+    };
+    let missing = 110..113;
+    for id in (100..130).filter(|id| !missing.contains(id)) {
+        let received = START_MS + 7 * (id - 99);
+        let mut data = trade(id, received - 3);
+        data["e"] = json!("aggTrade");
+        data["E"] = json!(received - 2);
+        data["s"] = json!(SYMBOL);
+        data["f"] = json!(id * 2);
+        data["l"] = json!(id * 2 + 1);
+        capture.push(
+            received,
+            FRAME_TEXT,
+            json!({ "stream": "btcusdt@aggTrade", "data": data }).to_string(),
+        );
+        if id == missing.end + 1 {
+            capture.rest(
+                received + 4,
+                &format!(
+                    "/fapi/v1/aggTrades?symbol={}&fromId={}&limit={}",
+                    SYMBOL,
+                    missing.start,
+                    missing.end - missing.start
+                ),
+                missing
+                    .clone()
+                    .map(|id| trade(id, START_MS + 7 * (id - 99)))
+                    .collect(),
+            );
+        }
+    }
+    capture
+}
+
+fn capture_dir() -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let dir = std::env::temp_dir().join(format!("replay-test-{}-{}", std::process::id(), nanos));
+    orderbook_capture().write(&dir, "binance/future/orderbook/0");
+    aggtrade_capture().write(&dir, "binance/future/aggtrade/0");
+    dir
+}
+
+/// BTCUSDT core on the replay clock, routed from `routes`
+fn core(replay: &Replay, routes: &mut FutureDataChannels) -> Core<FutureCore> {
+    let channel = FutureChannel::new(1024);
+    routes.add_route(SYMBOL, &channel);
+    // The senders left in `channel` go with it, the routes hold the only others
+    Core::<FutureCore>::new(
+        Venue::Binance,
+        SYMBOL,
+        channel.ob.1,
+        channel.agg.1,
+        channel.bbo.1,
+        channel.additional.mark.1,
+        channel.additional.liq.1,
+        channel.additional.stats.1,
+    )
+    .with_clock(replay.clock())
+}
+
+/// Replay the capture in `dir` into a BTCUSDT core and return the core once it stopped
+async fn replay(dir: &Path) -> Core<FutureCore> {
+    let replay = Replay::new(ReplayConfig {
+        dir: dir.to_path_buf(),
+        speed: ReplaySpeed::Max,
+    });
+
+    let mut routes = FutureDataChannels::new();
+    let mut core = core(&replay, &mut routes);
+    let core = tokio::spawn(async move {
+        core.work().await;
+        core
+    });
+
+    let supervisor =
+        Supervisor::new(StreamHealth::new(), RetryPolicy::new(0, 0, 0)).with_replay(replay.clone());
+    let mut tasks = JoinSet::new();
+    BinanceThreads::new(
+        routes.clone(),
+        FutureDataChannels::new(),
+        SpotDataChannels::new(),
+        supervisor,
+        BinanceEndpoints::mainnet(),
+        10,
+    )
+    .spawn_streams(&mut tasks, vec![SYMBOL.to_string()], Vec::new(), Vec::new());
+
+    replay
+        .run(vec![routes], Vec::new())
+        .await
+        .expect("the replay stalled");
+
+    // Streams without a capture stay idle until stopped. The core stops once they are gone
+    tasks.abort_all();
+    while tasks.join_next().await.is_some() {}
+    core.await.unwrap()
+}
+
+#[tokio::test]
+async fn same_capture_replays_to_the_same_state() {
+    let dir = capture_dir();
+
+    let first = replay(&dir).await;
+    let book = &first.total_orderbook;
+    // The last depth event ends at 1 + 3 * DEPTH_EVENTS
+    assert_eq!(book.update_id, Some(1 + 3 * DEPTH_EVENTS));
+    assert!(book.best_bid().is_some() && book.best_ask().is_some());
+    // Trade 129, after the backfilled ones
+    assert_eq!(first.market_state.price, Decimal::new(370035, 1));
+
+    let second = replay(&dir).await;
+    assert_eq!(first.state_digest(), second.state_digest());
+
+    fs::remove_dir_all(&dir).unwrap();
+}